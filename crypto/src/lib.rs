@@ -10,6 +10,7 @@ use rand::{random, Rng};
 use rsa::{BigUint, Oaep, Pkcs1v15Encrypt, Pss, RsaPublicKey};
 use sha1::Sha1;
 use std::convert::TryInto;
+use steamid_ng::Universe;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -20,6 +21,8 @@ pub enum CryptError {
     MalformedMessage,
     #[error("Invalid HMAC")]
     InvalidHmac,
+    #[error("No public key is known for the {0:?} universe")]
+    UnsupportedUniverse(Universe),
 }
 
 pub type Result<T> = std::result::Result<T, CryptError>;
@@ -49,27 +52,51 @@ pub fn verify_signature(data: &[u8], signature: &[u8]) -> Result<bool> {
     }
 }
 
+/// Calculate the sha1 hash of the data
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    use sha1::Digest;
+
+    Sha1::digest(data).into()
+}
+
 pub struct SessionKeys {
     pub plain: [u8; 32],
     pub encrypted: Vec<u8>,
 }
 
-pub fn generate_session_key(nonce: Option<&[u8; 16]>) -> SessionKeys {
+/// Generate a random session key, and encrypt it with the steam system public key
+///
+/// See [`encrypt_session_key`] for when to pass the `nonce`.
+pub fn generate_session_key(nonce: Option<&[u8; 16]>) -> Result<SessionKeys> {
     let mut rng = rand::thread_rng();
     let plain: [u8; 32] = rng.gen();
+    encrypt_session_key(&SYSTEM_PUBLIC_KEY, &plain, nonce)
+}
 
+/// Encrypt a session key with the public key of the server
+///
+/// The `nonce` is the challenge from the `ChannelEncryptRequest` of the server. When it's passed,
+/// the encrypted data is the key followed by the nonce, which lets the server verify that the key
+/// was generated for this handshake. Without a nonce only the key itself is encrypted.
+pub fn encrypt_session_key(
+    key: &RsaPublicKey,
+    plain: &[u8; 32],
+    nonce: Option<&[u8; 16]>,
+) -> Result<SessionKeys> {
     let encrypted = match nonce {
         Some(nonce) => {
             let mut data = [0; 48];
-            data[0..32].copy_from_slice(&plain);
+            data[0..32].copy_from_slice(plain);
             data[32..48].copy_from_slice(nonce);
-            encrypt_with_key(&SYSTEM_PUBLIC_KEY, &data)
+            encrypt_with_key(key, &data)
         }
-        None => encrypt_with_key(&SYSTEM_PUBLIC_KEY, &plain),
-    }
-    .expect("Invalid crypt setup");
+        None => encrypt_with_key(key, plain),
+    }?;
 
-    SessionKeys { plain, encrypted }
+    Ok(SessionKeys {
+        plain: *plain,
+        encrypted,
+    })
 }
 
 pub fn encrypt_with_key(key: &RsaPublicKey, data: &[u8]) -> Result<Vec<u8>> {
@@ -88,14 +115,39 @@ pub fn encrypt_with_key_pkcs1(key: &RsaPublicKey, data: &[u8]) -> Result<Vec<u8>
 
 #[test]
 fn test_gen_session_key() {
-    assert!(!generate_session_key(None).encrypted.is_empty());
+    assert!(!generate_session_key(None).unwrap().encrypted.is_empty());
     assert!(!generate_session_key(Some(&[
         1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
     ]))
+    .unwrap()
     .encrypted
     .is_empty(),);
 }
 
+#[test]
+fn test_encrypt_session_key() {
+    use rsa::RsaPrivateKey;
+
+    let private = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+    let public = RsaPublicKey::from(&private);
+    let plain = [7; 32];
+    let nonce = [3; 16];
+
+    let keys = encrypt_session_key(&public, &plain, Some(&nonce)).unwrap();
+    assert_eq!(plain, keys.plain);
+    let decrypted = private
+        .decrypt(Oaep::new::<Sha1>(), &keys.encrypted)
+        .unwrap();
+    assert_eq!(&plain[..], &decrypted[0..32]);
+    assert_eq!(&nonce[..], &decrypted[32..]);
+
+    let keys = encrypt_session_key(&public, &plain, None).unwrap();
+    let decrypted = private
+        .decrypt(Oaep::new::<Sha1>(), &keys.encrypted)
+        .unwrap();
+    assert_eq!(&plain[..], &decrypted[..]);
+}
+
 /// Decrypt an Initialization Vector with AES 256 ECB.
 fn encrypt_iv(iv: [u8; 16], key: &[u8; 32]) -> [u8; 16] {
     let iv_crypter = Aes256::new(GenericArray::from_slice(key));
@@ -184,6 +236,22 @@ fn symmetric_encrypt_with_iv(
 
 type HmacSha1 = Hmac<Sha1>;
 
+/// Calculate the HMAC-SHA1 of the data
+pub fn hmac_sha1(key: &[u8], data: &[&[u8]]) -> [u8; 20] {
+    let mut hmac = <HmacSha1 as Mac>::new_from_slice(key).expect("hmac accepts any key length");
+    for data in data {
+        hmac.update(data);
+    }
+    hmac.finalize().into_bytes().into()
+}
+
+/// The hmac key used for the iv is the first half of the session key, padded to 64 bytes
+fn iv_hmac_key(key: &[u8; 32]) -> [u8; 64] {
+    let mut hmac_key = [0; 64];
+    hmac_key[0..16].copy_from_slice(&key[0..16]);
+    hmac_key
+}
+
 /// Generate a random IV and encrypt `input` with it and `key` with a buffer for storing the iv.
 ///
 /// The `iv_buff` has to be 16 bytes large should come from a split slice in front of the input buffer
@@ -193,15 +261,7 @@ pub fn symmetric_encrypt_with_iv_buffer(
     key: &[u8; 32],
 ) -> BytesMut {
     let hmac_random: [u8; 3] = random();
-
-    let mut hmac_key = [0; 64];
-    hmac_key[0..16].copy_from_slice(&key[0..16]);
-
-    let mut hmac = <HmacSha1 as Mac>::new(GenericArray::from_slice(&hmac_key));
-    hmac.update(&hmac_random);
-    hmac.update(&input);
-
-    let hmac: [u8; 20] = hmac.finalize().into_bytes().into();
+    let hmac = hmac_sha1(&iv_hmac_key(key), &[&hmac_random, &input]);
 
     let mut iv = [0; 16];
     iv[0..13].copy_from_slice(&hmac[0..13]);
@@ -217,9 +277,17 @@ pub fn symmetric_encrypt(input: BytesMut, key: &[u8; 32]) -> BytesMut {
 
 /// Decrypt the IV stored in the first 16 bytes of `input`
 /// and use it to decrypt the remaining bytes.
+///
+/// Decryption is done in place, the returned buffer re-uses the allocation of `input`.
 pub fn symmetric_decrypt(mut input: BytesMut, key: &[u8; 32]) -> Result<BytesMut> {
+    if input.len() < 16 {
+        return Err(CryptError::MalformedMessage);
+    }
     let message = input.split_off(16);
-    let encrypted_iv = input.as_ref().try_into().unwrap();
+    let encrypted_iv = input
+        .as_ref()
+        .try_into()
+        .map_err(|_| CryptError::MalformedMessage)?;
     let plain_iv = decrypt_iv(encrypted_iv, key);
 
     let message = decrypt_message(message, key, &plain_iv)?;
@@ -227,15 +295,7 @@ pub fn symmetric_decrypt(mut input: BytesMut, key: &[u8; 32]) -> Result<BytesMut
     // message.resize(message.len() - padding as usize, 0);
 
     let hmac_random = &plain_iv[13..];
-
-    let mut hmac_key = [0; 64];
-    hmac_key[0..16].copy_from_slice(&key[0..16]);
-
-    let mut hmac = <HmacSha1 as Mac>::new(GenericArray::from_slice(&hmac_key));
-    hmac.update(hmac_random);
-    hmac.update(&message);
-
-    let hmac: [u8; 20] = hmac.finalize().into_bytes().into();
+    let hmac = hmac_sha1(&iv_hmac_key(key), &[hmac_random, &message]);
 
     if hmac[0..13] != plain_iv[0..13] {
         return Err(CryptError::InvalidHmac);
@@ -256,3 +316,233 @@ fn roundtrip_test() {
 
     assert_eq!(input, decrypted);
 }
+
+#[test]
+fn decrypt_short_input_test() {
+    let key = random();
+
+    // shorter than the iv
+    for len in 0..16 {
+        assert!(matches!(
+            symmetric_decrypt(BytesMut::from(&[55; 16][..len]), &key),
+            Err(CryptError::MalformedMessage)
+        ));
+    }
+    // an iv without a full block of message
+    assert!(symmetric_decrypt(BytesMut::from(&[55; 20][..]), &key).is_err());
+}
+
+#[test]
+fn decrypt_in_place_test() {
+    let key = random();
+
+    let input = BytesMut::from(&[55; 64][..]);
+
+    let encrypted = symmetric_encrypt(input.clone(), &key);
+    let body_ptr = encrypted[16..].as_ptr();
+
+    let decrypted = symmetric_decrypt(encrypted, &key).unwrap();
+
+    assert_eq!(input, decrypted);
+    assert_eq!(body_ptr, decrypted.as_ptr());
+}
+
+/// The cryptographic operations used for the encrypted connection to steam
+///
+/// This allows replacing the default implementation, for example with one that uses a certified crypto library.
+pub trait CryptoProvider: Send + Sync + 'static {
+    /// Generate a random session key, and encrypt it with the public key of the `universe`
+    ///
+    /// The tcp handshake passes the universe and the challenge from the `ChannelEncryptRequest` of the server
+    /// as `nonce`, see [`encrypt_session_key`].
+    /// Fails with [`CryptError::UnsupportedUniverse`] if the provider has no public key for the universe.
+    fn generate_session_key(
+        &self,
+        universe: Universe,
+        nonce: Option<&[u8; 16]>,
+    ) -> Result<SessionKeys>;
+
+    /// Encrypt a message with the session key, see [`symmetric_encrypt_with_iv_buffer`]
+    fn symmetric_encrypt(&self, iv_buff: BytesMut, input: BytesMut, key: &[u8; 32]) -> BytesMut;
+
+    /// Decrypt a message with the session key, see [`symmetric_decrypt`]
+    fn symmetric_decrypt(&self, input: BytesMut, key: &[u8; 32]) -> Result<BytesMut>;
+
+    /// Calculate the HMAC-SHA1 of the concatenated data
+    fn hmac_sha1(&self, key: &[u8], data: &[&[u8]]) -> [u8; 20];
+}
+
+/// The default crypto implementation, using the functions from this crate
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultCrypto;
+
+/// Only the public key of the [`Universe::Public`] universe is bundled, other universes fail with
+/// [`CryptError::UnsupportedUniverse`].
+impl CryptoProvider for DefaultCrypto {
+    fn generate_session_key(
+        &self,
+        universe: Universe,
+        nonce: Option<&[u8; 16]>,
+    ) -> Result<SessionKeys> {
+        match universe {
+            Universe::Public => generate_session_key(nonce),
+            universe => Err(CryptError::UnsupportedUniverse(universe)),
+        }
+    }
+
+    fn symmetric_encrypt(&self, iv_buff: BytesMut, input: BytesMut, key: &[u8; 32]) -> BytesMut {
+        symmetric_encrypt_with_iv_buffer(iv_buff, input, key)
+    }
+
+    fn symmetric_decrypt(&self, input: BytesMut, key: &[u8; 32]) -> Result<BytesMut> {
+        symmetric_decrypt(input, key)
+    }
+
+    fn hmac_sha1(&self, key: &[u8], data: &[&[u8]]) -> [u8; 20] {
+        hmac_sha1(key, data)
+    }
+}
+
+#[test]
+fn default_crypto_universe_test() {
+    assert!(DefaultCrypto
+        .generate_session_key(Universe::Public, None)
+        .is_ok());
+    assert!(matches!(
+        DefaultCrypto.generate_session_key(Universe::Beta, None),
+        Err(CryptError::UnsupportedUniverse(Universe::Beta))
+    ));
+}
+
+/// A reusable buffer to decrypt messages into
+///
+/// Each message is copied into the buffer and decrypted there, the returned message is split off from the buffer.
+/// Once the returned messages are dropped the buffer reclaims their space, so a steady flow of messages decrypts
+/// without allocating. While messages are kept around, the buffer allocates in blocks of at least
+/// [`DecryptBuffer::CAPACITY`] bytes instead of once per message.
+#[derive(Debug)]
+pub struct DecryptBuffer {
+    scratch: BytesMut,
+}
+
+impl DecryptBuffer {
+    /// The initial capacity of the buffer
+    pub const CAPACITY: usize = 64 * 1024;
+
+    pub fn new() -> Self {
+        DecryptBuffer {
+            scratch: BytesMut::with_capacity(Self::CAPACITY),
+        }
+    }
+
+    /// Decrypt a message with the session key into the buffer, see [`symmetric_decrypt`]
+    pub fn decrypt<C: CryptoProvider + ?Sized>(
+        &mut self,
+        crypto: &C,
+        input: &[u8],
+        key: &[u8; 32],
+    ) -> Result<BytesMut> {
+        self.scratch.reserve(input.len());
+        self.scratch.extend_from_slice(input);
+        crypto.symmetric_decrypt(self.scratch.split(), key)
+    }
+}
+
+impl Default for DecryptBuffer {
+    fn default() -> Self {
+        DecryptBuffer::new()
+    }
+}
+
+#[test]
+fn decrypt_buffer_test() {
+    let key = random();
+    let mut buffer = DecryptBuffer::new();
+
+    let input = BytesMut::from(&[55; 64][..]);
+    let encrypted = symmetric_encrypt(input.clone(), &key);
+
+    let decrypted = buffer.decrypt(&DefaultCrypto, &encrypted, &key).unwrap();
+    assert_eq!(input, decrypted);
+
+    // messages that are still alive keep their bytes
+    let second = buffer.decrypt(&DefaultCrypto, &encrypted, &key).unwrap();
+    assert_eq!(input, second);
+    assert_eq!(input, decrypted);
+
+    // once they are dropped, the space is reused instead of allocating a new buffer
+    let start = decrypted.as_ptr() as usize;
+    drop(decrypted);
+    drop(second);
+    for _ in 0..10 * DecryptBuffer::CAPACITY / encrypted.len() {
+        let decrypted = buffer.decrypt(&DefaultCrypto, &encrypted, &key).unwrap();
+        assert_eq!(input, decrypted);
+        let offset = (decrypted.as_ptr() as usize).wrapping_sub(start);
+        assert!(offset < DecryptBuffer::CAPACITY);
+    }
+
+    assert!(matches!(
+        buffer.decrypt(&DefaultCrypto, &encrypted[..10], &key),
+        Err(CryptError::MalformedMessage)
+    ));
+}
+
+/// A crypto implementation that doesn't encrypt anything, **only meant for tests**
+///
+/// Messages are prefixed with an all zero iv instead of being encrypted, and the session key is all zeros.
+/// The "encrypted" session key is the plain key, followed by the nonce if one is given.
+#[cfg(feature = "mock")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MockCrypto;
+
+#[cfg(feature = "mock")]
+impl CryptoProvider for MockCrypto {
+    fn generate_session_key(
+        &self,
+        _universe: Universe,
+        nonce: Option<&[u8; 16]>,
+    ) -> Result<SessionKeys> {
+        let mut encrypted = vec![0; 32];
+        if let Some(nonce) = nonce {
+            encrypted.extend_from_slice(nonce);
+        }
+        Ok(SessionKeys {
+            plain: [0; 32],
+            encrypted,
+        })
+    }
+
+    fn symmetric_encrypt(
+        &self,
+        mut iv_buff: BytesMut,
+        input: BytesMut,
+        _key: &[u8; 32],
+    ) -> BytesMut {
+        iv_buff.fill(0);
+        iv_buff.unsplit(input);
+        iv_buff
+    }
+
+    fn symmetric_decrypt(&self, mut input: BytesMut, _key: &[u8; 32]) -> Result<BytesMut> {
+        if input.len() < 16 {
+            return Err(CryptError::MalformedMessage);
+        }
+        Ok(input.split_off(16))
+    }
+
+    fn hmac_sha1(&self, _key: &[u8], _data: &[&[u8]]) -> [u8; 20] {
+        [0; 20]
+    }
+}
+
+#[test]
+fn test_hmac_sha1() {
+    // test case 2 from rfc 2202
+    assert_eq!(
+        [
+            0xef, 0xfc, 0xdf, 0x6a, 0xe5, 0xeb, 0x2f, 0xa2, 0xd2, 0x74, 0x16, 0xd5, 0xf1, 0x84,
+            0xdf, 0x9c, 0x25, 0x9a, 0x7c, 0x79
+        ],
+        hmac_sha1(b"Jefe", &[b"what do ya want ", b"for nothing?"])
+    );
+}
@@ -0,0 +1,67 @@
+//! Compare decrypting messages into a newly allocated buffer per message against decrypting into a reused buffer
+//!
+//! Run with `cargo run --release --example decrypt_throughput`
+
+use bytes::BytesMut;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use steam_vent::crypto::{symmetric_encrypt, CryptoProvider, DecryptBuffer, DefaultCrypto};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const KEY: [u8; 32] = [7; 32];
+
+/// Run `decrypt` for `count` messages and print the time and allocations per message
+fn measure(name: &str, size: usize, count: u32, mut decrypt: impl FnMut() -> BytesMut) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..count {
+        black_box(decrypt());
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{name:<8} {size:>6} bytes: {:>8.1} ns/message, {:>7.1} MiB/s, {:.3} allocations/message",
+        elapsed.as_nanos() as f64 / count as f64,
+        (size as f64 * count as f64) / elapsed.as_secs_f64() / (1024.0 * 1024.0),
+        allocations as f64 / count as f64,
+    );
+}
+
+fn main() {
+    for (size, count) in [(64, 1_000_000), (1024, 500_000), (16 * 1024, 50_000)] {
+        let encrypted = symmetric_encrypt(BytesMut::from(&vec![55; size][..]), &KEY);
+
+        // the message is copied out of the read buffer into its own allocation and decrypted there
+        measure("allocate", size, count, || {
+            DefaultCrypto
+                .symmetric_decrypt(BytesMut::from(&encrypted[..]), &KEY)
+                .expect("failed to decrypt")
+        });
+
+        let mut buffer = DecryptBuffer::new();
+        measure("reuse", size, count, || {
+            buffer
+                .decrypt(&DefaultCrypto, &encrypted, &KEY)
+                .expect("failed to decrypt")
+        });
+    }
+}
@@ -0,0 +1,135 @@
+//! Compare writing 1 MiB tcp frames by concatenating the header and payload into a new buffer
+//! against queueing the frames without copying them
+//!
+//! Run with `cargo run --release --example frame_write_throughput`
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::SinkExt;
+use std::hint::black_box;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use steam_vent::framing::{FrameHeader, FRAME_HEADER_SIZE};
+use steam_vent::FrameWriter;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+const PAYLOAD_SIZE: usize = 1024 * 1024;
+const FRAMES: u32 = 2_000;
+const PIPELINED: u32 = 8;
+
+/// A socket that copies the written bytes into its buffer like the kernel does, and then discards them
+///
+/// It takes up to 64 KiB per write, with or without support for vectored writes.
+struct Discard {
+    vectored: bool,
+    buffer: Vec<u8>,
+}
+
+impl Discard {
+    fn new(vectored: bool) -> Self {
+        Discard {
+            vectored,
+            buffer: vec![0; 64 * 1024],
+        }
+    }
+
+    fn copy(&mut self, bufs: &[IoSlice<'_>]) -> usize {
+        let mut written = 0;
+        for buf in bufs {
+            let len = buf.len().min(self.buffer.len() - written);
+            self.buffer[written..written + len].copy_from_slice(&buf[..len]);
+            written += len;
+        }
+        black_box(&self.buffer);
+        written
+    }
+}
+
+impl AsyncWrite for Discard {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Ok(self.get_mut().copy(&[IoSlice::new(buf)])))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(Ok(self.get_mut().copy(bufs)))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.vectored
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{name:<32} {:>8.1} µs/frame, {:>8.0} MiB/s",
+        elapsed.as_secs_f64() * 1_000_000.0 / FRAMES as f64,
+        (PAYLOAD_SIZE as f64 * FRAMES as f64) / elapsed.as_secs_f64() / (1024.0 * 1024.0)
+    );
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let payload = vec![55; PAYLOAD_SIZE];
+    let mut header = [0; FRAME_HEADER_SIZE];
+    FrameHeader::new(PAYLOAD_SIZE)
+        .expect("payload fits in a frame")
+        .write(&mut header);
+
+    // before: the header and payload are copied into a new buffer for every frame
+    let mut socket = Discard::new(false);
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + PAYLOAD_SIZE);
+        frame.extend_from_slice(&header);
+        frame.extend_from_slice(&payload);
+        socket.write_all(&frame).await.expect("failed to write");
+    }
+    report("concat", start.elapsed());
+
+    // after: the header is written in front of the payload when encoding, the frame is written as is
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_SIZE + PAYLOAD_SIZE);
+    frame.put_slice(&header);
+    frame.put_slice(&payload);
+    let frame: Bytes = frame.freeze();
+
+    for vectored in [false, true] {
+        let mut writer = FrameWriter::new(Discard::new(vectored));
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            writer.send(frame.clone()).await.expect("failed to write");
+        }
+        report(
+            &format!("frame writer (vectored: {vectored})"),
+            start.elapsed(),
+        );
+
+        let start = Instant::now();
+        for _ in 0..FRAMES / PIPELINED {
+            for _ in 0..PIPELINED {
+                writer.feed(frame.clone()).await.expect("failed to write");
+            }
+            writer.flush().await.expect("failed to flush");
+        }
+        report(
+            &format!("pipelined (vectored: {vectored})"),
+            start.elapsed(),
+        );
+    }
+}
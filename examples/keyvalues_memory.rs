@@ -0,0 +1,94 @@
+//! Compare the peak memory usage of the tree and streaming KeyValues parsers on a large appinfo blob
+//!
+//! Run with `cargo run --release --example keyvalues_memory`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use steam_vent::keyvalues::{Event, KeyValues, KeyValuesError, KeyValuesReader, Value};
+
+struct CountingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Run `f` and return its result with the peak memory allocated while it ran
+fn measure<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let start = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(start, Ordering::Relaxed);
+    let result = f();
+    (result, PEAK.load(Ordering::Relaxed) - start)
+}
+
+/// Build an appinfo blob with a large `extended` section next to a small `depots` section
+fn appinfo() -> Vec<u8> {
+    let mut extended = KeyValues::default();
+    for i in 0..200_000 {
+        extended.push(format!("key{i}"), Value::String(format!("value {i}")));
+    }
+    let mut depots = KeyValues::default();
+    for i in 0..10 {
+        let mut depot = KeyValues::default();
+        depot.push("name", Value::String(format!("depot {i}")));
+        depot.push("maxsize", Value::UInt64(i * 1024 * 1024));
+        depots.push(format!("{}", 1000 + i), Value::Object(depot));
+    }
+    let mut appinfo = KeyValues::default();
+    appinfo.push("appid", Value::Int32(1000));
+    appinfo.push("extended", Value::Object(extended));
+    appinfo.push("depots", Value::Object(depots));
+    let mut root = KeyValues::default();
+    root.push("appinfo", Value::Object(appinfo));
+    root.to_binary()
+}
+
+fn read_depots(blob: &[u8]) -> Result<Option<KeyValues>, KeyValuesError> {
+    let mut reader = KeyValuesReader::new(blob);
+    while let Some(event) = reader.next_event()? {
+        match event {
+            Event::Start(name) if name == "depots" => return reader.read_object().map(Some),
+            Event::Start(name) if name != "appinfo" => reader.skip_object()?,
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+fn main() -> Result<(), KeyValuesError> {
+    let blob = appinfo();
+    println!("blob size: {} bytes", blob.len());
+
+    let (tree, tree_peak) = measure(|| {
+        KeyValues::parse_binary(&blob).map(|root| {
+            root.get("appinfo")
+                .and_then(Value::as_object)
+                .and_then(|appinfo| appinfo.get("depots"))
+                .and_then(Value::as_object)
+                .map(KeyValues::len)
+        })
+    });
+    let (streamed, streaming_peak) = measure(|| read_depots(&blob));
+
+    assert_eq!(tree?, streamed?.as_ref().map(KeyValues::len));
+    println!("tree parser peak:      {tree_peak} bytes");
+    println!("streaming parser peak: {streaming_peak} bytes");
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//! Measure how fast messages are parsed, and how much of that is spent looking up the message kind
+//!
+//! Run with `cargo run --release --example parse_throughput`
+
+use protobuf::Enum;
+use std::hint::black_box;
+use std::time::Instant;
+use steam_vent::proto::enums_clientserver::EMsg;
+use steam_vent::proto::steammessages_clientserver_login::CMsgClientHeartBeat;
+use steam_vent::{NetMessageHeader, RawNetMessage};
+
+const MESSAGES: u32 = 5_000_000;
+const LOOKUPS: u32 = 50_000_000;
+
+fn main() {
+    let message =
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .expect("failed to encode message")
+            .into_bytes();
+    let start = Instant::now();
+    for _ in 0..MESSAGES {
+        black_box(RawNetMessage::read(black_box(message.clone())).expect("failed to read message"));
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "read:     {:.1} ns/message, {:.0} messages/s",
+        elapsed.as_nanos() as f64 / MESSAGES as f64,
+        MESSAGES as f64 / elapsed.as_secs_f64()
+    );
+
+    let kinds: Vec<i32> = EMsg::VALUES.iter().map(|kind| kind.value()).collect();
+    let start = Instant::now();
+    for i in 0..LOOKUPS as usize {
+        black_box(EMsg::from_i32(black_box(kinds[i % kinds.len()])));
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "from_i32: {:.1} ns/lookup",
+        elapsed.as_nanos() as f64 / LOOKUPS as f64
+    );
+}
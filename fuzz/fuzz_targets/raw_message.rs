@@ -0,0 +1,14 @@
+#![no_main]
+
+use bytes::BytesMut;
+use futures::executor::block_on_stream;
+use futures::future::ready;
+use futures::stream::once;
+use libfuzzer_sys::fuzz_target;
+use steam_vent::{flatten_multi, RawNetMessage};
+
+// parse the message header and expand any multi message
+fuzz_target!(|data: &[u8]| {
+    let message = RawNetMessage::read(BytesMut::from(data));
+    for _ in block_on_stream(Box::pin(flatten_multi(once(ready(message))))) {}
+});
@@ -1,3 +1,4 @@
+#[allow(renamed_and_removed_lints)]
 mod generated;
 
 use crate::enums_clientserver::EMsg;
@@ -0,0 +1,43 @@
+use crate::proto::steammessages_clientserver::CMsgClientIsLimitedAccount;
+
+/// The restrictions on the logged in account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccountLimits {
+    limited: bool,
+    community_banned: bool,
+    locked: bool,
+    allowed_to_invite_friends: bool,
+}
+
+impl AccountLimits {
+    /// The account is limited because it hasn't spent enough on steam yet, which blocks many community features
+    pub fn is_limited(&self) -> bool {
+        self.limited
+    }
+
+    /// The account is banned from the steam community
+    pub fn is_community_banned(&self) -> bool {
+        self.community_banned
+    }
+
+    /// The account is locked, e.g. because it's suspected to be compromised
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// The account can send friend invites, even though it might be limited
+    pub fn is_allowed_to_invite_friends(&self) -> bool {
+        self.allowed_to_invite_friends
+    }
+}
+
+impl From<&CMsgClientIsLimitedAccount> for AccountLimits {
+    fn from(message: &CMsgClientIsLimitedAccount) -> Self {
+        AccountLimits {
+            limited: message.bis_limited_account(),
+            community_banned: message.bis_community_banned(),
+            locked: message.bis_locked_account(),
+            allowed_to_invite_friends: message.bis_limited_account_allowed_to_invite_friends(),
+        }
+    }
+}
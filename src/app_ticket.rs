@@ -0,0 +1,72 @@
+use crate::connection::Connection;
+use crate::eresult::EResult;
+use crate::net::NetworkError;
+use crate::proto::steammessages_clientserver::{
+    CMsgClientRequestEncryptedAppTicket, CMsgClientRequestEncryptedAppTicketResponse,
+};
+use protobuf::Message;
+
+fn ticket_from_response(
+    response: CMsgClientRequestEncryptedAppTicketResponse,
+) -> Result<Vec<u8>, NetworkError> {
+    EResult::from_result(response.eresult())?;
+    let ticket = response
+        .encrypted_app_ticket
+        .into_option()
+        .ok_or(NetworkError::ApiError(EResult::Fail))?;
+    ticket
+        .write_to_bytes()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
+}
+
+impl Connection {
+    /// Request an encrypted app ticket, for authenticating the user with a game server or a backend of the app
+    ///
+    /// The `user_data` is included in the encrypted ticket. The ticket is returned as the encoded
+    /// `EncryptedAppTicket` message, which is the form the steamworks api uses.
+    pub async fn get_encrypted_app_ticket(
+        &self,
+        app_id: u32,
+        user_data: &[u8],
+    ) -> Result<Vec<u8>, NetworkError> {
+        let request = CMsgClientRequestEncryptedAppTicket {
+            app_id: Some(app_id),
+            userdata: Some(user_data.to_vec()),
+            ..CMsgClientRequestEncryptedAppTicket::default()
+        };
+        let response: CMsgClientRequestEncryptedAppTicketResponse = self.job(request).await?;
+        ticket_from_response(response)
+    }
+}
+
+#[test]
+fn test_ticket_from_response() {
+    use crate::proto::encrypted_app_ticket::EncryptedAppTicket;
+    use protobuf::MessageField;
+
+    let ticket = EncryptedAppTicket {
+        ticket_version_no: Some(1),
+        encrypted_ticket: Some(vec![1, 2, 3]),
+        ..EncryptedAppTicket::default()
+    };
+    let response = CMsgClientRequestEncryptedAppTicketResponse {
+        app_id: Some(440),
+        eresult: Some(EResult::OK as i32),
+        encrypted_app_ticket: MessageField::some(ticket.clone()),
+        ..CMsgClientRequestEncryptedAppTicketResponse::default()
+    };
+    let encoded = ticket_from_response(response).unwrap();
+    assert_eq!(
+        ticket,
+        EncryptedAppTicket::parse_from_bytes(&encoded).unwrap()
+    );
+
+    let response = CMsgClientRequestEncryptedAppTicketResponse {
+        eresult: Some(EResult::LimitExceeded as i32),
+        ..CMsgClientRequestEncryptedAppTicketResponse::default()
+    };
+    assert!(matches!(
+        ticket_from_response(response),
+        Err(NetworkError::ApiError(EResult::LimitExceeded))
+    ));
+}
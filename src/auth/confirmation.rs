@@ -1,7 +1,7 @@
-use std::pin::pin;
+use crate::auth::totp::generate_auth_code;
 use crate::auth::SteamGuardToken;
-use another_steam_totp::generate_auth_code;
 use futures_util::future::{select, Either};
+use std::pin::pin;
 use steam_vent_proto::steammessages_auth_steamclient::{
     CAuthentication_AllowedConfirmation, EAuthSessionGuardType,
 };
@@ -227,6 +227,7 @@ where
 /// This requires no user interaction during login but requires the user to retrieve the totp secret in advance
 pub struct SharedSecretAuthConfirmationHandler {
     shared_secret: String,
+    time_offset: i64,
 }
 
 impl SharedSecretAuthConfirmationHandler {
@@ -236,6 +237,15 @@ impl SharedSecretAuthConfirmationHandler {
     pub fn new(shared_secret: &str) -> Self {
         SharedSecretAuthConfirmationHandler {
             shared_secret: shared_secret.into(),
+            time_offset: 0,
+        }
+    }
+
+    /// Set the number of seconds the local clock is behind the steam servers, used when generating the code
+    pub fn with_time_offset(self, time_offset: i64) -> Self {
+        SharedSecretAuthConfirmationHandler {
+            time_offset,
+            ..self
         }
     }
 }
@@ -247,7 +257,7 @@ impl AuthConfirmationHandler for SharedSecretAuthConfirmationHandler {
     ) -> Option<ConfirmationAction> {
         for method in allowed_confirmations {
             if let Some(token_type) = method.token_type() {
-                let auth_code = generate_auth_code(self.shared_secret, None)
+                let auth_code = generate_auth_code(&self.shared_secret, self.time_offset)
                     .expect("Could not generate auth code given shared secret.");
                 let token = SteamGuardToken(auth_code);
                 return Some(ConfirmationAction::GuardToken(token, token_type));
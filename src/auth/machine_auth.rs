@@ -0,0 +1,152 @@
+use crate::connection::{Connection, ConnectionState};
+use crate::eresult::EResult;
+use crate::net::{NetworkError, RawNetMessage};
+use crate::proto::steammessages_clientserver_2::{
+    CMsgClientUpdateMachineAuth, CMsgClientUpdateMachineAuthResponse,
+};
+use futures_util::future::{select, Either};
+use std::fs::write;
+use std::path::Path;
+use std::pin::pin;
+use steam_vent_crypto::sha1;
+use tracing::{debug, error};
+
+impl Connection {
+    /// Wait for steam to send the sentry file for this machine, save it with the callback and acknowledge it
+    ///
+    /// Steam sends the sentry file after a new device is authorized with steam guard,
+    /// the callback receives the file name and contents of the sentry file.
+    /// The sentry file is kept until it's handled, so this can be called after logging on.
+    pub async fn handle_machine_auth<F>(&self, save: F) -> Result<(), NetworkError>
+    where
+        F: FnOnce(&str, &[u8]) -> std::io::Result<()>,
+    {
+        let raw = self.take_machine_auth().await?;
+        let header = raw.header.clone();
+        let update: CMsgClientUpdateMachineAuth = raw.into_message()?;
+        debug!(filename = update.filename(), "received sentry file");
+        let saved = match save(update.filename(), update.bytes()) {
+            Ok(()) => true,
+            Err(e) => {
+                error!(error = ?e, "failed to save sentry file");
+                false
+            }
+        };
+
+        let mut response_header = self.session.header();
+        response_header.target_job_id = header.source_job_id;
+        self.send(response_header, machine_auth_response(&update, saved))
+            .await
+    }
+
+    /// Wait for a sentry file update that isn't handled yet, failing if the connection closes first
+    async fn take_machine_auth(&self) -> Result<RawNetMessage, NetworkError> {
+        let mut updates = self.machine_auth().subscribe();
+        let mut state = self.state();
+        loop {
+            let update = pin!(updates.wait_for(Option::is_some));
+            let closed =
+                pin!(state.wait_for(|state| matches!(state, ConnectionState::Closed { .. })));
+            if let Either::Right(_) = select(update, closed).await {
+                return Err(NetworkError::EOF);
+            }
+            // take the update, so it's only handled once
+            if let Some(raw) = self.machine_auth().send_replace(None) {
+                return Ok(raw);
+            }
+        }
+    }
+
+    /// Wait for steam to send the sentry file for this machine, save it to the path and acknowledge it
+    pub async fn save_machine_auth_to(&self, path: impl AsRef<Path>) -> Result<(), NetworkError> {
+        self.handle_machine_auth(|_, bytes| write(path, bytes))
+            .await
+    }
+}
+
+fn machine_auth_response(
+    update: &CMsgClientUpdateMachineAuth,
+    saved: bool,
+) -> CMsgClientUpdateMachineAuthResponse {
+    let bytes = update.bytes();
+    let result = if saved { EResult::OK } else { EResult::Fail };
+    CMsgClientUpdateMachineAuthResponse {
+        filename: update.filename.clone(),
+        eresult: Some(result as u32),
+        filesize: Some(bytes.len() as u32),
+        sha_file: Some(sha1(bytes).to_vec()),
+        getlasterror: Some(0),
+        offset: Some(update.offset()),
+        cubwrote: Some(bytes.len() as u32),
+        otp_type: update.otp_type.map(|otp_type| otp_type as i32),
+        otp_identifier: update.otp_identifier.clone(),
+        ..CMsgClientUpdateMachineAuthResponse::default()
+    }
+}
+
+#[test]
+fn test_machine_auth_response() {
+    let update = CMsgClientUpdateMachineAuth {
+        filename: Some("ssfn1234".into()),
+        bytes: Some(b"hello world".to_vec()),
+        ..CMsgClientUpdateMachineAuth::default()
+    };
+    let response = machine_auth_response(&update, true);
+    assert_eq!("ssfn1234", response.filename());
+    assert_eq!(EResult::OK as u32, response.eresult());
+    assert_eq!(11, response.filesize());
+    assert_eq!(
+        &[
+            0x2a, 0xae, 0x6c, 0x35, 0xc9, 0x4f, 0xcf, 0xb4, 0x15, 0xdb, 0xe9, 0x5f, 0x40, 0x8b,
+            0x9c, 0xe9, 0x1e, 0xe8, 0x46, 0xed
+        ],
+        response.sha_file()
+    );
+
+    let response = machine_auth_response(&update, false);
+    assert_eq!(EResult::Fail as u32, response.eresult());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_machine_auth_received_before_waiting() {
+    use crate::net::NetMessageHeader;
+    use steam_vent_proto::enums_clientserver::EMsg;
+
+    let update = CMsgClientUpdateMachineAuth {
+        filename: Some("ssfn1234".into()),
+        bytes: Some(b"hello world".to_vec()),
+        ..CMsgClientUpdateMachineAuth::default()
+    };
+    let raw = RawNetMessage::from_message(NetMessageHeader::default(), update).unwrap();
+    let connection = Connection::replay([(
+        EMsg::k_EMsgClientUpdateMachineAuth,
+        raw.into_bytes().to_vec(),
+    )]);
+    // the update is received before anyone waits for it
+    connection
+        .machine_auth()
+        .subscribe()
+        .wait_for(Option::is_some)
+        .await
+        .unwrap();
+
+    let mut saved = None;
+    connection
+        .handle_machine_auth(|filename, bytes| {
+            saved = Some((filename.to_string(), bytes.to_vec()));
+            Ok(())
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        Some(("ssfn1234".to_string(), b"hello world".to_vec())),
+        saved
+    );
+
+    // the update is only handled once
+    assert!(matches!(
+        connection.handle_machine_auth(|_, _| Ok(())).await,
+        Err(NetworkError::EOF)
+    ));
+}
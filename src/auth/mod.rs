@@ -1,5 +1,8 @@
 mod confirmation;
 mod guarddata;
+mod machine_auth;
+mod totp;
+mod validation;
 
 use crate::connection::Connection;
 use crate::message::NetMessage;
@@ -27,6 +30,7 @@ use std::time::Duration;
 use steam_vent_crypto::encrypt_with_key_pkcs1;
 use thiserror::Error;
 use tokio::time::sleep;
+pub use totp::{generate_auth_code, generate_auth_code_at, AuthCodeError};
 use tracing::{debug, info, instrument};
 
 pub(crate) async fn begin_password_auth(
@@ -34,6 +38,7 @@ pub(crate) async fn begin_password_auth(
     account: &str,
     password: &str,
     guard_data: Option<&str>,
+    device_friendly_name: &str,
 ) -> Result<StartedAuth, ConnectionError> {
     let (pub_key, timestamp) = get_password_rsa(connection, account.into()).await?;
     let encrypted_password =
@@ -51,7 +56,7 @@ pub(crate) async fn begin_password_auth(
         // todo: platform types
         website_id: Some("Client".into()),
         device_details: MessageField::some(CAuthentication_DeviceDetails {
-            device_friendly_name: Some("DESKTOP-VENT".into()),
+            device_friendly_name: Some(device_friendly_name.into()),
             platform_type: Some(EnumOrUnknown::new(
                 EAuthTokenPlatformType::k_EAuthTokenPlatformType_SteamClient,
             )),
@@ -0,0 +1,85 @@
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use std::time::{SystemTime, UNIX_EPOCH};
+use steam_vent_crypto::hmac_sha1;
+use thiserror::Error;
+
+const CHARS: &[u8; 26] = b"23456789BCDFGHJKMNPQRTVWXY";
+
+/// Codes are valid for 30 seconds
+const PERIOD: u64 = 30;
+
+/// Error while generating a steam guard code
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum AuthCodeError {
+    #[error("shared secret is not valid base64: {0}")]
+    InvalidSecret(#[from] base64::DecodeError),
+    #[error("shared secret is empty")]
+    EmptySecret,
+}
+
+/// Generate the steam guard code from the base64 encoded shared secret for the current time
+///
+/// `time_offset` is the number of seconds the local clock is behind the steam servers, use 0 if
+/// the clock is in sync.
+pub fn generate_auth_code(shared_secret: &str, time_offset: i64) -> Result<String, AuthCodeError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    generate_auth_code_at(shared_secret, now.saturating_add_signed(time_offset))
+}
+
+/// Generate the steam guard code from the base64 encoded shared secret for a unix timestamp
+///
+/// The same secret and timestamp always give the same code, any timestamp in the same 30 second window gives the
+/// same code as well.
+pub fn generate_auth_code_at(shared_secret: &str, timestamp: u64) -> Result<String, AuthCodeError> {
+    let secret = BASE64_STANDARD.decode(shared_secret)?;
+    if secret.is_empty() {
+        return Err(AuthCodeError::EmptySecret);
+    }
+    let hash = hmac_sha1(&secret, &[&(timestamp / PERIOD).to_be_bytes()]);
+    let offset = (hash[19] & 0xf) as usize;
+    let mut value = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fffffff;
+    let mut code = String::with_capacity(5);
+    for _ in 0..5 {
+        code.push(CHARS[(value % 26) as usize] as char);
+        value /= 26;
+    }
+    Ok(code)
+}
+
+#[test]
+fn test_generate_auth_code_at() {
+    assert_eq!(
+        "6NYWP",
+        generate_auth_code_at("000000000000000000000000000=", 0).unwrap()
+    );
+    assert_eq!(
+        "6XBD7",
+        generate_auth_code_at("000000000000000000000000000=", 1700000000).unwrap()
+    );
+    assert_eq!(
+        "4JRHF",
+        generate_auth_code_at("cnOgv/KdpLoP6Nbh0GMkXkPXALQ=", 1634603498).unwrap()
+    );
+    assert_eq!(
+        "BBB63",
+        generate_auth_code_at("cnOgv/KdpLoP6Nbh0GMkXkPXALQ=", 1634603528).unwrap()
+    );
+    // the code stays the same within the 30 second window
+    assert_eq!(
+        generate_auth_code_at("cnOgv/KdpLoP6Nbh0GMkXkPXALQ=", 1634603490).unwrap(),
+        generate_auth_code_at("cnOgv/KdpLoP6Nbh0GMkXkPXALQ=", 1634603519).unwrap()
+    );
+    assert!(matches!(
+        generate_auth_code_at("", 0),
+        Err(AuthCodeError::EmptySecret)
+    ));
+    assert!(matches!(
+        generate_auth_code_at("not base64!", 0),
+        Err(AuthCodeError::InvalidSecret(_))
+    ));
+}
@@ -0,0 +1,81 @@
+use crate::connection::Connection;
+use crate::eresult::EResult;
+use crate::message::{MalformedBody, NetMessage};
+use crate::net::{NetMessageHeader, NetworkError};
+use bytes::BytesMut;
+use protobuf::rt::WireType;
+use protobuf::CodedInputStream;
+use std::io::Write;
+use steam_vent_proto::enums_clientserver::EMsg;
+
+#[derive(Debug)]
+struct RequestValidationMail;
+
+impl NetMessage for RequestValidationMail {
+    const KIND: EMsg = EMsg::k_EMsgClientRequestValidationMail;
+    const IS_PROTOBUF: bool = true;
+
+    fn write_body<W: Write>(&self, _writer: W) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+
+    fn encode_size(&self) -> usize {
+        0
+    }
+}
+
+#[derive(Debug)]
+struct RequestValidationMailResponse {
+    eresult: i32,
+}
+
+impl NetMessage for RequestValidationMailResponse {
+    const KIND: EMsg = EMsg::k_EMsgClientRequestValidationMailResponse;
+    const IS_PROTOBUF: bool = true;
+
+    fn read_body(data: BytesMut, _header: &NetMessageHeader) -> Result<Self, MalformedBody> {
+        // there is no protobuf definition for the response, the only field is the eresult with tag 1
+        let mut reader = CodedInputStream::from_bytes(&data);
+        let mut eresult = EResult::Fail as i32;
+        while let Some(tag) = reader
+            .read_raw_tag_or_eof()
+            .map_err(|e| MalformedBody::new(Self::KIND, e))?
+        {
+            if tag == (1 << 3) | WireType::Varint as u32 {
+                eresult = reader
+                    .read_int32()
+                    .map_err(|e| MalformedBody::new(Self::KIND, e))?;
+            } else {
+                let wire_type = WireType::new(tag & 0x07).ok_or_else(|| {
+                    MalformedBody::new(Self::KIND, format!("invalid wire type in tag {tag}"))
+                })?;
+                reader
+                    .skip_field(wire_type)
+                    .map_err(|e| MalformedBody::new(Self::KIND, e))?;
+            }
+        }
+        Ok(RequestValidationMailResponse { eresult })
+    }
+}
+
+impl Connection {
+    /// Request steam to (re)send the validation email for the account's email address
+    pub async fn request_validation_email(&self) -> Result<(), NetworkError> {
+        let response: RequestValidationMailResponse = self.job(RequestValidationMail).await?;
+        EResult::from_result(response.eresult)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_read_validation_mail_response() {
+    let header = NetMessageHeader::default();
+    let read =
+        |data: &[u8]| RequestValidationMailResponse::read_body(BytesMut::from(data), &header);
+    // eresult 1, after an unknown length delimited field
+    assert_eq!(1, read(&[0x12, 2, 0xff, 0xff, 0x08, 1]).unwrap().eresult);
+
+    // the varint or the skipped field ends early
+    assert!(read(&[0x08, 0x80]).is_err());
+    assert!(read(&[0x12, 5, 0xff]).is_err());
+}
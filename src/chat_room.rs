@@ -0,0 +1,233 @@
+use crate::connection::Connection;
+use crate::net::NetworkError;
+use crate::proto::steammessages_chat_steamclient::{
+    cchat_room_get_message_history_response::ChatMessage, CChatRoomSummaryPair,
+    CChatRoom_GetMessageHistory_Request, CChatRoom_GetMyChatRoomGroups_Request,
+    CChatRoom_IncomingChatMessage_Notification, CChatRoom_SendChatMessage_Request,
+};
+use steamid_ng::{AccountType, Instance, SteamID, Universe};
+
+/// The most messages steam returns for a single message history request
+const HISTORY_PAGE_SIZE: u32 = 100;
+
+/// A chat room group the account is a member of, see [`Connection::chat_room_groups`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatRoomGroup {
+    pub group_id: u64,
+    pub name: String,
+    pub rooms: Vec<ChatRoom>,
+}
+
+/// A text channel in a chat room group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatRoom {
+    pub chat_id: u64,
+    pub name: String,
+}
+
+impl From<&CChatRoomSummaryPair> for ChatRoomGroup {
+    fn from(pair: &CChatRoomSummaryPair) -> Self {
+        let summary = &pair.group_summary;
+        ChatRoomGroup {
+            group_id: summary.chat_group_id(),
+            name: summary.chat_group_name().into(),
+            rooms: summary
+                .chat_rooms
+                .iter()
+                .map(|room| ChatRoom {
+                    chat_id: room.chat_id(),
+                    name: room.chat_name().into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A message in a chat room, see [`Notification::ChatRoomMessage`](crate::Notification::ChatRoomMessage)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatRoomMessage {
+    pub group_id: u64,
+    pub chat_id: u64,
+    pub sender: SteamID,
+    pub message: String,
+    pub timestamp: u32,
+    /// Orders messages sent within the same second
+    pub ordinal: u32,
+}
+
+impl From<&CChatRoom_IncomingChatMessage_Notification> for ChatRoomMessage {
+    fn from(notification: &CChatRoom_IncomingChatMessage_Notification) -> Self {
+        ChatRoomMessage {
+            group_id: notification.chat_group_id(),
+            chat_id: notification.chat_id(),
+            sender: notification.steamid_sender().into(),
+            message: notification.message().into(),
+            timestamp: notification.timestamp(),
+            ordinal: notification.ordinal(),
+        }
+    }
+}
+
+impl ChatRoomMessage {
+    fn from_history(group_id: u64, chat_id: u64, message: &ChatMessage) -> Self {
+        ChatRoomMessage {
+            group_id,
+            chat_id,
+            sender: SteamID::new(
+                message.sender(),
+                Instance::Desktop,
+                AccountType::Individual,
+                Universe::Public,
+            ),
+            message: message.message().into(),
+            timestamp: message.server_timestamp(),
+            ordinal: message.ordinal(),
+        }
+    }
+}
+
+impl Connection {
+    /// Get the chat room groups the account is a member of
+    pub async fn chat_room_groups(&self) -> Result<Vec<ChatRoomGroup>, NetworkError> {
+        let response = self
+            .service_method(CChatRoom_GetMyChatRoomGroups_Request::default())
+            .await?;
+        Ok(response
+            .chat_room_groups
+            .iter()
+            .map(ChatRoomGroup::from)
+            .collect())
+    }
+
+    /// Get up to `limit` of the most recent messages in a chat room, newest first
+    ///
+    /// Steam returns the history in pages, as many pages are requested as needed to get `limit` messages.
+    pub async fn chat_room_message_history(
+        &self,
+        group_id: u64,
+        chat_id: u64,
+        limit: usize,
+    ) -> Result<Vec<ChatRoomMessage>, NetworkError> {
+        let mut messages = Vec::new();
+        let mut before = None;
+        while messages.len() < limit {
+            let (last_time, last_ordinal) = match before {
+                Some((time, ordinal)) => (Some(time), Some(ordinal)),
+                None => (None, None),
+            };
+            let page_size = (limit - messages.len()).min(HISTORY_PAGE_SIZE as usize) as u32;
+            let response = self
+                .service_method(CChatRoom_GetMessageHistory_Request {
+                    chat_group_id: Some(group_id),
+                    chat_id: Some(chat_id),
+                    last_time,
+                    last_ordinal,
+                    max_count: Some(page_size),
+                    ..CChatRoom_GetMessageHistory_Request::default()
+                })
+                .await?;
+            messages.extend(
+                response
+                    .messages
+                    .iter()
+                    .map(|message| ChatRoomMessage::from_history(group_id, chat_id, message)),
+            );
+            // continue before the oldest message of this page
+            before = response
+                .messages
+                .last()
+                .map(|message| (message.server_timestamp(), message.ordinal()));
+            if !response.more_available() || before.is_none() {
+                break;
+            }
+        }
+        messages.truncate(limit);
+        Ok(messages)
+    }
+
+    /// Send a message to a chat room, returning the message as stored by steam
+    ///
+    /// Steam can modify the message (e.g. to remove disallowed links), the returned message contains the modified text.
+    pub async fn send_chat_room_message(
+        &self,
+        group_id: u64,
+        chat_id: u64,
+        message: &str,
+    ) -> Result<ChatRoomMessage, NetworkError> {
+        let response = self
+            .service_method(CChatRoom_SendChatMessage_Request {
+                chat_group_id: Some(group_id),
+                chat_id: Some(chat_id),
+                message: Some(message.into()),
+                echo_to_sender: Some(false),
+                ..CChatRoom_SendChatMessage_Request::default()
+            })
+            .await?;
+        let sent = match response.modified_message() {
+            "" => message,
+            modified => modified,
+        };
+        Ok(ChatRoomMessage {
+            group_id,
+            chat_id,
+            sender: self.steam_id(),
+            message: sent.into(),
+            timestamp: response.server_timestamp(),
+            ordinal: response.ordinal(),
+        })
+    }
+}
+
+#[test]
+fn test_chat_room_conversions() {
+    use crate::proto::steammessages_chat_steamclient::{
+        CChatRoomState, CChatRoom_GetChatRoomGroupSummary_Response,
+    };
+    use protobuf::MessageField;
+
+    let pair = CChatRoomSummaryPair {
+        group_summary: MessageField::some(CChatRoom_GetChatRoomGroupSummary_Response {
+            chat_group_id: Some(12),
+            chat_group_name: Some("group".into()),
+            chat_rooms: vec![CChatRoomState {
+                chat_id: Some(34),
+                chat_name: Some("general".into()),
+                ..CChatRoomState::default()
+            }],
+            ..CChatRoom_GetChatRoomGroupSummary_Response::default()
+        }),
+        ..CChatRoomSummaryPair::default()
+    };
+    assert_eq!(
+        ChatRoomGroup {
+            group_id: 12,
+            name: "group".into(),
+            rooms: vec![ChatRoom {
+                chat_id: 34,
+                name: "general".into()
+            }]
+        },
+        ChatRoomGroup::from(&pair)
+    );
+
+    let history = ChatMessage {
+        sender: Some(1),
+        server_timestamp: Some(100),
+        message: Some("hello".into()),
+        ordinal: Some(2),
+        ..ChatMessage::default()
+    };
+    let incoming = CChatRoom_IncomingChatMessage_Notification {
+        chat_group_id: Some(12),
+        chat_id: Some(34),
+        steamid_sender: Some(76561197960265729),
+        message: Some("hello".into()),
+        timestamp: Some(100),
+        ordinal: Some(2),
+        ..CChatRoom_IncomingChatMessage_Notification::default()
+    };
+    assert_eq!(
+        ChatRoomMessage::from(&incoming),
+        ChatRoomMessage::from_history(12, 34, &history)
+    );
+}
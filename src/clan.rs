@@ -0,0 +1,195 @@
+use crate::game_id::GameId;
+use crate::proto::steammessages_clientserver::cmsg_client_clan_state::{
+    Event as ProtoEvent, UserCounts,
+};
+use crate::proto::steammessages_clientserver::CMsgClientClanState;
+use std::collections::HashMap;
+use steamid_ng::SteamID;
+
+/// An event or announcement posted in a clan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClanEvent {
+    pub gid: u64,
+    /// The unix timestamp of the event
+    pub time: u32,
+    pub headline: String,
+    pub game_id: Option<GameId>,
+    /// The event was posted just now, instead of being an existing event sent as part of the clan state
+    pub just_posted: bool,
+}
+
+impl From<&ProtoEvent> for ClanEvent {
+    fn from(event: &ProtoEvent) -> Self {
+        ClanEvent {
+            gid: event.gid(),
+            time: event.event_time(),
+            headline: event.headline().into(),
+            game_id: event.game_id.filter(|id| *id != 0).map(GameId::from),
+            just_posted: event.just_posted(),
+        }
+    }
+}
+
+/// The number of members of a clan, by their status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClanUserCounts {
+    pub members: u32,
+    pub online: u32,
+    pub chatting: u32,
+    pub in_game: u32,
+    pub chat_room_members: u32,
+}
+
+impl From<&UserCounts> for ClanUserCounts {
+    fn from(counts: &UserCounts) -> Self {
+        ClanUserCounts {
+            members: counts.members(),
+            online: counts.online(),
+            chatting: counts.chatting(),
+            in_game: counts.in_game(),
+            chat_room_members: counts.chat_room_members(),
+        }
+    }
+}
+
+/// The state of a clan (steam group)
+///
+/// Steam only sends the parts of the state that changed, so for a single update the fields
+/// that weren't sent are `None`. The state from [`Connection::clan_state`](crate::Connection::clan_state)
+/// combines all updates received for the clan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClanState {
+    pub steam_id: SteamID,
+    pub account_flags: Option<u32>,
+    pub name: Option<String>,
+    /// The sha1 hash of the clan avatar
+    pub avatar_hash: Option<Vec<u8>>,
+    pub user_counts: Option<ClanUserCounts>,
+    pub events: Vec<ClanEvent>,
+    pub announcements: Vec<ClanEvent>,
+}
+
+impl From<&CMsgClientClanState> for ClanState {
+    fn from(state: &CMsgClientClanState) -> Self {
+        let name_info = state.name_info.as_ref();
+        ClanState {
+            steam_id: state.steamid_clan().into(),
+            account_flags: state.clan_account_flags,
+            name: name_info.and_then(|info| info.clan_name.clone()),
+            avatar_hash: name_info.and_then(|info| info.sha_avatar.clone()),
+            user_counts: state.user_counts.as_ref().map(ClanUserCounts::from),
+            events: state.events.iter().map(ClanEvent::from).collect(),
+            announcements: state.announcements.iter().map(ClanEvent::from).collect(),
+        }
+    }
+}
+
+impl ClanState {
+    /// Apply an update for the same clan, keeping the fields that aren't part of the update
+    fn apply(&mut self, update: ClanState) {
+        if update.account_flags.is_some() {
+            self.account_flags = update.account_flags;
+        }
+        if update.name.is_some() {
+            self.name = update.name;
+        }
+        if update.avatar_hash.is_some() {
+            self.avatar_hash = update.avatar_hash;
+        }
+        if update.user_counts.is_some() {
+            self.user_counts = update.user_counts;
+        }
+        merge_events(&mut self.events, update.events);
+        merge_events(&mut self.announcements, update.announcements);
+    }
+}
+
+/// Add new events and replace the ones that were sent again
+fn merge_events(events: &mut Vec<ClanEvent>, update: Vec<ClanEvent>) {
+    for event in update {
+        match events.iter_mut().find(|existing| existing.gid == event.gid) {
+            Some(existing) => *existing = event,
+            None => events.push(event),
+        }
+    }
+}
+
+/// The known state of all clans that updates were received for
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Clans {
+    clans: HashMap<SteamID, ClanState>,
+}
+
+impl Clans {
+    pub fn get(&self, steam_id: SteamID) -> Option<&ClanState> {
+        self.clans.get(&steam_id)
+    }
+
+    pub fn apply(&mut self, update: ClanState) {
+        match self.clans.get_mut(&update.steam_id) {
+            Some(state) => state.apply(update),
+            None => {
+                self.clans.insert(update.steam_id, update);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_apply_clan_state() {
+    use crate::proto::steammessages_clientserver::cmsg_client_clan_state::NameInfo;
+    use protobuf::MessageField;
+
+    fn event(gid: u64, headline: &str) -> ProtoEvent {
+        ProtoEvent {
+            gid: Some(gid),
+            event_time: Some(1700000000),
+            headline: Some(headline.into()),
+            ..ProtoEvent::default()
+        }
+    }
+
+    let clan = SteamID::from(103582791429521412);
+    let mut clans = Clans::default();
+    clans.apply(ClanState::from(&CMsgClientClanState {
+        steamid_clan: Some(clan.into()),
+        name_info: MessageField::some(NameInfo {
+            clan_name: Some("Valve".into()),
+            ..NameInfo::default()
+        }),
+        user_counts: MessageField::some(UserCounts {
+            members: Some(100),
+            online: Some(10),
+            ..UserCounts::default()
+        }),
+        announcements: vec![event(1, "Update")],
+        ..CMsgClientClanState::default()
+    }));
+
+    // an update with only the changed user counts and a new announcement
+    clans.apply(ClanState::from(&CMsgClientClanState {
+        steamid_clan: Some(clan.into()),
+        user_counts: MessageField::some(UserCounts {
+            members: Some(101),
+            online: Some(12),
+            ..UserCounts::default()
+        }),
+        announcements: vec![event(1, "Update (edited)"), event(2, "Sale")],
+        ..CMsgClientClanState::default()
+    }));
+
+    let state = clans.get(clan).unwrap();
+    assert_eq!(Some("Valve"), state.name.as_deref());
+    assert_eq!(101, state.user_counts.unwrap().members);
+    assert_eq!(12, state.user_counts.unwrap().online);
+    assert_eq!(
+        vec!["Update (edited)", "Sale"],
+        state
+            .announcements
+            .iter()
+            .map(|event| event.headline.as_str())
+            .collect::<Vec<_>>()
+    );
+    assert!(state.events.is_empty());
+    assert!(clans.get(SteamID::from(0)).is_none());
+}
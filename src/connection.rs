@@ -1,70 +1,852 @@
+mod cache;
+
+use crate::account_limits::AccountLimits;
 use crate::auth::{begin_password_auth, AuthConfirmationHandler, GuardDataStore};
+use crate::clan::{ClanState, Clans};
+use crate::dedup::{is_read_only, RequestDeduplicator};
+use crate::friend_groups::FriendGroups;
+use crate::gc::{dispatch_gc, GcHandlers};
 use crate::message::{
-    NetMessage, ServiceMethodMessage, ServiceMethodNotification, ServiceMethodResponseMessage,
+    compress_multi, NetMessage, ServiceMethodMessage, ServiceMethodNotification,
+    ServiceMethodResponseMessage,
 };
+use crate::metrics::{ConnectionStats, MessageMetrics};
 use crate::net::{NetMessageHeader, NetworkError, RawNetMessage};
+use crate::nicknames::Nicknames;
+use crate::personas::Personas;
+use crate::presence::{Presence, PresenceReplay};
 use crate::proto::enums_clientserver::EMsg;
-use crate::proto::steammessages_clientserver_login::CMsgClientHeartBeat;
-use crate::serverlist::ServerList;
+use crate::proto::steammessages_chat_steamclient::CChatRoom_IncomingChatMessage_Notification;
+use crate::proto::steammessages_clientserver_login::{CMsgClientHeartBeat, CMsgClientLogOff};
+use crate::queue::{queue, OverflowPolicy, QueueReceiver, QueueSender};
+use crate::resolver::{Resolver, SharedResolver};
+use crate::scores::ServerScores;
+use crate::serverlist::{ServerDiscoveryError, ServerList};
 use crate::service_method::ServiceMethodRequest;
-use crate::session::{anonymous, hello, login, ConnectionError, Session};
-use crate::transport::websocket::connect;
+use crate::session::{
+    anonymous, hello, is_plausible_machine_id, login, ChatMode, ConnectionError, Session,
+};
+use crate::task::spawn_named;
+use crate::throttle::BandwidthLimits;
+use crate::transport::tcp::SharedCrypto;
+use crate::transport::websocket::TcpConnection;
+use crate::transport::{tcp, websocket, HttpProxy, Transport};
+use crate::ui_mode::UiMode;
+use crate::vac::VacBanStatus;
+use crate::wallet::Wallet;
+use bytes::BytesMut;
 use dashmap::DashMap;
 use futures_util::future::{select, Either};
 use futures_util::{Sink, SinkExt};
-use std::future::Future;
-use std::pin::pin;
+use gethostname::gethostname;
+use rand::{thread_rng, Rng};
+use std::collections::HashSet;
+use std::future::{ready, Future};
+use std::net::IpAddr;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use steamid_ng::SteamID;
-use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
-use tokio::task::spawn;
-use tokio::time::{sleep, timeout};
+use std::time::{Duration, Instant};
+use steam_vent_crypto::CryptoProvider;
+use steamid_ng::{Instance, SteamID, Universe};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Mutex};
+use tokio::task::AbortHandle;
+use tokio::time::{sleep, timeout, timeout_at};
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::{Stream, StreamExt};
-use tracing::{debug, error};
+use tracing::{debug, debug_span, error, field, info_span, trace, warn, Instrument, Span};
 
 type Result<T, E = NetworkError> = std::result::Result<T, E>;
 
+/// The state of a connection
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ConnectionState {
+    /// Establishing the connection to the server
+    Connecting,
+    /// Performing the channel encryption handshake, the tls handshake for websocket connections
+    Encrypting,
+    /// Authenticating and logging on
+    LoggingIn,
+    /// Logged on and ready to send requests
+    Connected,
+    /// The connection was lost and is being re-established
+    Reconnecting { error: String },
+    /// The connection is closed, either because of an error or because the server closed it
+    Closed { error: Option<String> },
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    pub(crate) machine_name: String,
+    pub(crate) machine_id: Option<Vec<u8>>,
+    pub(crate) device_friendly_name: String,
+    pub(crate) state: watch::Sender<ConnectionState>,
+    pub(crate) transport: Transport,
+    receive_timestamps: bool,
+    compression_threshold: Option<usize>,
+    deduplicate_requests: bool,
+    accept_invalid_certs: bool,
+    pub(crate) instance: Instance,
+    pub(crate) client_instance_id: Option<u64>,
+    pub(crate) supports_rate_limit_response: bool,
+    pub(crate) steam_box: bool,
+    pub(crate) steam_deck: bool,
+    pub(crate) steam2_ticket_request: bool,
+    pub(crate) launcher_type: u32,
+    pub(crate) ui_mode: UiMode,
+    pub(crate) chat_mode: ChatMode,
+    handshake_timeout: Duration,
+    connect_timeout: Duration,
+    nodelay: bool,
+    send_bandwidth_limit: Option<u64>,
+    receive_bandwidth_limit: Option<u64>,
+    heartbeat_jitter: f64,
+    liveness_heartbeats: u32,
+    receive_queue_size: usize,
+    receive_queue_policy: OverflowPolicy,
+    resolver: SharedResolver,
+    proxy: Option<HttpProxy>,
+    crypto: SharedCrypto,
+    server_scores: ServerScores,
+    pub(crate) presence_replay: PresenceReplay,
+    pub(crate) persona_cache_ttl: Duration,
+    pub(crate) persona_batch_size: usize,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        let hostname = gethostname().to_string_lossy().into_owned();
+        ConnectionOptions {
+            machine_name: hostname.clone(),
+            machine_id: None,
+            device_friendly_name: hostname,
+            state: watch::channel(ConnectionState::Connecting).0,
+            transport: Transport::WebSocket,
+            receive_timestamps: false,
+            compression_threshold: None,
+            deduplicate_requests: false,
+            accept_invalid_certs: false,
+            instance: Instance::Desktop,
+            client_instance_id: None,
+            supports_rate_limit_response: true,
+            steam_box: false,
+            steam_deck: false,
+            steam2_ticket_request: false,
+            launcher_type: 0,
+            ui_mode: UiMode::Desktop,
+            chat_mode: ChatMode::New,
+            handshake_timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(5),
+            nodelay: true,
+            send_bandwidth_limit: None,
+            receive_bandwidth_limit: None,
+            heartbeat_jitter: 0.1,
+            liveness_heartbeats: 0,
+            receive_queue_size: 16,
+            receive_queue_policy: OverflowPolicy::Block,
+            resolver: SharedResolver::default(),
+            proxy: None,
+            crypto: SharedCrypto::default(),
+            server_scores: ServerScores::default(),
+            presence_replay: PresenceReplay::default(),
+            persona_cache_ttl: Duration::from_secs(300),
+            persona_batch_size: 100,
+        }
+    }
+}
+
+impl ConnectionOptions {
+    /// A copy of the options with a separate state channel, each connection gets its own channel
+    ///
+    /// The state of the connection is observed with [`Connection::state`] or [`TcpConnected::state`](crate::TcpConnected::state).
+    pub(crate) fn with_new_state(&self) -> Self {
+        ConnectionOptions {
+            state: watch::channel(ConnectionState::Connecting).0,
+            ..self.clone()
+        }
+    }
+
+    /// The bandwidth limits applied to the socket of each connection
+    pub(crate) fn bandwidth_limits(&self) -> BandwidthLimits {
+        BandwidthLimits {
+            send: self.send_bandwidth_limit,
+            receive: self.receive_bandwidth_limit,
+        }
+    }
+
+    /// Set the machine name sent during logon, defaults to the hostname
+    pub fn with_machine_name(self, machine_name: impl Into<String>) -> Self {
+        ConnectionOptions {
+            machine_name: machine_name.into(),
+            ..self
+        }
+    }
+
+    /// Send this exact machine id blob during logon, by default no machine id is sent
+    ///
+    /// Using the blob of another client makes the session appear as the same device. The blob is binary
+    /// KeyValues with a `MessageObject` containing the `BB3`, `FF2` and `3B3` hashes, other data is still
+    /// sent as is but logs a warning.
+    pub fn with_machine_id_blob(self, machine_id: impl Into<Vec<u8>>) -> Self {
+        let machine_id = machine_id.into();
+        if !is_plausible_machine_id(&machine_id) {
+            warn!("machine id blob isn't a KeyValues object with the expected hashes, sending it anyway");
+        }
+        ConnectionOptions {
+            machine_id: Some(machine_id),
+            ..self
+        }
+    }
+
+    /// Set the protocol used for connecting to the servers, defaults to [`Transport::WebSocket`]
+    ///
+    /// With [`Transport::Tcp`] the tcp servers from the server list are used, and the connection
+    /// is encrypted with the encryption handshake of steam instead of tls.
+    pub fn with_transport(self, transport: Transport) -> Self {
+        ConnectionOptions { transport, ..self }
+    }
+
+    /// Record the time each message is received, disabled by default
+    ///
+    /// The time is available from [`RawNetMessage::received_at`], [`Connection::on_timestamped`]
+    /// and [`Connection::next_timestamped_notification`]
+    pub fn with_receive_timestamps(self, receive_timestamps: bool) -> Self {
+        ConnectionOptions {
+            receive_timestamps,
+            ..self
+        }
+    }
+
+    /// Compress outgoing messages that are larger than the threshold (in bytes)
+    ///
+    /// By default outgoing messages are never compressed
+    pub fn with_compression_threshold(self, threshold: usize) -> Self {
+        ConnectionOptions {
+            compression_threshold: Some(threshold),
+            ..self
+        }
+    }
+
+    /// Send only a single request when identical requests are made concurrently, disabled by default
+    ///
+    /// Requests made through [`Connection::job`] and [`Connection::service_method`] while an identical
+    /// request is waiting for its response will share that response (or error) instead of being sent again.
+    /// Only a fixed list of requests that just read data is deduplicated, like the PICS product info,
+    /// user stats and chat room history requests, other requests are always sent.
+    pub fn with_request_deduplication(self, deduplicate_requests: bool) -> Self {
+        ConnectionOptions {
+            deduplicate_requests,
+            ..self
+        }
+    }
+
+    /// Accept any tls certificate from the server, **disabling protection against man-in-the-middle attacks**
+    ///
+    /// This is only intended for inspecting the traffic with an intercepting proxy while debugging.
+    pub fn with_danger_accept_invalid_certs(self, accept_invalid_certs: bool) -> Self {
+        ConnectionOptions {
+            accept_invalid_certs,
+            ..self
+        }
+    }
+
+    /// Set the instance of the account to log on as, defaults to [`Instance::Desktop`]
+    ///
+    /// The instance is stored in the instance bits of the [`SteamID`] used by the session,
+    /// a single account can be logged on as multiple instances at the same time (e.g. [`Instance::Desktop`]
+    /// for the users own client and [`Instance::Web`] for a bot) without the sessions kicking each other off.
+    pub fn with_instance(self, instance: Instance) -> Self {
+        ConnectionOptions { instance, ..self }
+    }
+
+    /// Set the client instance id sent during logon
+    ///
+    /// This allows the server to tell apart multiple sessions that log on with the same instance
+    pub fn with_client_instance_id(self, client_instance_id: u64) -> Self {
+        ConnectionOptions {
+            client_instance_id: Some(client_instance_id),
+            ..self
+        }
+    }
+
+    /// Tell steam that the client handles rate limit responses during logon, enabled by default (recommended)
+    ///
+    /// With this enabled, steam answers a rate limited logon with a response that results in
+    /// [`LoginError::RateLimited`](crate::LoginError::RateLimited) instead of silently dropping the logon.
+    pub fn with_rate_limit_response(self, supports_rate_limit_response: bool) -> Self {
+        ConnectionOptions {
+            supports_rate_limit_response,
+            ..self
+        }
+    }
+
+    /// Log on as a steam box (big picture mode device), disabled by default
+    ///
+    /// This is only recommended when the client actually runs on such a device
+    pub fn with_steam_box(self, steam_box: bool) -> Self {
+        ConnectionOptions { steam_box, ..self }
+    }
+
+    /// Log on as a steam deck, disabled by default
+    ///
+    /// This is only recommended when the client actually runs on a steam deck
+    pub fn with_steam_deck(self, steam_deck: bool) -> Self {
+        ConnectionOptions { steam_deck, ..self }
+    }
+
+    /// Request the legacy steam2 ticket during logon, defaults to `false`
+    ///
+    /// The ticket is only used by some older authentication flows, see [`Connection::steam2_ticket`]
+    pub fn with_steam2_ticket_request(self, steam2_ticket_request: bool) -> Self {
+        ConnectionOptions {
+            steam2_ticket_request,
+            ..self
+        }
+    }
+
+    /// Set the launcher type sent during logon, defaults to `0` (the default launcher)
+    ///
+    /// Steam uses this to tell apart the ways the client was started, e.g. `3` for the command line
+    /// and `6` for headless clients. Leaving it at the default is recommended for most clients.
+    pub fn with_launcher_type(self, launcher_type: u32) -> Self {
+        ConnectionOptions {
+            launcher_type,
+            ..self
+        }
+    }
+
+    /// Set the kind of client the session presents itself as during logon, defaults to [`UiMode::Desktop`]
+    ///
+    /// The mode can be changed after logon with [`Connection::set_ui_mode`]
+    pub fn with_ui_mode(self, ui_mode: UiMode) -> Self {
+        ConnectionOptions { ui_mode, ..self }
+    }
+
+    /// Set which parts of the presence are restored after reconnecting, defaults to restoring everything
+    pub fn with_presence_replay(self, presence_replay: PresenceReplay) -> Self {
+        ConnectionOptions {
+            presence_replay,
+            ..self
+        }
+    }
+
+    /// Set how long persona data is cached by [`Connection::get_persona`], defaults to 5 minutes
+    ///
+    /// A zero ttl disables the cache, the persona data is requested from steam for every call.
+    pub fn with_persona_cache_ttl(self, persona_cache_ttl: Duration) -> Self {
+        ConnectionOptions {
+            persona_cache_ttl,
+            ..self
+        }
+    }
+
+    /// Set how many users are requested at once by [`Connection::get_personas`], defaults to 100
+    pub fn with_persona_batch_size(self, persona_batch_size: usize) -> Self {
+        ConnectionOptions {
+            persona_batch_size: persona_batch_size.max(1),
+            ..self
+        }
+    }
+
+    /// Set the chat protocol the session uses, defaults to [`ChatMode::New`] (recommended)
+    ///
+    /// With [`ChatMode::Legacy`] messages sent from current clients aren't delivered as
+    /// [`Notification::FriendMessage`](crate::Notification::FriendMessage).
+    pub fn with_chat_mode(self, chat_mode: ChatMode) -> Self {
+        ConnectionOptions { chat_mode, ..self }
+    }
+
+    /// Set how long establishing the connection to a server can take before giving up, defaults to 10 seconds
+    ///
+    /// This is separate from the timeout for requests set with [`Connection::set_timeout`],
+    /// and prevents getting stuck on a server that accepts the connection but never responds.
+    pub fn with_handshake_timeout(self, handshake_timeout: Duration) -> Self {
+        ConnectionOptions {
+            handshake_timeout,
+            ..self
+        }
+    }
+
+    /// Set how long a server can take to accept the tcp connection, defaults to 5 seconds
+    ///
+    /// When a host resolves to multiple addresses, each address gets this long before the next one is tried,
+    /// so an address that silently drops the connection attempt doesn't use up the whole [handshake timeout].
+    /// Connecting fails with [`NetworkError::Timeout`] if no address accepts the connection in time.
+    ///
+    /// [handshake timeout]: ConnectionOptions::with_handshake_timeout
+    pub fn with_connect_timeout(self, connect_timeout: Duration) -> Self {
+        ConnectionOptions {
+            connect_timeout,
+            ..self
+        }
+    }
+
+    /// Set whether `TCP_NODELAY` is set on the socket, disabling nagle's algorithm, defaults to `true`
+    ///
+    /// Since steam messages are mostly small and latency sensitive this is enabled by default,
+    /// clients that mostly do bulk transfers can disable it to reduce the number of packets sent.
+    pub fn with_nodelay(self, nodelay: bool) -> Self {
+        ConnectionOptions { nodelay, ..self }
+    }
+
+    /// Limit the number of bytes per second sent over the connection, defaults to unlimited
+    ///
+    /// The limit applies to the bytes written to the socket, so the framing, the tls and websocket overhead and
+    /// the handshakes are counted as well. Short bursts of up to one second worth of bytes are allowed.
+    pub fn with_send_bandwidth_limit(self, bytes_per_second: u64) -> Self {
+        ConnectionOptions {
+            send_bandwidth_limit: Some(bytes_per_second),
+            ..self
+        }
+    }
+
+    /// Limit the number of bytes per second read from the connection, defaults to unlimited
+    ///
+    /// Like [`ConnectionOptions::with_send_bandwidth_limit`] but for the bytes read from the socket,
+    /// the socket is read at a slower pace when the limit is reached.
+    pub fn with_receive_bandwidth_limit(self, bytes_per_second: u64) -> Self {
+        ConnectionOptions {
+            receive_bandwidth_limit: Some(bytes_per_second),
+            ..self
+        }
+    }
+
+    /// Set how much the time between heartbeats randomly varies, as a fraction of the interval, defaults to `0.1`
+    ///
+    /// Spreading out the heartbeats prevents many connections that were established at the same time
+    /// from all sending their heartbeats at once. Setting it to `0` sends the heartbeats at the exact interval.
+    /// The jitter is limited to `0.5`, so heartbeats are at least half an interval apart, non-finite values are ignored.
+    pub fn with_heartbeat_jitter(self, jitter: f64) -> Self {
+        if !jitter.is_finite() {
+            return self;
+        }
+        ConnectionOptions {
+            heartbeat_jitter: jitter.clamp(0.0, 0.5),
+            ..self
+        }
+    }
+
+    /// Consider the connection dead when nothing is received from the server for this many heartbeat intervals,
+    /// defaults to `0` which disables the check
+    ///
+    /// When enabled, the heartbeats ask the server for a reply so even an idle connection receives messages.
+    /// A dead connection is closed and its state changes to [`ConnectionState::Closed`], so it can be re-established
+    /// with [`Connection::reconnect`]. This detects connections that stopped working without the tcp connection
+    /// being closed, which otherwise goes unnoticed until a request times out.
+    pub fn with_liveness_heartbeats(self, liveness_heartbeats: u32) -> Self {
+        ConnectionOptions {
+            liveness_heartbeats,
+            ..self
+        }
+    }
+
+    /// Set how many messages are queued for [`Connection::next`] and what happens when the queue is full,
+    /// defaults to 16 messages with [`OverflowPolicy::Block`]
+    ///
+    /// Blocking stops reading from the server while the queue is full, which also delays responses
+    /// to requests and can get the connection closed for missing heartbeats if the application stops reading.
+    /// Applications that only monitor the messages can drop messages instead, the number of dropped messages
+    /// is available from [`Connection::dropped_messages`].
+    pub fn with_receive_queue(self, size: usize, policy: OverflowPolicy) -> Self {
+        ConnectionOptions {
+            receive_queue_size: size.max(1),
+            receive_queue_policy: policy,
+            ..self
+        }
+    }
+
+    /// Set the resolver used for looking up the addresses of the servers, defaults to the system resolver
+    ///
+    /// To also use it for discovering the servers, set it in the [`DiscoverOptions`](crate::DiscoverOptions) too.
+    pub fn with_resolver<R: Resolver>(self, resolver: R) -> Self {
+        ConnectionOptions {
+            resolver: SharedResolver::new(resolver),
+            ..self
+        }
+    }
+
+    /// Tunnel the connections to the servers through an http proxy
+    ///
+    /// The proxy address is looked up with the resolver, the server host names are resolved by the proxy.
+    pub fn with_http_proxy(self, proxy: HttpProxy) -> Self {
+        ConnectionOptions {
+            proxy: Some(proxy),
+            ..self
+        }
+    }
+
+    /// Set the crypto implementation used for encrypting the messages, defaults to the functions of the crypto crate
+    ///
+    /// This is only used by the [`Transport::Tcp`] transport, the websocket transport is encrypted with tls.
+    pub fn with_crypto_provider<C: CryptoProvider>(self, crypto: C) -> Self {
+        ConnectionOptions {
+            crypto: SharedCrypto::new(crypto),
+            ..self
+        }
+    }
+
+    /// Set the scores used for picking the servers to connect to, to share or restore them
+    ///
+    /// By default every set of options starts with empty scores, which are shared with its clones.
+    pub fn with_server_scores(self, server_scores: ServerScores) -> Self {
+        ConnectionOptions {
+            server_scores,
+            ..self
+        }
+    }
+
+    /// How well connecting to each server worked, connecting prefers the servers with the best scores
+    pub fn server_scores(&self) -> &ServerScores {
+        &self.server_scores
+    }
+
+    /// Set the device name shown in the authorized devices list of the account, defaults to the hostname
+    pub fn with_device_friendly_name(self, device_friendly_name: impl Into<String>) -> Self {
+        ConnectionOptions {
+            device_friendly_name: device_friendly_name.into(),
+            ..self
+        }
+    }
+}
+
+/// Handler for restoring the state of the session after [`Connection::reconnect`]
+///
+/// The handler runs before any notifications from the new connection are delivered,
+/// so it can restore things like the persona state or the games being played without missing anything.
+///
+/// ```
+/// use steam_vent::{Connection, ConnectionState, ReconnectHandler};
+///
+/// struct LogReconnect;
+///
+/// impl ReconnectHandler for LogReconnect {
+///     async fn on_reconnect(&self, old_state: &ConnectionState, connection: &Connection) {
+///         println!("reconnected as {:?} after {old_state:?}", connection.steam_id());
+///     }
+/// }
+/// ```
+pub trait ReconnectHandler: Send + Sync + 'static {
+    /// Called with the state the connection was in before reconnecting and the newly established connection
+    fn on_reconnect(
+        &self,
+        old_state: &ConnectionState,
+        connection: &Connection,
+    ) -> impl Future<Output = ()> + Send;
+}
+
+/// Object safe version of [`ReconnectHandler`] so the handlers can be stored in the connection
+trait DynReconnectHandler: Send + Sync {
+    fn on_reconnect<'a>(
+        &'a self,
+        old_state: &'a ConnectionState,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+}
+
+impl<H: ReconnectHandler> DynReconnectHandler for H {
+    fn on_reconnect<'a>(
+        &'a self,
+        old_state: &'a ConnectionState,
+        connection: &'a Connection,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(ReconnectHandler::on_reconnect(self, old_state, connection))
+    }
+}
+
+/// The credentials the session was started with, kept to log on again when reconnecting
+#[derive(Clone)]
+enum Credentials {
+    Anonymous,
+    RefreshToken {
+        account: String,
+        refresh_token: String,
+    },
+}
+
+/// How logging off completed, see [`Connection::log_off`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogOffOutcome {
+    /// Steam ended the session and closed the connection within the grace period
+    Closed,
+    /// The log off was sent, but steam didn't close the connection within the grace period
+    TimedOut,
+}
+
+/// The number of servers tried by [`Connection::connect_any`] before giving up
+const CONNECT_ATTEMPTS: usize = 3;
+
+/// Try `connect` for the servers from the list with the best score in turn, returning the first that succeeds
+///
+/// Fails with [`ConnectionError::Unreachable`] with the error for every server that was tried.
+async fn first_server<T, F, Fut>(
+    server_list: &ServerList,
+    options: &ConnectionOptions,
+    connect: F,
+) -> Result<T, ConnectionError>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<T, ConnectionError>>,
+{
+    let mut urls = options
+        .server_scores
+        .rank(server_list.urls(options.transport));
+    if urls.is_empty() {
+        return Err(ServerDiscoveryError::NoServers.into());
+    }
+    urls.truncate(CONNECT_ATTEMPTS);
+    tcp::first_reachable(&urls, connect)
+        .await
+        .map(|(_, connected)| connected)
+        .map_err(ConnectionError::Unreachable)
+}
+
+type SharedSink = Arc<Mutex<dyn Sink<RawNetMessage, Error = NetworkError> + Unpin + Send>>;
+
 pub struct Connection {
     pub(crate) session: Session,
     filter: MessageFilter,
-    rest: mpsc::Receiver<Result<RawNetMessage>>,
-    write: Arc<Mutex<dyn Sink<RawNetMessage, Error = NetworkError> + Unpin + Send>>,
-    timeout: Duration,
+    rest: QueueReceiver<Result<RawNetMessage>>,
+    write: SharedSink,
+    pub(crate) timeout: Duration,
+    pub(crate) state: watch::Sender<ConnectionState>,
+    compression_threshold: Option<usize>,
+    dedup: Option<RequestDeduplicator>,
+    pub(crate) options: ConnectionOptions,
+    credentials: Credentials,
+    reconnect_handlers: Vec<Arc<dyn DynReconnectHandler>>,
+    /// The presence last set on the connection, restored after reconnecting
+    pub(crate) presence: Arc<std::sync::Mutex<Presence>>,
+    /// The local ip address of the transport, sent to steam during logon
+    pub(crate) local_ip: Option<IpAddr>,
+    /// The universe the server announced in the encryption handshake, only known for the tcp transport
+    pub(crate) universe: Option<Universe>,
+    /// The span the background tasks of the connection run in, and the parent of the spans for requests
+    span: Span,
+    filter_task: AbortHandle,
+    heartbeat_task: Option<AbortHandle>,
+    /// Set when the connection is closed with [`Connection::close`] or detached with [`Connection::detach`]
+    closed: bool,
 }
 
 impl Connection {
-    async fn connect(server_list: ServerList) -> Result<Self, ConnectionError> {
-        let (read, write) = connect(&server_list.pick_ws()).await?;
-        let (filter, rest) = MessageFilter::new(read);
-        let mut connection = Connection {
+    pub(crate) async fn connect(
+        addr: &str,
+        options: &ConnectionOptions,
+    ) -> Result<Self, ConnectionError> {
+        options.state.send_replace(ConnectionState::Connecting);
+        let transport = open_tcp(addr, options).await?;
+        Self::encrypt_channel(transport, options).await
+    }
+
+    /// Connect to the server from the list with the best score, trying the next best ones if that fails
+    pub(crate) async fn connect_any(
+        server_list: &ServerList,
+        options: &ConnectionOptions,
+    ) -> Result<Self, ConnectionError> {
+        first_server(server_list, options, |url| async move {
+            Self::connect(&url, options).await
+        })
+        .await
+    }
+
+    /// Open the tcp connection to the server from the list with the best score, trying the next best ones if that fails
+    pub(crate) async fn connect_tcp_any(
+        server_list: &ServerList,
+        options: &ConnectionOptions,
+    ) -> Result<PendingTransport, ConnectionError> {
+        options.state.send_replace(ConnectionState::Connecting);
+        first_server(server_list, options, |url| async move {
+            Ok(open_tcp(&url, options).await?)
+        })
+        .await
+    }
+
+    /// Perform the encryption handshake on an open tcp connection and greet the server
+    pub(crate) async fn encrypt_channel(
+        transport: PendingTransport,
+        options: &ConnectionOptions,
+    ) -> Result<Self, ConnectionError> {
+        options.state.send_replace(ConnectionState::Encrypting);
+        let addr = transport.addr.clone();
+        let transport = encrypt_transport(transport, options).await?;
+        let mut connection = Self::from_transport(
+            &addr,
+            transport.read,
+            transport.write,
+            options,
+            MessageFilter::default(),
+            None,
+        );
+        connection.local_ip = transport.local_ip;
+        connection.universe = transport.universe;
+        hello(&mut connection).await?;
+        Ok(connection)
+    }
+
+    /// Create the connection for an already established transport
+    ///
+    /// The background tasks of the connection run in a span for the connection,
+    /// so their logs can be told apart when running multiple connections.
+    fn from_transport<
+        Read: Stream<Item = Result<RawNetMessage>> + Send + Unpin + 'static,
+        Write: Sink<RawNetMessage, Error = NetworkError> + Unpin + Send + 'static,
+    >(
+        addr: &str,
+        read: Read,
+        write: Write,
+        options: &ConnectionOptions,
+        filter: MessageFilter,
+        hold: Option<oneshot::Receiver<()>>,
+    ) -> Self {
+        let span = info_span!("connection", cm = addr, steam_id = field::Empty);
+        let state = options.state.clone();
+        let metrics = filter.metrics.clone();
+        let write = write.with(move |message: RawNetMessage| {
+            metrics.record_outbound(message.kind, message.encoded_len());
+            ready(Ok::<_, NetworkError>(message))
+        });
+        let write: SharedSink = Arc::new(Mutex::new(write));
+        let (rest, filter_task) =
+            span.in_scope(|| filter.spawn(read, write.clone(), options, hold));
+        Connection {
             session: Session::default(),
             filter,
             rest,
-            write: Arc::new(Mutex::new(write)),
+            write,
             timeout: Duration::from_secs(10),
-        };
-        hello(&mut connection).await?;
-        Ok(connection)
+            state,
+            compression_threshold: options.compression_threshold,
+            dedup: options
+                .deduplicate_requests
+                .then(RequestDeduplicator::default),
+            options: options.clone(),
+            credentials: Credentials::Anonymous,
+            reconnect_handlers: Vec::new(),
+            presence: Arc::default(),
+            local_ip: None,
+            universe: None,
+            span,
+            filter_task,
+            heartbeat_task: None,
+            closed: false,
+        }
     }
 
     pub async fn anonymous(server_list: ServerList) -> Result<Self, ConnectionError> {
-        let mut connection = Self::connect(server_list).await?;
-        connection.session = anonymous(&mut connection).await?;
-        connection.setup_heartbeat();
+        Self::anonymous_with(server_list, ConnectionOptions::default()).await
+    }
 
-        Ok(connection)
+    pub async fn anonymous_with(
+        server_list: ServerList,
+        options: ConnectionOptions,
+    ) -> Result<Self, ConnectionError> {
+        let options = options.with_new_state();
+        match Self::connect_any(&server_list, &options).await {
+            Ok(connection) => connection.anonymous_session().await,
+            Err(e) => set_result_state(&options.state, Err(e)),
+        }
+    }
+
+    /// Start an anonymous session on a connection that has completed the handshake
+    pub(crate) async fn anonymous_session(self) -> Result<Self, ConnectionError> {
+        let state = self.state.clone();
+        let result = self.anonymous_logon().await.map(Connection::start_session);
+        set_result_state(&state, result)
+    }
+
+    /// Log on anonymously, without starting the heartbeat
+    pub(crate) async fn anonymous_logon(mut self) -> Result<Self, ConnectionError> {
+        self.state.send_replace(ConnectionState::LoggingIn);
+        let options = self.options.clone();
+        self.session = anonymous(&mut self, &options).await?;
+        Ok(self)
+    }
+
+    /// Start the heartbeat for a connection that is logged on
+    pub(crate) fn start_session(mut self) -> Self {
+        self.setup_heartbeat();
+        self
     }
 
     pub async fn login<H: AuthConfirmationHandler, G: GuardDataStore>(
         server_list: ServerList,
         account: &str,
         password: &str,
+        guard_data_store: G,
+        confirmation_handler: H,
+    ) -> Result<Self, ConnectionError> {
+        Self::login_with(
+            server_list,
+            account,
+            password,
+            guard_data_store,
+            confirmation_handler,
+            ConnectionOptions::default(),
+        )
+        .await
+    }
+
+    pub async fn login_with<H: AuthConfirmationHandler, G: GuardDataStore>(
+        server_list: ServerList,
+        account: &str,
+        password: &str,
+        guard_data_store: G,
+        confirmation_handler: H,
+        options: ConnectionOptions,
+    ) -> Result<Self, ConnectionError> {
+        let options = options.with_new_state();
+        match Self::connect_any(&server_list, &options).await {
+            Ok(connection) => {
+                connection
+                    .login_session(
+                        account,
+                        password,
+                        guard_data_store,
+                        confirmation_handler,
+                        &options,
+                    )
+                    .await
+            }
+            Err(e) => set_result_state(&options.state, Err(e)),
+        }
+    }
+
+    /// Log in on a connection that has completed the handshake
+    pub(crate) async fn login_session<H: AuthConfirmationHandler, G: GuardDataStore>(
+        self,
+        account: &str,
+        password: &str,
+        guard_data_store: G,
+        confirmation_handler: H,
+        options: &ConnectionOptions,
+    ) -> Result<Self, ConnectionError> {
+        let state = self.state.clone();
+        let result = self
+            .login_logon(
+                account,
+                password,
+                guard_data_store,
+                confirmation_handler,
+                options,
+            )
+            .await
+            .map(Connection::start_session);
+        set_result_state(&state, result)
+    }
+
+    /// Log in with the account credentials, without starting the heartbeat
+    pub(crate) async fn login_logon<H: AuthConfirmationHandler, G: GuardDataStore>(
+        self,
+        account: &str,
+        password: &str,
         mut guard_data_store: G,
         confirmation_handler: H,
+        options: &ConnectionOptions,
     ) -> Result<Self, ConnectionError> {
-        let mut connection = Self::connect(server_list).await?;
+        let mut connection = self;
+        connection.state.send_replace(ConnectionState::LoggingIn);
         let guard_data = guard_data_store.load(account).await.unwrap_or_else(|e| {
             error!(error = ?e, "failed to retrieve guard data");
             None
@@ -72,8 +854,14 @@ impl Connection {
         if guard_data.is_some() {
             debug!(account, "found stored guard data");
         }
-        let begin =
-            begin_password_auth(&mut connection, account, password, guard_data.as_deref()).await?;
+        let begin = begin_password_auth(
+            &mut connection,
+            account,
+            password,
+            guard_data.as_deref(),
+            &options.device_friendly_name,
+        )
+        .await?;
         let steam_id = SteamID::from(begin.steam_id());
 
         let allowed_confirmations = begin.allowed_confirmations();
@@ -110,16 +898,150 @@ impl Connection {
             steam_id,
             // yes we send the refresh token as access token, yes it makes no sense, yes this is actually required
             tokens.refresh_token.as_ref(),
+            options,
         )
         .await?;
-        connection.setup_heartbeat();
+        connection.credentials = Credentials::RefreshToken {
+            account: account.into(),
+            refresh_token: tokens.refresh_token.as_ref().into(),
+        };
+
+        Ok(connection)
+    }
+
+    /// Register a handler that is called every time the connection is re-established with [`Connection::reconnect`]
+    pub fn on_reconnect<H: ReconnectHandler>(&mut self, handler: H) {
+        self.reconnect_handlers.push(Arc::new(handler));
+    }
+
+    /// Re-establish the connection to a server from the server list and log on again with the same credentials
+    ///
+    /// If the connection isn't closed yet, it is closed first. Streams from [`Connection::on`] keep receiving
+    /// notifications from the new connection, requests that were waiting for a response on the old connection fail.
+    /// The ui mode, persona state and games played are restored first, see [`PresenceReplay`](crate::PresenceReplay).
+    /// Then the handlers registered with [`Connection::on_reconnect`] are called, before any notifications are delivered.
+    ///
+    /// If reconnecting fails, the connection stays closed and reconnecting can be retried.
+    pub async fn reconnect(&mut self, server_list: &ServerList) -> Result<(), ConnectionError> {
+        if !self.is_closed() {
+            self.write.lock().await.close().await.ok();
+            // wait for the old connection to finish, so it doesn't overwrite the state of the new connection
+            let mut state = self.state.subscribe();
+            timeout(
+                self.timeout,
+                state.wait_for(|state| matches!(state, ConnectionState::Closed { .. })),
+            )
+            .await
+            .ok();
+        }
+        let old_state = self.state.borrow().clone();
+        let error = match &old_state {
+            ConnectionState::Closed { error: Some(error) } => error.clone(),
+            _ => String::from("reconnect requested"),
+        };
+        self.state
+            .send_replace(ConnectionState::Reconnecting { error });
+
+        let (release, hold) = oneshot::channel();
+        let result = async {
+            let mut connection = self.connect_for_reconnect(server_list, hold).await?;
+            connection.state.send_replace(ConnectionState::LoggingIn);
+            connection.session = match &self.credentials {
+                Credentials::Anonymous => anonymous(&mut connection, &self.options).await?,
+                Credentials::RefreshToken {
+                    account,
+                    refresh_token,
+                } => {
+                    login(
+                        &mut connection,
+                        account,
+                        self.steam_id(),
+                        refresh_token,
+                        &self.options,
+                    )
+                    .await?
+                }
+            };
+            connection.setup_heartbeat();
+            connection.timeout = self.timeout;
+            connection.credentials = self.credentials.clone();
+            connection.reconnect_handlers = self.reconnect_handlers.clone();
+            connection.presence = self.presence.clone();
+            connection.replay_presence().await;
+
+            for handler in &connection.reconnect_handlers {
+                handler.on_reconnect(&old_state, &connection).await;
+            }
+            Ok(connection)
+        }
+        .await;
 
+        let connection = set_result_state(&self.state, result)?;
+        // the old connection shares the state with the new one, so only stop its tasks
+        std::mem::replace(self, connection).abort_tasks();
+        debug!("reconnected");
+        release.send(()).ok();
+        Ok(())
+    }
+
+    /// Connect to a server in the same cell as the current one if steam sent a list of them,
+    /// falling back to the given server list
+    async fn connect_for_reconnect(
+        &self,
+        server_list: &ServerList,
+        hold: oneshot::Receiver<()>,
+    ) -> Result<Self, ConnectionError> {
+        let scores = &self.options.server_scores;
+        let transport = self.options.transport;
+        let fallback = scores
+            .rank(server_list.urls(transport))
+            .into_iter()
+            .next()
+            .ok_or(ServerDiscoveryError::NoServers)?;
+        let preferred = self
+            .cm_list()
+            .and_then(|list| scores.rank(list.urls(transport)).into_iter().next())
+            .filter(|url| *url != fallback);
+        let (url, transport) = match preferred {
+            Some(url) => match open_transport(&url, &self.options).await {
+                Ok(transport) => (url, transport),
+                Err(e) => {
+                    debug!(url, error = ?e, "failed to connect to a server in the same cell");
+                    let transport = open_transport(&fallback, &self.options).await?;
+                    (fallback, transport)
+                }
+            },
+            None => {
+                let transport = open_transport(&fallback, &self.options).await?;
+                (fallback, transport)
+            }
+        };
+        let mut connection = Self::from_transport(
+            &url,
+            transport.read,
+            transport.write,
+            &self.options,
+            self.filter.resubscribe(),
+            Some(hold),
+        );
+        connection.local_ip = transport.local_ip;
+        connection.universe = transport.universe;
+        hello(&mut connection).await?;
         Ok(connection)
     }
 
-    fn setup_heartbeat(&self) {
+    fn setup_heartbeat(&mut self) {
+        // the session is logged on from here on, so the steam id is known
+        self.span.record("steam_id", u64::from(self.steam_id()));
         let write = self.write.clone();
         let interval = self.session.heartbeat_interval;
+        let jitter = self.options.heartbeat_jitter;
+        let liveness_heartbeats = self.options.liveness_heartbeats;
+        if liveness_heartbeats > 0 {
+            self.filter
+                .liveness_timeout
+                .send_replace(Some(interval * liveness_heartbeats));
+        }
         let header = NetMessageHeader {
             session_id: self.session.session_id,
             source_job_id: u64::MAX,
@@ -127,14 +1049,20 @@ impl Connection {
             steam_id: self.steam_id(),
             ..NetMessageHeader::default()
         };
-        spawn(async move {
+        let heartbeat = CMsgClientHeartBeat {
+            send_reply: (liveness_heartbeats > 0).then_some(true),
+            ..CMsgClientHeartBeat::default()
+        };
+        let heartbeats = async move {
             loop {
-                sleep(interval).await;
-                match RawNetMessage::from_message(header.clone(), CMsgClientHeartBeat::default()) {
+                sleep(jittered(interval, jitter)).await;
+                match RawNetMessage::from_message(header.clone(), heartbeat.clone()) {
                     Ok(msg) => {
                         let mut writer = write.lock().await;
                         if let Err(e) = writer.send(msg).await {
                             error!(error = ?e, "Failed to send heartbeat message");
+                            // the connection is closed, stop sending heartbeats for it
+                            break;
                         }
                     }
                     Err(e) => {
@@ -142,40 +1070,273 @@ impl Connection {
                     }
                 }
             }
-        });
+        }
+        .instrument(self.span.clone());
+        let task = spawn_named("steam-vent heartbeat", heartbeats);
+        self.heartbeat_task = Some(task.abort_handle());
+    }
+
+    /// Close the connection and stop its background tasks
+    pub async fn close(mut self) -> Result<()> {
+        let result = self.write.lock().await.close().await;
+        self.stop_tasks();
+        result
+    }
+
+    /// Log off the session and close the connection
+    ///
+    /// Steam closes the connection once the session is logged off, which is waited for up to `grace_period`.
+    /// After that the connection is closed from this side and the background tasks are aborted.
+    ///
+    /// The log off message has no body, steam doesn't accept a reason for logging off.
+    /// Once the message is written to the socket, the returned [`LogOffOutcome`] tells if steam confirmed
+    /// the log off by closing the connection.
+    pub async fn log_off(mut self, grace_period: Duration) -> Result<LogOffOutcome> {
+        let deadline = tokio::time::Instant::now() + grace_period;
+        let mut state = self.state();
+        // don't keep the session alive while waiting for it to end
+        if let Some(heartbeat) = self.heartbeat_task.take() {
+            heartbeat.abort();
+        }
+        let sent = timeout_at(
+            deadline,
+            self.send(self.session.header(), CMsgClientLogOff::default()),
+        )
+        .await
+        .unwrap_or(Err(NetworkError::Timeout));
+        let mut outcome = LogOffOutcome::TimedOut;
+        if sent.is_ok() {
+            let closed = state.wait_for(|state| matches!(state, ConnectionState::Closed { .. }));
+            match timeout_at(deadline, closed).await {
+                Ok(_) => outcome = LogOffOutcome::Closed,
+                Err(_) => debug!("server didn't close the connection after logging off"),
+            }
+        }
+        if !self.is_closed() {
+            timeout(Duration::from_secs(1), async {
+                self.write.lock().await.close().await
+            })
+            .await
+            .ok();
+        }
+        self.stop_tasks();
+        sent.map(|_| outcome)
+    }
+
+    /// Drop the connection but keep the background tasks running, keeping the session logged on
+    ///
+    /// Without this, dropping a logged on connection warns about the connection not being closed.
+    pub fn detach(mut self) {
+        self.closed = true;
+    }
+
+    fn stop_tasks(&mut self) {
+        self.abort_tasks();
+        if !self.is_closed() {
+            self.state
+                .send_replace(ConnectionState::Closed { error: None });
+        }
+    }
+
+    fn abort_tasks(&mut self) {
+        self.filter_task.abort();
+        if let Some(heartbeat) = self.heartbeat_task.take() {
+            heartbeat.abort();
+        }
+        self.closed = true;
+    }
+
+    /// Subscribe to changes in the state of the connection
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        matches!(*self.state.borrow(), ConnectionState::Closed { .. })
     }
 
+    /// The steam id of the session, including the instance bits
     pub fn steam_id(&self) -> SteamID {
         self.session.steam_id
     }
 
+    /// The universe of the session
+    ///
+    /// With the tcp transport this is the universe the server announced in the encryption handshake,
+    /// otherwise the universe of the steam id assigned by the server.
+    /// The default crypto provider only has the public key of the [`Universe::Public`] universe, so connecting to
+    /// servers of other universes over tcp fails with [`NetworkError::UnsupportedUniverse`].
+    pub fn universe(&self) -> Universe {
+        self.session.universe
+    }
+
+    /// The client instance id assigned to the session by the server
+    pub fn client_instance_id(&self) -> u64 {
+        self.session.client_instance_id
+    }
+
+    /// The legacy steam2 ticket returned by the logon
+    ///
+    /// This is only included when requested with [`ConnectionOptions::with_steam2_ticket_request`]
+    pub fn steam2_ticket(&self) -> Option<&[u8]> {
+        self.session.steam2_ticket.as_deref()
+    }
+
     fn prepare(&self) -> NetMessageHeader {
         self.session.header()
     }
 
     pub async fn send<Msg: NetMessage>(&self, header: NetMessageHeader, msg: Msg) -> Result<()> {
         let msg = RawNetMessage::from_message(header, msg)?;
-        self.write.lock().await.send(msg).await?;
-        Ok(())
+        self.write_raw(msg).await
     }
 
-    pub fn set_timeout(&mut self, timeout: Duration) {
-        self.timeout = timeout;
+    async fn write_raw(&self, msg: RawNetMessage) -> Result<()> {
+        write_message(&self.write, self.compression_threshold, msg, true).await
     }
 
-    pub async fn service_method<Msg: ServiceMethodRequest>(
+    /// Write a message without flushing it to the transport
+    ///
+    /// This allows sending a batch of messages at once, call [`Connection::flush`] after the last message.
+    /// The transport might still flush on its own when its buffer is full.
+    pub async fn feed<Msg: NetMessage>(&self, header: NetMessageHeader, msg: Msg) -> Result<()> {
+        let msg = RawNetMessage::from_message(header, msg)?;
+        write_message(&self.write, self.compression_threshold, msg, false).await
+    }
+
+    /// Flush the messages written with [`Connection::feed`] to the transport
+    ///
+    /// Fails with [`NetworkError::Flush`] if the transport can't be flushed.
+    pub async fn flush(&self) -> Result<()> {
+        flush_messages(&self.write).await
+    }
+
+    /// A sink for sending messages, for forwarding a stream of messages to steam
+    ///
+    /// The messages are sent without a job id, like notifications, use [`Connection::job`] for requests that expect a response.
+    /// Sending waits until the message is written to the transport, so a producer that is faster than the connection
+    /// is slowed down to its pace. Every message is flushed to the transport as it's sent.
+    ///
+    /// ```no_run
+    /// # use steam_vent::Connection;
+    /// # use steam_vent::proto::steammessages_clientserver_friends::CMsgClientFriendMsg;
+    /// # use futures_util::StreamExt;
+    /// # async fn run(connection: &Connection, messages: Vec<CMsgClientFriendMsg>) -> Result<(), steam_vent::NetworkError> {
+    /// futures_util::stream::iter(messages)
+    ///     .map(Ok)
+    ///     .forward(connection.sink())
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn sink<Msg: NetMessage + Send + 'static>(
         &self,
-        msg: Msg,
+    ) -> impl Sink<Msg, Error = NetworkError> + Send + 'static {
+        let header = NetMessageHeader {
+            session_id: self.session.session_id,
+            source_job_id: u64::MAX,
+            target_job_id: u64::MAX,
+            steam_id: self.steam_id(),
+            ..NetMessageHeader::default()
+        };
+        let write = self.write.clone();
+        let compression_threshold = self.compression_threshold;
+        futures_util::sink::unfold((), move |(), msg: Msg| {
+            let header = header.clone();
+            let write = write.clone();
+            async move {
+                let msg = RawNetMessage::from_message(header, msg)?;
+                write_message(&write, compression_threshold, msg, true).await
+            }
+        })
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub async fn service_method<Msg: ServiceMethodRequest>(
+        &self,
+        msg: Msg,
     ) -> Result<Msg::Response> {
-        let header = self.prepare();
-        let recv = self.filter.on_job_id(header.source_job_id);
-        self.send(header, ServiceMethodMessage(msg)).await?;
-        let message = timeout(self.timeout, recv)
-            .await
-            .map_err(|_| NetworkError::Timeout)?
-            .map_err(|_| NetworkError::EOF)?
-            .into_message::<ServiceMethodResponseMessage>()?;
-        message.into_response::<Msg>()
+        let msg = ServiceMethodMessage(msg);
+        let raw = match &self.dedup {
+            Some(dedup) if is_read_only(ServiceMethodMessage::<Msg>::KIND, Some(Msg::REQ_NAME)) => {
+                let key = (
+                    ServiceMethodMessage::<Msg>::KIND,
+                    Some(Msg::REQ_NAME),
+                    msg.to_bytes()?,
+                );
+                dedup.run(key, self.job_raw(msg)).await?
+            }
+            _ => self.job_raw(msg).await?,
+        };
+        raw.into_message::<ServiceMethodResponseMessage>()?
+            .into_response::<Msg>()
+    }
+
+    /// Send a message and wait for the response with the matching job id
+    pub async fn job<Msg: NetMessage, Response: NetMessage>(&self, msg: Msg) -> Result<Response> {
+        let raw = match &self.dedup {
+            Some(dedup) if is_read_only(Msg::KIND, None) => {
+                let key = (Msg::KIND, None, msg.to_bytes()?);
+                dedup.run(key, self.job_raw(msg)).await?
+            }
+            _ => self.job_raw(msg).await?,
+        };
+        raw.into_message::<Response>()
+    }
+
+    /// Send a message routed to the services of an app on the CM
+    ///
+    /// Some in-game features are handled by the CM per game instead of by the game coordinator,
+    /// these messages need the app they are for in the header. This is the case for the lobby
+    /// and matchmaking messages (`ClientMMS*`, e.g. [`CMsgClientMMSCreateLobby`](crate::proto::steammessages_clientserver_mms::CMsgClientMMSCreateLobby)),
+    /// sending them with [`Connection::send`] makes the CM ignore them. Other messages don't need it.
+    pub async fn send_for_app<Msg: NetMessage>(&self, app_id: u32, msg: Msg) -> Result<()> {
+        let header = NetMessageHeader {
+            routing_app_id: Some(app_id),
+            ..self.prepare()
+        };
+        self.send(header, msg).await
+    }
+
+    /// Like [`Connection::job`] but route the message to the services of an app, see [`Connection::send_for_app`]
+    pub async fn job_for_app<Msg: NetMessage, Response: NetMessage>(
+        &self,
+        app_id: u32,
+        msg: Msg,
+    ) -> Result<Response> {
+        let header = NetMessageHeader {
+            routing_app_id: Some(app_id),
+            ..self.prepare()
+        };
+        self.job_raw_with_header(header, msg)
+            .await?
+            .into_message::<Response>()
+    }
+
+    async fn job_raw<Msg: NetMessage>(&self, msg: Msg) -> Result<RawNetMessage> {
+        self.job_raw_with_header(self.prepare(), msg).await
+    }
+
+    async fn job_raw_with_header<Msg: NetMessage>(
+        &self,
+        header: NetMessageHeader,
+        msg: Msg,
+    ) -> Result<RawNetMessage> {
+        let header = header.with_awaits_response();
+        let span = debug_span!(parent: &self.span, "job", job_id = header.source_job_id, kind = ?Msg::KIND);
+        async {
+            let recv = self.filter.on_job_id(header.source_job_id);
+            self.send(header, msg).await?;
+            timeout(self.timeout, recv)
+                .await
+                .map_err(|_| NetworkError::Timeout)?
+                .map_err(|_| NetworkError::EOF)
+        }
+        .instrument(span)
+        .await
     }
 
     pub(crate) async fn service_method_un_authenticated<Msg: ServiceMethodRequest>(
@@ -189,7 +1350,7 @@ impl Connection {
             ServiceMethodMessage(msg),
             EMsg::k_EMsgServiceMethodCallFromClientNonAuthed,
         )?;
-        self.write.lock().await.send(msg).await?;
+        self.write_raw(msg).await?;
         let message = timeout(self.timeout, recv)
             .await
             .map_err(|_| NetworkError::Timeout)?
@@ -199,7 +1360,105 @@ impl Connection {
     }
 
     pub async fn next(&mut self) -> Result<RawNetMessage> {
-        self.rest.recv().await.ok_or(NetworkError::EOF)?
+        let message = self.rest.recv().await.ok_or(NetworkError::EOF)??;
+        self.filter
+            .unread_bytes
+            .fetch_sub(message.encoded_len(), Ordering::Relaxed);
+        Ok(message)
+    }
+
+    /// The number of messages dropped because the queue for [`Connection::next`] was full
+    ///
+    /// Messages are only dropped when enabled with [`ConnectionOptions::with_receive_queue`]
+    pub fn dropped_messages(&self) -> u64 {
+        self.rest.dropped()
+    }
+
+    /// The number and sizes of the messages sent and received per kind
+    ///
+    /// The counts include the messages of the connections before reconnecting.
+    pub fn stats(&self) -> ConnectionStats {
+        self.filter.metrics.snapshot()
+    }
+
+    /// Only deliver messages of the given kinds from [`Connection::next`] and the methods built on it
+    ///
+    /// Other messages are dropped when they are routed, which saves buffering messages that the
+    /// application would ignore anyway. Only their header is read, to route the responses to requests,
+    /// their body isn't decoded or copied. Responses to requests and the messages waited for with [`Connection::on`]
+    /// or [`Connection::one`] aren't affected. Multi messages don't need to be included,
+    /// they are always unpacked and their content is filtered like other messages.
+    ///
+    /// The allowed kinds can be changed at any time, calling this again replaces the previous list.
+    pub fn set_allowed_kinds(&self, kinds: impl IntoIterator<Item = EMsg>) {
+        self.filter
+            .allowed_kinds
+            .send_replace(Some(kinds.into_iter().collect()));
+    }
+
+    /// Remove the list of allowed kinds set with [`Connection::set_allowed_kinds`], delivering all messages again
+    pub fn allow_all_kinds(&self) {
+        self.filter.allowed_kinds.send_replace(None);
+    }
+
+    /// The number of bytes in received messages that are waiting to be read with [`Connection::next`]
+    ///
+    /// This can be used to detect that the application isn't keeping up with the incoming messages.
+    pub fn buffered_len(&self) -> usize {
+        self.filter.unread_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Send an already encoded message (header and body), bypassing all message encoding
+    ///
+    /// **Unstable**: this is intended for protocol research and might change or be removed in any release.
+    ///
+    /// The message is still encrypted by the transport if the connection is encrypted,
+    /// but the header is sent as-is, so it has to contain the correct session id and steam id.
+    pub async fn send_raw_frame(&self, frame: BytesMut) -> Result<()> {
+        let msg = RawNetMessage::read(frame)?;
+        self.write.lock().await.send(msg).await
+    }
+
+    /// Create a connection that receives the captured frames instead of connecting to a server
+    ///
+    /// **Unstable**: this is intended for debugging and might change or be removed in any release.
+    ///
+    /// Each frame is the encoded message (header and body) as received from [`Connection::recv_raw_frame`]
+    /// together with the kind it was captured as. The frames go through the same decoding and routing as
+    /// messages from a server, so they can be read with [`Connection::next`], [`Connection::one`] or
+    /// the other listeners. A frame that fails to decode, or decodes to a different kind, is delivered
+    /// as an error from [`Connection::next`]. Messages sent over the connection are discarded.
+    ///
+    /// This has to be called from within a tokio runtime.
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn replay(frames: impl IntoIterator<Item = (EMsg, Vec<u8>)>) -> Connection {
+        let frames: Vec<_> = frames.into_iter().collect();
+        let decoded = tokio_stream::iter(frames).map(|(kind, frame)| {
+            let message = RawNetMessage::read(BytesMut::from(frame.as_slice()))?;
+            if message.kind != kind {
+                return Err(NetworkError::DifferentMessage(kind, message.kind));
+            }
+            Ok(message)
+        });
+        let read = crate::message::flatten_multi(decoded);
+        let write = futures_util::sink::drain().sink_map_err(|never| match never {});
+        Self::from_transport(
+            "replay",
+            Box::pin(read),
+            write,
+            &ConnectionOptions::default(),
+            MessageFilter::default(),
+            None,
+        )
+    }
+
+    /// Receive the next message not handled by any other listener as encoded bytes (header and body)
+    ///
+    /// **Unstable**: this is intended for protocol research and might change or be removed in any release.
+    ///
+    /// The message is already decrypted, and multi messages are already expanded.
+    pub async fn recv_raw_frame(&mut self) -> Result<BytesMut> {
+        Ok(self.next().await?.into_bytes())
     }
 
     pub fn on<T: ServiceMethodRequest>(&self) -> impl Stream<Item = Result<T>> {
@@ -208,6 +1467,151 @@ impl Connection {
             .map(|raw| raw.into_notification())
     }
 
+    /// Respond to calls of a service method made by steam
+    ///
+    /// The handler receives the request and the returned response is sent back to steam.
+    /// Registering a handler for a method replaces the previous one,
+    /// the calls are still delivered to the listeners from [`Connection::on`] as well.
+    pub fn handle_service_method<T, F>(&self, handler: F)
+    where
+        T: ServiceMethodRequest + 'static,
+        F: Fn(T) -> T::Response + Send + Sync + 'static,
+    {
+        self.filter.method_handlers.insert(
+            T::REQ_NAME,
+            Arc::new(move |call: ServiceMethodNotification| {
+                let request = call.into_notification::<T>()?;
+                ServiceMethodResponseMessage::from_response::<T>(&handler(request))
+            }),
+        );
+    }
+
+    pub(crate) fn personas(&self) -> &watch::Sender<Personas> {
+        &self.filter.personas
+    }
+
+    pub(crate) fn machine_auth(&self) -> &watch::Sender<Option<RawNetMessage>> {
+        &self.filter.machine_auth
+    }
+
+    pub(crate) fn gc_handlers(&self) -> &GcHandlers {
+        &self.filter.gc_handlers
+    }
+
+    /// Like [`Connection::on`] but include the time the notification was received
+    ///
+    /// The time is only recorded when enabled with [`ConnectionOptions::with_receive_timestamps`]
+    pub fn on_timestamped<T: ServiceMethodRequest>(
+        &self,
+    ) -> impl Stream<Item = Result<(T, Option<Instant>)>> {
+        BroadcastStream::new(self.filter.on_notification(T::REQ_NAME))
+            .filter_map(|res| res.ok())
+            .map(|raw| {
+                let received_at = raw.received_at;
+                Ok((raw.into_notification()?, received_at))
+            })
+    }
+
+    /// The cell (region) steam assigned to the session during logon
+    pub fn cell_id(&self) -> u32 {
+        self.session.cell_id
+    }
+
+    /// The servers steam suggested for the cell of the session, as sent after logging on
+    ///
+    /// This is `None` until the list is received. [`Connection::reconnect`] prefers these servers,
+    /// to stay in the same region as the current server.
+    pub fn cm_list(&self) -> Option<ServerList> {
+        self.filter.cm_list.borrow().clone()
+    }
+
+    /// The last login key sent by steam during the session
+    ///
+    /// Steam can rotate the login key at any time, the new key is accepted automatically and also delivered
+    /// as [`Notification::NewLoginKey`](crate::Notification::NewLoginKey) so it can be persisted.
+    pub fn login_key(&self) -> Option<String> {
+        self.filter.login_key.borrow().clone()
+    }
+
+    /// Get the server types that steam has announced as available for this connection
+    pub fn available_services(&self) -> HashSet<u32> {
+        self.filter.servers_available.borrow().clone()
+    }
+
+    /// The wallet of the account, as last sent by steam
+    ///
+    /// Steam sends the wallet info shortly after logging on and whenever it changes,
+    /// this is `None` until the first update is received.
+    /// The updates are also delivered as [`Notification::Wallet`](crate::Notification::Wallet).
+    pub fn wallet(&self) -> Option<Wallet> {
+        *self.filter.wallet.borrow()
+    }
+
+    /// The restrictions on the account, as sent by steam after logging on
+    ///
+    /// This is `None` until the limits are received, the limits are also delivered as
+    /// [`Notification::AccountLimits`](crate::Notification::AccountLimits).
+    pub fn account_limits(&self) -> Option<AccountLimits> {
+        *self.filter.account_limits.borrow()
+    }
+
+    /// The VAC bans on the account, as sent by steam after logging on
+    ///
+    /// This is `None` until the ban status is received, the status is also delivered as
+    /// [`Notification::VacBanStatus`](crate::Notification::VacBanStatus).
+    pub fn vac_bans(&self) -> Option<VacBanStatus> {
+        self.filter.vac_bans.borrow().clone()
+    }
+
+    /// The categories the user has sorted their friends into
+    ///
+    /// The groups are kept up to date with the updates from steam, which are also delivered as
+    /// [`Notification::FriendGroups`](crate::Notification::FriendGroups).
+    pub fn friend_groups(&self) -> FriendGroups {
+        self.filter.friend_groups.borrow().clone()
+    }
+
+    /// The nicknames the user has given to other users
+    ///
+    /// The nicknames are kept up to date with the updates from steam, which are also delivered as
+    /// [`Notification::Nicknames`](crate::Notification::Nicknames).
+    pub fn nicknames(&self) -> Nicknames {
+        self.filter.nicknames.borrow().clone()
+    }
+
+    /// The state of a clan, combined from all updates received for it
+    ///
+    /// This is `None` if steam hasn't sent the state of the clan, the updates are also delivered as
+    /// [`Notification::ClanState`](crate::Notification::ClanState).
+    pub fn clan_state(&self, steam_id: SteamID) -> Option<ClanState> {
+        self.filter.clans.borrow().get(steam_id).cloned()
+    }
+
+    /// Wait until steam announces that all the requested server types are available
+    ///
+    /// Server types are the `EServerType` values from `CMsgClientServersAvailable`,
+    /// requests to a server type before it's available can fail with "not logged on" style errors.
+    pub async fn wait_for_services(&self, service_types: &[u32]) -> Result<()> {
+        let mut available = self.filter.servers_available.subscribe();
+        timeout(
+            self.timeout,
+            available.wait_for(|available| {
+                service_types
+                    .iter()
+                    .all(|service_type| available.contains(service_type))
+            }),
+        )
+        .await
+        .map_err(|_| NetworkError::Timeout)?
+        .map_err(|_| NetworkError::EOF)?;
+        Ok(())
+    }
+
+    /// Receive all messages of a kind, for responses that can only be matched to their request by their content
+    pub(crate) fn on_kind(&self, kind: EMsg) -> broadcast::Receiver<RawNetMessage> {
+        self.filter.on_kind(kind)
+    }
+
     pub fn one<T: NetMessage>(&self) -> impl Future<Output = Result<(NetMessageHeader, T)>> {
         // async block instead of async fn so we don't have to tie the lifetime of the returned future
         // to the lifetime of &self
@@ -219,31 +1623,395 @@ impl Connection {
     }
 }
 
+/// Write a message to the transport, compressing it if it's larger than the threshold
+///
+/// The message is flushed to the transport afterwards if `flush` is set.
+async fn write_message(
+    write: &SharedSink,
+    compression_threshold: Option<usize>,
+    msg: RawNetMessage,
+    flush: bool,
+) -> Result<()> {
+    let msg = match compression_threshold {
+        Some(threshold) if msg.header_buffer.len() + msg.data.len() > threshold => {
+            compress_multi(msg)?
+        }
+        _ => msg,
+    };
+    let mut write = write.lock().await;
+    write.feed(msg).await?;
+    if flush {
+        write
+            .flush()
+            .await
+            .map_err(|e| NetworkError::Flush(Box::new(e)))?;
+    }
+    Ok(())
+}
+
+async fn flush_messages(write: &SharedSink) -> Result<()> {
+    write
+        .lock()
+        .await
+        .flush()
+        .await
+        .map_err(|e| NetworkError::Flush(Box::new(e)))
+}
+
+/// Randomly vary the interval by up to `jitter` times the interval in either direction
+fn jittered(interval: Duration, jitter: f64) -> Duration {
+    if jitter <= 0.0 {
+        return interval;
+    }
+    interval.mul_f64(1.0 + thread_rng().gen_range(-jitter..=jitter))
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        // before logging on there is no heartbeat keeping the connection open
+        if self.heartbeat_task.is_some() {
+            warn!("connection dropped without calling close, use Connection::detach to keep the session running");
+        }
+        self.stop_tasks();
+    }
+}
+
+/// Open the connection to the server and perform the encryption handshake, giving up after the handshake timeout
+async fn open_transport(
+    addr: &str,
+    options: &ConnectionOptions,
+) -> Result<EncryptedTransport, NetworkError> {
+    let transport = open_tcp(addr, options).await?;
+    encrypt_transport(transport, options).await
+}
+
+/// The open tcp connection of a [`PendingTransport`]
+#[derive(Debug)]
+enum PendingStream {
+    WebSocket(Box<TcpConnection>),
+    Tcp(TcpStream),
+}
+
+/// A tcp connection to a server that still needs the encryption handshake
+#[derive(Debug)]
+pub(crate) struct PendingTransport {
+    pub(crate) addr: String,
+    stream: PendingStream,
+    started: Instant,
+}
+
+impl PendingTransport {
+    fn local_ip(&self) -> Option<IpAddr> {
+        match &self.stream {
+            PendingStream::WebSocket(tcp) => tcp.local_ip(),
+            PendingStream::Tcp(stream) => stream.local_addr().ok().map(|addr| addr.ip()),
+        }
+    }
+
+    /// Take the socket of a [`Transport::Tcp`] connection, other transports are returned as is
+    pub(crate) fn into_tcp_stream(self) -> Result<TcpStream, Self> {
+        match self.stream {
+            PendingStream::Tcp(stream) => Ok(stream),
+            stream => Err(PendingTransport { stream, ..self }),
+        }
+    }
+}
+
+/// A connection to a server that completed the encryption handshake
+struct EncryptedTransport {
+    read: Pin<Box<dyn Stream<Item = Result<RawNetMessage>> + Send>>,
+    write: Pin<Box<dyn Sink<RawNetMessage, Error = NetworkError> + Send>>,
+    /// The local ip address of the connection, sent to steam during logon
+    local_ip: Option<IpAddr>,
+    /// The universe announced by the server during the encryption handshake
+    universe: Option<Universe>,
+}
+
+/// Open the tcp connection to the server, giving up after the handshake timeout
+pub(crate) async fn open_tcp(
+    addr: &str,
+    options: &ConnectionOptions,
+) -> Result<PendingTransport, NetworkError> {
+    let started = Instant::now();
+    let connect = async {
+        match options.transport {
+            Transport::WebSocket => websocket::connect_tcp(
+                addr,
+                options.nodelay,
+                options.connect_timeout,
+                options.proxy.as_ref(),
+                &options.resolver,
+            )
+            .await
+            .map(|tcp| PendingStream::WebSocket(Box::new(tcp))),
+            Transport::Tcp => tcp::connect_tcp(
+                addr,
+                options.nodelay,
+                options.connect_timeout,
+                options.proxy.as_ref(),
+                &options.resolver,
+            )
+            .await
+            .map(PendingStream::Tcp),
+        }
+    };
+    let result = timeout(options.handshake_timeout, connect)
+        .await
+        .map_err(|_| NetworkError::Timeout)
+        .and_then(|result| result);
+    match result {
+        Ok(stream) => Ok(PendingTransport {
+            addr: addr.into(),
+            stream,
+            started,
+        }),
+        Err(e) => {
+            options.server_scores.record_failure(addr);
+            Err(e)
+        }
+    }
+}
+
+/// Perform the encryption handshake, giving up once the handshake timeout since opening the tcp connection passed
+async fn encrypt_transport(
+    transport: PendingTransport,
+    options: &ConnectionOptions,
+) -> Result<EncryptedTransport, NetworkError> {
+    let local_ip = transport.local_ip();
+    let PendingTransport {
+        addr,
+        stream,
+        started,
+    } = transport;
+    let encrypt = async {
+        match stream {
+            PendingStream::WebSocket(tcp) => {
+                let (read, write) = websocket::encrypt(
+                    *tcp,
+                    options.accept_invalid_certs,
+                    options.bandwidth_limits(),
+                )
+                .await?;
+                Ok(EncryptedTransport {
+                    read: Box::pin(read),
+                    write: Box::pin(write),
+                    local_ip,
+                    universe: None,
+                })
+            }
+            PendingStream::Tcp(stream) => {
+                let stream = options.bandwidth_limits().wrap(stream);
+                let (info, read, write) = tcp::encrypt(stream, options.crypto.clone()).await?;
+                debug!(protocol = info.protocol, universe = ?info.universe, "encrypted channel established");
+                Ok(EncryptedTransport {
+                    read: Box::pin(read),
+                    write: Box::pin(write),
+                    local_ip,
+                    universe: Some(info.universe),
+                })
+            }
+        }
+    };
+    let result = timeout_at((started + options.handshake_timeout).into(), encrypt)
+        .await
+        .map_err(|_| NetworkError::Timeout)
+        .and_then(|result| result);
+    match &result {
+        Ok(_) => options
+            .server_scores
+            .record_success(&addr, started.elapsed()),
+        Err(_) => options.server_scores.record_failure(&addr),
+    }
+    result
+}
+
+/// Set the state to closed if a connection step failed
+pub(crate) fn set_error_state<T>(
+    state: &watch::Sender<ConnectionState>,
+    result: Result<T, ConnectionError>,
+) -> Result<T, ConnectionError> {
+    if let Err(e) = &result {
+        state.send_replace(ConnectionState::Closed {
+            error: Some(e.to_string()),
+        });
+    }
+    result
+}
+
+fn set_result_state(
+    state: &watch::Sender<ConnectionState>,
+    result: Result<Connection, ConnectionError>,
+) -> Result<Connection, ConnectionError> {
+    let result = set_error_state(state, result);
+    if result.is_ok() {
+        state.send_replace(ConnectionState::Connected);
+    }
+    result
+}
+
+/// Handler for a service method called by steam, producing the encoded response
+type MethodHandler =
+    Arc<dyn Fn(ServiceMethodNotification) -> Result<ServiceMethodResponseMessage> + Send + Sync>;
+
 #[derive(Clone)]
 struct MessageFilter {
     job_id_filters: Arc<DashMap<u64, oneshot::Sender<RawNetMessage>>>,
     notification_filters: Arc<DashMap<&'static str, broadcast::Sender<ServiceMethodNotification>>>,
+    method_handlers: Arc<DashMap<&'static str, MethodHandler>>,
+    gc_handlers: GcHandlers,
     kind_filters: Arc<DashMap<EMsg, broadcast::Sender<RawNetMessage>>>,
     oneshot_kind_filters: Arc<DashMap<EMsg, oneshot::Sender<RawNetMessage>>>,
+    servers_available: watch::Sender<HashSet<u32>>,
+    wallet: watch::Sender<Option<Wallet>>,
+    account_limits: watch::Sender<Option<AccountLimits>>,
+    vac_bans: watch::Sender<Option<VacBanStatus>>,
+    friend_groups: watch::Sender<FriendGroups>,
+    clans: watch::Sender<Clans>,
+    nicknames: watch::Sender<Nicknames>,
+    /// The persona data received from steam, see [`Connection::get_persona`]
+    personas: watch::Sender<Personas>,
+    metrics: Arc<MessageMetrics>,
+    /// The kinds of messages delivered to [`Connection::next`], all kinds if `None`
+    allowed_kinds: watch::Sender<Option<HashSet<EMsg>>>,
+    /// Size of the messages waiting to be read with [`Connection::next`]
+    unread_bytes: Arc<AtomicUsize>,
+    /// Set while the queue for [`Connection::next`] is full and messages are dropped, to only warn once
+    queue_overflowing: Arc<AtomicBool>,
+    /// How long the connection can go without receiving anything before it's considered dead
+    liveness_timeout: watch::Sender<Option<Duration>>,
+    /// The servers steam suggested for the cell of the session
+    cm_list: watch::Sender<Option<ServerList>>,
+    login_key: watch::Sender<Option<String>>,
+    /// The last sentry file update that isn't handled yet, see [`Connection::handle_machine_auth`]
+    machine_auth: watch::Sender<Option<RawNetMessage>>,
 }
 
-impl MessageFilter {
-    pub fn new<Input: Stream<Item = Result<RawNetMessage>> + Send + Unpin + 'static>(
-        mut source: Input,
-    ) -> (Self, mpsc::Receiver<Result<RawNetMessage>>) {
-        let (rest_tx, rx) = mpsc::channel(16);
-        let filter = MessageFilter {
+impl Default for MessageFilter {
+    fn default() -> Self {
+        MessageFilter {
             job_id_filters: Default::default(),
             kind_filters: Default::default(),
             notification_filters: Default::default(),
+            method_handlers: Default::default(),
+            gc_handlers: Default::default(),
             oneshot_kind_filters: Default::default(),
+            servers_available: watch::channel(HashSet::new()).0,
+            wallet: watch::channel(None).0,
+            account_limits: watch::channel(None).0,
+            vac_bans: watch::channel(None).0,
+            friend_groups: watch::channel(FriendGroups::default()).0,
+            clans: watch::channel(Clans::default()).0,
+            nicknames: watch::channel(Nicknames::default()).0,
+            personas: watch::channel(Personas::default()).0,
+            metrics: Default::default(),
+            allowed_kinds: watch::channel(None).0,
+            unread_bytes: Default::default(),
+            queue_overflowing: Default::default(),
+            liveness_timeout: watch::channel(None).0,
+            cm_list: watch::channel(None).0,
+            login_key: watch::channel(None).0,
+            machine_auth: watch::channel(None).0,
+        }
+    }
+}
+
+/// Wait for the next message from the source, failing with the liveness timeout if nothing arrives in time
+///
+/// A change of the liveness timeout applies right away, restarting the wait with the new timeout.
+async fn next_within<S: Stream + Unpin>(
+    source: &mut S,
+    liveness_timeout: &mut watch::Receiver<Option<Duration>>,
+) -> Result<Option<S::Item>, Duration> {
+    loop {
+        let current = *liveness_timeout.borrow_and_update();
+        let next = async {
+            match current {
+                Some(current) => timeout(current, source.next()).await.map_err(|_| current),
+                None => Ok(source.next().await),
+            }
         };
+        let mut next = pin!(next);
+        match select(next.as_mut(), pin!(liveness_timeout.changed())).await {
+            Either::Left((next, _)) => return next,
+            Either::Right((Ok(()), _)) => continue,
+            // the timeout can't change anymore
+            Either::Right((Err(_), _)) => return next.await,
+        }
+    }
+}
+
+impl MessageFilter {
+    /// Start routing the messages from the source
+    ///
+    /// If `hold` is set, only responses are routed until it resolves,
+    /// other messages are kept back and delivered in order afterwards.
+    /// Responses to service methods called by steam are sent to `write`.
+    pub fn spawn<Input: Stream<Item = Result<RawNetMessage>> + Send + Unpin + 'static>(
+        &self,
+        mut source: Input,
+        write: SharedSink,
+        options: &ConnectionOptions,
+        hold: Option<oneshot::Receiver<()>>,
+    ) -> (QueueReceiver<Result<RawNetMessage>>, AbortHandle) {
+        let state = options.state.clone();
+        let receive_timestamps = options.receive_timestamps;
+        let (rest_tx, rx) = queue(options.receive_queue_size, options.receive_queue_policy);
 
-        let filter_send = filter.clone();
-        spawn(async move {
-            while let Some(res) = source.next().await {
-                if let Ok(message) = res {
+        let held = hold.map(|hold| {
+            let (held_tx, mut held_rx) = mpsc::unbounded_channel();
+            let filter = self.clone();
+            let rest_tx = rest_tx.clone();
+            let write = write.clone();
+            spawn_named(
+                "steam-vent held messages",
+                async move {
+                    hold.await.ok();
+                    while let Some(res) = held_rx.recv().await {
+                        filter.dispatch(res, &rest_tx, &write).await;
+                    }
+                }
+                .in_current_span(),
+            );
+            held_tx
+        });
+
+        let filter_send = self.clone();
+        let mut liveness_timeout = self.liveness_timeout.subscribe();
+        let read_loop = async move {
+            let mut last_error = None;
+            loop {
+                let res = match next_within(&mut source, &mut liveness_timeout).await {
+                    Ok(Some(res)) => res,
+                    Ok(None) => break,
+                    Err(liveness_timeout) => {
+                        warn!("no messages received from the server, closing the connection");
+                        last_error = Some(format!(
+                            "no messages received from the server for {}s",
+                            liveness_timeout.as_secs()
+                        ));
+                        // the server isn't responding, so don't wait for it to acknowledge the close
+                        timeout(Duration::from_secs(1), async {
+                            write.lock().await.close().await
+                        })
+                        .await
+                        .ok();
+                        break;
+                    }
+                };
+                if let Ok(mut message) = res {
+                    if receive_timestamps {
+                        message.received_at = Some(Instant::now());
+                    }
+                    filter_send
+                        .metrics
+                        .record_inbound(message.kind, message.encoded_len());
                     debug!(job_id = message.header.target_job_id, kind = ?message.kind, "processing message");
+                    filter_send
+                        .update_cache(&message, &write)
+                        .await;
                     if let Some((_, tx)) = filter_send
                         .job_id_filters
                         .remove(&message.header.target_job_id)
@@ -253,32 +2021,206 @@ impl MessageFilter {
                         filter_send.oneshot_kind_filters.remove(&message.kind)
                     {
                         tx.send(message).ok();
-                    } else if message.kind == EMsg::k_EMsgServiceMethod {
-                        if let Ok(notification) =
-                            message.into_message::<ServiceMethodNotification>()
-                        {
-                            debug!(
-                                job_name = notification.job_name.as_str(),
-                                "processing notification"
-                            );
-                            if let Some(tx) = filter_send
-                                .notification_filters
-                                .get(notification.job_name.as_str())
-                            {
-                                tx.send(notification).ok();
-                            }
-                        }
-                    } else if let Some(tx) = filter_send.kind_filters.get(&message.kind) {
-                        tx.send(message).ok();
+                    } else if let Some(held) = &held {
+                        held.send(Ok(message)).ok();
                     } else {
-                        rest_tx.send(Ok(message)).await.ok();
+                        filter_send.dispatch(Ok(message), &rest_tx, &write).await;
                     }
                 } else {
-                    rest_tx.send(res).await.ok();
+                    if let Err(e) = &res {
+                        last_error = Some(e.to_string());
+                    }
+                    if let Some(held) = &held {
+                        held.send(res).ok();
+                    } else {
+                        filter_send.enqueue(res, &rest_tx).await;
+                    }
                 }
             }
-        });
-        (filter, rx)
+            debug!("connection closed");
+            state.send_replace(ConnectionState::Closed { error: last_error });
+        }
+        .in_current_span();
+        let task = spawn_named("steam-vent read loop", read_loop);
+        (rx, task.abort_handle())
+    }
+
+    /// Deliver a message that isn't a response to the notification and kind listeners, or the remaining messages
+    async fn dispatch(
+        &self,
+        res: Result<RawNetMessage>,
+        rest_tx: &QueueSender<Result<RawNetMessage>>,
+        write: &SharedSink,
+    ) {
+        match res {
+            Ok(message) if message.kind == EMsg::k_EMsgServiceMethod => {
+                // chat room messages are also delivered as notification, like friend messages,
+                // check the allowed kinds first to not copy messages that would be dropped anyway
+                if message.header.target_job_name.as_deref()
+                    == Some(CChatRoom_IncomingChatMessage_Notification::REQ_NAME)
+                    && (self.kind_filters.contains_key(&message.kind)
+                        || self.is_allowed(message.kind))
+                {
+                    self.deliver(message.clone(), rest_tx).await;
+                }
+                // only decode service methods somebody handles or listens to
+                let handled = message
+                    .header
+                    .target_job_name
+                    .as_deref()
+                    .is_some_and(|job_name| {
+                        self.notification_filters.contains_key(job_name)
+                            || self.method_handlers.contains_key(job_name)
+                    });
+                if !handled {
+                    trace!(job_name = ?message.header.target_job_name, "dropping unhandled notification");
+                    return;
+                }
+                let received_at = message.received_at;
+                let header = message.header.clone();
+                if let Ok(mut notification) = message.into_message::<ServiceMethodNotification>() {
+                    notification.received_at = received_at;
+                    debug!(
+                        job_name = notification.job_name.as_str(),
+                        "processing notification"
+                    );
+                    self.respond_to_method(&header, &notification, write).await;
+                    if let Some(tx) = self
+                        .notification_filters
+                        .get(notification.job_name.as_str())
+                    {
+                        tx.send(notification).ok();
+                    }
+                }
+            }
+            Ok(message)
+                if message.kind == EMsg::k_EMsgClientFromGC
+                    && dispatch_gc(&self.gc_handlers, &message) => {}
+            Ok(message) => self.deliver(message, rest_tx).await,
+            Err(e) => {
+                self.enqueue(Err(e), rest_tx).await;
+            }
+        }
+    }
+
+    /// Deliver a message to the kind listeners, or the remaining messages if it's allowed
+    async fn deliver(&self, message: RawNetMessage, rest_tx: &QueueSender<Result<RawNetMessage>>) {
+        // listeners that are gone don't keep messages of their kind from the queue
+        let listener = self
+            .kind_filters
+            .get(&message.kind)
+            .filter(|tx| tx.receiver_count() > 0);
+        if let Some(tx) = listener {
+            tx.send(message).ok();
+        } else if !self.is_allowed(message.kind) {
+            trace!(kind = ?message.kind, "dropping message of kind that isn't allowed");
+        } else {
+            self.enqueue(Ok(message), rest_tx).await;
+        }
+    }
+
+    /// Add a message to the queue read by [`Connection::next`], keeping track of the size of the queued messages
+    async fn enqueue(
+        &self,
+        res: Result<RawNetMessage>,
+        rest_tx: &QueueSender<Result<RawNetMessage>>,
+    ) {
+        if let Ok(message) = &res {
+            self.unread_bytes
+                .fetch_add(message.encoded_len(), Ordering::Relaxed);
+        }
+        let removed = match rest_tx.send(res).await {
+            Ok(Some(dropped)) => {
+                if !self.queue_overflowing.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        dropped = rest_tx.dropped(),
+                        "receive queue is full, dropping messages until there is room"
+                    );
+                }
+                dropped
+            }
+            Ok(None) => {
+                if self.queue_overflowing.swap(false, Ordering::Relaxed) {
+                    debug!(dropped = rest_tx.dropped(), "receive queue has room again");
+                }
+                return;
+            }
+            Err(unsent) => unsent,
+        };
+        if let Ok(message) = removed {
+            self.unread_bytes
+                .fetch_sub(message.encoded_len(), Ordering::Relaxed);
+        }
+    }
+
+    /// A filter for a new connection that keeps the notification and kind listeners of this filter
+    fn resubscribe(&self) -> Self {
+        self.servers_available.send_replace(HashSet::new());
+        MessageFilter {
+            job_id_filters: Default::default(),
+            oneshot_kind_filters: Default::default(),
+            notification_filters: self.notification_filters.clone(),
+            method_handlers: self.method_handlers.clone(),
+            gc_handlers: self.gc_handlers.clone(),
+            kind_filters: self.kind_filters.clone(),
+            servers_available: self.servers_available.clone(),
+            wallet: self.wallet.clone(),
+            account_limits: self.account_limits.clone(),
+            vac_bans: self.vac_bans.clone(),
+            friend_groups: self.friend_groups.clone(),
+            clans: self.clans.clone(),
+            nicknames: self.nicknames.clone(),
+            personas: self.personas.clone(),
+            metrics: self.metrics.clone(),
+            allowed_kinds: self.allowed_kinds.clone(),
+            unread_bytes: Default::default(),
+            queue_overflowing: Default::default(),
+            liveness_timeout: watch::channel(None).0,
+            cm_list: self.cm_list.clone(),
+            login_key: self.login_key.clone(),
+            // the update has to be acknowledged on the connection it was received on
+            machine_auth: watch::channel(None).0,
+        }
+    }
+
+    fn is_allowed(&self, kind: EMsg) -> bool {
+        match &*self.allowed_kinds.borrow() {
+            Some(allowed) => allowed.contains(&kind),
+            None => true,
+        }
+    }
+
+    /// Run the registered handler for a service method called by steam and send back the response
+    async fn respond_to_method(
+        &self,
+        header: &NetMessageHeader,
+        call: &ServiceMethodNotification,
+        write: &SharedSink,
+    ) {
+        // clone the handler so the map isn't locked while it runs
+        let Some(handler) = self
+            .method_handlers
+            .get(call.job_name.as_str())
+            .map(|handler| handler.clone())
+        else {
+            return;
+        };
+        let response_header = NetMessageHeader {
+            source_job_id: u64::MAX,
+            target_job_id: header.source_job_id,
+            steam_id: header.steam_id,
+            session_id: header.session_id,
+            ..NetMessageHeader::default()
+        };
+        let result = handler(call.clone())
+            .and_then(|response| RawNetMessage::from_message(response_header, response));
+        let result = match result {
+            Ok(response) => write.lock().await.send(response).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!(error = ?e, job_name = call.job_name.as_str(), "failed to respond to service method");
+        }
     }
 
     pub fn on_job_id(&self, id: u64) -> oneshot::Receiver<RawNetMessage> {
@@ -298,9 +2240,676 @@ impl MessageFilter {
         tx.subscribe()
     }
 
+    pub fn on_kind(&self, kind: EMsg) -> broadcast::Receiver<RawNetMessage> {
+        let tx = self
+            .kind_filters
+            .entry(kind)
+            .or_insert_with(|| broadcast::channel(16).0);
+        tx.subscribe()
+    }
+
     pub fn one_kind(&self, kind: EMsg) -> oneshot::Receiver<RawNetMessage> {
         let (tx, rx) = oneshot::channel();
         self.oneshot_kind_filters.insert(kind, tx);
         rx
     }
 }
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_filter_hold() {
+    let message = |target_job_id| {
+        let header = NetMessageHeader {
+            target_job_id,
+            ..NetMessageHeader::default()
+        };
+        RawNetMessage::from_message(header, CMsgClientHeartBeat::default())
+    };
+    let source = tokio_stream::iter(vec![message(u64::MAX), message(5)]);
+
+    let filter = MessageFilter::default();
+    let job = filter.on_job_id(5);
+    let (release, hold) = oneshot::channel();
+    let write: SharedSink = Arc::new(Mutex::new(drain_sink()));
+    let (mut rest, _) = filter.spawn(source, write, &ConnectionOptions::default(), Some(hold));
+
+    // responses are delivered while other messages are held back
+    assert_eq!(5, job.await.unwrap().header.target_job_id);
+    assert!(rest.try_recv().is_err());
+
+    release.send(()).unwrap();
+    let held = rest.recv().await.unwrap().unwrap();
+    assert_eq!(u64::MAX, held.header.target_job_id);
+    // the message is counted until it's read by the connection
+    assert_eq!(
+        held.header_buffer.len() + held.data.len(),
+        filter.unread_bytes.load(Ordering::Relaxed)
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_respond_to_service_method() {
+    use crate::message::ServiceMethodMessage;
+    use crate::proto::steammessages_gameservers_steamclient::{
+        CMsgGameServerPingQueryData, GameServerClient_QueryServerData_Request,
+        GameServerClient_QueryServerData_Response,
+    };
+    use protobuf::MessageField;
+
+    let response = GameServerClient_QueryServerData_Response {
+        ping_data: MessageField::some(CMsgGameServerPingQueryData {
+            game_port: Some(27015),
+            ..CMsgGameServerPingQueryData::default()
+        }),
+        ..GameServerClient_QueryServerData_Response::default()
+    };
+
+    // steam calling the method on the client, waiting for the response
+    let header = NetMessageHeader {
+        source_job_id: 42,
+        target_job_id: u64::MAX,
+        ..NetMessageHeader::default()
+    }
+    .with_awaits_response();
+    let call = RawNetMessage::from_message_with_kind(
+        header,
+        ServiceMethodMessage(GameServerClient_QueryServerData_Request::default()),
+        EMsg::k_EMsgServiceMethod,
+    )
+    .unwrap();
+    let source = tokio_stream::iter(vec![RawNetMessage::read(call.into_bytes())]);
+
+    let (write, mut sent) = capture_sink();
+    let write: SharedSink = Arc::new(Mutex::new(write));
+
+    let filter = MessageFilter::default();
+    let handler_response = response.clone();
+    filter.method_handlers.insert(
+        GameServerClient_QueryServerData_Request::REQ_NAME,
+        Arc::new(move |call: ServiceMethodNotification| {
+            call.into_notification::<GameServerClient_QueryServerData_Request>()?;
+            ServiceMethodResponseMessage::from_response::<GameServerClient_QueryServerData_Request>(
+                &handler_response,
+            )
+        }),
+    );
+    let _rest = filter.spawn(source, write, &ConnectionOptions::default(), None);
+
+    let sent = RawNetMessage::read(sent.recv().await.unwrap().into_bytes()).unwrap();
+    assert_eq!(EMsg::k_EMsgServiceMethodResponse, sent.kind);
+    assert_eq!(42, sent.header.target_job_id);
+    let sent = sent
+        .into_message::<ServiceMethodResponseMessage>()
+        .unwrap()
+        .into_response::<GameServerClient_QueryServerData_Request>()
+        .unwrap();
+    assert_eq!(response, sent);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_accept_login_key() {
+    use crate::proto::steammessages_clientserver_login::{
+        CMsgClientNewLoginKey, CMsgClientNewLoginKeyAccepted,
+    };
+
+    let new_key = RawNetMessage::from_message(
+        NetMessageHeader {
+            session_id: 3,
+            ..NetMessageHeader::default()
+        },
+        CMsgClientNewLoginKey {
+            unique_id: Some(1234),
+            login_key: Some("new key".into()),
+            ..CMsgClientNewLoginKey::default()
+        },
+    )
+    .unwrap();
+    let source = tokio_stream::iter(vec![RawNetMessage::read(new_key.into_bytes())]);
+
+    let (write, mut sent) = capture_sink();
+    let write: SharedSink = Arc::new(Mutex::new(write));
+
+    let filter = MessageFilter::default();
+    let mut login_key = filter.login_key.subscribe();
+    let (mut rest, _) = filter.spawn(source, write, &ConnectionOptions::default(), None);
+
+    let sent = RawNetMessage::read(sent.recv().await.unwrap().into_bytes()).unwrap();
+    assert_eq!(3, sent.header.session_id);
+    let accepted: CMsgClientNewLoginKeyAccepted = sent.into_message().unwrap();
+    assert_eq!(1234, accepted.unique_id());
+    login_key.wait_for(Option::is_some).await.unwrap();
+    assert_eq!(Some("new key"), login_key.borrow().as_deref());
+    // the message is still delivered so it can be handled as notification
+    let delivered = rest.recv().await.unwrap().unwrap();
+    assert_eq!(EMsg::k_EMsgClientNewLoginKey, delivered.kind);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_allowed_kinds() {
+    use crate::proto::steammessages_clientserver_login::CMsgClientLoggedOff;
+
+    let heartbeat = || {
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .unwrap()
+    };
+    let logged_off = || {
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientLoggedOff::default())
+            .unwrap()
+    };
+
+    let connection = Connection::replay([]);
+    connection.set_allowed_kinds([EMsg::k_EMsgClientLoggedOff]);
+    assert!(!connection.filter.is_allowed(EMsg::k_EMsgClientHeartBeat));
+    assert!(connection.filter.is_allowed(EMsg::k_EMsgClientLoggedOff));
+    connection.allow_all_kinds();
+    assert!(connection.filter.is_allowed(EMsg::k_EMsgClientHeartBeat));
+
+    let filter = MessageFilter::default();
+    filter
+        .allowed_kinds
+        .send_replace(Some([EMsg::k_EMsgClientLoggedOff].into()));
+    let one = filter.one_kind(EMsg::k_EMsgClientHeartBeat);
+    let source = tokio_stream::iter(vec![Ok(heartbeat()), Ok(heartbeat()), Ok(logged_off())]);
+    let write: SharedSink = Arc::new(Mutex::new(drain_sink()));
+    let (mut rest, _) = filter.spawn(source, write, &ConnectionOptions::default(), None);
+
+    // explicitly waited for messages are still delivered
+    assert_eq!(EMsg::k_EMsgClientHeartBeat, one.await.unwrap().kind);
+    // the second heartbeat is dropped
+    assert_eq!(
+        EMsg::k_EMsgClientLoggedOff,
+        rest.recv().await.unwrap().unwrap().kind
+    );
+    assert!(rest.recv().await.is_none());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_sink() {
+    let (write, mut sent) = capture_sink();
+    let mut connection = test_connection(
+        tokio_stream::pending(),
+        write,
+        &ConnectionOptions::default(),
+    );
+    connection.session.session_id = 7;
+
+    let messages = futures_util::stream::iter([
+        Ok(CMsgClientHeartBeat::default()),
+        Ok(CMsgClientHeartBeat::default()),
+    ]);
+    futures_util::StreamExt::forward(messages, connection.sink())
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        let message = RawNetMessage::read(sent.recv().await.unwrap().into_bytes()).unwrap();
+        assert_eq!(EMsg::k_EMsgClientHeartBeat, message.kind);
+        assert_eq!(7, message.header.session_id);
+    }
+    assert!(sent.try_recv().is_err());
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_dead_connection_detection() {
+    let options = ConnectionOptions::default()
+        .with_liveness_heartbeats(2)
+        .with_heartbeat_jitter(0.0);
+    let (write, mut sent) = capture_sink();
+    let mut connection = test_connection(tokio_stream::pending(), write, &options);
+    connection.session.heartbeat_interval = Duration::from_secs(10);
+    let mut state = connection.state();
+    connection.setup_heartbeat();
+
+    let sent_heartbeat = RawNetMessage::read(sent.recv().await.unwrap().into_bytes())
+        .unwrap()
+        .into_message::<CMsgClientHeartBeat>()
+        .unwrap();
+    assert!(sent_heartbeat.send_reply());
+
+    let closed = state
+        .wait_for(|state| matches!(state, ConnectionState::Closed { .. }))
+        .await
+        .unwrap()
+        .clone();
+    let ConnectionState::Closed { error: Some(error) } = closed else {
+        panic!("unexpected state {closed:?}");
+    };
+    assert!(error.contains("no messages received"));
+    // the filter task stopped, so nothing more is delivered
+    assert!(connection.next().await.is_err());
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_liveness_timeout_change_while_waiting() {
+    let (tx, mut rx) = watch::channel(None);
+    let mut source = tokio_stream::pending::<()>();
+    let waiting = tokio::spawn(async move { next_within(&mut source, &mut rx).await });
+    // the wait already started without a timeout
+    tokio::task::yield_now().await;
+    tx.send_replace(Some(Duration::from_secs(5)));
+    assert_eq!(Err(Duration::from_secs(5)), waiting.await.unwrap());
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_log_off() {
+    let (write, mut sent) = capture_sink();
+    let (read_tx, read_rx) = mpsc::unbounded_channel();
+    let connection = test_connection(
+        tokio_stream::wrappers::UnboundedReceiverStream::new(read_rx),
+        write,
+        &ConnectionOptions::default(),
+    );
+    let state = connection.state();
+
+    // the server closes the connection after receiving the log off
+    let server = async move {
+        let message = RawNetMessage::read(sent.recv().await.unwrap().into_bytes()).unwrap();
+        drop(read_tx);
+        message
+    };
+    let start = tokio::time::Instant::now();
+    let (result, message) = tokio::join!(connection.log_off(Duration::from_secs(10)), server);
+    assert_eq!(LogOffOutcome::Closed, result.unwrap());
+    assert_eq!(EMsg::k_EMsgClientLogOff, message.kind);
+    assert!(message.data.is_empty());
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(matches!(*state.borrow(), ConnectionState::Closed { .. }));
+
+    // a server that doesn't close the connection only delays closing by the grace period
+    let connection = test_connection(
+        tokio_stream::pending(),
+        drain_sink(),
+        &ConnectionOptions::default(),
+    );
+    let state = connection.state();
+    let start = tokio::time::Instant::now();
+    assert_eq!(
+        LogOffOutcome::TimedOut,
+        connection.log_off(Duration::from_secs(5)).await.unwrap()
+    );
+    assert!(start.elapsed() >= Duration::from_secs(5));
+    assert!(matches!(*state.borrow(), ConnectionState::Closed { .. }));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_replay() {
+    use crate::eresult::EResult;
+    use crate::proto::steammessages_clientserver_login::CMsgClientLoggedOff;
+
+    let frame = |message: RawNetMessage| message.into_bytes().to_vec();
+    let heartbeat =
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .unwrap();
+    let logged_off = RawNetMessage::from_message(
+        NetMessageHeader::default(),
+        CMsgClientLoggedOff {
+            eresult: Some(EResult::LoggedInElsewhere as i32),
+            ..CMsgClientLoggedOff::default()
+        },
+    )
+    .unwrap();
+
+    let mut connection = Connection::replay([
+        (EMsg::k_EMsgClientHeartBeat, frame(heartbeat.clone())),
+        (EMsg::k_EMsgClientLoggedOff, vec![0xff; 3]),
+        (EMsg::k_EMsgClientLoggedOff, frame(heartbeat)),
+        (EMsg::k_EMsgClientLoggedOff, frame(logged_off)),
+    ]);
+    assert_eq!(
+        EMsg::k_EMsgClientHeartBeat,
+        connection.next().await.unwrap().kind
+    );
+    assert!(matches!(
+        connection.next().await,
+        Err(NetworkError::InvalidHeader)
+    ));
+    assert!(matches!(
+        connection.next().await,
+        Err(NetworkError::DifferentMessage(
+            EMsg::k_EMsgClientLoggedOff,
+            EMsg::k_EMsgClientHeartBeat
+        ))
+    ));
+    let logged_off: CMsgClientLoggedOff = connection.next().await.unwrap().into_message().unwrap();
+    assert_eq!(EResult::LoggedInElsewhere as i32, logged_off.eresult());
+
+    // frames that fail to decode aren't counted
+    let stats = connection.stats();
+    assert_eq!(1, stats.inbound[&EMsg::k_EMsgClientHeartBeat].count);
+    assert_eq!(1, stats.inbound[&EMsg::k_EMsgClientLoggedOff].count);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_anonymous_logon_with_mock_server() {
+    use crate::proto::steammessages_clientserver_login::CMsgClientLogonResponse;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("ws://{}/cmsocket/", listener.local_addr().unwrap());
+    let steam_id = SteamID::from(0x02a0_0000_0000_1234);
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        for kind in [EMsg::k_EMsgClientHello, EMsg::k_EMsgClientLogon] {
+            let data = ws.next().await.unwrap().unwrap().into_data();
+            let message = RawNetMessage::read(data.into_iter().collect()).unwrap();
+            assert_eq!(kind, message.kind);
+        }
+
+        let header = NetMessageHeader {
+            session_id: 1234,
+            steam_id,
+            ..NetMessageHeader::default()
+        };
+        let response = CMsgClientLogonResponse {
+            eresult: Some(1),
+            heartbeat_seconds: Some(30),
+            client_instance_id: Some(7),
+            ..CMsgClientLogonResponse::default()
+        };
+        let response = RawNetMessage::from_message(header, response).unwrap();
+        ws.send(WsMessage::binary(response.into_bytes()))
+            .await
+            .unwrap();
+        // keep the connection open until the client is done
+        ws.next().await;
+    });
+
+    let options = ConnectionOptions::default();
+    let connection = Connection::connect(&addr, &options)
+        .await
+        .unwrap()
+        .anonymous_session()
+        .await
+        .unwrap();
+    assert_eq!(1234, connection.session.session_id);
+    assert_eq!(steam_id, connection.steam_id());
+    // without the encryption handshake the universe comes from the steam id assigned by the server
+    assert_eq!(Universe::Beta, connection.universe());
+    assert_eq!(
+        Duration::from_secs(30),
+        connection.session.heartbeat_interval
+    );
+    assert_eq!(7, connection.client_instance_id());
+    let state = connection.state();
+    assert!(matches!(*state.borrow(), ConnectionState::Connected));
+
+    connection.close().await.unwrap();
+    assert!(matches!(*state.borrow(), ConnectionState::Closed { .. }));
+    server.abort();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_timestamped_notification() {
+    use crate::proto::steammessages_clientserver_login::CMsgClientLoggedOff;
+    use crate::Notification;
+
+    let logged_off = || {
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientLoggedOff::default())
+            .unwrap()
+    };
+
+    let before = Instant::now();
+    let options = ConnectionOptions::default().with_receive_timestamps(true);
+    let read = tokio_stream::iter(vec![Ok(logged_off())]);
+    let mut connection = test_connection(read, drain_sink(), &options);
+    let (notification, received_at) = connection.next_timestamped_notification().await.unwrap();
+    assert!(matches!(notification, Notification::LoggedOff { .. }));
+    assert!(received_at.unwrap() >= before);
+
+    // without the option no time is recorded
+    let read = tokio_stream::iter(vec![Ok(logged_off())]);
+    let mut connection = test_connection(read, drain_sink(), &ConnectionOptions::default());
+    let (_, received_at) = connection.next_timestamped_notification().await.unwrap();
+    assert_eq!(None, received_at);
+}
+
+#[test]
+fn test_jittered_heartbeat_interval() {
+    let interval = Duration::from_secs(10);
+    assert_eq!(interval, jittered(interval, 0.0));
+    for _ in 0..100 {
+        let jittered = jittered(interval, 0.1);
+        assert!(jittered >= Duration::from_secs(9));
+        assert!(jittered <= Duration::from_secs(11));
+    }
+
+    let options = ConnectionOptions::default();
+    assert_eq!(
+        0.1,
+        options
+            .clone()
+            .with_heartbeat_jitter(f64::NAN)
+            .heartbeat_jitter
+    );
+    assert_eq!(
+        0.1,
+        options
+            .clone()
+            .with_heartbeat_jitter(f64::INFINITY)
+            .heartbeat_jitter
+    );
+    assert_eq!(
+        0.5,
+        options.clone().with_heartbeat_jitter(2.0).heartbeat_jitter
+    );
+    assert_eq!(0.0, options.with_heartbeat_jitter(-1.0).heartbeat_jitter);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_flush_error() {
+    use std::task::{Context, Poll};
+
+    /// A sink that buffers written messages and fails writing or flushing when told to
+    struct FailingSink {
+        fail_write: bool,
+        fail_flush: bool,
+        buffered: usize,
+    }
+
+    impl Sink<RawNetMessage> for FailingSink {
+        type Error = NetworkError;
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(self: Pin<&mut Self>, _item: RawNetMessage) -> Result<()> {
+            if self.fail_write {
+                return Err(NetworkError::EOF);
+            }
+            self.get_mut().buffered += 1;
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            if self.fail_flush {
+                return Poll::Ready(Err(NetworkError::EOF));
+            }
+            self.get_mut().buffered = 0;
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.poll_flush(cx)
+        }
+    }
+
+    let sink = |fail_write, fail_flush| {
+        Arc::new(Mutex::new(FailingSink {
+            fail_write,
+            fail_flush,
+            buffered: 0,
+        }))
+    };
+    let message = || {
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .unwrap()
+    };
+
+    let failing_write = sink(true, false);
+    assert!(matches!(
+        write_message(&(failing_write as SharedSink), None, message(), true).await,
+        Err(NetworkError::EOF)
+    ));
+
+    let failing_flush = sink(false, true);
+    let shared: SharedSink = failing_flush.clone();
+    // the message is only buffered, so the failing flush isn't noticed yet
+    write_message(&shared, None, message(), false)
+        .await
+        .unwrap();
+    assert_eq!(1, failing_flush.lock().await.buffered);
+    assert!(matches!(
+        flush_messages(&shared).await,
+        Err(NetworkError::Flush(inner)) if matches!(*inner, NetworkError::EOF)
+    ));
+    assert!(matches!(
+        write_message(&shared, None, message(), true).await,
+        Err(NetworkError::Flush(_))
+    ));
+
+    let working = sink(false, false);
+    let shared: SharedSink = working.clone();
+    write_message(&shared, None, message(), false)
+        .await
+        .unwrap();
+    write_message(&shared, None, message(), false)
+        .await
+        .unwrap();
+    assert_eq!(2, working.lock().await.buffered);
+    flush_messages(&shared).await.unwrap();
+    assert_eq!(0, working.lock().await.buffered);
+}
+
+/// Logs written while capturing them with [`capture_logs`]
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl CapturedLogs {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Capture the logs up to `level` on the current thread, until the guard is dropped
+#[cfg(test)]
+fn capture_logs(level: tracing::Level) -> (CapturedLogs, tracing::subscriber::DefaultGuard) {
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    (logs, tracing::subscriber::set_default(subscriber))
+}
+
+/// A sink that discards the written messages
+#[cfg(test)]
+fn drain_sink() -> impl Sink<RawNetMessage, Error = NetworkError> + Unpin + Send + 'static {
+    futures_util::sink::drain().sink_map_err(|never| match never {})
+}
+
+/// A sink that sends the written messages to the returned receiver
+#[cfg(test)]
+fn capture_sink() -> (
+    impl Sink<RawNetMessage, Error = NetworkError> + Unpin + Send + 'static,
+    mpsc::UnboundedReceiver<RawNetMessage>,
+) {
+    let (sent_tx, sent) = mpsc::unbounded_channel();
+    let write = futures_util::sink::unfold(sent_tx, |sent_tx, message: RawNetMessage| async move {
+        sent_tx.send(message).ok();
+        Ok::<_, NetworkError>(sent_tx)
+    });
+    (Box::pin(write), sent)
+}
+
+/// A connection reading from `read` and writing to `write`, without any handshake
+#[cfg(test)]
+fn test_connection<
+    Read: Stream<Item = Result<RawNetMessage>> + Send + Unpin + 'static,
+    Write: Sink<RawNetMessage, Error = NetworkError> + Unpin + Send + 'static,
+>(
+    read: Read,
+    write: Write,
+    options: &ConnectionOptions,
+) -> Connection {
+    Connection::from_transport("test", read, write, options, MessageFilter::default(), None)
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_queue_overflow_warns_once() {
+    let (logs, _guard) = capture_logs(tracing::Level::WARN);
+
+    let heartbeat =
+        || {
+            Ok(RawNetMessage::from_message(
+                NetMessageHeader::default(),
+                CMsgClientHeartBeat::default(),
+            )
+            .unwrap())
+        };
+    let source = tokio_stream::iter((0..5).map(|_| heartbeat()).collect::<Vec<_>>());
+    let write: SharedSink = Arc::new(Mutex::new(drain_sink()));
+    let options = ConnectionOptions::default().with_receive_queue(1, OverflowPolicy::DropNewest);
+    let mut state = options.state.subscribe();
+    let (mut rest, _) = MessageFilter::default().spawn(source, write, &options, None);
+    // the read loop closes the connection once every message of the source is handled
+    state
+        .wait_for(|state| matches!(state, ConnectionState::Closed { .. }))
+        .await
+        .unwrap();
+
+    assert_eq!(4, rest.dropped());
+    assert!(rest.recv().await.is_some());
+    let logs = logs.contents();
+    assert_eq!(1, logs.matches("receive queue is full").count(), "{logs}");
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_connection_span() {
+    let (logs, _guard) = capture_logs(tracing::Level::DEBUG);
+
+    let heartbeat =
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .unwrap();
+    let mut connection =
+        Connection::replay([(EMsg::k_EMsgClientHeartBeat, heartbeat.into_bytes().to_vec())]);
+    connection.next().await.unwrap();
+
+    // the read loop logs in the span of the connection
+    let logs = logs.contents();
+    let line = logs
+        .lines()
+        .find(|line| line.contains("processing message"))
+        .unwrap();
+    assert!(line.contains("connection{cm=\"replay\"}"), "{line}");
+}
@@ -0,0 +1,183 @@
+//! Keep the state steam pushes during the session, so it can be read without handling the messages
+
+use super::{MessageFilter, SharedSink};
+use crate::account_limits::AccountLimits;
+use crate::clan::ClanState;
+use crate::message::NetMessage;
+use crate::net::{NetMessageHeader, RawNetMessage};
+use crate::proto::enums_clientserver::EMsg;
+use crate::proto::steammessages_clientserver::{
+    CMsgClientCMList, CMsgClientClanState, CMsgClientIsLimitedAccount, CMsgClientServersAvailable,
+    CMsgClientWalletInfoUpdate,
+};
+use crate::proto::steammessages_clientserver_friends::{
+    CMsgClientFriendsGroupsList, CMsgClientPersonaState, CMsgClientPlayerNicknameList,
+};
+use crate::proto::steammessages_clientserver_login::{
+    CMsgClientNewLoginKey, CMsgClientNewLoginKeyAccepted,
+};
+use crate::serverlist::ServerList;
+use crate::vac::VacBanStatus;
+use crate::wallet::Wallet;
+use futures_util::SinkExt;
+use protobuf::Message;
+use std::collections::HashSet;
+use std::time::Instant;
+use tracing::{debug, error};
+
+impl MessageFilter {
+    /// Update the cached state from a received message, before it's routed to any listener
+    pub(super) async fn update_cache(&self, message: &RawNetMessage, write: &SharedSink) {
+        match message.kind {
+            EMsg::k_EMsgClientServersAvailable => self.cache_servers_available(message),
+            EMsg::k_EMsgClientWalletInfoUpdate => self.cache_wallet(message),
+            EMsg::k_EMsgClientIsLimitedAccount => self.cache_account_limits(message),
+            EMsg::k_EMsgClientVACBanStatus => self.cache_vac_bans(message),
+            EMsg::k_EMsgClientFriendsGroupsList => self.cache_friend_groups(message),
+            EMsg::k_EMsgClientClanState => self.cache_clan_state(message),
+            EMsg::k_EMsgClientPlayerNicknameList => self.cache_nicknames(message),
+            EMsg::k_EMsgClientPersonaState => self.cache_personas(message),
+            EMsg::k_EMsgClientCMList => self.cache_cm_list(message),
+            EMsg::k_EMsgClientUpdateMachineAuth => self.cache_machine_auth(message),
+            EMsg::k_EMsgClientNewLoginKey => self.accept_login_key(message, write).await,
+            _ => {}
+        }
+    }
+
+    /// Store a login key sent by steam and acknowledge it, steam keeps resending the key until it's accepted
+    async fn accept_login_key(&self, message: &RawNetMessage, write: &SharedSink) {
+        let login_key = match CMsgClientNewLoginKey::parse_from_bytes(&message.data) {
+            Ok(login_key) => login_key,
+            Err(e) => {
+                error!(error = ?e, "failed to parse new login key");
+                return;
+            }
+        };
+        debug!(unique_id = login_key.unique_id(), "received new login key");
+        let header = NetMessageHeader {
+            source_job_id: u64::MAX,
+            target_job_id: u64::MAX,
+            steam_id: message.header.steam_id,
+            session_id: message.header.session_id,
+            ..NetMessageHeader::default()
+        };
+        let accepted = CMsgClientNewLoginKeyAccepted {
+            unique_id: login_key.unique_id,
+            ..CMsgClientNewLoginKeyAccepted::default()
+        };
+        let result = match RawNetMessage::from_message(header, accepted) {
+            Ok(accepted) => write.lock().await.send(accepted).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!(error = ?e, "failed to accept new login key");
+        }
+        self.login_key.send_replace(login_key.login_key);
+    }
+
+    /// Keep the servers steam suggests for the cell of the session, to prefer them when reconnecting
+    fn cache_cm_list(&self, message: &RawNetMessage) {
+        match CMsgClientCMList::parse_from_bytes(&message.data) {
+            Ok(list) => {
+                let list = ServerList::from(&list);
+                debug!(servers = list.ws_urls().len(), "received server list");
+                self.cm_list.send_replace(Some(list));
+            }
+            Err(e) => error!(error = ?e, "failed to parse server list"),
+        }
+    }
+
+    /// Keep track of the announced servers so requests can wait for them, even when the announcement
+    /// arrives before anyone is waiting for it
+    fn cache_servers_available(&self, message: &RawNetMessage) {
+        match CMsgClientServersAvailable::parse_from_bytes(&message.data) {
+            Ok(servers) => {
+                let available: HashSet<u32> = servers
+                    .server_types_available
+                    .iter()
+                    .map(|server| server.server())
+                    .collect();
+                debug!(?available, "servers available");
+                self.servers_available.send_replace(available);
+            }
+            Err(e) => error!(error = ?e, "failed to parse available servers"),
+        }
+    }
+
+    /// Keep the last wallet update, so the wallet can be read without handling the notifications
+    fn cache_wallet(&self, message: &RawNetMessage) {
+        match CMsgClientWalletInfoUpdate::parse_from_bytes(&message.data) {
+            Ok(update) => {
+                self.wallet.send_replace(Some(Wallet::from(&update)));
+            }
+            Err(e) => error!(error = ?e, "failed to parse wallet info"),
+        }
+    }
+
+    /// Keep the account limits, which are only sent after logging on
+    fn cache_account_limits(&self, message: &RawNetMessage) {
+        match CMsgClientIsLimitedAccount::parse_from_bytes(&message.data) {
+            Ok(limits) => {
+                self.account_limits
+                    .send_replace(Some(AccountLimits::from(&limits)));
+            }
+            Err(e) => error!(error = ?e, "failed to parse account limits"),
+        }
+    }
+
+    /// Keep the VAC ban status, which is only sent after logging on
+    fn cache_vac_bans(&self, message: &RawNetMessage) {
+        match VacBanStatus::read_body(message.data.clone(), &message.header) {
+            Ok(bans) => {
+                self.vac_bans.send_replace(Some(bans));
+            }
+            Err(e) => error!(error = ?e, "failed to parse vac ban status"),
+        }
+    }
+
+    /// Keep track of the friend groups, since they are sent as a full list followed by incremental updates
+    fn cache_friend_groups(&self, message: &RawNetMessage) {
+        match CMsgClientFriendsGroupsList::parse_from_bytes(&message.data) {
+            Ok(update) => self
+                .friend_groups
+                .send_modify(|groups| groups.apply(&update)),
+            Err(e) => error!(error = ?e, "failed to parse friend groups"),
+        }
+    }
+
+    /// Keep track of the state of clans, since updates only contain the changed fields
+    fn cache_clan_state(&self, message: &RawNetMessage) {
+        match CMsgClientClanState::parse_from_bytes(&message.data) {
+            Ok(update) => self
+                .clans
+                .send_modify(|clans| clans.apply(ClanState::from(&update))),
+            Err(e) => error!(error = ?e, "failed to parse clan state"),
+        }
+    }
+
+    /// Keep track of the nicknames, since they are sent as a full list followed by incremental updates
+    fn cache_nicknames(&self, message: &RawNetMessage) {
+        match CMsgClientPlayerNicknameList::parse_from_bytes(&message.data) {
+            Ok(update) => self
+                .nicknames
+                .send_modify(|nicknames| nicknames.apply(&update)),
+            Err(e) => error!(error = ?e, "failed to parse nicknames"),
+        }
+    }
+
+    /// Keep the persona data, so it can be looked up without requesting it again
+    fn cache_personas(&self, message: &RawNetMessage) {
+        match CMsgClientPersonaState::parse_from_bytes(&message.data) {
+            Ok(update) => self
+                .personas
+                .send_modify(|personas| personas.apply(&update, Instant::now())),
+            Err(e) => error!(error = ?e, "failed to parse persona state"),
+        }
+    }
+
+    /// Keep the sentry file update, since it's sent right after logging on, before anyone can wait for it
+    fn cache_machine_auth(&self, message: &RawNetMessage) {
+        debug!("received sentry file update");
+        self.machine_auth.send_replace(Some(message.clone()));
+    }
+}
@@ -0,0 +1,241 @@
+use crate::net::{NetworkError, RawNetMessage};
+use crate::proto::steammessages_chat_steamclient::{
+    CChatRoom_GetMessageHistory_Request, CChatRoom_GetMyChatRoomGroups_Request,
+};
+use crate::service_method::ServiceMethodRequest;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::future::Future;
+use steam_vent_proto::enums_clientserver::EMsg;
+use tokio::sync::watch;
+
+type Result<T, E = NetworkError> = std::result::Result<T, E>;
+
+/// The message kind, service method name and encoded body of a request
+pub(crate) type RequestKey = (EMsg, Option<&'static str>, Vec<u8>);
+
+/// The requests that only read data, only these are deduplicated
+const READ_ONLY_KINDS: &[EMsg] = &[
+    EMsg::k_EMsgClientPICSProductInfoRequest,
+    EMsg::k_EMsgClientPICSAccessTokenRequest,
+    EMsg::k_EMsgClientPICSChangesSinceRequest,
+    EMsg::k_EMsgClientGetUserStats,
+    EMsg::k_EMsgClientGetDepotDecryptionKey,
+];
+
+/// The service methods that only read data, only these are deduplicated
+const READ_ONLY_METHODS: &[&str] = &[
+    CChatRoom_GetMyChatRoomGroups_Request::REQ_NAME,
+    CChatRoom_GetMessageHistory_Request::REQ_NAME,
+];
+
+/// Whether the request only reads data, so identical requests can share a response
+pub(crate) fn is_read_only(kind: EMsg, method: Option<&str>) -> bool {
+    match method {
+        Some(method) => READ_ONLY_METHODS.contains(&method),
+        None => READ_ONLY_KINDS.contains(&kind),
+    }
+}
+
+/// The result of a request, `None` while it's in flight
+type Outcome = Option<Result<RawNetMessage>>;
+
+/// Coalesces identical requests that are in flight at the same time into a single request
+#[derive(Default)]
+pub(crate) struct RequestDeduplicator {
+    in_flight: DashMap<RequestKey, watch::Receiver<Outcome>>,
+}
+
+impl RequestDeduplicator {
+    /// Run the request, unless an identical request is already in flight in which case its result is used
+    ///
+    /// If the original request is cancelled before completing, the request is run instead.
+    pub async fn run<F: Future<Output = Result<RawNetMessage>>>(
+        &self,
+        key: RequestKey,
+        request: F,
+    ) -> Result<RawNetMessage> {
+        let tx = loop {
+            let mut rx = match self.in_flight.entry(key.clone()) {
+                Entry::Occupied(entry) => entry.get().clone(),
+                Entry::Vacant(entry) => {
+                    let (tx, rx) = watch::channel(None);
+                    entry.insert(rx);
+                    break tx;
+                }
+            };
+            // the result stays in the channel, so waiters that subscribe after it's sent still get it
+            let outcome = rx.wait_for(Option::is_some).await.ok().map(|outcome| {
+                let result = outcome.as_ref().expect("waited for the result");
+                copy_result(result)
+            });
+            match outcome {
+                Some(result) => return result,
+                // the original request was cancelled, normally its entry is already removed
+                None => {
+                    self.in_flight
+                        .remove_if(&key, |_, entry| entry.same_channel(&rx));
+                }
+            }
+        };
+        // remove the entry even if the request future is dropped before completing
+        let _guard = InFlightGuard {
+            in_flight: &self.in_flight,
+            key: Some(key),
+        };
+
+        let result = request.await;
+        tx.send_replace(Some(copy_result(&result)));
+        result
+    }
+}
+
+fn copy_result(result: &Result<RawNetMessage>) -> Result<RawNetMessage> {
+    match result {
+        Ok(response) => Ok(response.clone()),
+        Err(e) => Err(copy_error(e)),
+    }
+}
+
+/// Copy the error of a request for the coalesced requests
+///
+/// Errors that can't be cloned are replaced by an io error with the same message.
+fn copy_error(error: &NetworkError) -> NetworkError {
+    match error {
+        NetworkError::IO(e) => NetworkError::IO(std::io::Error::new(e.kind(), e.to_string())),
+        NetworkError::InvalidHeader => NetworkError::InvalidHeader,
+        NetworkError::InvalidMessageKind(kind) => NetworkError::InvalidMessageKind(*kind),
+        NetworkError::CryptoHandshakeFailed => NetworkError::CryptoHandshakeFailed,
+        NetworkError::DifferentMessage(expected, got) => {
+            NetworkError::DifferentMessage(*expected, *got)
+        }
+        NetworkError::DifferentServiceMethod(expected, got) => {
+            NetworkError::DifferentServiceMethod(expected, got.clone())
+        }
+        NetworkError::EOF => NetworkError::EOF,
+        NetworkError::Timeout => NetworkError::Timeout,
+        NetworkError::ApiError(result) => NetworkError::ApiError(*result),
+        NetworkError::ProxyFailed(message) => NetworkError::ProxyFailed(message.clone()),
+        NetworkError::FrameTooLarge(len) => NetworkError::FrameTooLarge(*len),
+        NetworkError::UnknownUniverse(universe) => NetworkError::UnknownUniverse(*universe),
+        NetworkError::UnsupportedUniverse(universe) => NetworkError::UnsupportedUniverse(*universe),
+        NetworkError::Flush(e) => NetworkError::Flush(Box::new(copy_error(e))),
+        NetworkError::Ws(_) | NetworkError::MalformedBody(_) | NetworkError::CryptoError(_) => {
+            NetworkError::IO(std::io::Error::other(error.to_string()))
+        }
+    }
+}
+
+struct InFlightGuard<'a> {
+    in_flight: &'a DashMap<RequestKey, watch::Receiver<Outcome>>,
+    key: Option<RequestKey>,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.in_flight.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_deduplicate_concurrent_requests() {
+    use crate::net::NetMessageHeader;
+    use crate::proto::steammessages_clientserver_appinfo::CMsgClientPICSProductInfoRequest;
+    use crate::NetMessage;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::Notify;
+
+    let dedup = RequestDeduplicator::default();
+    let request = CMsgClientPICSProductInfoRequest {
+        meta_data_only: Some(true),
+        ..CMsgClientPICSProductInfoRequest::default()
+    };
+    let key = (
+        CMsgClientPICSProductInfoRequest::KIND,
+        None,
+        request.to_bytes().unwrap(),
+    );
+    let sent = AtomicUsize::new(0);
+    let response_ready = Notify::new();
+    let send_request = || async {
+        sent.fetch_add(1, Ordering::SeqCst);
+        response_ready.notified().await;
+        RawNetMessage::from_message(NetMessageHeader::default(), request.clone())
+    };
+
+    let (first, second, _) = tokio::join!(
+        dedup.run(key.clone(), send_request()),
+        dedup.run(key.clone(), send_request()),
+        async {
+            tokio::task::yield_now().await;
+            response_ready.notify_waiters();
+        }
+    );
+    assert_eq!(1, sent.load(Ordering::SeqCst));
+    assert_eq!(CMsgClientPICSProductInfoRequest::KIND, first.unwrap().kind);
+    assert_eq!(CMsgClientPICSProductInfoRequest::KIND, second.unwrap().kind);
+    assert!(dedup.in_flight.is_empty());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_deduplicate_error_and_late_waiter() {
+    use crate::net::NetMessageHeader;
+    use crate::proto::steammessages_clientserver_appinfo::CMsgClientPICSProductInfoRequest;
+    use crate::proto::steammessages_friendmessages_steamclient::CFriendMessages_SendMessage_Request;
+    use crate::EResult;
+    use tokio::sync::Notify;
+
+    assert!(is_read_only(EMsg::k_EMsgClientPICSProductInfoRequest, None));
+    assert!(!is_read_only(EMsg::k_EMsgClientAddFriend, None));
+    assert!(!is_read_only(
+        EMsg::k_EMsgServiceMethodCallFromClient,
+        Some(CFriendMessages_SendMessage_Request::REQ_NAME)
+    ));
+
+    let dedup = RequestDeduplicator::default();
+    let key = (EMsg::k_EMsgClientPICSProductInfoRequest, None, vec![1]);
+
+    // the error of the original request is forwarded
+    let response_ready = Notify::new();
+    let (first, second, _) = tokio::join!(
+        dedup.run(key.clone(), async {
+            response_ready.notified().await;
+            Err(NetworkError::ApiError(EResult::Busy))
+        }),
+        dedup.run(key.clone(), async { panic!("request should be coalesced") }),
+        async {
+            tokio::task::yield_now().await;
+            response_ready.notify_waiters();
+        }
+    );
+    assert!(matches!(first, Err(NetworkError::ApiError(EResult::Busy))));
+    assert!(matches!(second, Err(NetworkError::ApiError(EResult::Busy))));
+
+    // a waiter that subscribes after the response is sent, but before the entry is removed, still gets it
+    let (tx, rx) = watch::channel(None);
+    dedup.in_flight.insert(key.clone(), rx);
+    tx.send_replace(Some(RawNetMessage::from_message(
+        NetMessageHeader::default(),
+        CMsgClientPICSProductInfoRequest::default(),
+    )));
+    let late = dedup
+        .run(key.clone(), async { panic!("request should be coalesced") })
+        .await;
+    assert_eq!(EMsg::k_EMsgClientPICSProductInfoRequest, late.unwrap().kind);
+
+    dedup.in_flight.remove(&key);
+
+    // if the original request is cancelled, the waiter runs its own request
+    let (tx, rx) = watch::channel(None);
+    dedup.in_flight.insert(key.clone(), rx);
+    drop(tx);
+    let retried = dedup
+        .run(key.clone(), async { Err(NetworkError::Timeout) })
+        .await;
+    assert!(matches!(retried, Err(NetworkError::Timeout)));
+    assert!(dedup.in_flight.is_empty());
+}
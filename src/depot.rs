@@ -0,0 +1,138 @@
+use crate::connection::Connection;
+use crate::eresult::EResult;
+use crate::net::NetworkError;
+use crate::proto::steammessages_clientserver_2::{
+    CMsgClientGetCDNAuthToken, CMsgClientGetCDNAuthTokenResponse, CMsgClientGetDepotDecryptionKey,
+    CMsgClientGetDepotDecryptionKeyResponse,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum DepotKeyError {
+    #[error(transparent)]
+    Network(#[from] NetworkError),
+    /// The account doesn't have access to the depot, usually because it doesn't own the app
+    #[error("access to the depot was denied")]
+    AccessDenied,
+    #[error("requesting the depot key failed with {0:?}")]
+    Failed(EResult),
+}
+
+fn depot_key_from_response(
+    response: CMsgClientGetDepotDecryptionKeyResponse,
+) -> Result<Vec<u8>, DepotKeyError> {
+    match EResult::from_result(response.eresult()) {
+        Ok(()) => Ok(response.depot_encryption_key.unwrap_or_default()),
+        Err(EResult::AccessDenied) => Err(DepotKeyError::AccessDenied),
+        Err(result) => Err(DepotKeyError::Failed(result)),
+    }
+}
+
+/// A token for downloading content of a depot from a cdn server, see [`Connection::get_cdn_auth_token`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdnAuthToken {
+    pub token: String,
+    /// The time after which the token is no longer accepted and a new one has to be requested
+    pub expires_at: SystemTime,
+}
+
+impl CdnAuthToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= SystemTime::now()
+    }
+}
+
+fn cdn_token_from_response(
+    response: CMsgClientGetCDNAuthTokenResponse,
+) -> Result<CdnAuthToken, NetworkError> {
+    EResult::from_result(response.eresult() as i32)?;
+    let expires_at = UNIX_EPOCH + Duration::from_secs(response.expiration_time().into());
+    Ok(CdnAuthToken {
+        token: response.token.unwrap_or_default(),
+        expires_at,
+    })
+}
+
+impl Connection {
+    /// Get a token for downloading the content of a depot from the cdn server with the given host name
+    ///
+    /// Denied requests, e.g. because the account doesn't own the app, fail with [`NetworkError::ApiError`].
+    pub async fn get_cdn_auth_token(
+        &self,
+        app_id: u32,
+        depot_id: u32,
+        host: &str,
+    ) -> Result<CdnAuthToken, NetworkError> {
+        let request = CMsgClientGetCDNAuthToken {
+            depot_id: Some(depot_id),
+            host_name: Some(host.into()),
+            app_id: Some(app_id),
+            ..CMsgClientGetCDNAuthToken::default()
+        };
+        let response: CMsgClientGetCDNAuthTokenResponse = self.job(request).await?;
+        cdn_token_from_response(response)
+    }
+
+    /// Get the key for decrypting the content of a depot
+    pub async fn get_depot_key(
+        &self,
+        depot_id: u32,
+        app_id: u32,
+    ) -> Result<Vec<u8>, DepotKeyError> {
+        let request = CMsgClientGetDepotDecryptionKey {
+            depot_id: Some(depot_id),
+            app_id: Some(app_id),
+            ..CMsgClientGetDepotDecryptionKey::default()
+        };
+        let response: CMsgClientGetDepotDecryptionKeyResponse = self.job(request).await?;
+        depot_key_from_response(response)
+    }
+}
+
+#[test]
+fn test_depot_key_from_response() {
+    let response = CMsgClientGetDepotDecryptionKeyResponse {
+        eresult: Some(EResult::OK as i32),
+        depot_id: Some(441),
+        depot_encryption_key: Some(vec![1; 32]),
+        ..CMsgClientGetDepotDecryptionKeyResponse::default()
+    };
+    assert_eq!(vec![1; 32], depot_key_from_response(response).unwrap());
+
+    let response = CMsgClientGetDepotDecryptionKeyResponse {
+        eresult: Some(EResult::AccessDenied as i32),
+        ..CMsgClientGetDepotDecryptionKeyResponse::default()
+    };
+    assert!(matches!(
+        depot_key_from_response(response),
+        Err(DepotKeyError::AccessDenied)
+    ));
+}
+
+#[test]
+fn test_cdn_token_from_response() {
+    let response = CMsgClientGetCDNAuthTokenResponse {
+        eresult: Some(EResult::OK as u32),
+        token: Some("token".into()),
+        expiration_time: Some(1700000000),
+        ..CMsgClientGetCDNAuthTokenResponse::default()
+    };
+    let token = cdn_token_from_response(response).unwrap();
+    assert_eq!("token", token.token);
+    assert_eq!(
+        UNIX_EPOCH + Duration::from_secs(1700000000),
+        token.expires_at
+    );
+    assert!(token.is_expired());
+
+    let response = CMsgClientGetCDNAuthTokenResponse {
+        eresult: Some(EResult::AccessDenied as u32),
+        ..CMsgClientGetCDNAuthTokenResponse::default()
+    };
+    assert!(matches!(
+        cdn_token_from_response(response),
+        Err(NetworkError::ApiError(EResult::AccessDenied))
+    ));
+}
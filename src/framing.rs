@@ -0,0 +1,324 @@
+//! Helpers for length prefixed data and the frames of tcp connections
+//!
+//! Steam encodes both protobuf message headers and the messages inside multi messages as a
+//! little endian `u32` length followed by that many bytes. Over tcp every message is additionally
+//! wrapped in a frame, with a header of the `u32` length and the `VT01` magic.
+//!
+//! Nothing in here depends on an async runtime, the transports only handle the io around these helpers.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use bytes::{Buf, BufMut, BytesMut};
+use protobuf::Message;
+use std::io::{copy, ErrorKind, Read, Write};
+use thiserror::Error;
+
+/// Error while reading or writing length prefixed data
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum FramingError {
+    #[error("unexpected end of length prefixed data")]
+    Truncated,
+    #[error("data is too large to be length prefixed")]
+    TooLarge,
+    #[error("{0}")]
+    IO(std::io::Error),
+    #[error("malformed protobuf message: {0}")]
+    Malformed(#[from] protobuf::Error),
+    #[error("invalid frame magic {0:?}")]
+    InvalidMagic([u8; 4]),
+    #[error("frame of {0} bytes is larger than the protocol allows")]
+    FrameTooLarge(u64),
+}
+
+impl From<std::io::Error> for FramingError {
+    fn from(value: std::io::Error) -> Self {
+        match value.kind() {
+            ErrorKind::UnexpectedEof => FramingError::Truncated,
+            _ => FramingError::IO(value),
+        }
+    }
+}
+
+/// Read a `u32` length followed by that many bytes
+///
+/// Fails with [`FramingError::Truncated`] if the data ends before the length or the bytes it announces.
+pub fn read_length_prefixed<R: Read>(mut reader: R) -> Result<BytesMut, FramingError> {
+    let length = reader.read_u32::<LittleEndian>()?;
+    read_exact_length(reader, length)
+}
+
+/// Read `length` bytes, without trusting the length for allocating since it might be larger than the remaining data
+pub(crate) fn read_exact_length<R: Read>(reader: R, length: u32) -> Result<BytesMut, FramingError> {
+    let mut data = BytesMut::new();
+    let read = copy(&mut reader.take(length as u64), &mut (&mut data).writer())?;
+    if read != length as u64 {
+        return Err(FramingError::Truncated);
+    }
+    Ok(data)
+}
+
+/// Read a `u32` length followed by a protobuf message of that length
+pub fn read_length_prefixed_proto<T: Message, R: Read>(reader: R) -> Result<T, FramingError> {
+    let data = read_length_prefixed(reader)?;
+    Ok(T::parse_from_bytes(&data)?)
+}
+
+/// Write the length of the data as `u32` followed by the data
+pub fn write_length_prefixed<W: Write>(mut writer: W, data: &[u8]) -> Result<(), FramingError> {
+    let length = u32::try_from(data.len()).map_err(|_| FramingError::TooLarge)?;
+    writer.write_u32::<LittleEndian>(length)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// Write the encoded length of a protobuf message as `u32` followed by the message
+pub fn write_length_prefixed_proto<T: Message, W: Write>(
+    writer: W,
+    message: &T,
+) -> Result<(), FramingError> {
+    write_length_prefixed(writer, &message.write_to_bytes()?)
+}
+
+/// The magic bytes in every frame header
+pub const FRAME_MAGIC: [u8; 4] = *b"VT01";
+
+/// Serialized size of the frame header: 4 byte length and 4 byte magic
+pub const FRAME_HEADER_SIZE: usize = 4 + 4;
+
+/// The largest frame payload that is accepted from the server
+///
+/// The length in the frame header is only checked against this limit, so a peer can't make the
+/// reader buffer up to 4 GiB for a single frame. Steam's largest messages are compressed multi
+/// messages, which stay far below this.
+pub const MAX_FRAME_SIZE: u32 = 32 * 1024 * 1024;
+
+/// The header in front of every message sent over tcp
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct FrameHeader {
+    /// The length of the frame, without the header
+    pub length: u32,
+    pub magic: [u8; 4],
+}
+
+impl FrameHeader {
+    /// The header for a frame with `len` bytes of payload
+    pub fn new(len: usize) -> Result<Self, FramingError> {
+        Ok(FrameHeader {
+            length: frame_length(len)?,
+            magic: FRAME_MAGIC,
+        })
+    }
+
+    pub fn read(bytes: &[u8; FRAME_HEADER_SIZE]) -> Self {
+        let [l0, l1, l2, l3, m0, m1, m2, m3] = *bytes;
+        FrameHeader {
+            length: u32::from_le_bytes([l0, l1, l2, l3]),
+            magic: [m0, m1, m2, m3],
+        }
+    }
+
+    pub fn write(&self, bytes: &mut [u8; FRAME_HEADER_SIZE]) {
+        bytes[0..4].copy_from_slice(&self.length.to_le_bytes());
+        bytes[4..FRAME_HEADER_SIZE].copy_from_slice(&self.magic);
+    }
+
+    pub fn validate(&self) -> Result<(), FramingError> {
+        if self.magic != FRAME_MAGIC {
+            Err(FramingError::InvalidMagic(self.magic))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Validate the header and get the length of the payload that follows it
+    ///
+    /// Fails with [`FramingError::FrameTooLarge`] if the length is above [`MAX_FRAME_SIZE`].
+    pub fn payload_length(&self) -> Result<usize, FramingError> {
+        self.validate()?;
+        if self.length > MAX_FRAME_SIZE {
+            return Err(FramingError::FrameTooLarge(self.length.into()));
+        }
+        // on 32 bit targets the largest lengths don't fit in memory together with the header
+        usize::try_from(self.length)
+            .ok()
+            .filter(|length| length.checked_add(FRAME_HEADER_SIZE).is_some())
+            .ok_or(FramingError::FrameTooLarge(self.length.into()))
+    }
+}
+
+/// The length of a frame with `len` bytes of payload, as written in the frame header
+pub fn frame_length(len: usize) -> Result<u32, FramingError> {
+    u32::try_from(len).map_err(|_| FramingError::FrameTooLarge(len as u64))
+}
+
+/// Split the first complete frame from the buffer, returning the payload without the header
+///
+/// Returns `None` and leaves the buffer untouched if the buffer doesn't contain a complete frame yet.
+pub fn decode_frame(src: &mut BytesMut) -> Result<Option<BytesMut>, FramingError> {
+    if src.len() < FRAME_HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let header = FrameHeader::read(src[0..FRAME_HEADER_SIZE].try_into().unwrap());
+    let length = header.payload_length()?;
+    if src.len() - FRAME_HEADER_SIZE < length {
+        return Ok(None);
+    }
+
+    src.advance(FRAME_HEADER_SIZE);
+    Ok(Some(src.split_to(length)))
+}
+
+/// Write the frame header and the payload
+pub fn write_frame<W: Write>(mut writer: W, payload: &[u8]) -> Result<(), FramingError> {
+    let mut header = [0; FRAME_HEADER_SIZE];
+    FrameHeader::new(payload.len())?.write(&mut header);
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    Ok(())
+}
+
+#[test]
+fn test_length_prefixed_proto() {
+    use crate::proto::steammessages_base::CMsgProtoBufHeader;
+
+    let header = CMsgProtoBufHeader {
+        steamid: Some(76561198000000000),
+        jobid_source: Some(12),
+        ..CMsgProtoBufHeader::default()
+    };
+    let mut data = Vec::new();
+    write_length_prefixed_proto(&mut data, &header).unwrap();
+    assert_eq!(
+        header.compute_size() as u32,
+        u32::from_le_bytes(data[..4].try_into().unwrap())
+    );
+    // trailing data after the message is left in the reader
+    data.extend_from_slice(&[1, 2, 3]);
+
+    let mut reader = data.as_slice();
+    let read: CMsgProtoBufHeader = read_length_prefixed_proto(&mut reader).unwrap();
+    assert_eq!(header, read);
+    assert_eq!(&[1, 2, 3], reader);
+}
+
+#[test]
+fn test_read_length_prefixed_truncated() {
+    // the length itself is cut off
+    assert!(matches!(
+        read_length_prefixed(&[4, 0][..]),
+        Err(FramingError::Truncated)
+    ));
+    // the length announces more data than there is
+    assert!(matches!(
+        read_length_prefixed(&[4, 0, 0, 0, 1, 2][..]),
+        Err(FramingError::Truncated)
+    ));
+    assert!(matches!(
+        read_length_prefixed(&u32::MAX.to_le_bytes()[..]),
+        Err(FramingError::Truncated)
+    ));
+    assert_eq!(
+        &[1, 2][..],
+        read_length_prefixed(&[2, 0, 0, 0, 1, 2][..]).unwrap()
+    );
+}
+
+#[test]
+fn test_read_length_prefixed_proto_malformed() {
+    // a field tag with an invalid wire type
+    assert!(matches!(
+        read_length_prefixed_proto::<crate::proto::steammessages_base::CMsgMulti, _>(
+            &[1, 0, 0, 0, 0x0f][..]
+        ),
+        Err(FramingError::Malformed(_))
+    ));
+}
+
+#[test]
+fn test_frame_length_boundaries() {
+    assert_eq!(0, frame_length(0).unwrap());
+    assert_eq!(u32::MAX, frame_length(u32::MAX as usize).unwrap());
+    if let Ok(len) = usize::try_from(u64::from(u32::MAX) + 1) {
+        assert!(matches!(
+            frame_length(len),
+            Err(FramingError::FrameTooLarge(4294967296))
+        ));
+    }
+    assert!(frame_length(usize::MAX).is_err());
+
+    // a header announcing the largest possible frame is rejected instead of buffered
+    let header_with_length = |length| {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        FrameHeader {
+            length,
+            magic: FRAME_MAGIC,
+        }
+        .write(&mut header);
+        let mut src = BytesMut::from(&header[..]);
+        src.extend_from_slice(&[1, 2, 3]);
+        src
+    };
+    assert!(matches!(
+        decode_frame(&mut header_with_length(u32::MAX)),
+        Err(FramingError::FrameTooLarge(4294967295))
+    ));
+}
+
+#[test]
+fn test_max_frame_size() {
+    let header = |length| FrameHeader {
+        length,
+        magic: FRAME_MAGIC,
+    };
+    assert_eq!(
+        MAX_FRAME_SIZE as usize,
+        header(MAX_FRAME_SIZE).payload_length().unwrap()
+    );
+    assert!(matches!(
+        header(MAX_FRAME_SIZE + 1).payload_length(),
+        Err(FramingError::FrameTooLarge(len)) if len == u64::from(MAX_FRAME_SIZE) + 1
+    ));
+
+    // a frame at the limit waits for the rest of its payload, one above it fails right away
+    let mut src = BytesMut::new();
+    let mut bytes = [0; FRAME_HEADER_SIZE];
+    header(MAX_FRAME_SIZE).write(&mut bytes);
+    src.extend_from_slice(&bytes);
+    assert!(matches!(decode_frame(&mut src), Ok(None)));
+
+    let mut src = BytesMut::new();
+    header(MAX_FRAME_SIZE + 1).write(&mut bytes);
+    src.extend_from_slice(&bytes);
+    assert!(matches!(
+        decode_frame(&mut src),
+        Err(FramingError::FrameTooLarge(_))
+    ));
+}
+
+#[test]
+fn test_decode_frame() {
+    // an incomplete frame header waits for more data instead of failing
+    for len in 0..FRAME_HEADER_SIZE {
+        let mut src = BytesMut::from(&[4, 0, 0, 0, b'V', b'T', b'0', b'1'][..len]);
+        assert!(matches!(decode_frame(&mut src), Ok(None)));
+        assert_eq!(len, src.len());
+    }
+
+    let mut src = BytesMut::new();
+    write_frame((&mut src).writer(), &[1, 2, 3, 4]).unwrap();
+    write_frame((&mut src).writer(), &[5]).unwrap();
+    assert_eq!(
+        &[4, 0, 0, 0, b'V', b'T', b'0', b'1', 1, 2, 3, 4],
+        &src[..12]
+    );
+    assert_eq!(&[1, 2, 3, 4][..], decode_frame(&mut src).unwrap().unwrap());
+    assert_eq!(&[5][..], decode_frame(&mut src).unwrap().unwrap());
+    assert!(src.is_empty());
+
+    let mut src = BytesMut::from(&[0, 0, 0, 0, b'V', b'T', b'0', b'2'][..]);
+    assert!(matches!(
+        decode_frame(&mut src),
+        Err(FramingError::InvalidMagic(magic)) if magic == *b"VT02"
+    ));
+}
@@ -0,0 +1,131 @@
+use crate::proto::steammessages_clientserver_friends::CMsgClientFriendsGroupsList;
+use std::collections::{BTreeMap, HashSet};
+use steamid_ng::SteamID;
+
+/// A category the user has sorted some of their friends into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FriendGroup {
+    pub id: i32,
+    pub name: String,
+    pub members: HashSet<SteamID>,
+}
+
+/// The friend categories of the logged in account, see [`Connection::friend_groups`](crate::Connection::friend_groups)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FriendGroups {
+    groups: BTreeMap<i32, FriendGroup>,
+}
+
+impl FriendGroups {
+    pub fn groups(&self) -> impl Iterator<Item = &FriendGroup> {
+        self.groups.values()
+    }
+
+    pub fn get(&self, id: i32) -> Option<&FriendGroup> {
+        self.groups.get(&id)
+    }
+
+    /// The groups a friend is a member of
+    pub fn groups_of(&self, steam_id: SteamID) -> impl Iterator<Item = &FriendGroup> {
+        self.groups
+            .values()
+            .filter(move |group| group.members.contains(&steam_id))
+    }
+
+    /// Apply the full list or an incremental update of the groups
+    pub(crate) fn apply(&mut self, update: &CMsgClientFriendsGroupsList) {
+        if !update.bincremental() {
+            self.groups.clear();
+        }
+
+        if update.bremoval() {
+            for group in &update.friendGroups {
+                self.groups.remove(&group.nGroupID());
+            }
+            for membership in &update.memberships {
+                if let Some(group) = self.groups.get_mut(&membership.nGroupID()) {
+                    group.members.remove(&membership.ulSteamID().into());
+                }
+            }
+        } else {
+            for group in &update.friendGroups {
+                let id = group.nGroupID();
+                let name = group.strGroupName().to_string();
+                self.groups
+                    .entry(id)
+                    .and_modify(|group| group.name.clone_from(&name))
+                    .or_insert_with(|| FriendGroup {
+                        id,
+                        name,
+                        members: HashSet::new(),
+                    });
+            }
+            for membership in &update.memberships {
+                if let Some(group) = self.groups.get_mut(&membership.nGroupID()) {
+                    group.members.insert(membership.ulSteamID().into());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_apply_friend_groups() {
+    use crate::proto::steammessages_clientserver_friends::cmsg_client_friends_groups_list::{
+        FriendGroup as ProtoGroup, FriendGroupsMembership,
+    };
+
+    fn group(id: i32, name: &str) -> ProtoGroup {
+        ProtoGroup {
+            nGroupID: Some(id),
+            strGroupName: Some(name.into()),
+            ..ProtoGroup::default()
+        }
+    }
+    fn membership(id: i32, steam_id: u64) -> FriendGroupsMembership {
+        FriendGroupsMembership {
+            ulSteamID: Some(steam_id),
+            nGroupID: Some(id),
+            ..FriendGroupsMembership::default()
+        }
+    }
+
+    let friend = SteamID::from(76561198000000001);
+    let other = SteamID::from(76561198000000002);
+
+    let mut groups = FriendGroups::default();
+    groups.apply(&CMsgClientFriendsGroupsList {
+        friendGroups: vec![group(1, "Work"), group(2, "Games")],
+        memberships: vec![membership(1, friend.into()), membership(2, friend.into())],
+        ..CMsgClientFriendsGroupsList::default()
+    });
+    assert_eq!(2, groups.groups_of(friend).count());
+
+    // incremental update renaming a group and adding a member
+    groups.apply(&CMsgClientFriendsGroupsList {
+        bincremental: Some(true),
+        friendGroups: vec![group(2, "Gaming")],
+        memberships: vec![membership(2, other.into())],
+        ..CMsgClientFriendsGroupsList::default()
+    });
+    let gaming = groups.get(2).unwrap();
+    assert_eq!("Gaming", gaming.name);
+    assert!(gaming.members.contains(&friend));
+    assert!(gaming.members.contains(&other));
+
+    // incremental removal of a group and a membership
+    groups.apply(&CMsgClientFriendsGroupsList {
+        bincremental: Some(true),
+        bremoval: Some(true),
+        friendGroups: vec![group(1, "")],
+        memberships: vec![membership(2, friend.into())],
+        ..CMsgClientFriendsGroupsList::default()
+    });
+    assert!(groups.get(1).is_none());
+    assert_eq!(0, groups.groups_of(friend).count());
+    assert_eq!(1, groups.groups_of(other).count());
+
+    // a full list replaces everything
+    groups.apply(&CMsgClientFriendsGroupsList::default());
+    assert_eq!(0, groups.groups().count());
+}
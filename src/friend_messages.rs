@@ -0,0 +1,85 @@
+use crate::connection::Connection;
+use crate::net::NetworkError;
+use crate::notification::ChatEntryType;
+use crate::proto::steammessages_friendmessages_steamclient::{
+    CFriendMessages_SendMessage_Request, CFriendMessages_SendMessage_Response,
+};
+use steamid_ng::SteamID;
+
+/// A message to a friend that was accepted by steam, see [`Connection::send_friend_message`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentFriendMessage {
+    pub recipient: SteamID,
+    /// The message as stored by steam, which can differ from the sent message
+    pub message: String,
+    pub timestamp: u32,
+    /// Orders messages sent within the same second
+    pub ordinal: u32,
+}
+
+impl SentFriendMessage {
+    fn from_response(
+        recipient: SteamID,
+        message: &str,
+        response: &CFriendMessages_SendMessage_Response,
+    ) -> Self {
+        let stored = match response.modified_message() {
+            "" => message,
+            modified => modified,
+        };
+        SentFriendMessage {
+            recipient,
+            message: stored.into(),
+            timestamp: response.server_timestamp(),
+            ordinal: response.ordinal(),
+        }
+    }
+}
+
+impl Connection {
+    /// Send a chat message to a friend and wait for steam to accept it
+    ///
+    /// Steam doesn't acknowledge messages sent as `CMsgClientFriendMsg`, so those are lost silently when steam
+    /// doesn't deliver them. This fails with [`NetworkError::ApiError`] instead, e.g. when the recipient blocked
+    /// the account or the account is limited.
+    pub async fn send_friend_message(
+        &self,
+        recipient: SteamID,
+        message: &str,
+    ) -> Result<SentFriendMessage, NetworkError> {
+        let response = self
+            .service_method(CFriendMessages_SendMessage_Request {
+                steamid: Some(recipient.into()),
+                chat_entry_type: Some(ChatEntryType::ChatMsg.into()),
+                message: Some(message.into()),
+                contains_bbcode: Some(false),
+                echo_to_sender: Some(false),
+                ..CFriendMessages_SendMessage_Request::default()
+            })
+            .await?;
+        Ok(SentFriendMessage::from_response(
+            recipient, message, &response,
+        ))
+    }
+}
+
+#[test]
+fn test_sent_friend_message_from_response() {
+    let recipient = SteamID::from(76561198000000000);
+    let response = CFriendMessages_SendMessage_Response {
+        server_timestamp: Some(1700000000),
+        ordinal: Some(2),
+        ..CFriendMessages_SendMessage_Response::default()
+    };
+    let sent = SentFriendMessage::from_response(recipient, "hello", &response);
+    assert_eq!("hello", sent.message);
+    assert_eq!(1700000000, sent.timestamp);
+    assert_eq!(2, sent.ordinal);
+
+    let response = CFriendMessages_SendMessage_Response {
+        modified_message: Some("hel[lo]".into()),
+        ..CFriendMessages_SendMessage_Response::default()
+    };
+    let sent = SentFriendMessage::from_response(recipient, "hello", &response);
+    assert_eq!("hel[lo]", sent.message);
+}
@@ -0,0 +1,98 @@
+use crate::connection::Connection;
+use crate::eresult::EResult;
+use crate::net::NetworkError;
+use crate::proto::steammessages_clientserver_friends::{
+    CMsgClientAddFriend, CMsgClientAddFriendResponse, CMsgClientRemoveFriend,
+};
+use steamid_ng::SteamID;
+
+/// A friend invite that was sent, see [`Connection::add_friend`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddedFriend {
+    pub steam_id: SteamID,
+    pub persona_name: String,
+}
+
+impl AddedFriend {
+    fn from_response(response: &CMsgClientAddFriendResponse) -> Result<Self, NetworkError> {
+        EResult::from_result(response.eresult())?;
+        Ok(AddedFriend {
+            steam_id: SteamID::from(response.steam_id_added()),
+            persona_name: response.persona_name_added().into(),
+        })
+    }
+}
+
+impl Connection {
+    /// Send a friend invite, or accept the invite if the account already received one from the user
+    ///
+    /// The user has to be identified by steam id, vanity urls have to be resolved to a steam id first,
+    /// e.g. with the `ResolveVanityURL` web api. Fails with [`NetworkError::ApiError`] if steam refused
+    /// the invite, e.g. because the friends list is full.
+    pub async fn add_friend(&self, steam_id: SteamID) -> Result<AddedFriend, NetworkError> {
+        self.add_friend_request(CMsgClientAddFriend {
+            steamid_to_add: Some(steam_id.into()),
+            ..CMsgClientAddFriend::default()
+        })
+        .await
+    }
+
+    /// Send a friend invite to the account with the account name or email address
+    pub async fn add_friend_by_account_name(
+        &self,
+        account_name_or_email: &str,
+    ) -> Result<AddedFriend, NetworkError> {
+        self.add_friend_request(CMsgClientAddFriend {
+            accountname_or_email_to_add: Some(account_name_or_email.into()),
+            ..CMsgClientAddFriend::default()
+        })
+        .await
+    }
+
+    async fn add_friend_request(
+        &self,
+        request: CMsgClientAddFriend,
+    ) -> Result<AddedFriend, NetworkError> {
+        let response: CMsgClientAddFriendResponse = self.job(request).await?;
+        AddedFriend::from_response(&response)
+    }
+
+    /// Remove a friend, or decline or cancel a pending friend invite
+    ///
+    /// Steam doesn't respond to the removal, the changed relationship is sent as a friends list update.
+    pub async fn remove_friend(&self, steam_id: SteamID) -> Result<(), NetworkError> {
+        let request = CMsgClientRemoveFriend {
+            friendid: Some(steam_id.into()),
+            ..CMsgClientRemoveFriend::default()
+        };
+        self.send(self.session.header(), request).await
+    }
+}
+
+#[test]
+fn test_add_friend_response() {
+    let response = CMsgClientAddFriendResponse {
+        eresult: Some(EResult::OK as i32),
+        steam_id_added: Some(76561198000000001),
+        persona_name_added: Some("friend".into()),
+        ..CMsgClientAddFriendResponse::default()
+    };
+    assert_eq!(
+        AddedFriend {
+            steam_id: SteamID::from(76561198000000001),
+            persona_name: "friend".into(),
+        },
+        AddedFriend::from_response(&response).unwrap()
+    );
+
+    let response = CMsgClientAddFriendResponse {
+        eresult: Some(EResult::LimitExceeded as i32),
+        ..CMsgClientAddFriendResponse::default()
+    };
+    assert!(matches!(
+        AddedFriend::from_response(&response),
+        Err(NetworkError::ApiError(EResult::LimitExceeded))
+    ));
+    // the result defaults to a failure when it's missing
+    assert!(AddedFriend::from_response(&CMsgClientAddFriendResponse::default()).is_err());
+}
@@ -0,0 +1,115 @@
+use crc::{Crc, CRC_32_ISO_HDLC};
+use num_enum::TryFromPrimitive;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+const APP_ID_MASK: u64 = 0xFF_FFFF;
+const TYPE_SHIFT: u64 = 24;
+const TYPE_MASK: u64 = 0xFF;
+const MOD_ID_SHIFT: u64 = 32;
+/// The high bit of the mod id is always set for mods and shortcuts
+const MOD_ID_FLAG: u32 = 0x8000_0000;
+
+/// The type of game a [`GameId`] refers to
+#[derive(TryFromPrimitive, Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum GameType {
+    App = 0,
+    GameMod = 1,
+    Shortcut = 2,
+    P2P = 3,
+}
+
+/// A game id as used by steam, packing the app id, game type and mod id into a single u64
+///
+/// The lower 24 bits contain the app id, the next 8 bits the [`GameType`] and the upper 32 bits the mod id.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct GameId(u64);
+
+impl GameId {
+    fn new(app_id: u32, game_type: GameType, mod_id: u32) -> Self {
+        GameId(
+            (app_id as u64 & APP_ID_MASK)
+                | (game_type as u64) << TYPE_SHIFT
+                | (mod_id as u64) << MOD_ID_SHIFT,
+        )
+    }
+
+    /// The game id for a regular steam app
+    pub fn from_app_id(app_id: u32) -> Self {
+        Self::new(app_id, GameType::App, 0)
+    }
+
+    /// The game id for a mod of a steam app, identified by the path of the mod
+    pub fn from_mod(app_id: u32, mod_path: &str) -> Self {
+        let mod_id = CRC.checksum(mod_path.as_bytes()) | MOD_ID_FLAG;
+        Self::new(app_id, GameType::GameMod, mod_id)
+    }
+
+    /// The game id for a non-steam game added as a shortcut, identified by the executable and name of the shortcut
+    pub fn from_shortcut(exe_path: &str, app_name: &str) -> Self {
+        let mut digest = CRC.digest();
+        digest.update(exe_path.as_bytes());
+        digest.update(app_name.as_bytes());
+        Self::new(0, GameType::Shortcut, digest.finalize() | MOD_ID_FLAG)
+    }
+
+    pub fn app_id(&self) -> u32 {
+        (self.0 & APP_ID_MASK) as u32
+    }
+
+    /// The type of the game, or `None` if the type is unknown
+    pub fn game_type(&self) -> Option<GameType> {
+        GameType::try_from(((self.0 >> TYPE_SHIFT) & TYPE_MASK) as u8).ok()
+    }
+
+    pub fn mod_id(&self) -> u32 {
+        (self.0 >> MOD_ID_SHIFT) as u32
+    }
+}
+
+impl From<u64> for GameId {
+    fn from(value: u64) -> Self {
+        GameId(value)
+    }
+}
+
+impl From<GameId> for u64 {
+    fn from(value: GameId) -> Self {
+        value.0
+    }
+}
+
+#[test]
+fn test_game_id_app() {
+    let game_id = GameId::from_app_id(440);
+    assert_eq!(440, u64::from(game_id));
+    assert_eq!(440, game_id.app_id());
+    assert_eq!(Some(GameType::App), game_id.game_type());
+    assert_eq!(0, game_id.mod_id());
+}
+
+#[test]
+fn test_game_id_layout() {
+    let game_id = GameId::from(0x8000_1234_0300_00DA);
+    assert_eq!(0xDA, game_id.app_id());
+    assert_eq!(Some(GameType::P2P), game_id.game_type());
+    assert_eq!(0x8000_1234, game_id.mod_id());
+
+    let game_id = GameId::from_mod(240, "cstrike");
+    assert_eq!(240, game_id.app_id());
+    assert_eq!(Some(GameType::GameMod), game_id.game_type());
+    assert_eq!(CRC.checksum(b"cstrike") | MOD_ID_FLAG, game_id.mod_id());
+
+    let game_id = GameId::from_shortcut("/usr/bin/game", "Game");
+    assert_eq!(0, game_id.app_id());
+    assert_eq!(Some(GameType::Shortcut), game_id.game_type());
+    assert_eq!(
+        CRC.checksum(b"/usr/bin/gameGame") | MOD_ID_FLAG,
+        game_id.mod_id()
+    );
+    assert_eq!(
+        (game_id.mod_id() as u64) << 32 | 2 << 24,
+        u64::from(game_id)
+    );
+}
@@ -0,0 +1,252 @@
+use crate::connection::Connection;
+use crate::eresult::EResult;
+use crate::game_id::GameId;
+use crate::keyvalues::{KeyValues, Value};
+use crate::message::{MalformedBody, NetMessage};
+use crate::net::{NetMessageHeader, NetworkError};
+use crate::proto::enums_clientserver::EMsg;
+use crate::proto::steammessages_clientserver::cmsg_client_games_played::GamePlayed;
+use crate::proto::steammessages_clientserver::CMsgClientGamesPlayed;
+use binread::BinRead;
+use byteorder::{LittleEndian, WriteBytesExt};
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::trace;
+
+#[derive(Debug)]
+struct ClientOGSBeginSession {
+    account_type: u8,
+    steam_id: u64,
+    app_id: u32,
+    time_started: u32,
+}
+
+impl NetMessage for ClientOGSBeginSession {
+    const KIND: EMsg = EMsg::k_EMsgClientOGSBeginSession;
+
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
+        trace!("writing body of {:?} message", Self::KIND);
+        writer.write_u8(self.account_type)?;
+        writer.write_u64::<LittleEndian>(self.steam_id)?;
+        writer.write_u32::<LittleEndian>(self.app_id)?;
+        writer.write_u32::<LittleEndian>(self.time_started)?;
+        Ok(())
+    }
+
+    fn encode_size(&self) -> usize {
+        1 + 8 + 4 + 4
+    }
+}
+
+#[derive(Debug, BinRead)]
+#[br(little)]
+struct ClientOGSBeginSessionResponse {
+    result: i32,
+    #[allow(dead_code)]
+    collecting_any: u8,
+    #[allow(dead_code)]
+    collecting_details: u8,
+    session_id: u64,
+}
+
+impl NetMessage for ClientOGSBeginSessionResponse {
+    const KIND: EMsg = EMsg::k_EMsgClientOGSBeginSessionResponse;
+
+    fn read_body(data: BytesMut, _header: &NetMessageHeader) -> Result<Self, MalformedBody> {
+        trace!("reading body of {:?} message", Self::KIND);
+        let mut reader = Cursor::new(data);
+        ClientOGSBeginSessionResponse::read(&mut reader)
+            .map_err(|e| MalformedBody::new(Self::KIND, e))
+    }
+}
+
+#[derive(Debug)]
+struct ClientOGSEndSession {
+    session_id: u64,
+    time_ended: u32,
+    reason_code: i32,
+    count_attributes: i32,
+}
+
+impl NetMessage for ClientOGSEndSession {
+    const KIND: EMsg = EMsg::k_EMsgClientOGSEndSession;
+
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
+        trace!("writing body of {:?} message", Self::KIND);
+        writer.write_u64::<LittleEndian>(self.session_id)?;
+        writer.write_u32::<LittleEndian>(self.time_ended)?;
+        writer.write_i32::<LittleEndian>(self.reason_code)?;
+        writer.write_i32::<LittleEndian>(self.count_attributes)?;
+        Ok(())
+    }
+
+    fn encode_size(&self) -> usize {
+        8 + 4 + 4 + 4
+    }
+}
+
+#[derive(Debug, BinRead)]
+#[br(little)]
+struct ClientOGSEndSessionResponse {
+    result: i32,
+}
+
+impl NetMessage for ClientOGSEndSessionResponse {
+    const KIND: EMsg = EMsg::k_EMsgClientOGSEndSessionResponse;
+
+    fn read_body(data: BytesMut, _header: &NetMessageHeader) -> Result<Self, MalformedBody> {
+        trace!("reading body of {:?} message", Self::KIND);
+        let mut reader = Cursor::new(data);
+        ClientOGSEndSessionResponse::read(&mut reader)
+            .map_err(|e| MalformedBody::new(Self::KIND, e))
+    }
+}
+
+/// A game session started with [`Connection::begin_game_session`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameSession {
+    pub app_id: u32,
+    /// The session id assigned by the server
+    pub session_id: u64,
+}
+
+fn unix_time() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+/// Encode the rich presence as the `RP` object of a binary KeyValues blob, sorted by key
+fn rich_presence_blob(rich_presence: &HashMap<String, String>) -> Vec<u8> {
+    let mut entries: Vec<_> = rich_presence.iter().collect();
+    entries.sort();
+    let mut presence = KeyValues::default();
+    for (key, value) in entries {
+        presence.push(key.as_str(), Value::String(value.clone()));
+    }
+    let mut blob = KeyValues::default();
+    blob.push("RP", Value::Object(presence));
+    blob.to_binary()
+}
+
+fn games_played_request(games: impl Iterator<Item = GamePlayed>) -> CMsgClientGamesPlayed {
+    CMsgClientGamesPlayed {
+        games_played: games.collect(),
+        ..CMsgClientGamesPlayed::default()
+    }
+}
+
+impl Connection {
+    /// Begin a game session for the app, used by steam to record playtime
+    pub async fn begin_game_session(&self, app_id: u32) -> Result<GameSession, NetworkError> {
+        let steam_id = self.steam_id();
+        let request = ClientOGSBeginSession {
+            account_type: steam_id.account_type() as u8,
+            steam_id: steam_id.into(),
+            app_id,
+            time_started: unix_time(),
+        };
+        let response: ClientOGSBeginSessionResponse = self.job(request).await?;
+        EResult::from_result(response.result)?;
+        Ok(GameSession {
+            app_id,
+            session_id: response.session_id,
+        })
+    }
+
+    /// End a game session started with [`Connection::begin_game_session`]
+    pub async fn end_game_session(&self, session: GameSession) -> Result<(), NetworkError> {
+        let request = ClientOGSEndSession {
+            session_id: session.session_id,
+            time_ended: unix_time(),
+            reason_code: 0,
+            count_attributes: 0,
+        };
+        let response: ClientOGSEndSessionResponse = self.job(request).await?;
+        EResult::from_result(response.result)?;
+        Ok(())
+    }
+
+    /// Set the games the user is shown as playing, an empty list stops playing
+    pub async fn set_games_played(&self, games: &[GameId]) -> Result<(), NetworkError> {
+        let request = games_played_request(games.iter().map(|game_id| GamePlayed {
+            game_id: Some((*game_id).into()),
+            ..GamePlayed::default()
+        }));
+        self.send_games_played(request).await
+    }
+
+    /// Like [`set_games_played`](Self::set_games_played) but also set the rich presence shown to friends for each game
+    ///
+    /// The rich presence is a set of key/value pairs, what keys are shown depends on the localization of the game,
+    /// `status` and `steam_display` are used by most games.
+    pub async fn set_games_played_with_rich_presence(
+        &self,
+        games: &[(GameId, HashMap<String, String>)],
+    ) -> Result<(), NetworkError> {
+        let request =
+            games_played_request(games.iter().map(|(game_id, rich_presence)| GamePlayed {
+                game_id: Some((*game_id).into()),
+                game_data_blob: Some(rich_presence_blob(rich_presence)),
+                ..GamePlayed::default()
+            }));
+        self.send_games_played(request).await
+    }
+
+    /// Send the games played and remember them for restoring them after reconnecting
+    pub(crate) async fn send_games_played(
+        &self,
+        request: CMsgClientGamesPlayed,
+    ) -> Result<(), NetworkError> {
+        self.send(self.session.header(), request.clone()).await?;
+        self.presence.lock().unwrap().games_played = Some(request);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_read_begin_session_response() {
+    let data = BytesMut::from(&[1, 0, 0, 0, 1, 0, 0x0d, 0xf0, 0, 0, 0, 0, 0, 0][..]);
+    let response =
+        ClientOGSBeginSessionResponse::read_body(data, &NetMessageHeader::default()).unwrap();
+    assert_eq!(1, response.result);
+    assert_eq!(0xf00d, response.session_id);
+
+    let data = BytesMut::from(&[1, 0, 0, 0, 1, 0, 0x0d, 0xf0][..]);
+    assert!(ClientOGSBeginSessionResponse::read_body(data, &NetMessageHeader::default()).is_err());
+}
+
+#[test]
+fn test_games_played_with_rich_presence() {
+    use protobuf::Message;
+
+    let rich_presence = HashMap::from([
+        ("steam_display".to_string(), "#Status".to_string()),
+        ("status".to_string(), "Idling".to_string()),
+    ]);
+    let request = games_played_request(
+        [GamePlayed {
+            game_id: Some(GameId::from_app_id(440).into()),
+            game_data_blob: Some(rich_presence_blob(&rich_presence)),
+            ..GamePlayed::default()
+        }]
+        .into_iter(),
+    );
+    // binary KeyValues: 0x00 starts an object, 0x01 a string and 0x08 ends an object,
+    // keys and values are nul terminated
+    let blob = b"\x00RP\x00\x01status\x00Idling\x00\x01steam_display\x00#Status\x00\x08\x08";
+    assert_eq!(&blob[..], rich_presence_blob(&rich_presence));
+
+    // protobuf: the game is field 1 of the request, with the game id as fixed64 field 2
+    // and the blob as bytes field 8
+    let mut game = vec![0x11];
+    game.extend_from_slice(&440u64.to_le_bytes());
+    game.extend_from_slice(&[0x42, blob.len() as u8]);
+    game.extend_from_slice(blob);
+    let mut expected = vec![0x0a, game.len() as u8];
+    expected.extend_from_slice(&game);
+    assert_eq!(expected, request.write_to_bytes().unwrap());
+}
@@ -0,0 +1,253 @@
+use crate::connection::Connection;
+use crate::message::{MalformedBody, NetMessage};
+use crate::net::{NetMessageHeader, NetworkError, RawNetMessage};
+use crate::proto::enums_clientserver::EMsg;
+use crate::proto::steammessages_clientserver_2::CMsgGCClient;
+use bytes::BytesMut;
+use dashmap::DashMap;
+use protobuf::Message;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, error};
+
+/// Set in the message type of game coordinator messages with a protobuf body
+const PROTOBUF_FLAG: u32 = 0x8000_0000;
+
+/// A message from or to the game coordinator of an app
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcMessage {
+    pub app_id: u32,
+    /// The message type as defined by the game coordinator, without the protobuf flag
+    pub msg_type: u32,
+    /// Whether the payload starts with a protobuf header
+    pub is_protobuf: bool,
+    pub payload: Vec<u8>,
+}
+
+impl From<&CMsgGCClient> for GcMessage {
+    fn from(message: &CMsgGCClient) -> Self {
+        GcMessage {
+            app_id: message.appid(),
+            msg_type: message.msgtype() & !PROTOBUF_FLAG,
+            is_protobuf: message.msgtype() & PROTOBUF_FLAG != 0,
+            payload: message.payload().into(),
+        }
+    }
+}
+
+impl From<&GcMessage> for CMsgGCClient {
+    fn from(message: &GcMessage) -> Self {
+        let flag = if message.is_protobuf {
+            PROTOBUF_FLAG
+        } else {
+            0
+        };
+        CMsgGCClient {
+            appid: Some(message.app_id),
+            msgtype: Some(message.msg_type | flag),
+            payload: Some(message.payload.clone()),
+            ..CMsgGCClient::default()
+        }
+    }
+}
+
+/// The envelope for sending a message to a game coordinator, the same protobuf is used for both directions
+#[derive(Debug)]
+struct ClientToGc(CMsgGCClient);
+
+impl NetMessage for ClientToGc {
+    const KIND: EMsg = EMsg::k_EMsgClientToGC;
+    const IS_PROTOBUF: bool = true;
+
+    fn read_body(data: BytesMut, _header: &NetMessageHeader) -> Result<Self, MalformedBody> {
+        CMsgGCClient::parse_from_bytes(&data)
+            .map(ClientToGc)
+            .map_err(|e| MalformedBody::new(Self::KIND, e))
+    }
+
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
+        self.0
+            .write_to_writer(&mut writer)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))
+    }
+
+    fn encode_size(&self) -> usize {
+        self.0.compute_size() as usize
+    }
+}
+
+type GcHandler = Arc<dyn Fn(GcMessage) + Send + Sync>;
+
+/// The registered game coordinator handlers by app id and message type, with the id of the registration
+pub(crate) type GcHandlers = Arc<DashMap<(u32, u32), (u64, GcHandler)>>;
+
+static NEXT_REGISTRATION: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps a game coordinator handler registered, the handler is removed when the guard is dropped
+///
+/// See [`Connection::handle_gc_message`]
+#[must_use = "the handler is unregistered when the guard is dropped"]
+pub struct GcHandlerGuard {
+    handlers: GcHandlers,
+    key: (u32, u32),
+    registration: u64,
+}
+
+impl GcHandlerGuard {
+    /// The app id and message type the handler is registered for
+    pub fn key(&self) -> (u32, u32) {
+        self.key
+    }
+}
+
+impl Drop for GcHandlerGuard {
+    fn drop(&mut self) {
+        // the handler might have been replaced by a newer registration that should stay
+        self.handlers.remove_if(&self.key, |_, (registration, _)| {
+            *registration == self.registration
+        });
+    }
+}
+
+pub(crate) fn register_gc_handler(
+    handlers: &GcHandlers,
+    app_id: u32,
+    msg_type: u32,
+    handler: GcHandler,
+) -> GcHandlerGuard {
+    let registration = NEXT_REGISTRATION.fetch_add(1, Ordering::Relaxed);
+    let key = (app_id, msg_type & !PROTOBUF_FLAG);
+    handlers.insert(key, (registration, handler));
+    GcHandlerGuard {
+        handlers: handlers.clone(),
+        key,
+        registration,
+    }
+}
+
+/// Pass a `ClientFromGC` message to the handler registered for it, returns `false` if there is no handler
+pub(crate) fn dispatch_gc(handlers: &GcHandlers, message: &RawNetMessage) -> bool {
+    let message = match CMsgGCClient::parse_from_bytes(&message.data) {
+        Ok(message) => GcMessage::from(&message),
+        Err(e) => {
+            error!(error = ?e, "failed to parse game coordinator message");
+            return false;
+        }
+    };
+    // clone the handler so the map isn't locked while it runs
+    let Some(handler) = handlers
+        .get(&(message.app_id, message.msg_type))
+        .map(|entry| entry.1.clone())
+    else {
+        return false;
+    };
+    debug!(
+        app_id = message.app_id,
+        msg_type = message.msg_type,
+        "dispatching game coordinator message"
+    );
+    handler(message);
+    true
+}
+
+impl Connection {
+    /// Send a message to the game coordinator of an app
+    ///
+    /// The account has to be playing the app, see [`Connection::set_games_played`].
+    pub async fn send_to_gc(&self, message: &GcMessage) -> Result<(), NetworkError> {
+        self.send_for_app(message.app_id, ClientToGc(message.into()))
+            .await
+    }
+
+    /// Handle the messages of one type from the game coordinator of an app
+    ///
+    /// This allows a single connection to talk to the game coordinators of several apps at once, each
+    /// handled separately. The handler runs on the read loop of the connection, so it should only forward
+    /// the message, e.g. into a channel. Registering a handler for the same app and message type replaces the
+    /// previous one. Messages without a handler are delivered to [`Connection::next`] and the kind
+    /// listeners for `ClientFromGC` like before. The handler stays registered after reconnecting,
+    /// until the returned guard is dropped.
+    pub fn handle_gc_message<F>(&self, app_id: u32, msg_type: u32, handler: F) -> GcHandlerGuard
+    where
+        F: Fn(GcMessage) + Send + Sync + 'static,
+    {
+        register_gc_handler(self.gc_handlers(), app_id, msg_type, Arc::new(handler))
+    }
+}
+
+#[test]
+fn test_gc_handlers() {
+    use std::sync::Mutex;
+
+    let incoming = |app_id, msgtype| {
+        let message = CMsgGCClient {
+            appid: Some(app_id),
+            msgtype: Some(msgtype),
+            payload: Some(vec![1, 2, 3]),
+            ..CMsgGCClient::default()
+        };
+        let header = NetMessageHeader::default();
+        RawNetMessage::from_message(header, ClientToGc(message)).unwrap()
+    };
+
+    let handlers = GcHandlers::default();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let sink = |name: &'static str| {
+        let received = received.clone();
+        Arc::new(move |message: GcMessage| received.lock().unwrap().push((name, message)))
+    };
+    let tf2 = register_gc_handler(&handlers, 440, 4004, sink("tf2"));
+    let cs2 = register_gc_handler(&handlers, 730, 4004, sink("cs2"));
+
+    assert!(dispatch_gc(&handlers, &incoming(440, 4004 | PROTOBUF_FLAG)));
+    assert!(dispatch_gc(&handlers, &incoming(730, 4004)));
+    assert!(!dispatch_gc(&handlers, &incoming(570, 4004)));
+    assert!(!dispatch_gc(&handlers, &incoming(440, 4005)));
+    assert_eq!(
+        vec![
+            (
+                "tf2",
+                GcMessage {
+                    app_id: 440,
+                    msg_type: 4004,
+                    is_protobuf: true,
+                    payload: vec![1, 2, 3],
+                }
+            ),
+            (
+                "cs2",
+                GcMessage {
+                    app_id: 730,
+                    msg_type: 4004,
+                    is_protobuf: false,
+                    payload: vec![1, 2, 3],
+                }
+            ),
+        ],
+        *received.lock().unwrap()
+    );
+
+    drop(cs2);
+    assert!(!dispatch_gc(&handlers, &incoming(730, 4004)));
+
+    // dropping a replaced registration keeps the newer handler
+    let replacement = register_gc_handler(&handlers, 440, 4004, sink("replacement"));
+    drop(tf2);
+    assert!(dispatch_gc(&handlers, &incoming(440, 4004)));
+    assert_eq!("replacement", received.lock().unwrap().last().unwrap().0);
+    assert_eq!((440, 4004), replacement.key());
+}
+
+#[test]
+fn test_gc_message_envelope() {
+    let message = GcMessage {
+        app_id: 730,
+        msg_type: 9107,
+        is_protobuf: true,
+        payload: vec![4, 5],
+    };
+    let envelope = CMsgGCClient::from(&message);
+    assert_eq!(9107 | PROTOBUF_FLAG, envelope.msgtype());
+    assert_eq!(message, GcMessage::from(&envelope));
+}
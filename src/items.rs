@@ -0,0 +1,91 @@
+use crate::connection::Connection;
+use crate::net::NetworkError;
+use crate::proto::steammessages_clientserver_2::{
+    cmsg_client_item_announcements, CMsgClientItemAnnouncements, CMsgClientRequestItemAnnouncements,
+};
+
+/// An item that was added to the inventory and wasn't seen yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnseenItem {
+    pub app_id: u32,
+    pub context_id: u64,
+    pub asset_id: u64,
+    pub amount: u64,
+    /// Unix timestamp of when the item was received
+    pub gained_at: u32,
+    /// The app that granted the item, if it is different from the app the item belongs to
+    pub source_app_id: Option<u32>,
+}
+
+impl From<&cmsg_client_item_announcements::UnseenItem> for UnseenItem {
+    fn from(item: &cmsg_client_item_announcements::UnseenItem) -> Self {
+        UnseenItem {
+            app_id: item.appid(),
+            context_id: item.context_id(),
+            asset_id: item.asset_id(),
+            amount: item.amount(),
+            gained_at: item.rtime32_gained(),
+            source_app_id: item.source_appid,
+        }
+    }
+}
+
+/// The new items in the inventory, sent by steam when items are received and when requested with
+/// [`Connection::request_item_announcements`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ItemAnnouncements {
+    /// The number of new items, this can be larger than the number of listed items
+    pub new_items: u32,
+    pub unseen_items: Vec<UnseenItem>,
+}
+
+impl From<&CMsgClientItemAnnouncements> for ItemAnnouncements {
+    fn from(message: &CMsgClientItemAnnouncements) -> Self {
+        ItemAnnouncements {
+            new_items: message.count_new_items(),
+            unseen_items: message.unseen_items.iter().map(UnseenItem::from).collect(),
+        }
+    }
+}
+
+impl Connection {
+    /// Request the new items in the inventory
+    ///
+    /// Later changes are delivered as [`Notification::ItemAnnouncements`](crate::Notification::ItemAnnouncements).
+    pub async fn request_item_announcements(&self) -> Result<ItemAnnouncements, NetworkError> {
+        let response: CMsgClientItemAnnouncements = self
+            .job(CMsgClientRequestItemAnnouncements::default())
+            .await?;
+        Ok(ItemAnnouncements::from(&response))
+    }
+}
+
+#[test]
+fn test_item_announcements() {
+    let message = CMsgClientItemAnnouncements {
+        count_new_items: Some(3),
+        unseen_items: vec![cmsg_client_item_announcements::UnseenItem {
+            appid: Some(730),
+            context_id: Some(2),
+            asset_id: Some(28_000_000_001),
+            amount: Some(1),
+            rtime32_gained: Some(1_700_000_000),
+            ..Default::default()
+        }],
+        ..CMsgClientItemAnnouncements::default()
+    };
+    assert_eq!(
+        ItemAnnouncements {
+            new_items: 3,
+            unseen_items: vec![UnseenItem {
+                app_id: 730,
+                context_id: 2,
+                asset_id: 28_000_000_001,
+                amount: 1,
+                gained_at: 1_700_000_000,
+                source_app_id: None,
+            }],
+        },
+        ItemAnnouncements::from(&message)
+    );
+}
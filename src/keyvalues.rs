@@ -0,0 +1,485 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read};
+use thiserror::Error;
+
+/// Error while parsing binary KeyValues data
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum KeyValuesError {
+    #[error("unexpected end of KeyValues data")]
+    Truncated,
+    #[error("unknown KeyValues value type {0}")]
+    UnknownType(u8),
+    #[error("invalid string in KeyValues data")]
+    InvalidString,
+    #[error("KeyValues objects are nested more than {MAX_DEPTH} levels deep")]
+    TooDeep,
+}
+
+impl From<std::io::Error> for KeyValuesError {
+    fn from(_: std::io::Error) -> Self {
+        KeyValuesError::Truncated
+    }
+}
+
+/// The deepest nesting of objects that is parsed
+///
+/// Parsing and dropping a tree recurses once per nested object, so a blob of only object starts could
+/// otherwise overflow the stack. Steam's KeyValues data is nested only a few levels deep.
+pub const MAX_DEPTH: usize = 256;
+
+const TYPE_NONE: u8 = 0;
+const TYPE_STRING: u8 = 1;
+const TYPE_INT32: u8 = 2;
+const TYPE_FLOAT32: u8 = 3;
+const TYPE_POINTER: u8 = 4;
+const TYPE_WIDE_STRING: u8 = 5;
+const TYPE_COLOR: u8 = 6;
+const TYPE_UINT64: u8 = 7;
+const TYPE_END: u8 = 8;
+const TYPE_INT64: u8 = 10;
+const TYPE_ALTERNATE_END: u8 = 11;
+
+/// A single value in a KeyValues tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Object(KeyValues),
+    String(String),
+    Int32(i32),
+    Float32(f32),
+    Pointer(i32),
+    WideString(String),
+    Color(u32),
+    UInt64(u64),
+    Int64(i64),
+}
+
+impl Value {
+    pub fn as_object(&self) -> Option<&KeyValues> {
+        match self {
+            Value::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) | Value::WideString(value) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Get the value as integer, converting from the other integer types if it fits
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int32(value) | Value::Pointer(value) => Some(*value as i64),
+            Value::Color(value) => Some(*value as i64),
+            Value::UInt64(value) => (*value).try_into().ok(),
+            Value::Int64(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        self.as_i64().and_then(|value| value.try_into().ok())
+    }
+
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Value::Float32(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// An ordered set of KeyValues entries
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyValues {
+    entries: Vec<(String, Value)>,
+}
+
+impl KeyValues {
+    /// Parse a binary encoded KeyValues blob
+    ///
+    /// For large blobs where only a few entries are needed, [`KeyValuesReader`] avoids building the whole tree.
+    pub fn parse_binary(data: &[u8]) -> Result<KeyValues, KeyValuesError> {
+        KeyValuesReader::new(Cursor::new(data)).read_object()
+    }
+
+    /// Get the value for a key, keys are compared case-insensitive
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add an entry after the existing entries
+    pub fn push(&mut self, key: impl Into<String>, value: Value) {
+        self.entries.push((key.into(), value));
+    }
+
+    /// Encode as binary KeyValues blob, the inverse of [`KeyValues::parse_binary`]
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_object(&mut out, self);
+        out
+    }
+}
+
+fn write_object(out: &mut Vec<u8>, object: &KeyValues) {
+    for (name, value) in &object.entries {
+        let ty = match value {
+            Value::Object(_) => TYPE_NONE,
+            Value::String(_) => TYPE_STRING,
+            Value::Int32(_) => TYPE_INT32,
+            Value::Float32(_) => TYPE_FLOAT32,
+            Value::Pointer(_) => TYPE_POINTER,
+            Value::WideString(_) => TYPE_WIDE_STRING,
+            Value::Color(_) => TYPE_COLOR,
+            Value::UInt64(_) => TYPE_UINT64,
+            Value::Int64(_) => TYPE_INT64,
+        };
+        out.push(ty);
+        write_string(out, name);
+        // writing to a vec can't fail
+        match value {
+            Value::Object(object) => write_object(out, object),
+            Value::String(value) => write_string(out, value),
+            Value::Int32(value) | Value::Pointer(value) => {
+                out.write_i32::<LittleEndian>(*value).unwrap()
+            }
+            Value::Float32(value) => out.write_f32::<LittleEndian>(*value).unwrap(),
+            Value::WideString(value) => {
+                for char in value.encode_utf16().chain([0]) {
+                    out.write_u16::<LittleEndian>(char).unwrap();
+                }
+            }
+            Value::Color(value) => out.write_u32::<LittleEndian>(*value).unwrap(),
+            Value::UInt64(value) => out.write_u64::<LittleEndian>(*value).unwrap(),
+            Value::Int64(value) => out.write_i64::<LittleEndian>(*value).unwrap(),
+        }
+    }
+    out.push(TYPE_END);
+}
+
+/// Strings are null terminated, so anything after a null byte in the string is cut off
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    let value = value.split('\0').next().unwrap_or_default();
+    out.extend_from_slice(value.as_bytes());
+    out.push(0);
+}
+
+/// An entry read by [`KeyValuesReader`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// The start of an object, followed by the entries of the object and an [`Event::End`]
+    Start(String),
+    /// An entry that isn't an object
+    Entry(String, Value),
+    /// The end of the last started object
+    End,
+}
+
+/// Streaming parser for binary KeyValues, reading a single entry at a time
+///
+/// Unlike [`KeyValues::parse_binary`] this doesn't require the blob to be in memory or build the whole tree,
+/// objects that aren't needed can be skipped with [`KeyValuesReader::skip_object`] and the objects that are
+/// needed read with [`KeyValuesReader::read_object`].
+///
+/// ```
+/// # use steam_vent::keyvalues::{Event, KeyValuesReader};
+/// # fn depots(blob: &[u8]) -> Result<(), steam_vent::keyvalues::KeyValuesError> {
+/// let mut reader = KeyValuesReader::new(blob);
+/// while let Some(event) = reader.next_event()? {
+///     match event {
+///         Event::Start(name) if name == "depots" => {
+///             let depots = reader.read_object()?;
+///             println!("{} depots", depots.len());
+///         }
+///         Event::Start(name) if name != "appinfo" => reader.skip_object()?,
+///         _ => {}
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct KeyValuesReader<R> {
+    reader: R,
+    depth: usize,
+    done: bool,
+}
+
+impl<R: Read> KeyValuesReader<R> {
+    pub fn new(reader: R) -> Self {
+        KeyValuesReader {
+            reader,
+            depth: 0,
+            done: false,
+        }
+    }
+
+    /// The number of objects that are started but not ended
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Start a nested object, fails with [`KeyValuesError::TooDeep`] past [`MAX_DEPTH`]
+    fn enter_object(&mut self) -> Result<(), KeyValuesError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(KeyValuesError::TooDeep);
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Read the type of the next entry, `None` once the end of the blob is reached
+    fn next_type(&mut self) -> Result<Option<u8>, KeyValuesError> {
+        if self.done {
+            return Ok(None);
+        }
+        let ty = match self.reader.read_u8() {
+            Ok(ty) => ty,
+            // only the root object can end without an end marker
+            Err(_) if self.depth == 0 => TYPE_END,
+            Err(_) => return Err(KeyValuesError::Truncated),
+        };
+        if ty == TYPE_END || ty == TYPE_ALTERNATE_END {
+            if self.depth == 0 {
+                self.done = true;
+                return Ok(None);
+            }
+            self.depth -= 1;
+        }
+        Ok(Some(ty))
+    }
+
+    /// Read the next entry, `None` once the end of the blob is reached
+    pub fn next_event(&mut self) -> Result<Option<Event>, KeyValuesError> {
+        let Some(ty) = self.next_type()? else {
+            return Ok(None);
+        };
+        if ty == TYPE_END || ty == TYPE_ALTERNATE_END {
+            return Ok(Some(Event::End));
+        }
+        let name = read_string(&mut self.reader)?;
+        let reader = &mut self.reader;
+        let value = match ty {
+            TYPE_NONE => {
+                self.enter_object()?;
+                return Ok(Some(Event::Start(name)));
+            }
+            TYPE_STRING => Value::String(read_string(reader)?),
+            TYPE_INT32 => Value::Int32(reader.read_i32::<LittleEndian>()?),
+            TYPE_FLOAT32 => Value::Float32(reader.read_f32::<LittleEndian>()?),
+            TYPE_POINTER => Value::Pointer(reader.read_i32::<LittleEndian>()?),
+            TYPE_WIDE_STRING => Value::WideString(read_wide_string(reader)?),
+            TYPE_COLOR => Value::Color(reader.read_u32::<LittleEndian>()?),
+            TYPE_UINT64 => Value::UInt64(reader.read_u64::<LittleEndian>()?),
+            TYPE_INT64 => Value::Int64(reader.read_i64::<LittleEndian>()?),
+            ty => return Err(KeyValuesError::UnknownType(ty)),
+        };
+        Ok(Some(Event::Entry(name, value)))
+    }
+
+    /// Read the rest of the current object into a tree, including its end marker
+    ///
+    /// At the start of the blob this reads the whole blob, like [`KeyValues::parse_binary`].
+    pub fn read_object(&mut self) -> Result<KeyValues, KeyValuesError> {
+        let mut entries = Vec::new();
+        while let Some(event) = self.next_event()? {
+            match event {
+                Event::Start(name) => entries.push((name, Value::Object(self.read_object()?))),
+                Event::Entry(name, value) => entries.push((name, value)),
+                Event::End => break,
+            }
+        }
+        Ok(KeyValues { entries })
+    }
+
+    /// Skip the rest of the current object, including its end marker, without parsing the entries
+    pub fn skip_object(&mut self) -> Result<(), KeyValuesError> {
+        let depth = self.depth;
+        while self.depth >= depth {
+            let Some(ty) = self.next_type()? else {
+                break;
+            };
+            if ty == TYPE_END || ty == TYPE_ALTERNATE_END {
+                continue;
+            }
+            let reader = &mut self.reader;
+            skip_string(reader)?;
+            match ty {
+                TYPE_NONE => self.enter_object()?,
+                TYPE_STRING => skip_string(reader)?,
+                TYPE_INT32 | TYPE_FLOAT32 | TYPE_POINTER | TYPE_COLOR => skip_bytes(reader, 4)?,
+                TYPE_UINT64 | TYPE_INT64 => skip_bytes(reader, 8)?,
+                TYPE_WIDE_STRING => while reader.read_u16::<LittleEndian>()? != 0 {},
+                ty => return Err(KeyValuesError::UnknownType(ty)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn skip_string<R: Read>(reader: &mut R) -> Result<(), KeyValuesError> {
+    while reader.read_u8()? != 0 {}
+    Ok(())
+}
+
+fn skip_bytes<R: Read>(reader: &mut R, count: u64) -> Result<(), KeyValuesError> {
+    let skipped = std::io::copy(&mut reader.take(count), &mut std::io::sink())?;
+    if skipped < count {
+        return Err(KeyValuesError::Truncated);
+    }
+    Ok(())
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String, KeyValuesError> {
+    let mut bytes = Vec::new();
+    loop {
+        match reader.read_u8()? {
+            0 => break,
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| KeyValuesError::InvalidString)
+}
+
+fn read_wide_string<R: Read>(reader: &mut R) -> Result<String, KeyValuesError> {
+    let mut chars = Vec::new();
+    loop {
+        match reader.read_u16::<LittleEndian>()? {
+            0 => break,
+            char => chars.push(char),
+        }
+    }
+    String::from_utf16(&chars).map_err(|_| KeyValuesError::InvalidString)
+}
+
+#[test]
+fn test_parse_binary() {
+    let data = b"\x00MessageObject\x00\x01name\x00value\x00\x02count\x00\x02\x00\x00\x00\
+        \x00items\x00\x07big\x00\x01\x00\x00\x00\x01\x00\x00\x00\x08\x08\x08";
+    let parsed = KeyValues::parse_binary(data).unwrap();
+    let object = parsed.get("messageobject").unwrap().as_object().unwrap();
+    assert_eq!(Some("value"), object.get("name").unwrap().as_str());
+    assert_eq!(Some(2), object.get("count").unwrap().as_u32());
+    let items = object.get("items").unwrap().as_object().unwrap();
+    assert_eq!(Some(0x1_0000_0001), items.get("big").unwrap().as_i64());
+}
+
+#[test]
+fn test_binary_roundtrip() {
+    let data = b"\x00MessageObject\x00\x01name\x00value\x00\x02count\x00\x02\x00\x00\x00\
+        \x00items\x00\x07big\x00\x01\x00\x00\x00\x01\x00\x00\x00\x08\x08\x08";
+    let parsed = KeyValues::parse_binary(data).unwrap();
+    assert_eq!(&data[..], parsed.to_binary());
+
+    let mut object = KeyValues::default();
+    object.push("wide", Value::WideString("wide".into()));
+    object.push("float", Value::Float32(1.5));
+    let mut root = KeyValues::default();
+    root.push("root", Value::Object(object));
+    assert_eq!(root, KeyValues::parse_binary(&root.to_binary()).unwrap());
+}
+
+#[test]
+fn test_reader_events() {
+    let data = b"\x00MessageObject\x00\x01name\x00value\x00\x02count\x00\x02\x00\x00\x00\
+        \x00items\x00\x07big\x00\x01\x00\x00\x00\x01\x00\x00\x00\x08\x08\x08";
+    let mut reader = KeyValuesReader::new(&data[..]);
+    let mut events = Vec::new();
+    while let Some(event) = reader.next_event().unwrap() {
+        events.push(event);
+    }
+    assert_eq!(
+        vec![
+            Event::Start("MessageObject".into()),
+            Event::Entry("name".into(), Value::String("value".into())),
+            Event::Entry("count".into(), Value::Int32(2)),
+            Event::Start("items".into()),
+            Event::Entry("big".into(), Value::UInt64(0x1_0000_0001)),
+            Event::End,
+            Event::End,
+        ],
+        events
+    );
+
+    // skipping the nested object continues after its end marker
+    let data =
+        b"\x00outer\x00\x00skipped\x00\x01a\x00b\x00\x05wide\x00w\x00\x00\x00\x00inner\x00\x08\x08\
+        \x02kept\x00\x01\x00\x00\x00\x08\x08";
+    let mut reader = KeyValuesReader::new(&data[..]);
+    assert_eq!(
+        Some(Event::Start("outer".into())),
+        reader.next_event().unwrap()
+    );
+    assert_eq!(
+        Some(Event::Start("skipped".into())),
+        reader.next_event().unwrap()
+    );
+    reader.skip_object().unwrap();
+    assert_eq!(1, reader.depth());
+    assert_eq!(
+        Some(Event::Entry("kept".into(), Value::Int32(1))),
+        reader.next_event().unwrap()
+    );
+    assert_eq!(Some(Event::End), reader.next_event().unwrap());
+    assert_eq!(None, reader.next_event().unwrap());
+
+    let mut reader = KeyValuesReader::new(&b"\x00outer\x00\x01a\x00b"[..]);
+    reader.next_event().unwrap();
+    assert!(matches!(
+        reader.skip_object(),
+        Err(KeyValuesError::Truncated)
+    ));
+}
+
+#[test]
+fn test_parse_binary_too_deep() {
+    // an object start with an empty name is only 2 bytes per level
+    let data = b"\x00\x00".repeat(100_000);
+    assert!(matches!(
+        KeyValues::parse_binary(&data),
+        Err(KeyValuesError::TooDeep)
+    ));
+    let mut reader = KeyValuesReader::new(&data[..]);
+    reader.next_event().unwrap();
+    assert!(matches!(reader.skip_object(), Err(KeyValuesError::TooDeep)));
+
+    // nesting up to the limit is fine
+    let mut data = b"\x00\x00".repeat(MAX_DEPTH);
+    data.extend(b"\x08".repeat(MAX_DEPTH));
+    let mut parsed = KeyValues::parse_binary(&data).unwrap();
+    for _ in 1..MAX_DEPTH {
+        parsed = parsed.get("").unwrap().as_object().unwrap().clone();
+    }
+    assert!(parsed.get("").unwrap().as_object().unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_binary_truncated() {
+    assert!(matches!(
+        KeyValues::parse_binary(b"\x00MessageObject\x00\x02count\x00\x02\x00"),
+        Err(KeyValuesError::Truncated)
+    ));
+    assert!(matches!(
+        KeyValues::parse_binary(b"\x00MessageObject\x00"),
+        Err(KeyValuesError::Truncated)
+    ));
+}
@@ -0,0 +1,376 @@
+//! The legacy multi-user chat, which predates the chat room groups
+//!
+//! New integrations should use the chat room groups instead, see [`Connection::chat_room_groups`].
+//! The legacy chat is still used by older clan chats and bots.
+
+use crate::connection::Connection;
+use crate::message::{MalformedBody, NetMessage};
+use crate::net::{NetMessageHeader, NetworkError};
+use crate::notification::ChatEntryType;
+use crate::proto::enums_clientserver::EMsg;
+use binread::{BinRead, BinReaderExt, NullString};
+use byteorder::{LittleEndian, WriteBytesExt};
+use bytes::BytesMut;
+use std::io::{Cursor, Write};
+use steamid_ng::{AccountType, Instance, SteamID};
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::timeout;
+use tracing::trace;
+
+/// `EChatInfoType::StateChange`
+const CHAT_INFO_STATE_CHANGE: u32 = 1;
+/// `EChatMemberStateChange::Left`
+const CHAT_MEMBER_LEFT: u32 = 2;
+
+/// Get the id of the legacy chat room of a clan, other ids are returned unchanged
+///
+/// The legacy chat messages identify the chat room of a clan by a chat id with the clan flag,
+/// the methods for the legacy chat accept either form.
+pub fn legacy_chat_id(steam_id: SteamID) -> SteamID {
+    let mut chat_id = steam_id;
+    if steam_id.account_type() == AccountType::Clan {
+        chat_id.set_account_type(AccountType::Chat);
+        chat_id.set_instance(Instance::FlagClan);
+    }
+    chat_id
+}
+
+/// The result of joining a legacy chat room, see [`Connection::join_legacy_chat`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChatEnterResponse {
+    Success,
+    DoesntExist,
+    NotAllowed,
+    Full,
+    Error,
+    Banned,
+    Limited,
+    ClanDisabled,
+    CommunityBan,
+    MemberBlockedYou,
+    YouBlockedMember,
+    RateLimitExceeded,
+    Other(i32),
+}
+
+impl From<i32> for ChatEnterResponse {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => ChatEnterResponse::Success,
+            2 => ChatEnterResponse::DoesntExist,
+            3 => ChatEnterResponse::NotAllowed,
+            4 => ChatEnterResponse::Full,
+            5 => ChatEnterResponse::Error,
+            6 => ChatEnterResponse::Banned,
+            7 => ChatEnterResponse::Limited,
+            8 => ChatEnterResponse::ClanDisabled,
+            9 => ChatEnterResponse::CommunityBan,
+            10 => ChatEnterResponse::MemberBlockedYou,
+            11 => ChatEnterResponse::YouBlockedMember,
+            15 => ChatEnterResponse::RateLimitExceeded,
+            value => ChatEnterResponse::Other(value),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum LegacyChatError {
+    #[error(transparent)]
+    Network(#[from] NetworkError),
+    #[error("joining the chat room failed with {0:?}")]
+    Denied(ChatEnterResponse),
+}
+
+/// A legacy chat room that was joined, see [`Connection::join_legacy_chat`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyChatRoom {
+    pub chat_id: SteamID,
+    pub name: String,
+    pub owner: SteamID,
+    /// The clan the chat room belongs to, if it's a clan chat
+    pub clan: Option<SteamID>,
+    pub member_count: u32,
+}
+
+/// A message in a legacy chat room, see [`Notification::LegacyChatMessage`](crate::Notification::LegacyChatMessage)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyChatMessage {
+    pub chat_id: SteamID,
+    pub sender: SteamID,
+    /// Only [`ChatEntryType::ChatMsg`] entries contain text written by the user
+    pub entry_type: ChatEntryType,
+    pub message: String,
+}
+
+#[derive(Debug, BinRead)]
+#[br(little)]
+struct ChatMsgBody {
+    chatter: u64,
+    chat_room: u64,
+    entry_type: i32,
+}
+
+impl NetMessage for LegacyChatMessage {
+    const KIND: EMsg = EMsg::k_EMsgClientChatMsg;
+
+    fn read_body(data: BytesMut, _header: &NetMessageHeader) -> Result<Self, MalformedBody> {
+        trace!("reading body of {:?} message", Self::KIND);
+        let mut reader = Cursor::new(data);
+        let body: ChatMsgBody = reader
+            .read_le()
+            .map_err(|e| MalformedBody::new(Self::KIND, e))?;
+        // the message is null terminated
+        let rest = &reader.get_ref()[reader.position() as usize..];
+        let text = rest.split(|byte| *byte == 0).next().unwrap_or_default();
+        Ok(LegacyChatMessage {
+            chat_id: SteamID::from(body.chat_room),
+            sender: SteamID::from(body.chatter),
+            entry_type: ChatEntryType::from(body.entry_type),
+            message: String::from_utf8_lossy(text).into_owned(),
+        })
+    }
+
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
+        trace!("writing body of {:?} message", Self::KIND);
+        writer.write_u64::<LittleEndian>(self.sender.into())?;
+        writer.write_u64::<LittleEndian>(self.chat_id.into())?;
+        writer.write_i32::<LittleEndian>(self.entry_type.into())?;
+        writer.write_all(self.message.as_bytes())?;
+        writer.write_u8(0)
+    }
+
+    fn encode_size(&self) -> usize {
+        8 + 8 + 4 + self.message.len() + 1
+    }
+}
+
+#[derive(Debug)]
+struct JoinChat {
+    chat_id: SteamID,
+}
+
+impl NetMessage for JoinChat {
+    const KIND: EMsg = EMsg::k_EMsgClientJoinChat;
+
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
+        trace!("writing body of {:?} message", Self::KIND);
+        writer.write_u64::<LittleEndian>(self.chat_id.into())?;
+        // not joining as voice speaker
+        writer.write_u8(0)
+    }
+
+    fn encode_size(&self) -> usize {
+        8 + 1
+    }
+}
+
+#[derive(Debug, BinRead)]
+#[br(little)]
+struct ChatEnter {
+    chat_id: u64,
+    #[allow(dead_code)]
+    friend: u64,
+    #[allow(dead_code)]
+    room_type: i32,
+    owner: u64,
+    clan: u64,
+    #[allow(dead_code)]
+    flags: u8,
+    response: i32,
+    member_count: u32,
+    name: NullString,
+    // followed by the members as KeyValues, which aren't decoded
+}
+
+impl NetMessage for ChatEnter {
+    const KIND: EMsg = EMsg::k_EMsgClientChatEnter;
+
+    fn read_body(data: BytesMut, _header: &NetMessageHeader) -> Result<Self, MalformedBody> {
+        trace!("reading body of {:?} message", Self::KIND);
+        Cursor::new(data)
+            .read_le()
+            .map_err(|e| MalformedBody::new(Self::KIND, e))
+    }
+}
+
+impl TryFrom<ChatEnter> for LegacyChatRoom {
+    type Error = LegacyChatError;
+
+    fn try_from(enter: ChatEnter) -> Result<Self, Self::Error> {
+        match ChatEnterResponse::from(enter.response) {
+            ChatEnterResponse::Success => Ok(LegacyChatRoom {
+                chat_id: SteamID::from(enter.chat_id),
+                name: enter.name.into_string(),
+                owner: SteamID::from(enter.owner),
+                clan: (enter.clan != 0).then(|| SteamID::from(enter.clan)),
+                member_count: enter.member_count,
+            }),
+            response => Err(LegacyChatError::Denied(response)),
+        }
+    }
+}
+
+/// Leaving a chat room is sent as a state change of the member
+#[derive(Debug)]
+struct LeaveChat {
+    chat_id: SteamID,
+    member: SteamID,
+}
+
+impl NetMessage for LeaveChat {
+    const KIND: EMsg = EMsg::k_EMsgClientChatMemberInfo;
+
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
+        trace!("writing body of {:?} message", Self::KIND);
+        writer.write_u64::<LittleEndian>(self.chat_id.into())?;
+        writer.write_u32::<LittleEndian>(CHAT_INFO_STATE_CHANGE)?;
+        // the member that left, the change and the member that made the change
+        writer.write_u64::<LittleEndian>(self.member.into())?;
+        writer.write_u32::<LittleEndian>(CHAT_MEMBER_LEFT)?;
+        writer.write_u64::<LittleEndian>(self.member.into())
+    }
+
+    fn encode_size(&self) -> usize {
+        8 + 4 + 8 + 4 + 8
+    }
+}
+
+impl Connection {
+    /// Join a legacy chat room, by chat id or clan id
+    pub async fn join_legacy_chat(
+        &self,
+        chat_id: SteamID,
+    ) -> Result<LegacyChatRoom, LegacyChatError> {
+        let chat_id = legacy_chat_id(chat_id);
+        // the responses don't carry a job id, concurrent joins are told apart by the room they're for
+        let mut responses = self.on_kind(ChatEnter::KIND);
+        self.send(self.session.header(), JoinChat { chat_id })
+            .await?;
+        let enter = timeout(self.timeout, async {
+            loop {
+                let raw = match responses.recv().await {
+                    Ok(raw) => raw,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Err(NetworkError::EOF),
+                };
+                let enter: ChatEnter = raw.into_message()?;
+                if enter.chat_id == u64::from(chat_id) {
+                    return Ok(enter);
+                }
+            }
+        })
+        .await
+        .map_err(|_| NetworkError::Timeout)??;
+        LegacyChatRoom::try_from(enter)
+    }
+
+    /// Send a message to a legacy chat room that was joined, by chat id or clan id
+    pub async fn send_legacy_chat_message(
+        &self,
+        chat_id: SteamID,
+        message: &str,
+    ) -> Result<(), NetworkError> {
+        let message = LegacyChatMessage {
+            chat_id: legacy_chat_id(chat_id),
+            sender: self.steam_id(),
+            entry_type: ChatEntryType::ChatMsg,
+            message: message.into(),
+        };
+        self.send(self.session.header(), message).await
+    }
+
+    /// Leave a legacy chat room, by chat id or clan id
+    pub async fn leave_legacy_chat(&self, chat_id: SteamID) -> Result<(), NetworkError> {
+        let request = LeaveChat {
+            chat_id: legacy_chat_id(chat_id),
+            member: self.steam_id(),
+        };
+        self.send(self.session.header(), request).await
+    }
+}
+
+#[test]
+fn test_legacy_chat_id() {
+    use steamid_ng::Universe;
+
+    let clan = SteamID::new(4, Instance::All, AccountType::Clan, Universe::Public);
+    let chat_id = legacy_chat_id(clan);
+    assert_eq!(0x0188_0000_0000_0004, u64::from(chat_id));
+    assert_eq!(4, chat_id.account_id());
+    // chat ids are already in the right form
+    assert_eq!(chat_id, legacy_chat_id(chat_id));
+}
+
+#[test]
+fn test_legacy_chat_message() {
+    use crate::net::RawNetMessage;
+
+    let message = LegacyChatMessage {
+        chat_id: SteamID::from(0x0188_0000_0000_0004),
+        sender: SteamID::from(76561198000000001),
+        entry_type: ChatEntryType::ChatMsg,
+        message: "hello".into(),
+    };
+    let raw = RawNetMessage::from_message(NetMessageHeader::default(), message.clone()).unwrap();
+    assert_eq!(message.encode_size(), raw.data.len());
+    let raw = RawNetMessage::read(raw.into_bytes()).unwrap();
+    assert_eq!(message, raw.into_message::<LegacyChatMessage>().unwrap());
+
+    // the text can be missing, but not the fixed fields before it
+    let header = NetMessageHeader::default();
+    let data = BytesMut::from(&[0; 20][..]);
+    assert_eq!(
+        "",
+        LegacyChatMessage::read_body(data, &header).unwrap().message
+    );
+    let data = BytesMut::from(&[0; 19][..]);
+    assert!(LegacyChatMessage::read_body(data, &header).is_err());
+}
+
+#[test]
+fn test_chat_enter() {
+    let body = |response: i32| {
+        let mut data = BytesMut::new();
+        data.extend_from_slice(&0x0188_0000_0000_0004u64.to_le_bytes());
+        data.extend_from_slice(&76561198000000001u64.to_le_bytes());
+        data.extend_from_slice(&3i32.to_le_bytes());
+        data.extend_from_slice(&76561198000000002u64.to_le_bytes());
+        data.extend_from_slice(&0x0170_0000_0000_0004u64.to_le_bytes());
+        data.extend_from_slice(&[0]);
+        data.extend_from_slice(&response.to_le_bytes());
+        data.extend_from_slice(&12u32.to_le_bytes());
+        data.extend_from_slice(b"Clan chat\0");
+        data
+    };
+    let fields =
+        |response: i32| ChatEnter::read_body(body(response), &NetMessageHeader::default()).unwrap();
+
+    let room = LegacyChatRoom::try_from(fields(1)).unwrap();
+    assert_eq!(
+        LegacyChatRoom {
+            chat_id: SteamID::from(0x0188_0000_0000_0004),
+            name: "Clan chat".into(),
+            owner: SteamID::from(76561198000000002),
+            clan: Some(SteamID::from(0x0170_0000_0000_0004)),
+            member_count: 12,
+        },
+        room
+    );
+    assert!(matches!(
+        LegacyChatRoom::try_from(fields(6)),
+        Err(LegacyChatError::Denied(ChatEnterResponse::Banned))
+    ));
+
+    // truncated fields are rejected instead of read past the end
+    let full = body(1);
+    let name_start = full.len() - b"Clan chat\0".len();
+    for len in 0..name_start {
+        assert!(
+            ChatEnter::read_body(BytesMut::from(&full[..len]), &NetMessageHeader::default())
+                .is_err()
+        );
+    }
+}
@@ -1,18 +1,96 @@
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+compile_error!("either the \"rustls\" or \"native-tls\" feature needs to be enabled");
+
+mod account_limits;
+mod app_ticket;
 pub mod auth;
+mod chat_room;
+mod clan;
 mod connection;
+mod dedup;
+mod depot;
 mod eresult;
+pub mod framing;
+mod friend_groups;
+mod friend_messages;
+mod friends;
+mod game_id;
+mod game_session;
+mod gc;
+mod items;
+pub mod keyvalues;
+pub mod legacy_chat;
 mod message;
+mod metrics;
 mod net;
+mod nicknames;
+mod notification;
+mod offline_messages;
+mod personas;
+mod pool;
+mod presence;
+mod purchase;
+mod queue;
+mod resolver;
+mod scores;
 mod serverlist;
 mod service_method;
 mod session;
+#[cfg(feature = "signal")]
+mod shutdown;
+mod stages;
+mod stats;
+mod task;
+mod throttle;
 mod transport;
+mod ui_mode;
+mod vac;
+mod wallet;
+mod webapi;
 
+pub use steam_vent_crypto as crypto;
 pub use steam_vent_proto as proto;
 
-pub use connection::Connection;
+pub use account_limits::AccountLimits;
+pub use chat_room::{ChatRoom, ChatRoomGroup, ChatRoomMessage};
+pub use clan::{ClanEvent, ClanState, ClanUserCounts};
+pub use connection::{
+    Connection, ConnectionOptions, ConnectionState, LogOffOutcome, ReconnectHandler,
+};
+pub use depot::{CdnAuthToken, DepotKeyError};
 pub use eresult::EResult;
+pub use friend_groups::{FriendGroup, FriendGroups};
+pub use friend_messages::SentFriendMessage;
+pub use friends::AddedFriend;
+pub use game_id::{GameId, GameType};
+pub use game_session::GameSession;
+pub use gc::{GcHandlerGuard, GcMessage};
+pub use items::{ItemAnnouncements, UnseenItem};
+#[doc(hidden)]
+pub use message::flatten_multi;
 pub use message::NetMessage;
-pub use net::NetworkError;
-pub use serverlist::{ServerDiscoveryError, ServerList};
-pub use session::{ConnectionError, LoginError};
+pub use metrics::{ConnectionStats, MessageSizes, MESSAGE_SIZE_BUCKETS};
+pub use net::{NetMessageHeader, NetworkError, RawNetMessage};
+pub use nicknames::Nicknames;
+pub use notification::{ChatEntryType, Event, LogOffReason, Notification};
+pub use offline_messages::OfflineMessages;
+pub use personas::Persona;
+pub use pool::ConnectionPool;
+pub use presence::{PersonaState, PresenceReplay};
+pub use purchase::{PurchaseError, PurchaseReceipt, PurchasedPackage};
+pub use queue::OverflowPolicy;
+pub use resolver::{Resolver, StaticResolver, SystemResolver};
+pub use scores::{ServerScore, ServerScores};
+pub use serverlist::{DiscoverOptions, ServerDiscoveryError, ServerList};
+pub use session::{ChatMode, ConnectionError, LoginError};
+#[cfg(feature = "signal")]
+pub use shutdown::shutdown_signal;
+pub use stages::{EncryptedChannel, LoggedOn, TcpConnected};
+pub use stats::{Achievement, StatValue, StatsError, UserStats};
+#[doc(hidden)]
+pub use transport::tcp::FrameWriter;
+pub use transport::tcp::{connect_addrs, read_message, ConnectAddrsError, HandshakeInfo};
+pub use transport::{HttpProxy, Transport};
+pub use ui_mode::UiMode;
+pub use vac::VacBanStatus;
+pub use wallet::Wallet;
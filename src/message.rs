@@ -1,3 +1,5 @@
+use crate::framing::{read_exact_length, FramingError};
+use crate::keyvalues::KeyValuesError;
 use crate::net::{NetMessageHeader, NetworkError, RawNetMessage};
 use crate::service_method::ServiceMethodRequest;
 use binread::BinRead;
@@ -5,6 +7,8 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use bytes::{Buf, BytesMut};
 use crc::{Crc, CRC_32_ISO_HDLC};
 use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use futures_util::{
     future::ready,
     stream::{iter, once},
@@ -14,7 +18,8 @@ use num_bigint_dig::ParseBigIntError;
 use protobuf::Message;
 use std::any::type_name;
 use std::fmt::Debug;
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::time::Instant;
 use steam_vent_proto::enums_clientserver::EMsg;
 use steam_vent_proto::steammessages_base::CMsgMulti;
 use steam_vent_proto::{RpcMessage, RpcMessageWithKind};
@@ -48,6 +53,8 @@ pub enum MessageBodyError {
     BigInt(#[from] ParseBigIntError),
     #[error("invalid rsa key: {0:#}")]
     Rsa(#[from] rsa::Error),
+    #[error("malformed KeyValues: {0:#}")]
+    KeyValues(#[from] KeyValuesError),
 }
 
 impl From<String> for MessageBodyError {
@@ -72,13 +79,22 @@ pub trait NetMessage: Sized + Debug {
         panic!("Writing not implemented for {}", type_name::<Self>())
     }
 
+    /// Encode the message body into a newly allocated buffer
+    ///
+    /// Use [`NetMessage::write_body`] to write the body into an existing writer instead
+    fn to_bytes(&self) -> Result<Vec<u8>, std::io::Error> {
+        let mut bytes = Vec::with_capacity(self.encode_size());
+        self.write_body(&mut bytes)?;
+        Ok(bytes)
+    }
+
     fn process_header(&self, _header: &mut NetMessageHeader) {}
 }
 
 #[derive(Debug, BinRead)]
+#[br(little)]
 pub struct ChannelEncryptRequest {
     pub protocol: u32,
-    #[allow(dead_code)]
     pub universe: u32,
     pub nonce: [u8; 16],
 }
@@ -94,6 +110,7 @@ impl NetMessage for ChannelEncryptRequest {
 }
 
 #[derive(Debug, BinRead)]
+#[br(little)]
 pub struct ChannelEncryptResult {
     pub result: u32,
 }
@@ -169,6 +186,32 @@ pub fn flatten_multi<S: Stream<Item = Result<RawNetMessage, NetworkError>>>(
     })
 }
 
+/// Wrap a message in a gzip compressed multi message, the same way the server compresses large messages
+pub(crate) fn compress_multi(message: RawNetMessage) -> Result<RawNetMessage, NetworkError> {
+    let size = message.header_buffer.len() + message.data.len();
+    let too_large = || NetworkError::FrameTooLarge(size as u64);
+    let length = u32::try_from(size).map_err(|_| too_large())?;
+    // the unzipped data is the length followed by the message
+    let size_unzipped = length.checked_add(4).ok_or_else(too_large)?;
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_u32::<LittleEndian>(length)?;
+    encoder.write_all(&message.header_buffer)?;
+    encoder.write_all(&message.data)?;
+    let multi = CMsgMulti {
+        size_unzipped: Some(size_unzipped),
+        message_body: Some(encoder.finish()?),
+        ..CMsgMulti::default()
+    };
+    let header = NetMessageHeader {
+        source_job_id: u64::MAX,
+        target_job_id: u64::MAX,
+        steam_id: message.header.steam_id,
+        session_id: message.header.session_id,
+        ..NetMessageHeader::default()
+    };
+    RawNetMessage::from_message(header, multi)
+}
+
 struct MultiBodyIter<R> {
     reader: R,
 }
@@ -198,11 +241,11 @@ impl<R: Read> Iterator for MultiBodyIter<R> {
             Err(_) => return None,
         };
 
-        let mut msg_data = BytesMut::with_capacity(size as usize);
-        msg_data.resize(size as usize, 0);
-        if let Err(e) = self.reader.read_exact(&mut msg_data) {
-            return Some(Err(NetworkError::IO(e)));
-        }
+        let msg_data = match read_exact_length(&mut self.reader, size) {
+            Ok(data) => data,
+            Err(FramingError::IO(e)) => return Some(Err(NetworkError::IO(e))),
+            Err(_) => return Some(Err(NetworkError::IO(ErrorKind::UnexpectedEof.into()))),
+        };
         let raw = match RawNetMessage::read(msg_data) {
             Ok(raw) => raw,
             Err(e) => return Some(Err(e)),
@@ -251,6 +294,20 @@ pub struct ServiceMethodResponseMessage {
 }
 
 impl ServiceMethodResponseMessage {
+    /// Encode the response to a service method called by steam
+    pub(crate) fn from_response<Request: ServiceMethodRequest>(
+        response: &Request::Response,
+    ) -> Result<Self, NetworkError> {
+        let mut body = Vec::with_capacity(response.encode_size());
+        response
+            .write(&mut body)
+            .map_err(|e| MalformedBody(Self::KIND, e.into()))?;
+        Ok(ServiceMethodResponseMessage {
+            job_name: Request::REQ_NAME.into(),
+            body: body.as_slice().into(),
+        })
+    }
+
     pub fn into_response<Request: ServiceMethodRequest>(
         self,
     ) -> Result<Request::Response, NetworkError> {
@@ -281,11 +338,25 @@ impl NetMessage for ServiceMethodResponseMessage {
             body: data,
         })
     }
+
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
+        trace!("writing body of protobuf message {:?}", Self::KIND);
+        writer.write_all(&self.body)
+    }
+
+    fn encode_size(&self) -> usize {
+        self.body.len()
+    }
+
+    fn process_header(&self, header: &mut NetMessageHeader) {
+        header.target_job_name = Some(self.job_name.clone().into())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ServiceMethodNotification {
     pub(crate) job_name: String,
+    pub(crate) received_at: Option<Instant>,
     body: BytesMut,
 }
 
@@ -315,6 +386,7 @@ impl NetMessage for ServiceMethodNotification {
                 .as_deref()
                 .unwrap_or_default()
                 .to_string(),
+            received_at: None,
             body: data,
         })
     }
@@ -339,3 +411,97 @@ impl<ProtoMsg: RpcMessageWithKind> NetMessage for ProtoMsg {
         <Self as RpcMessage>::encode_size(self)
     }
 }
+
+#[test]
+fn test_to_bytes() {
+    let response = ClientEncryptResponse {
+        protocol: 1,
+        encrypted_key: vec![1, 2, 3, 4],
+    };
+    let bytes = response.to_bytes().unwrap();
+    assert_eq!(response.encode_size(), bytes.len());
+    assert_eq!(&[1, 2, 3, 4], &bytes[24..28]);
+}
+
+#[test]
+fn test_read_truncated_handshake() {
+    let header = NetMessageHeader::default();
+    let mut request = BytesMut::new();
+    request.extend_from_slice(&1u32.to_le_bytes());
+    request.extend_from_slice(&1u32.to_le_bytes());
+    request.extend_from_slice(&[7; 16]);
+    let read = ChannelEncryptRequest::read_body(request.clone(), &header).unwrap();
+    assert_eq!([7; 16], read.nonce);
+    for len in 0..request.len() {
+        assert!(ChannelEncryptRequest::read_body(request.clone().split_to(len), &header).is_err());
+    }
+
+    assert!(ChannelEncryptResult::read_body(BytesMut::from(&[1, 0, 0][..]), &header).is_err());
+}
+
+#[test]
+fn test_read_truncated_multi() {
+    let child = |size: u32, data: &[u8]| {
+        let mut body = size.to_le_bytes().to_vec();
+        body.extend_from_slice(data);
+        MultiBodyIter::new(Cursor::new(
+            CMsgMulti {
+                message_body: Some(body),
+                ..CMsgMulti::default()
+            }
+            .write_to_bytes()
+            .unwrap(),
+        ))
+        .unwrap()
+        .collect::<Vec<_>>()
+    };
+
+    // the child is shorter than its size
+    assert!(matches!(
+        child(100, &[1, 2, 3, 4]).as_slice(),
+        [Err(NetworkError::IO(e))] if e.kind() == ErrorKind::UnexpectedEof
+    ));
+    // the child is too short for a message
+    assert!(matches!(
+        child(2, &[1, 2]).as_slice(),
+        [Err(NetworkError::InvalidHeader)]
+    ));
+    // a partial size after the last child ends the message
+    let mut body = CMsgMulti::default();
+    body.set_message_body(vec![1, 0]);
+    let multi = MultiBodyIter::new(Cursor::new(body.write_to_bytes().unwrap())).unwrap();
+    assert_eq!(0, multi.count());
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_compress_multi() {
+    use steam_vent_proto::steammessages_clientserver_login::CMsgClientHeartBeat;
+
+    let header = NetMessageHeader {
+        session_id: 5,
+        ..NetMessageHeader::default()
+    };
+    let heartbeat = CMsgClientHeartBeat {
+        send_reply: Some(true),
+        ..CMsgClientHeartBeat::default()
+    };
+    let raw = RawNetMessage::from_message(header, heartbeat).unwrap();
+    let multi = compress_multi(raw).unwrap();
+    assert_eq!(EMsg::k_EMsgMulti, multi.kind);
+
+    let mut bytes = multi.header_buffer;
+    bytes.extend_from_slice(&multi.data);
+    let messages: Vec<_> = flatten_multi(once(ready(RawNetMessage::read(bytes))))
+        .collect()
+        .await;
+    assert_eq!(1, messages.len());
+    let (header, heartbeat): (_, CMsgClientHeartBeat) = messages
+        .into_iter()
+        .next()
+        .unwrap()
+        .and_then(|raw| Ok((raw.header.clone(), raw.into_message()?)))
+        .unwrap();
+    assert_eq!(5, header.session_id);
+    assert_eq!(Some(true), heartbeat.send_reply);
+}
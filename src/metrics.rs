@@ -0,0 +1,121 @@
+use crate::proto::enums_clientserver::EMsg;
+use dashmap::DashMap;
+use std::collections::HashMap;
+
+/// The upper bounds of the message size buckets in bytes, the last bucket holds the messages larger than the last bound
+pub const MESSAGE_SIZE_BUCKETS: [usize; 8] = [
+    64,
+    256,
+    1024,
+    4 * 1024,
+    16 * 1024,
+    64 * 1024,
+    256 * 1024,
+    1024 * 1024,
+];
+
+/// The number and sizes of the messages of one kind, see [`ConnectionStats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageSizes {
+    pub count: u64,
+    pub total_bytes: u64,
+    pub largest: usize,
+    /// The number of messages per size bucket, see [`MESSAGE_SIZE_BUCKETS`]
+    pub buckets: [u64; MESSAGE_SIZE_BUCKETS.len() + 1],
+}
+
+impl MessageSizes {
+    fn record(&mut self, size: usize) {
+        self.count += 1;
+        self.total_bytes += size as u64;
+        self.largest = self.largest.max(size);
+        let bucket = MESSAGE_SIZE_BUCKETS.partition_point(|bound| *bound < size);
+        self.buckets[bucket] += 1;
+    }
+}
+
+/// A snapshot of the number and sizes of the messages per kind, see [`Connection::stats`](crate::Connection::stats)
+///
+/// Inbound messages are counted after multi messages are expanded. Outbound messages are counted as written to the
+/// transport, so messages compressed with [`ConnectionOptions::with_compression_threshold`](crate::ConnectionOptions::with_compression_threshold)
+/// are counted as `Multi`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub inbound: HashMap<EMsg, MessageSizes>,
+    pub outbound: HashMap<EMsg, MessageSizes>,
+}
+
+impl ConnectionStats {
+    /// The inbound message kind with the largest message
+    pub fn largest_inbound(&self) -> Option<(EMsg, usize)> {
+        largest(&self.inbound)
+    }
+
+    /// The outbound message kind with the largest message
+    pub fn largest_outbound(&self) -> Option<(EMsg, usize)> {
+        largest(&self.outbound)
+    }
+}
+
+fn largest(sizes: &HashMap<EMsg, MessageSizes>) -> Option<(EMsg, usize)> {
+    sizes
+        .iter()
+        .map(|(kind, sizes)| (*kind, sizes.largest))
+        .max_by_key(|(_, largest)| *largest)
+}
+
+/// The counters for the messages of a connection, shared with the connections created by reconnecting
+#[derive(Debug, Default)]
+pub(crate) struct MessageMetrics {
+    inbound: DashMap<EMsg, MessageSizes>,
+    outbound: DashMap<EMsg, MessageSizes>,
+}
+
+impl MessageMetrics {
+    pub(crate) fn record_inbound(&self, kind: EMsg, size: usize) {
+        self.inbound.entry(kind).or_default().record(size);
+    }
+
+    pub(crate) fn record_outbound(&self, kind: EMsg, size: usize) {
+        self.outbound.entry(kind).or_default().record(size);
+    }
+
+    pub(crate) fn snapshot(&self) -> ConnectionStats {
+        let collect = |sizes: &DashMap<EMsg, MessageSizes>| {
+            sizes
+                .iter()
+                .map(|entry| (*entry.key(), *entry.value()))
+                .collect()
+        };
+        ConnectionStats {
+            inbound: collect(&self.inbound),
+            outbound: collect(&self.outbound),
+        }
+    }
+}
+
+#[test]
+fn test_message_size_histogram() {
+    let metrics = MessageMetrics::default();
+    for size in [10, 64, 65, 300, 2_000_000] {
+        metrics.record_inbound(EMsg::k_EMsgClientPICSProductInfoResponse, size);
+    }
+    metrics.record_inbound(EMsg::k_EMsgClientHeartBeat, 36);
+    metrics.record_outbound(EMsg::k_EMsgClientHeartBeat, 36);
+
+    let stats = metrics.snapshot();
+    let pics = stats.inbound[&EMsg::k_EMsgClientPICSProductInfoResponse];
+    assert_eq!(5, pics.count);
+    assert_eq!(2_000_439, pics.total_bytes);
+    assert_eq!(2_000_000, pics.largest);
+    assert_eq!([2, 1, 1, 0, 0, 0, 0, 0, 1], pics.buckets);
+    assert_eq!(
+        Some((EMsg::k_EMsgClientPICSProductInfoResponse, 2_000_000)),
+        stats.largest_inbound()
+    );
+    assert_eq!(
+        Some((EMsg::k_EMsgClientHeartBeat, 36)),
+        stats.largest_outbound()
+    );
+    assert_eq!(1, stats.outbound.len());
+}
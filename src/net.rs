@@ -1,26 +1,40 @@
 use crate::eresult::EResult;
+use crate::framing::{read_length_prefixed, FramingError};
 use crate::message::NetMessage;
+use crate::proto::steammessages_base::cmsg_proto_buf_header::Ip_addr;
 use crate::proto::steammessages_base::CMsgProtoBufHeader;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{BufMut, BytesMut};
 use protobuf::{Enum, Message};
 use std::borrow::Cow;
 use std::fmt::Debug;
 use std::io::{Cursor, Seek, SeekFrom};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Instant;
 use steam_vent_crypto::CryptError;
 use steam_vent_proto::enums_clientserver::EMsg;
-use steamid_ng::SteamID;
+use steamid_ng::{SteamID, Universe};
 use thiserror::Error;
 use tracing::{debug, trace};
 
 pub const PROTO_MASK: u32 = 0x80000000;
 
+/// Serialized size of the kind and header length that precede the protobuf header
+const PROTO_HEADER_PREFIX_SIZE: usize = 4 + 4;
+/// Serialized size of the header for the channel encryption messages: kind, target and source job id
+const ENCRYPT_HEADER_SIZE: usize = 4 + 8 + 8;
+/// Serialized size of the header for non-protobuf messages
+///
+/// kind, header size, header version, target and source job id, canary, steam id and session id
+const EXTENDED_HEADER_SIZE: usize = 4 + 1 + 2 + 8 + 8 + 1 + 8 + 4;
+
 #[derive(Debug, Error)]
 pub enum NetworkError {
     #[error("{0}")]
     IO(#[from] std::io::Error),
+    /// Boxed to keep the error small
     #[error("{0}")]
-    Ws(#[from] tokio_tungstenite::tungstenite::Error),
+    Ws(Box<tokio_tungstenite::tungstenite::Error>),
     #[error("Invalid message header")]
     InvalidHeader,
     #[error("Invalid message kind {0}")]
@@ -41,6 +55,33 @@ pub enum NetworkError {
     Timeout,
     #[error("Remote returned an error code: {0:?}")]
     ApiError(EResult),
+    #[error("Failed to connect through proxy: {0}")]
+    ProxyFailed(String),
+    #[error("Frame of {0} bytes is larger than the protocol allows")]
+    FrameTooLarge(u64),
+    #[error("Unknown universe {0}")]
+    UnknownUniverse(u32),
+    #[error("No public key is known for the {0:?} universe")]
+    UnsupportedUniverse(Universe),
+    /// The messages were written, but flushing them to the transport failed
+    #[error("Failed to flush messages: {0}")]
+    Flush(Box<NetworkError>),
+}
+
+impl From<FramingError> for NetworkError {
+    fn from(value: FramingError) -> Self {
+        match value {
+            FramingError::IO(e) => NetworkError::IO(e),
+            FramingError::FrameTooLarge(len) => NetworkError::FrameTooLarge(len),
+            _ => NetworkError::InvalidHeader,
+        }
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for NetworkError {
+    fn from(value: tokio_tungstenite::tungstenite::Error) -> Self {
+        NetworkError::Ws(Box::new(value))
+    }
 }
 
 impl From<EResult> for NetworkError {
@@ -51,6 +92,9 @@ impl From<EResult> for NetworkError {
 
 pub type Result<T, E = NetworkError> = std::result::Result<T, E>;
 
+/// The header of a message, with the job ids used to match responses to requests and the session it belongs to
+///
+/// Headers for sending can be built from [`NetMessageHeader::default`] with the `with_*` methods.
 #[derive(Debug, Default, Clone)]
 pub struct NetMessageHeader {
     pub source_job_id: u64,
@@ -59,6 +103,18 @@ pub struct NetMessageHeader {
     pub session_id: i32,
     pub target_job_name: Option<Cow<'static, str>>,
     pub result: Option<i32>,
+    /// Tag included in the server side logs, for correlating messages when debugging issues with the CM
+    pub trace_tag: Option<u64>,
+    /// The realm the message is routed in, only present on protobuf messages
+    pub realm: Option<u32>,
+    /// The ip address of the client, as seen by the server
+    pub ip: Option<IpAddr>,
+    /// The app the message is routed to on the CM, see [`Connection::send_for_app`](crate::Connection::send_for_app)
+    pub routing_app_id: Option<u32>,
+    /// Whether the message waits for a response with its source job id, see [`Connection::job`](crate::Connection::job)
+    ///
+    /// Protobuf messages only send their source job id if they wait for a response, or call a service method.
+    pub awaits_response: bool,
 }
 
 impl From<CMsgProtoBufHeader> for NetMessageHeader {
@@ -72,29 +128,100 @@ impl From<CMsgProtoBufHeader> for NetMessageHeader {
                 .has_target_job_name()
                 .then(|| header.target_job_name().to_string().into()),
             result: header.eresult,
+            trace_tag: header.trace_tag,
+            realm: header.realm,
+            ip: match header.ip_addr {
+                Some(Ip_addr::Ip(ip)) => Some(Ipv4Addr::from(ip).into()),
+                Some(Ip_addr::IpV6(ip)) => <[u8; 16]>::try_from(ip)
+                    .ok()
+                    .map(|ip| Ipv6Addr::from(ip).into()),
+                _ => None,
+            },
+            routing_app_id: header.routing_appid,
+            awaits_response: false,
         }
     }
 }
 
 impl NetMessageHeader {
+    pub fn with_source_job_id(self, source_job_id: u64) -> Self {
+        NetMessageHeader {
+            source_job_id,
+            ..self
+        }
+    }
+
+    pub fn with_target_job_id(self, target_job_id: u64) -> Self {
+        NetMessageHeader {
+            target_job_id,
+            ..self
+        }
+    }
+
+    pub fn with_steam_id(self, steam_id: SteamID) -> Self {
+        NetMessageHeader { steam_id, ..self }
+    }
+
+    pub fn with_session_id(self, session_id: i32) -> Self {
+        NetMessageHeader { session_id, ..self }
+    }
+
+    /// Set the name of the service method the message calls
+    pub fn with_target_job_name(self, target_job_name: impl Into<Cow<'static, str>>) -> Self {
+        NetMessageHeader {
+            target_job_name: Some(target_job_name.into()),
+            ..self
+        }
+    }
+
+    /// Set the tag included in the server side logs
+    pub fn with_trace_tag(self, trace_tag: u64) -> Self {
+        NetMessageHeader {
+            trace_tag: Some(trace_tag),
+            ..self
+        }
+    }
+
+    /// Set the realm the message is routed in, only sent for protobuf messages
+    pub fn with_realm(self, realm: u32) -> Self {
+        NetMessageHeader {
+            realm: Some(realm),
+            ..self
+        }
+    }
+
+    /// Set the app the message is routed to on the CM
+    pub fn with_routing_app_id(self, routing_app_id: u32) -> Self {
+        NetMessageHeader {
+            routing_app_id: Some(routing_app_id),
+            ..self
+        }
+    }
+
+    /// Send the source job id, so the response can be matched to the message
+    pub fn with_awaits_response(self) -> Self {
+        NetMessageHeader {
+            awaits_response: true,
+            ..self
+        }
+    }
+
     fn read<R: ReadBytesExt + Seek>(
         mut reader: R,
         kind: EMsg,
         is_protobuf: bool,
     ) -> Result<(Self, usize)> {
         if is_protobuf {
-            let header_length = reader.read_u32::<LittleEndian>()?;
-            trace!("reading protobuf header of {} bytes", header_length);
-            let header = if header_length > 0 {
-                let mut bytes = vec![0; header_length as usize];
-                let num = reader.read(&mut bytes)?;
-                CMsgProtoBufHeader::parse_from_bytes(&bytes[0..num])
+            let bytes = read_length_prefixed(&mut reader)?;
+            trace!("read protobuf header of {} bytes", bytes.len());
+            let header = if !bytes.is_empty() {
+                CMsgProtoBufHeader::parse_from_bytes(&bytes)
                     .map_err(|_| NetworkError::InvalidHeader)?
                     .into()
             } else {
                 NetMessageHeader::default()
             };
-            Ok((header, 8 + header_length as usize))
+            Ok((header, PROTO_HEADER_PREFIX_SIZE + bytes.len()))
         } else if kind == EMsg::k_EMsgChannelEncryptRequest
             || kind == EMsg::k_EMsgChannelEncryptResult
         {
@@ -108,7 +235,7 @@ impl NetMessageHeader {
                     steam_id: SteamID::default(),
                     ..NetMessageHeader::default()
                 },
-                4 + 8 + 8,
+                ENCRYPT_HEADER_SIZE,
             ))
         } else {
             reader.seek(SeekFrom::Current(3))?; // 1 byte (fixed) header size, 2 bytes (fixed) header version
@@ -123,10 +250,9 @@ impl NetMessageHeader {
                     target_job_id,
                     steam_id,
                     session_id,
-                    target_job_name: None,
-                    result: None,
+                    ..NetMessageHeader::default()
                 },
-                4 + 3 + 8 + 8 + 1 + 8 + 4,
+                EXTENDED_HEADER_SIZE,
             ))
         }
     }
@@ -177,31 +303,51 @@ impl NetMessageHeader {
             if self.source_job_id > 0 {
                 proto_header.set_jobid_target(self.target_job_id);
             }
+        } else if self.awaits_response {
+            proto_header.set_jobid_source(self.source_job_id);
+        }
+        if kind == EMsg::k_EMsgServiceMethodResponse {
+            // responses to methods called by steam are matched to the call by its job id
+            proto_header.set_jobid_target(self.target_job_id);
         }
         if let Some(target_job_name) = self.target_job_name.as_deref() {
             proto_header.set_target_job_name(target_job_name.into());
         }
+        if let Some(trace_tag) = self.trace_tag {
+            proto_header.set_trace_tag(trace_tag);
+        }
+        if let Some(realm) = self.realm {
+            proto_header.set_realm(realm);
+        }
+        if let Some(app_id) = self.routing_app_id {
+            proto_header.set_routing_appid(app_id);
+        }
         proto_header
     }
 
-    fn encode_size(&self, kind: EMsg, proto: bool) -> usize {
+    pub(crate) fn encode_size(&self, kind: EMsg, proto: bool) -> usize {
         if kind == EMsg::k_EMsgChannelEncryptResponse {
             4
         } else if proto {
             let proto_header = self.proto_header(kind);
-            4 + 4 + proto_header.compute_size() as usize
+            PROTO_HEADER_PREFIX_SIZE + proto_header.compute_size() as usize
         } else {
-            4 + 1 + 2 + 8 + 8 + 1 + 8 + 4 + 4
+            EXTENDED_HEADER_SIZE
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct RawNetMessage {
+    /// The kind of the message, [`EMsg::k_EMsgInvalid`] if the kind isn't known to the crate
     pub kind: EMsg,
+    /// The numeric kind of the message, also set when the kind isn't known to the crate
+    pub raw_kind: i32,
     pub is_protobuf: bool,
     pub header: NetMessageHeader,
     pub data: BytesMut,
+    /// The time the message was received, only set when enabled in the connection options
+    pub received_at: Option<Instant>,
     pub(crate) frame_header_buffer: Option<BytesMut>,
     pub(crate) iv_buffer: Option<BytesMut>,
     pub(crate) header_buffer: BytesMut,
@@ -217,10 +363,12 @@ impl RawNetMessage {
         let is_protobuf = kind < 0;
         let kind = kind & (!PROTO_MASK) as i32;
 
-        let kind = match EMsg::from_i32(kind) {
-            Some(kind) => kind,
-            None => return Err(NetworkError::InvalidMessageKind(kind)),
-        };
+        let raw_kind = kind;
+        // steam keeps adding new kinds, don't fail on them so they can be handled as unknown messages
+        let kind = EMsg::from_i32(raw_kind).unwrap_or_else(|| {
+            debug!(kind = raw_kind, "received message of unknown kind");
+            EMsg::k_EMsgInvalid
+        });
 
         trace!(
             "reading header for {:?} {}message",
@@ -228,17 +376,25 @@ impl RawNetMessage {
             if is_protobuf { "protobuf " } else { "" }
         );
 
-        let header_start = reader.position() as usize;
-        let (header, body_start) = NetMessageHeader::read(&mut reader, kind, is_protobuf)?;
+        let (header, body_start) =
+            NetMessageHeader::read(&mut reader, kind, is_protobuf).map_err(|e| match e {
+                NetworkError::IO(_) => NetworkError::InvalidHeader,
+                e => e,
+            })?;
+        if body_start > value.len() {
+            return Err(NetworkError::InvalidHeader);
+        }
 
-        value.advance(header_start);
-        let header_buffer = value.split_to(body_start - header_start);
+        // keep the kind in the header buffer, so the message can be re-encoded as-is
+        let header_buffer = value.split_to(body_start);
 
         Ok(RawNetMessage {
             kind,
+            raw_kind,
             is_protobuf,
             header,
             data: value,
+            received_at: None,
             frame_header_buffer: None,
             iv_buffer: None,
             header_buffer,
@@ -284,9 +440,11 @@ impl RawNetMessage {
 
         Ok(RawNetMessage {
             kind,
+            raw_kind: kind.value(),
             is_protobuf: T::IS_PROTOBUF,
             header,
             data: buff,
+            received_at: None,
             frame_header_buffer: Some(frame_header_buffer),
             iv_buffer: Some(iv_buffer),
             header_buffer,
@@ -295,6 +453,18 @@ impl RawNetMessage {
 }
 
 impl RawNetMessage {
+    /// The size of the encoded message, the header and the body
+    pub(crate) fn encoded_len(&self) -> usize {
+        self.header_buffer.len() + self.data.len()
+    }
+
+    /// Get the encoded message, the header followed by the body
+    pub fn into_bytes(self) -> BytesMut {
+        let mut bytes = self.header_buffer;
+        bytes.unsplit(self.data);
+        bytes
+    }
+
     pub fn into_message<T: NetMessage>(self) -> Result<T> {
         if let Some(result) = self.header.result {
             EResult::from_result(result)?;
@@ -312,3 +482,166 @@ impl RawNetMessage {
         }
     }
 }
+
+#[test]
+fn test_read_short_message() {
+    for len in 0..4 {
+        assert!(matches!(
+            RawNetMessage::read(BytesMut::from(&[0xff; 4][..len])),
+            Err(NetworkError::InvalidHeader)
+        ));
+    }
+}
+
+#[test]
+fn test_read_unknown_kind() {
+    let kind = 0x7fff_0000 | PROTO_MASK;
+    let mut data = BytesMut::from(&kind.to_le_bytes()[..]);
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&[1, 2, 3]);
+    let message = RawNetMessage::read(data).unwrap();
+    assert_eq!(EMsg::k_EMsgInvalid, message.kind);
+    assert_eq!(0x7fff_0000, message.raw_kind);
+    assert!(message.is_protobuf);
+    assert_eq!(&[1, 2, 3], message.data.as_ref());
+}
+
+#[test]
+fn test_read_truncated_header() {
+    let kind = EMsg::k_EMsgClientLogOnResponse.value() as u32 | PROTO_MASK;
+    let mut data = BytesMut::from(&kind.to_le_bytes()[..]);
+    // header claims to be much larger than the message
+    data.extend_from_slice(&u32::MAX.to_le_bytes());
+    data.extend_from_slice(&[0; 8]);
+    assert!(matches!(
+        RawNetMessage::read(data),
+        Err(NetworkError::InvalidHeader)
+    ));
+
+    let kind = EMsg::k_EMsgClientLogOnResponse.value() as u32;
+    let mut data = BytesMut::from(&kind.to_le_bytes()[..]);
+    data.extend_from_slice(&[0; 8]);
+    assert!(matches!(
+        RawNetMessage::read(data),
+        Err(NetworkError::InvalidHeader)
+    ));
+
+    // the handshake messages have a shorter header, which still needs both job ids
+    let kind = EMsg::k_EMsgChannelEncryptRequest.value() as u32;
+    let mut data = BytesMut::from(&kind.to_le_bytes()[..]);
+    data.extend_from_slice(&[0; 12]);
+    assert!(matches!(
+        RawNetMessage::read(data),
+        Err(NetworkError::InvalidHeader)
+    ));
+}
+
+#[test]
+fn test_header_wire_sizes() {
+    let header = NetMessageHeader {
+        source_job_id: 1,
+        target_job_id: 2,
+        steam_id: SteamID::from(76561198000000000),
+        session_id: 3,
+        ..NetMessageHeader::default()
+    };
+    for (kind, proto) in [
+        (EMsg::k_EMsgClientOGSBeginSession, false),
+        (EMsg::k_EMsgClientHeartBeat, true),
+        (EMsg::k_EMsgServiceMethodCallFromClient, true),
+        (EMsg::k_EMsgChannelEncryptResponse, false),
+    ] {
+        let mut bytes = Vec::new();
+        header.write(&mut bytes, kind, proto).unwrap();
+        assert_eq!(bytes.len(), header.encode_size(kind, proto), "{kind:?}");
+    }
+
+    let mut bytes = Vec::new();
+    header
+        .write(&mut bytes, EMsg::k_EMsgClientOGSBeginSession, false)
+        .unwrap();
+    assert_eq!(36, EXTENDED_HEADER_SIZE);
+    assert_eq!(EXTENDED_HEADER_SIZE, bytes.len());
+    // the kind is read before the rest of the header
+    let (read, size) = NetMessageHeader::read(
+        &mut Cursor::new(&bytes[4..]),
+        EMsg::k_EMsgClientOGSBeginSession,
+        false,
+    )
+    .unwrap();
+    assert_eq!(EXTENDED_HEADER_SIZE, size);
+    assert_eq!(1, read.source_job_id);
+    assert_eq!(2, read.target_job_id);
+    assert_eq!(3, read.session_id);
+}
+
+#[test]
+fn test_proto_header_job_id() {
+    let header = NetMessageHeader {
+        source_job_id: 5,
+        ..NetMessageHeader::default()
+    };
+    // only messages waiting for a response send their job id
+    assert!(!header
+        .proto_header(EMsg::k_EMsgClientHeartBeat)
+        .has_jobid_source());
+    assert_eq!(
+        5,
+        header
+            .with_awaits_response()
+            .proto_header(EMsg::k_EMsgClientRegisterKey)
+            .jobid_source()
+    );
+}
+
+#[test]
+fn test_into_bytes() {
+    use crate::proto::steammessages_clientserver_login::CMsgClientHeartBeat;
+
+    let raw =
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .unwrap();
+    let bytes = raw.clone().into_bytes();
+    assert_eq!(raw.header_buffer.len() + raw.data.len(), bytes.len());
+    let read = RawNetMessage::read(bytes.clone()).unwrap();
+    assert_eq!(EMsg::k_EMsgClientHeartBeat, read.kind);
+    assert_eq!(raw.data, read.data);
+    // messages that were read can be turned back into the same bytes
+    assert_eq!(bytes, read.into_bytes());
+}
+
+#[test]
+fn test_header_trace_fields() {
+    let mut proto_header = CMsgProtoBufHeader::new();
+    proto_header.set_trace_tag(1234);
+    proto_header.set_realm(1);
+    proto_header.set_ip(0x7f000001);
+    let header = NetMessageHeader::from(proto_header);
+    assert_eq!(Some(1234), header.trace_tag);
+    assert_eq!(Some(1), header.realm);
+    assert_eq!(Some(IpAddr::from(Ipv4Addr::LOCALHOST)), header.ip);
+
+    let mut proto_header = CMsgProtoBufHeader::new();
+    proto_header.set_ip_v6(Ipv6Addr::LOCALHOST.octets().to_vec());
+    let header = NetMessageHeader::from(proto_header);
+    assert_eq!(None, header.trace_tag);
+    assert_eq!(Some(IpAddr::from(Ipv6Addr::LOCALHOST)), header.ip);
+
+    let header = NetMessageHeader::default()
+        .with_trace_tag(5678)
+        .with_routing_app_id(440)
+        .with_realm(2)
+        .with_target_job_name("Player.GetGameBadgeLevels#1");
+    let proto_header = header.proto_header(EMsg::k_EMsgClientHeartBeat);
+    assert_eq!(5678, proto_header.trace_tag());
+    assert_eq!(440, proto_header.routing_appid());
+    assert_eq!(2, proto_header.realm());
+    assert_eq!(
+        "Player.GetGameBadgeLevels#1",
+        proto_header.target_job_name()
+    );
+    assert_eq!(
+        Some(440),
+        NetMessageHeader::from(proto_header).routing_app_id
+    );
+}
@@ -0,0 +1,85 @@
+use crate::proto::steammessages_clientserver_friends::CMsgClientPlayerNicknameList;
+use std::collections::HashMap;
+use steamid_ng::SteamID;
+
+/// The nicknames the logged in account has given to other users, see [`Connection::nicknames`](crate::Connection::nicknames)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Nicknames {
+    nicknames: HashMap<SteamID, String>,
+}
+
+impl Nicknames {
+    pub fn get(&self, steam_id: SteamID) -> Option<&str> {
+        self.nicknames.get(&steam_id).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SteamID, &str)> {
+        self.nicknames
+            .iter()
+            .map(|(steam_id, nickname)| (*steam_id, nickname.as_str()))
+    }
+
+    /// Apply the full list or an incremental update of the nicknames
+    pub(crate) fn apply(&mut self, update: &CMsgClientPlayerNicknameList) {
+        if !update.incremental() {
+            self.nicknames.clear();
+        }
+
+        for nickname in &update.nicknames {
+            let steam_id = nickname.steamid().into();
+            // clearing a nickname is sent as an empty nickname
+            if update.removal() || nickname.nickname().is_empty() {
+                self.nicknames.remove(&steam_id);
+            } else {
+                self.nicknames.insert(steam_id, nickname.nickname().into());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_apply_nicknames() {
+    use crate::proto::steammessages_clientserver_friends::cmsg_client_player_nickname_list::PlayerNickname;
+
+    fn nickname(steam_id: SteamID, nickname: &str) -> PlayerNickname {
+        PlayerNickname {
+            steamid: Some(steam_id.into()),
+            nickname: Some(nickname.into()),
+            ..PlayerNickname::default()
+        }
+    }
+
+    let friend = SteamID::from(76561198000000001);
+    let other = SteamID::from(76561198000000002);
+
+    let mut nicknames = Nicknames::default();
+    nicknames.apply(&CMsgClientPlayerNicknameList {
+        nicknames: vec![nickname(friend, "Bob")],
+        ..CMsgClientPlayerNicknameList::default()
+    });
+    assert_eq!(Some("Bob"), nicknames.get(friend));
+
+    nicknames.apply(&CMsgClientPlayerNicknameList {
+        incremental: Some(true),
+        nicknames: vec![nickname(other, "Alice"), nickname(friend, "Robert")],
+        ..CMsgClientPlayerNicknameList::default()
+    });
+    assert_eq!(Some("Robert"), nicknames.get(friend));
+    assert_eq!(Some("Alice"), nicknames.get(other));
+
+    nicknames.apply(&CMsgClientPlayerNicknameList {
+        incremental: Some(true),
+        removal: Some(true),
+        nicknames: vec![nickname(friend, "")],
+        ..CMsgClientPlayerNicknameList::default()
+    });
+    assert_eq!(None, nicknames.get(friend));
+    assert_eq!(1, nicknames.iter().count());
+
+    // a full list replaces the existing nicknames
+    nicknames.apply(&CMsgClientPlayerNicknameList {
+        nicknames: vec![nickname(friend, "Bob")],
+        ..CMsgClientPlayerNicknameList::default()
+    });
+    assert_eq!(None, nicknames.get(other));
+}
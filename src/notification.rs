@@ -0,0 +1,482 @@
+use crate::account_limits::AccountLimits;
+use crate::chat_room::ChatRoomMessage;
+use crate::clan::ClanState;
+use crate::connection::{Connection, ConnectionState};
+use crate::eresult::EResult;
+use crate::items::ItemAnnouncements;
+use crate::legacy_chat::LegacyChatMessage;
+use crate::message::MalformedBody;
+use crate::message::ServiceMethodNotification;
+use crate::net::{NetworkError, RawNetMessage};
+use crate::offline_messages::OfflineMessages;
+use crate::proto::enums_clientserver::EMsg;
+use crate::proto::steammessages_chat_steamclient::CChatRoom_IncomingChatMessage_Notification;
+use crate::proto::steammessages_clientserver::{
+    CMsgClientCMList, CMsgClientClanState, CMsgClientIsLimitedAccount, CMsgClientLicenseList,
+    CMsgClientWalletInfoUpdate,
+};
+use crate::proto::steammessages_clientserver_2::{
+    CMsgClientItemAnnouncements, CMsgClientOfflineMessageNotification,
+};
+use crate::proto::steammessages_clientserver_friends::{
+    CMsgClientFriendMsgIncoming, CMsgClientFriendsGroupsList, CMsgClientPersonaState,
+    CMsgClientPlayerNicknameList,
+};
+use crate::proto::steammessages_clientserver_login::{CMsgClientLoggedOff, CMsgClientNewLoginKey};
+use crate::service_method::ServiceMethodRequest;
+use crate::vac::VacBanStatus;
+use crate::wallet::Wallet;
+use futures_util::future::{pending, select, Either};
+use protobuf::Message;
+use std::future::Future;
+use std::pin::pin;
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// A message pushed by the server that isn't a response to a request
+///
+/// Multi messages are already expanded into the messages they contain.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Notification {
+    FriendMessage {
+        message: CMsgClientFriendMsgIncoming,
+        /// What kind of entry this is, only [`ChatEntryType::ChatMsg`] entries contain text written by the user
+        entry_type: ChatEntryType,
+        /// The message was sent by the logged on account (from this or another session) and echoed back to it
+        ///
+        /// Bots should generally not react to echoed messages, to avoid replying to themselves
+        echo: bool,
+    },
+    /// A message in a chat room group the account is a member of
+    ChatRoomMessage(ChatRoomMessage),
+    /// A message in a legacy chat room that was joined with [`Connection::join_legacy_chat`]
+    ///
+    /// This is the older multi-user chat, messages in chat room groups are delivered as [`Notification::ChatRoomMessage`].
+    LegacyChatMessage(LegacyChatMessage),
+    /// Friends sent messages while the account was offline, fetch them with [`Connection::get_offline_messages`]
+    OfflineMessages(OfflineMessages),
+    PersonaState(CMsgClientPersonaState),
+    /// The full list of friend groups or an update to it, the resulting groups are available from [`Connection::friend_groups`]
+    FriendGroups(CMsgClientFriendsGroupsList),
+    /// The full list of nicknames or an update to it, the resulting nicknames are available from [`Connection::nicknames`]
+    Nicknames(CMsgClientPlayerNicknameList),
+    LicenseList(CMsgClientLicenseList),
+    /// Steam ended the session
+    LoggedOff {
+        message: CMsgClientLoggedOff,
+        /// Why the session was ended, decoded from the result in the message
+        reason: LogOffReason,
+    },
+    CmList(CMsgClientCMList),
+    /// Steam rotated the login key, the new key is already accepted and also available from [`Connection::login_key`]
+    NewLoginKey(CMsgClientNewLoginKey),
+    /// The wallet balance changed, also available from [`Connection::wallet`]
+    Wallet(Wallet),
+    /// The restrictions on the account, also available from [`Connection::account_limits`]
+    AccountLimits(AccountLimits),
+    /// The VAC bans on the account, also available from [`Connection::vac_bans`]
+    VacBanStatus(VacBanStatus),
+    /// An update to the state of a clan, only containing the changed fields
+    ///
+    /// The combined state is available from [`Connection::clan_state`]
+    ClanState(ClanState),
+    /// New items were added to the inventory, see [`Connection::request_item_announcements`]
+    ItemAnnouncements(ItemAnnouncements),
+    /// Marketing messages pushed by steam, most applications can ignore these
+    ///
+    /// These are separated from [`Notification::Unknown`] so they can be filtered out easily.
+    Marketing(RawNetMessage),
+    /// A message that isn't modelled by the crate
+    Unknown(RawNetMessage),
+}
+
+/// `k_EMsgClientMarketingMessageUpdate2`, no longer part of the message kinds known to the crate but still sent by steam
+const MARKETING_MESSAGE_UPDATE: i32 = 5510;
+
+impl Notification {
+    /// Decode a raw message into a notification, messages of kinds without a variant become [`Notification::Unknown`]
+    pub fn from_raw(raw: RawNetMessage) -> Result<Self, NetworkError> {
+        Ok(match raw.kind {
+            EMsg::k_EMsgClientFriendMsgIncoming => {
+                Notification::friend_message(raw.into_message()?, false)
+            }
+            // echoes use the same message body as incoming messages
+            EMsg::k_EMsgClientFriendMsgEchoToSender => Notification::friend_message(
+                CMsgClientFriendMsgIncoming::parse_from_bytes(&raw.data)
+                    .map_err(|e| MalformedBody::new(raw.kind, e))?,
+                true,
+            ),
+            EMsg::k_EMsgClientChatOfflineMessageNotification => {
+                Notification::OfflineMessages(OfflineMessages::from(
+                    &CMsgClientOfflineMessageNotification::parse_from_bytes(&raw.data)
+                        .map_err(|e| MalformedBody::new(raw.kind, e))?,
+                ))
+            }
+            EMsg::k_EMsgServiceMethod
+                if raw.header.target_job_name.as_deref()
+                    == Some(CChatRoom_IncomingChatMessage_Notification::REQ_NAME) =>
+            {
+                Notification::ChatRoomMessage(ChatRoomMessage::from(
+                    &raw.into_message::<ServiceMethodNotification>()?
+                        .into_notification::<CChatRoom_IncomingChatMessage_Notification>()?,
+                ))
+            }
+            EMsg::k_EMsgClientChatMsg => Notification::LegacyChatMessage(raw.into_message()?),
+            EMsg::k_EMsgClientPersonaState => Notification::PersonaState(raw.into_message()?),
+            EMsg::k_EMsgClientFriendsGroupsList => Notification::FriendGroups(raw.into_message()?),
+            EMsg::k_EMsgClientPlayerNicknameList => Notification::Nicknames(raw.into_message()?),
+            EMsg::k_EMsgClientLicenseList => Notification::LicenseList(raw.into_message()?),
+            EMsg::k_EMsgClientLoggedOff => {
+                let message: CMsgClientLoggedOff = raw.into_message()?;
+                Notification::LoggedOff {
+                    reason: LogOffReason::from(
+                        EResult::try_from(message.eresult()).unwrap_or(EResult::Invalid),
+                    ),
+                    message,
+                }
+            }
+            EMsg::k_EMsgClientCMList => Notification::CmList(raw.into_message()?),
+            EMsg::k_EMsgClientNewLoginKey => Notification::NewLoginKey(raw.into_message()?),
+            EMsg::k_EMsgClientIsLimitedAccount => Notification::AccountLimits(AccountLimits::from(
+                &raw.into_message::<CMsgClientIsLimitedAccount>()?,
+            )),
+            EMsg::k_EMsgClientWalletInfoUpdate => Notification::Wallet(Wallet::from(
+                &raw.into_message::<CMsgClientWalletInfoUpdate>()?,
+            )),
+            EMsg::k_EMsgClientVACBanStatus => Notification::VacBanStatus(raw.into_message()?),
+            EMsg::k_EMsgClientClanState => Notification::ClanState(ClanState::from(
+                &raw.into_message::<CMsgClientClanState>()?,
+            )),
+            EMsg::k_EMsgClientItemAnnouncements => Notification::ItemAnnouncements(
+                ItemAnnouncements::from(&raw.into_message::<CMsgClientItemAnnouncements>()?),
+            ),
+            _ if raw.raw_kind == MARKETING_MESSAGE_UPDATE => Notification::Marketing(raw),
+            _ => Notification::Unknown(raw),
+        })
+    }
+
+    pub(crate) fn friend_message(message: CMsgClientFriendMsgIncoming, echo: bool) -> Self {
+        Notification::FriendMessage {
+            entry_type: ChatEntryType::from(message.chat_entry_type()),
+            message,
+            echo,
+        }
+    }
+}
+
+/// The kind of entry in a chat, see [`Notification::FriendMessage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChatEntryType {
+    /// A text message
+    ChatMsg,
+    /// The user is typing a message
+    Typing,
+    /// An invite to a game
+    InviteGame,
+    /// The user closed the chat window
+    LeftConversation,
+    /// The user entered the chat
+    Entered,
+    WasKicked,
+    WasBanned,
+    /// The user disconnected
+    Disconnected,
+    /// A message from the chat history, sent before the user joined
+    HistoricalChat,
+    /// A message containing a link that was blocked
+    LinkBlocked,
+    Other(i32),
+}
+
+impl From<i32> for ChatEntryType {
+    fn from(value: i32) -> Self {
+        match value {
+            1 => ChatEntryType::ChatMsg,
+            2 => ChatEntryType::Typing,
+            3 => ChatEntryType::InviteGame,
+            6 => ChatEntryType::LeftConversation,
+            7 => ChatEntryType::Entered,
+            8 => ChatEntryType::WasKicked,
+            9 => ChatEntryType::WasBanned,
+            10 => ChatEntryType::Disconnected,
+            11 => ChatEntryType::HistoricalChat,
+            14 => ChatEntryType::LinkBlocked,
+            value => ChatEntryType::Other(value),
+        }
+    }
+}
+
+impl From<ChatEntryType> for i32 {
+    fn from(value: ChatEntryType) -> Self {
+        match value {
+            ChatEntryType::ChatMsg => 1,
+            ChatEntryType::Typing => 2,
+            ChatEntryType::InviteGame => 3,
+            ChatEntryType::LeftConversation => 6,
+            ChatEntryType::Entered => 7,
+            ChatEntryType::WasKicked => 8,
+            ChatEntryType::WasBanned => 9,
+            ChatEntryType::Disconnected => 10,
+            ChatEntryType::HistoricalChat => 11,
+            ChatEntryType::LinkBlocked => 14,
+            ChatEntryType::Other(value) => value,
+        }
+    }
+}
+
+/// The reason steam ended the session, see [`Notification::LoggedOff`]
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum LogOffReason {
+    /// The account logged on in another session, which replaced this one
+    ///
+    /// Logging on again will in turn end the other session, so applications should generally back off
+    /// instead of reconnecting right away, to avoid two sessions fighting over the account.
+    LoggedInElsewhere,
+    /// The server is unavailable, e.g. because of maintenance, logging on again through another server can work
+    ServiceUnavailable,
+    Other(EResult),
+}
+
+impl From<EResult> for LogOffReason {
+    fn from(value: EResult) -> Self {
+        match value {
+            EResult::LoggedInElsewhere | EResult::LogonSessionReplaced => {
+                LogOffReason::LoggedInElsewhere
+            }
+            EResult::ServiceUnavailable | EResult::TryAnotherCM => LogOffReason::ServiceUnavailable,
+            value => LogOffReason::Other(value),
+        }
+    }
+}
+
+/// An event for the top level loop of an application, see [`Connection::next_event`]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Event {
+    /// A notification pushed by the server
+    Notification(Box<Notification>),
+    /// The state of the connection changed, e.g. it's reconnecting or closed
+    StateChanged(ConnectionState),
+    /// The shutdown future resolved
+    Shutdown,
+}
+
+impl Connection {
+    /// Like [`Connection::next`] but decode the message into a [`Notification`]
+    pub async fn next_notification(&mut self) -> Result<Notification, NetworkError> {
+        Notification::from_raw(self.next().await?)
+    }
+
+    /// Like [`Connection::next_notification`] but include the time the message was received
+    ///
+    /// The time is only recorded when enabled with [`ConnectionOptions::with_receive_timestamps`](crate::ConnectionOptions::with_receive_timestamps)
+    pub async fn next_timestamped_notification(
+        &mut self,
+    ) -> Result<(Notification, Option<Instant>), NetworkError> {
+        let raw = self.next().await?;
+        let received_at = raw.received_at;
+        Ok((Notification::from_raw(raw)?, received_at))
+    }
+
+    /// Wait for the next notification, connection state change or shutdown request
+    ///
+    /// The `state` receiver is used to keep track of which state changes have been seen,
+    /// pass the same receiver (from [`Connection::state`]) on every call.
+    /// If multiple events are ready at once, shutdown takes priority over state changes, which take priority over notifications.
+    ///
+    /// ```no_run
+    /// # use steam_vent::{ChatEntryType, Connection, ConnectionState, Event, Notification, ServerList};
+    /// # use std::pin::pin;
+    /// # async fn run(shutdown: tokio::sync::oneshot::Receiver<()>) -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut connection = Connection::anonymous(ServerList::discover().await?).await?;
+    /// let mut state = connection.state();
+    /// let mut shutdown = pin!(async {
+    ///     shutdown.await.ok();
+    /// });
+    ///
+    /// loop {
+    ///     match connection.next_event(&mut state, shutdown.as_mut()).await? {
+    ///         Event::Notification(notification) => {
+    ///             if let Notification::FriendMessage {
+    ///                 message,
+    ///                 entry_type: ChatEntryType::ChatMsg,
+    ///                 echo: false,
+    ///             } = *notification
+    ///             {
+    ///                 println!("{}", String::from_utf8_lossy(message.message()));
+    ///             }
+    ///         }
+    ///         Event::StateChanged(ConnectionState::Closed { error }) => {
+    ///             println!("connection closed: {error:?}");
+    ///             break;
+    ///         }
+    ///         Event::Shutdown => break,
+    ///         _ => {}
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn next_event<F: Future<Output = ()>>(
+        &mut self,
+        state: &mut watch::Receiver<ConnectionState>,
+        shutdown: F,
+    ) -> Result<Event, NetworkError> {
+        let state_changed = async {
+            if state.changed().await.is_err() {
+                pending::<()>().await;
+            }
+            state.borrow_and_update().clone()
+        };
+        let state_changed = pin!(state_changed);
+        let notification = pin!(self.next_notification());
+        let events = select(state_changed, notification);
+        match select(pin!(shutdown), events).await {
+            Either::Left(_) => Ok(Event::Shutdown),
+            Either::Right((Either::Left((state, _)), _)) => Ok(Event::StateChanged(state)),
+            Either::Right((Either::Right((notification, _)), _)) => {
+                notification.map(|notification| Event::Notification(Box::new(notification)))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_friend_message_echo() {
+    use crate::net::NetMessageHeader;
+    use steamid_ng::SteamID;
+
+    let message = CMsgClientFriendMsgIncoming {
+        steamid_from: Some(76561198000000000),
+        chat_entry_type: Some(1),
+        message: Some(b"hello".to_vec()),
+        ..CMsgClientFriendMsgIncoming::default()
+    };
+    let header = NetMessageHeader {
+        steam_id: SteamID::from(76561198000000000),
+        ..NetMessageHeader::default()
+    };
+
+    let incoming = RawNetMessage::from_message(header.clone(), message.clone()).unwrap();
+    assert!(matches!(
+        Notification::from_raw(incoming).unwrap(),
+        Notification::FriendMessage { echo: false, .. }
+    ));
+
+    let echo = RawNetMessage::from_message_with_kind(
+        header,
+        message,
+        EMsg::k_EMsgClientFriendMsgEchoToSender,
+    )
+    .unwrap();
+    match Notification::from_raw(echo).unwrap() {
+        Notification::FriendMessage {
+            message,
+            entry_type,
+            echo,
+        } => {
+            assert!(echo);
+            assert_eq!(ChatEntryType::ChatMsg, entry_type);
+            assert_eq!(b"hello", message.message());
+        }
+        notification => panic!("unexpected notification {notification:?}"),
+    }
+}
+
+#[test]
+fn test_marketing_message() {
+    use bytes::BytesMut;
+
+    let mut data = BytesMut::from(&(MARKETING_MESSAGE_UPDATE as u32).to_le_bytes()[..]);
+    // extended header: size, version, job ids, canary, steam id and session id
+    data.extend_from_slice(&[36, 2, 0]);
+    data.extend_from_slice(&u64::MAX.to_le_bytes());
+    data.extend_from_slice(&u64::MAX.to_le_bytes());
+    data.extend_from_slice(&[0xef]);
+    data.extend_from_slice(&76561198000000000u64.to_le_bytes());
+    data.extend_from_slice(&1i32.to_le_bytes());
+    // update time and message count
+    data.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0]);
+    let raw = RawNetMessage::read(data).unwrap();
+    assert!(matches!(
+        Notification::from_raw(raw).unwrap(),
+        Notification::Marketing(_)
+    ));
+}
+
+#[test]
+fn test_item_announcements_notification() {
+    use crate::net::NetMessageHeader;
+
+    let message = CMsgClientItemAnnouncements {
+        count_new_items: Some(2),
+        ..CMsgClientItemAnnouncements::default()
+    };
+    let raw = RawNetMessage::from_message(NetMessageHeader::default(), message).unwrap();
+    let raw = RawNetMessage::read(raw.into_bytes()).unwrap();
+    assert!(matches!(
+        Notification::from_raw(raw).unwrap(),
+        Notification::ItemAnnouncements(ItemAnnouncements { new_items: 2, .. })
+    ));
+}
+
+#[test]
+fn test_logged_off_reason() {
+    use crate::net::NetMessageHeader;
+
+    let logged_off = |result: EResult| {
+        let message = CMsgClientLoggedOff {
+            eresult: Some(result as i32),
+            ..CMsgClientLoggedOff::default()
+        };
+        let raw = RawNetMessage::from_message(NetMessageHeader::default(), message).unwrap();
+        match Notification::from_raw(raw).unwrap() {
+            Notification::LoggedOff { reason, .. } => reason,
+            notification => panic!("unexpected notification {notification:?}"),
+        }
+    };
+    assert!(matches!(
+        logged_off(EResult::LoggedInElsewhere),
+        LogOffReason::LoggedInElsewhere
+    ));
+    assert!(matches!(
+        logged_off(EResult::LogonSessionReplaced),
+        LogOffReason::LoggedInElsewhere
+    ));
+    assert!(matches!(
+        logged_off(EResult::TryAnotherCM),
+        LogOffReason::ServiceUnavailable
+    ));
+    assert!(matches!(
+        logged_off(EResult::Revoked),
+        LogOffReason::Other(EResult::Revoked)
+    ));
+}
+
+#[test]
+fn test_chat_entry_type() {
+    assert_eq!(ChatEntryType::Typing, ChatEntryType::from(2));
+    assert_eq!(ChatEntryType::LeftConversation, ChatEntryType::from(6));
+    assert_eq!(ChatEntryType::Other(42), ChatEntryType::from(42));
+    for value in 0..16 {
+        assert_eq!(value, i32::from(ChatEntryType::from(value)));
+    }
+
+    let typing = Notification::friend_message(
+        CMsgClientFriendMsgIncoming {
+            chat_entry_type: Some(2),
+            ..CMsgClientFriendMsgIncoming::default()
+        },
+        false,
+    );
+    assert!(matches!(
+        typing,
+        Notification::FriendMessage {
+            entry_type: ChatEntryType::Typing,
+            ..
+        }
+    ));
+}
@@ -0,0 +1,226 @@
+use crate::connection::Connection;
+use crate::message::{NetMessage, ServiceMethodMessage};
+use crate::net::NetworkError;
+use crate::notification::{ChatEntryType, Notification};
+use crate::proto::steammessages_clientserver_2::{
+    CMsgClientChatGetFriendMessageHistory, CMsgClientChatGetFriendMessageHistoryResponse,
+    CMsgClientOfflineMessageNotification,
+};
+use crate::proto::steammessages_clientserver_friends::CMsgClientFriendMsgIncoming;
+use crate::proto::steammessages_friendmessages_steamclient::CFriendMessages_AckMessage_Notification;
+use steamid_ng::{AccountType, Instance, SteamID, Universe};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::timeout;
+
+/// The friends that sent messages while the account was offline
+///
+/// The messages can be fetched with [`Connection::get_offline_messages`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OfflineMessages {
+    /// The total number of unread messages
+    pub count: u32,
+    pub friends: Vec<SteamID>,
+}
+
+impl From<&CMsgClientOfflineMessageNotification> for OfflineMessages {
+    fn from(notification: &CMsgClientOfflineMessageNotification) -> Self {
+        OfflineMessages {
+            count: notification.offline_messages(),
+            friends: notification
+                .friends_with_offline_messages
+                .iter()
+                .map(|account_id| individual(*account_id))
+                .collect(),
+        }
+    }
+}
+
+fn individual(account_id: u32) -> SteamID {
+    SteamID::new(
+        account_id,
+        Instance::Desktop,
+        AccountType::Individual,
+        Universe::Public,
+    )
+}
+
+/// Convert the unread messages from a message history into friend message notifications
+fn unread_messages(
+    history: &CMsgClientChatGetFriendMessageHistoryResponse,
+    own_account_id: u32,
+) -> Vec<Notification> {
+    history
+        .messages
+        .iter()
+        .filter(|message| message.unread())
+        .map(|message| {
+            Notification::friend_message(
+                CMsgClientFriendMsgIncoming {
+                    steamid_from: Some(individual(message.accountid()).into()),
+                    chat_entry_type: Some(ChatEntryType::ChatMsg.into()),
+                    message: Some(message.message().as_bytes().to_vec()),
+                    rtime32_server_timestamp: message.timestamp,
+                    ..CMsgClientFriendMsgIncoming::default()
+                },
+                message.accountid() == own_account_id,
+            )
+        })
+        .collect()
+}
+
+impl Connection {
+    /// Get the messages a friend sent while the account was offline and mark them as read
+    ///
+    /// The messages are returned as [`Notification::FriendMessage`], the same as messages received while online,
+    /// so they can be handled by the same code. Since they are marked as read they won't be returned again.
+    pub async fn get_offline_messages(
+        &self,
+        friend: SteamID,
+    ) -> Result<Vec<Notification>, NetworkError> {
+        // the responses don't carry a job id, concurrent requests are told apart by the friend they're for
+        let mut responses = self.on_kind(CMsgClientChatGetFriendMessageHistoryResponse::KIND);
+        self.send(
+            self.session.header(),
+            CMsgClientChatGetFriendMessageHistory {
+                steamid: Some(friend.into()),
+                ..CMsgClientChatGetFriendMessageHistory::default()
+            },
+        )
+        .await?;
+        let history = timeout(self.timeout, async {
+            loop {
+                let raw = match responses.recv().await {
+                    Ok(raw) => raw,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Err(NetworkError::EOF),
+                };
+                let history: CMsgClientChatGetFriendMessageHistoryResponse = raw.into_message()?;
+                if history.steamid() == u64::from(friend) {
+                    return Ok(history);
+                }
+            }
+        })
+        .await
+        .map_err(|_| NetworkError::Timeout)??;
+
+        let messages = unread_messages(&history, self.steam_id().account_id());
+        let last_timestamp = history
+            .messages
+            .iter()
+            .filter(|message| message.unread())
+            .map(|message| message.timestamp())
+            .max();
+        if let Some(timestamp) = last_timestamp {
+            self.send(
+                self.session.header(),
+                ServiceMethodMessage(CFriendMessages_AckMessage_Notification {
+                    steamid_partner: Some(friend.into()),
+                    timestamp: Some(timestamp),
+                    ..CFriendMessages_AckMessage_Notification::default()
+                }),
+            )
+            .await?;
+        }
+        Ok(messages)
+    }
+}
+
+#[test]
+fn test_unread_messages() {
+    use crate::proto::steammessages_clientserver_2::cmsg_client_chat_get_friend_message_history_response::FriendMessage;
+
+    let own = individual(2);
+    let history = CMsgClientChatGetFriendMessageHistoryResponse {
+        steamid: Some(individual(1).into()),
+        messages: vec![
+            FriendMessage {
+                accountid: Some(1),
+                timestamp: Some(100),
+                message: Some("already read".into()),
+                unread: Some(false),
+                ..FriendMessage::default()
+            },
+            FriendMessage {
+                accountid: Some(1),
+                timestamp: Some(200),
+                message: Some("hello".into()),
+                unread: Some(true),
+                ..FriendMessage::default()
+            },
+        ],
+        ..CMsgClientChatGetFriendMessageHistoryResponse::default()
+    };
+    let messages = unread_messages(&history, own.account_id());
+    assert_eq!(1, messages.len());
+    match &messages[0] {
+        Notification::FriendMessage {
+            message,
+            entry_type,
+            echo,
+        } => {
+            assert!(!echo);
+            assert_eq!(ChatEntryType::ChatMsg, *entry_type);
+            assert_eq!(b"hello", message.message());
+            assert_eq!(u64::from(individual(1)), message.steamid_from());
+            assert_eq!(200, message.rtime32_server_timestamp());
+        }
+        notification => panic!("unexpected notification {notification:?}"),
+    }
+
+    let notification = CMsgClientOfflineMessageNotification {
+        offline_messages: Some(3),
+        friends_with_offline_messages: vec![1],
+        ..CMsgClientOfflineMessageNotification::default()
+    };
+    assert_eq!(
+        OfflineMessages {
+            count: 3,
+            friends: vec![individual(1)]
+        },
+        OfflineMessages::from(&notification)
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_concurrent_offline_messages() {
+    use crate::net::{NetMessageHeader, RawNetMessage};
+    use crate::proto::steammessages_clientserver_2::cmsg_client_chat_get_friend_message_history_response::FriendMessage;
+    use steam_vent_proto::enums_clientserver::EMsg;
+
+    let response = |friend: SteamID, message: &str| {
+        let history = CMsgClientChatGetFriendMessageHistoryResponse {
+            steamid: Some(friend.into()),
+            messages: vec![FriendMessage {
+                accountid: Some(friend.account_id()),
+                timestamp: Some(100),
+                message: Some(message.into()),
+                unread: Some(true),
+                ..FriendMessage::default()
+            }],
+            ..CMsgClientChatGetFriendMessageHistoryResponse::default()
+        };
+        let raw = RawNetMessage::from_message(NetMessageHeader::default(), history).unwrap();
+        (
+            EMsg::k_EMsgClientChatGetFriendMessageHistoryResponse,
+            raw.into_bytes().to_vec(),
+        )
+    };
+    let (first, second) = (individual(1), individual(2));
+    // the responses arrive in a different order than the requests were sent
+    let connection = Connection::replay([
+        response(second, "from second"),
+        response(first, "from first"),
+    ]);
+
+    let (first_messages, second_messages) = tokio::join!(
+        connection.get_offline_messages(first),
+        connection.get_offline_messages(second)
+    );
+    let text = |messages: Vec<Notification>| match messages.as_slice() {
+        [Notification::FriendMessage { message, .. }] => message.message().to_vec(),
+        other => panic!("unexpected messages {other:?}"),
+    };
+    assert_eq!(b"from first".to_vec(), text(first_messages.unwrap()));
+    assert_eq!(b"from second".to_vec(), text(second_messages.unwrap()));
+}
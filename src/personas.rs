@@ -0,0 +1,304 @@
+use crate::connection::Connection;
+use crate::net::NetworkError;
+use crate::presence::PersonaState;
+use crate::proto::steammessages_clientserver_friends::{
+    cmsg_client_persona_state, CMsgClientPersonaState, CMsgClientRequestFriendData,
+};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use steamid_ng::SteamID;
+use tokio::time::timeout;
+
+/// The persona data requested with [`Connection::get_persona`]: status, name, presence, last seen,
+/// game extra info and rich presence
+const REQUESTED_PERSONA_FLAGS: u32 = 1 | 2 | 16 | 64 | 256 | 4096;
+
+/// The public profile data of a user, see [`Connection::get_persona`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Persona {
+    pub steam_id: SteamID,
+    pub name: Option<String>,
+    pub state: Option<PersonaState>,
+    pub game_app_id: Option<u32>,
+    pub game_name: Option<String>,
+    pub avatar_hash: Option<Vec<u8>>,
+    /// Unix timestamp of the last logon
+    pub last_logon: Option<u32>,
+    /// Unix timestamp of the last logoff
+    pub last_logoff: Option<u32>,
+    /// Unix timestamp of when the user was last seen online
+    pub last_seen_online: Option<u32>,
+}
+
+impl Persona {
+    /// Update the persona with the fields that are set in the update, steam only sends the requested fields
+    fn apply(&mut self, update: &cmsg_client_persona_state::Friend) {
+        fn set<T: Clone>(field: &mut Option<T>, value: &Option<T>) {
+            if value.is_some() {
+                field.clone_from(value);
+            }
+        }
+        set(&mut self.name, &update.player_name);
+        if let Some(state) = update.persona_state.and_then(PersonaState::from_u32) {
+            self.state = Some(state);
+        }
+        set(&mut self.game_app_id, &update.game_played_app_id);
+        set(&mut self.game_name, &update.game_name);
+        set(&mut self.avatar_hash, &update.avatar_hash);
+        set(&mut self.last_logon, &update.last_logon);
+        set(&mut self.last_logoff, &update.last_logoff);
+        set(&mut self.last_seen_online, &update.last_seen_online);
+    }
+}
+
+/// The persona data received from steam with the time it was last updated
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Personas {
+    personas: HashMap<SteamID, (Persona, Instant)>,
+}
+
+impl Personas {
+    /// Apply an update of the persona data received at `now`
+    pub(crate) fn apply(&mut self, update: &CMsgClientPersonaState, now: Instant) {
+        for friend in &update.friends {
+            let steam_id = SteamID::from(friend.friendid());
+            let (persona, updated_at) = self.personas.entry(steam_id).or_insert_with(|| {
+                let persona = Persona {
+                    steam_id,
+                    ..Persona::default()
+                };
+                (persona, now)
+            });
+            persona.apply(friend);
+            *updated_at = now;
+        }
+    }
+
+    /// Drop the personas that weren't updated within `max_age`
+    ///
+    /// Expired personas are requested again before they're used, so they only take up memory.
+    /// Returns whether any persona was dropped.
+    fn evict(&mut self, now: Instant, max_age: Duration) -> bool {
+        let len = self.personas.len();
+        self.personas
+            .retain(|_, (_, updated_at)| now.saturating_duration_since(*updated_at) < max_age);
+        self.personas.len() != len
+    }
+
+    /// Whether the persona was updated less than `ttl` ago
+    fn is_fresh(&self, steam_id: SteamID, ttl: Duration, now: Instant) -> bool {
+        self.personas
+            .get(&steam_id)
+            .is_some_and(|(_, updated_at)| now.saturating_duration_since(*updated_at) < ttl)
+    }
+
+    /// The persona if it was updated after `since`
+    fn updated_since(&self, steam_id: SteamID, since: Instant) -> Option<&Persona> {
+        self.personas
+            .get(&steam_id)
+            .filter(|(_, updated_at)| *updated_at >= since)
+            .map(|(persona, _)| persona)
+    }
+}
+
+/// Split the users into requests of at most `batch_size` users
+fn friend_data_requests(
+    steam_ids: &[SteamID],
+    batch_size: usize,
+) -> impl Iterator<Item = CMsgClientRequestFriendData> + '_ {
+    steam_ids
+        .chunks(batch_size.max(1))
+        .map(|batch| CMsgClientRequestFriendData {
+            persona_state_requested: Some(REQUESTED_PERSONA_FLAGS),
+            friends: batch.iter().map(|steam_id| u64::from(*steam_id)).collect(),
+            ..CMsgClientRequestFriendData::default()
+        })
+}
+
+impl Connection {
+    /// Get the persona data of a user, from the cache if it was received recently
+    ///
+    /// See [`Connection::get_personas`] for requesting the data of many users at once.
+    pub async fn get_persona(&self, steam_id: SteamID) -> Result<Persona, NetworkError> {
+        let mut personas = self.get_personas(&[steam_id]).await?;
+        Ok(personas.remove(0))
+    }
+
+    /// Get the persona data of the users, in the same order
+    ///
+    /// Only users whose data isn't cached, or was received longer ago than the ttl set with
+    /// [`ConnectionOptions::with_persona_cache_ttl`](crate::ConnectionOptions::with_persona_cache_ttl),
+    /// are requested from steam. They are requested in batches of the size set with
+    /// [`ConnectionOptions::with_persona_batch_size`](crate::ConnectionOptions::with_persona_batch_size).
+    /// Fails with [`NetworkError::Timeout`] if steam doesn't send the data of every user within the timeout.
+    pub async fn get_personas(&self, steam_ids: &[SteamID]) -> Result<Vec<Persona>, NetworkError> {
+        let now = Instant::now();
+        let ttl = self.options.persona_cache_ttl;
+        // a concurrent call can still be waiting for personas received up to the timeout ago,
+        // even if they're already expired
+        let cache = self.personas();
+        cache.send_if_modified(|personas| personas.evict(now, ttl.max(self.timeout)));
+        let mut personas = cache.subscribe();
+        let mut seen = HashSet::new();
+        let missing: Vec<SteamID> = {
+            let cached = personas.borrow();
+            steam_ids
+                .iter()
+                .copied()
+                .filter(|steam_id| seen.insert(*steam_id))
+                .filter(|steam_id| !cached.is_fresh(*steam_id, ttl, now))
+                .collect()
+        };
+
+        if !missing.is_empty() {
+            for request in friend_data_requests(&missing, self.options.persona_batch_size) {
+                self.send(self.session.header(), request).await?;
+            }
+            let received = personas.wait_for(|cached| {
+                missing
+                    .iter()
+                    .all(|steam_id| cached.updated_since(*steam_id, now).is_some())
+            });
+            timeout(self.timeout, received)
+                .await
+                .map_err(|_| NetworkError::Timeout)?
+                .map_err(|_| NetworkError::EOF)?;
+        }
+
+        let cached = personas.borrow();
+        Ok(steam_ids
+            .iter()
+            .map(|steam_id| {
+                cached
+                    .personas
+                    .get(steam_id)
+                    .map(|(persona, _)| persona.clone())
+                    .unwrap_or_else(|| Persona {
+                        steam_id: *steam_id,
+                        ..Persona::default()
+                    })
+            })
+            .collect())
+    }
+}
+
+#[test]
+fn test_persona_cache() {
+    use crate::proto::steammessages_clientserver_friends::cmsg_client_persona_state::Friend;
+
+    let friend = SteamID::from(76561198000000001);
+    let ttl = Duration::from_secs(300);
+    let start = Instant::now();
+    let mut personas = Personas::default();
+    personas.apply(
+        &CMsgClientPersonaState {
+            friends: vec![Friend {
+                friendid: Some(friend.into()),
+                player_name: Some("friend".into()),
+                persona_state: Some(3),
+                game_played_app_id: Some(440),
+                ..Friend::default()
+            }],
+            ..CMsgClientPersonaState::default()
+        },
+        start,
+    );
+    // later updates only contain the changed fields
+    let later = start + Duration::from_secs(60);
+    personas.apply(
+        &CMsgClientPersonaState {
+            friends: vec![Friend {
+                friendid: Some(friend.into()),
+                persona_state: Some(1),
+                ..Friend::default()
+            }],
+            ..CMsgClientPersonaState::default()
+        },
+        later,
+    );
+
+    assert_eq!(
+        Some(&Persona {
+            steam_id: friend,
+            name: Some("friend".into()),
+            state: Some(PersonaState::Online),
+            game_app_id: Some(440),
+            ..Persona::default()
+        }),
+        personas.updated_since(friend, later)
+    );
+    assert!(personas
+        .updated_since(friend, later + Duration::from_secs(1))
+        .is_none());
+    assert!(personas
+        .updated_since(SteamID::from(76561198000000002), start)
+        .is_none());
+
+    assert!(personas.is_fresh(friend, ttl, later + Duration::from_secs(299)));
+    assert!(!personas.is_fresh(friend, ttl, later + Duration::from_secs(300)));
+
+    // updates never evict, expired personas are only dropped when reading
+    let other = SteamID::from(76561198000000002);
+    personas.apply(
+        &CMsgClientPersonaState {
+            friends: vec![Friend {
+                friendid: Some(other.into()),
+                ..Friend::default()
+            }],
+            ..CMsgClientPersonaState::default()
+        },
+        later + ttl,
+    );
+    assert!(personas.personas.contains_key(&friend));
+    assert!(!personas.evict(later + ttl - Duration::from_secs(1), ttl));
+    assert!(personas.evict(later + ttl, ttl));
+    assert!(!personas.personas.contains_key(&friend));
+    assert!(personas.personas.contains_key(&other));
+}
+
+#[test]
+fn test_persona_cache_zero_ttl() {
+    use crate::proto::steammessages_clientserver_friends::cmsg_client_persona_state::Friend;
+
+    let update = |steam_id: SteamID| CMsgClientPersonaState {
+        friends: vec![Friend {
+            friendid: Some(steam_id.into()),
+            player_name: Some("friend".into()),
+            ..Friend::default()
+        }],
+        ..CMsgClientPersonaState::default()
+    };
+    let friend = SteamID::from(76561198000000001);
+    let other = SteamID::from(76561198000000002);
+    let start = Instant::now();
+    let mut personas = Personas::default();
+
+    // steam can answer a batch in separate updates, the earlier ones are kept for the request waiting on them
+    personas.apply(&update(friend), start);
+    personas.apply(&update(other), start + Duration::from_millis(10));
+    assert!(personas.updated_since(friend, start).is_some());
+    assert!(personas.updated_since(other, start).is_some());
+    // but they're never fresh, so the next request asks steam again
+    assert!(!personas.is_fresh(friend, Duration::ZERO, start));
+    assert!(!personas.is_fresh(other, Duration::ZERO, start));
+}
+
+#[test]
+fn test_friend_data_batches() {
+    let steam_ids: Vec<SteamID> = (0..250)
+        .map(|id| SteamID::from(76561198000000000 + id))
+        .collect();
+    let requests: Vec<_> = friend_data_requests(&steam_ids, 100).collect();
+    assert_eq!(
+        vec![100, 100, 50],
+        requests
+            .iter()
+            .map(|request| request.friends.len())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(u64::from(steam_ids[100]), requests[1].friends[0]);
+    assert_eq!(
+        Some(REQUESTED_PERSONA_FLAGS),
+        requests[0].persona_state_requested
+    );
+}
@@ -0,0 +1,226 @@
+use crate::auth::{AuthConfirmationHandler, GuardDataStore};
+use crate::connection::{Connection, ConnectionOptions};
+use crate::net::NetworkError;
+use crate::serverlist::ServerList;
+use crate::session::ConnectionError;
+use crate::task::spawn_named;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::sleep;
+use tracing::{debug, error};
+
+/// Keeps a number of connections that have completed the handshake ready, to quickly log on or fail over
+///
+/// The pool connects to the servers from the server list in turn, so the pooled connections
+/// are spread over different servers. Connections taken from the pool are replaced in the background.
+pub struct ConnectionPool {
+    connections: Mutex<mpsc::Receiver<Connection>>,
+    options: ConnectionOptions,
+}
+
+impl ConnectionPool {
+    /// Create a pool that keeps `size` connections to the servers from `server_list` ready
+    ///
+    /// The options are used for all connections from the pool, every connection has its own state,
+    /// available from [`Connection::state`]. A `size` of 0 is treated as 1, the pool always keeps a connection ready.
+    pub fn new(server_list: ServerList, size: usize, options: ConnectionOptions) -> Self {
+        let (tx, rx) = mpsc::channel(size.max(1));
+        let urls = server_list.urls(options.transport);
+        spawn_named(
+            "steam-vent connection pool",
+            fill(urls, tx, options.clone()),
+        );
+
+        ConnectionPool {
+            connections: Mutex::new(rx),
+            options,
+        }
+    }
+
+    /// Take a connection from the pool, waiting for one to become available if the pool is empty
+    async fn take(&self) -> Result<Connection, ConnectionError> {
+        let mut connections = self.connections.lock().await;
+        loop {
+            let connection = connections.recv().await.ok_or(NetworkError::EOF)?;
+            // the server might have closed the connection while it was waiting in the pool
+            if !connection.is_closed() {
+                return Ok(connection);
+            }
+            debug!("discarding closed connection from pool");
+        }
+    }
+
+    /// Start an anonymous session using a connection from the pool
+    pub async fn anonymous(&self) -> Result<Connection, ConnectionError> {
+        self.take().await?.anonymous_session().await
+    }
+
+    /// Log in using a connection from the pool
+    pub async fn login<H: AuthConfirmationHandler, G: GuardDataStore>(
+        &self,
+        account: &str,
+        password: &str,
+        guard_data_store: G,
+        confirmation_handler: H,
+    ) -> Result<Connection, ConnectionError> {
+        self.take()
+            .await?
+            .login_session(
+                account,
+                password,
+                guard_data_store,
+                confirmation_handler,
+                &self.options,
+            )
+            .await
+    }
+}
+
+/// Keep the pool filled with connections to the servers from `urls` in turn, until the pool is dropped
+async fn fill(urls: Vec<String>, tx: mpsc::Sender<Connection>, options: ConnectionOptions) {
+    if urls.is_empty() {
+        error!("no servers to fill the connection pool with");
+        return;
+    }
+    for url in urls.iter().cycle() {
+        // wait for room in the pool before connecting, to only keep `size` connections open
+        let Ok(permit) = tx.reserve().await else {
+            break;
+        };
+        match Connection::connect(url, &options.with_new_state()).await {
+            Ok(connection) => {
+                debug!(url, "added connection to pool");
+                permit.send(connection);
+            }
+            Err(e) => {
+                error!(error = ?e, url, "failed to create connection for pool");
+                drop(permit);
+                sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+    debug!("connection pool closed");
+}
+
+#[cfg(test)]
+fn mock_options() -> ConnectionOptions {
+    use crate::Transport;
+    use steam_vent_crypto::MockCrypto;
+
+    ConnectionOptions::default()
+        .with_transport(Transport::Tcp)
+        .with_crypto_provider(MockCrypto)
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_pool_fill_and_replenish() {
+    use crate::transport::tcp::{accept_mock_handshake, receive_mock_message};
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_list = ServerList::new(vec![listener.local_addr().unwrap()], Vec::new());
+    let (accepted_tx, mut accepted) = mpsc::unbounded_channel();
+    let server = tokio::spawn(async move {
+        loop {
+            let mut framed = accept_mock_handshake(&listener).await;
+            receive_mock_message(&mut framed).await;
+            // keep the connection open
+            if accepted_tx.send(framed).is_err() {
+                break;
+            }
+        }
+    });
+
+    let pool = ConnectionPool::new(server_list, 2, mock_options());
+    let mut servers = vec![
+        accepted.recv().await.unwrap(),
+        accepted.recv().await.unwrap(),
+    ];
+    // the pool is full, no more connections are opened
+    assert!(timeout(Duration::from_millis(100), accepted.recv())
+        .await
+        .is_err());
+
+    let connection = pool.take().await.unwrap();
+    assert!(!connection.is_closed());
+    // the taken connection is replaced
+    servers.push(accepted.recv().await.unwrap());
+    server.abort();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_pool_discards_closed_connections() {
+    use crate::connection::ConnectionState;
+    use crate::transport::tcp::{accept_mock_handshake, receive_mock_message};
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let options = mock_options();
+    let accept = || async {
+        let mut framed = accept_mock_handshake(&listener).await;
+        receive_mock_message(&mut framed).await;
+        framed
+    };
+    let connect = || async { Connection::connect(&addr, &options.with_new_state()).await };
+
+    let (server, closed) = tokio::join!(accept(), connect());
+    let closed = closed.unwrap();
+    let mut state = closed.state();
+    drop(server);
+    state
+        .wait_for(|state| matches!(state, ConnectionState::Closed { .. }))
+        .await
+        .unwrap();
+    let (_server, open) = tokio::join!(accept(), connect());
+
+    let (tx, rx) = mpsc::channel(2);
+    tx.send(closed).await.unwrap();
+    tx.send(open.unwrap()).await.unwrap();
+    drop(tx);
+    let pool = ConnectionPool {
+        connections: Mutex::new(rx),
+        options,
+    };
+    assert!(!pool.take().await.unwrap().is_closed());
+    // once the pool stops filling, taking fails instead of waiting forever
+    assert!(matches!(
+        pool.take().await,
+        Err(ConnectionError::Network(NetworkError::EOF))
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_pool_without_servers() {
+    let pool = ConnectionPool::new(ServerList::new(Vec::new(), Vec::new()), 1, mock_options());
+    assert!(matches!(
+        pool.take().await,
+        Err(ConnectionError::Network(NetworkError::EOF))
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_pool_fill_ends_when_dropped() {
+    use crate::transport::tcp::{accept_mock_handshake, receive_mock_message};
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let urls = vec![listener.local_addr().unwrap().to_string()];
+    let (tx, rx) = mpsc::channel(1);
+    let filling = tokio::spawn(fill(urls, tx, mock_options()));
+
+    let mut server = accept_mock_handshake(&listener).await;
+    receive_mock_message(&mut server).await;
+    // the pool is full, the task waits for room until the pool is dropped
+    drop(rx);
+    timeout(Duration::from_secs(5), filling)
+        .await
+        .unwrap()
+        .unwrap();
+}
@@ -0,0 +1,156 @@
+use crate::connection::Connection;
+use crate::net::NetworkError;
+use crate::proto::steammessages_clientserver::CMsgClientGamesPlayed;
+use crate::proto::steammessages_clientserver_friends::CMsgClientChangeStatus;
+use crate::ui_mode::UiMode;
+use tracing::{debug, warn};
+
+/// The online status shown to friends, see [`Connection::set_persona_state`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PersonaState {
+    Offline = 0,
+    Online = 1,
+    Busy = 2,
+    Away = 3,
+    Snooze = 4,
+    LookingToTrade = 5,
+    LookingToPlay = 6,
+    /// Online, but shown as offline to friends
+    Invisible = 7,
+}
+
+impl PersonaState {
+    pub(crate) fn from_u32(state: u32) -> Option<Self> {
+        Some(match state {
+            0 => PersonaState::Offline,
+            1 => PersonaState::Online,
+            2 => PersonaState::Busy,
+            3 => PersonaState::Away,
+            4 => PersonaState::Snooze,
+            5 => PersonaState::LookingToTrade,
+            6 => PersonaState::LookingToPlay,
+            7 => PersonaState::Invisible,
+            _ => return None,
+        })
+    }
+}
+
+/// Which parts of the presence are restored after [`Connection::reconnect`]
+///
+/// Steam forgets the ui mode, persona state and games played of the old session, so by default the last
+/// values set on the connection are sent again after logging on to the new session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresenceReplay {
+    pub ui_mode: bool,
+    pub persona_state: bool,
+    pub games_played: bool,
+}
+
+impl Default for PresenceReplay {
+    fn default() -> Self {
+        PresenceReplay {
+            ui_mode: true,
+            persona_state: true,
+            games_played: true,
+        }
+    }
+}
+
+impl PresenceReplay {
+    /// Don't restore anything after reconnecting
+    pub fn none() -> Self {
+        PresenceReplay {
+            ui_mode: false,
+            persona_state: false,
+            games_played: false,
+        }
+    }
+}
+
+/// The presence last set on the connection, replayed after reconnecting
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Presence {
+    pub ui_mode: Option<UiMode>,
+    pub persona_state: Option<PersonaState>,
+    pub games_played: Option<CMsgClientGamesPlayed>,
+}
+
+impl Presence {
+    /// The parts of the presence that should be replayed
+    fn filtered(&self, replay: PresenceReplay) -> Presence {
+        Presence {
+            ui_mode: self.ui_mode.filter(|_| replay.ui_mode),
+            persona_state: self.persona_state.filter(|_| replay.persona_state),
+            games_played: self.games_played.clone().filter(|_| replay.games_played),
+        }
+    }
+}
+
+fn change_status_request(state: PersonaState) -> CMsgClientChangeStatus {
+    CMsgClientChangeStatus {
+        persona_state: Some(state as u32),
+        ..CMsgClientChangeStatus::default()
+    }
+}
+
+impl Connection {
+    /// Set the online status shown to friends
+    pub async fn set_persona_state(&self, state: PersonaState) -> Result<(), NetworkError> {
+        self.send(self.session.header(), change_status_request(state))
+            .await?;
+        self.presence.lock().unwrap().persona_state = Some(state);
+        Ok(())
+    }
+
+    /// Send the presence of the old session again, see [`PresenceReplay`]
+    pub(crate) async fn replay_presence(&self) {
+        let presence = self
+            .presence
+            .lock()
+            .unwrap()
+            .filtered(self.options.presence_replay);
+        debug!(?presence, "replaying presence");
+        if let Some(mode) = presence.ui_mode {
+            if let Err(e) = self.set_ui_mode(mode).await {
+                warn!(error = ?e, "failed to restore ui mode");
+            }
+        }
+        if let Some(state) = presence.persona_state {
+            if let Err(e) = self.set_persona_state(state).await {
+                warn!(error = ?e, "failed to restore persona state");
+            }
+        }
+        if let Some(games_played) = presence.games_played {
+            if let Err(e) = self.send_games_played(games_played).await {
+                warn!(error = ?e, "failed to restore games played");
+            }
+        }
+    }
+}
+
+#[test]
+fn test_presence_replay_filter() {
+    let presence = Presence {
+        ui_mode: Some(UiMode::Mobile),
+        persona_state: Some(PersonaState::Away),
+        games_played: Some(CMsgClientGamesPlayed::default()),
+    };
+    let all = presence.filtered(PresenceReplay::default());
+    assert_eq!(Some(UiMode::Mobile), all.ui_mode);
+    assert_eq!(Some(PersonaState::Away), all.persona_state);
+    assert!(all.games_played.is_some());
+
+    let games_only = presence.filtered(PresenceReplay {
+        games_played: true,
+        ..PresenceReplay::none()
+    });
+    assert_eq!(None, games_only.ui_mode);
+    assert_eq!(None, games_only.persona_state);
+    assert!(games_only.games_played.is_some());
+
+    assert_eq!(
+        Some(7),
+        change_status_request(PersonaState::Invisible).persona_state
+    );
+}
@@ -0,0 +1,226 @@
+use crate::connection::Connection;
+use crate::eresult::EResult;
+use crate::keyvalues::{KeyValues, KeyValuesError, Value};
+use crate::message::{MalformedBody, NetMessage};
+use crate::net::NetworkError;
+use crate::proto::steammessages_clientserver_2::{
+    CMsgClientPurchaseResponse, CMsgClientRegisterKey,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum PurchaseError {
+    #[error(transparent)]
+    Network(#[from] NetworkError),
+    #[error("purchase failed with {result:?}, purchase result details {details}")]
+    Failed { result: EResult, details: i32 },
+}
+
+impl From<KeyValuesError> for PurchaseError {
+    fn from(value: KeyValuesError) -> Self {
+        PurchaseError::Network(MalformedBody::new(CMsgClientPurchaseResponse::KIND, value).into())
+    }
+}
+
+/// A package granted by a purchase or key activation
+#[derive(Debug, Clone, PartialEq)]
+pub struct PurchasedPackage {
+    pub package_id: u32,
+    pub description: String,
+    pub transaction_id: Option<u64>,
+    pub item_flags: Option<i32>,
+}
+
+impl PurchasedPackage {
+    fn from_kv(item: &KeyValues) -> Option<Self> {
+        Some(PurchasedPackage {
+            package_id: item.get("PackageID")?.as_u32()?,
+            description: item
+                .get("ItemDescription")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .into(),
+            transaction_id: get_u64(item, "TransactionID"),
+            item_flags: get_i32(item, "ItemFlags"),
+        })
+    }
+}
+
+/// The receipt for a successful purchase, key activation or other action that grants packages
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PurchaseReceipt {
+    pub packages: Vec<PurchasedPackage>,
+    pub transaction_id: Option<u64>,
+    /// Unix timestamp of the transaction
+    pub transaction_time: Option<u32>,
+    pub payment_method: Option<i32>,
+    pub purchase_status: Option<i32>,
+    pub result_detail: Option<i32>,
+    pub base_price: Option<i64>,
+    pub total_discount: Option<i64>,
+    pub tax: Option<i64>,
+    pub shipping_cost: Option<i64>,
+    pub currency_code: Option<String>,
+}
+
+fn get_i32(object: &KeyValues, key: &str) -> Option<i32> {
+    object.get(key)?.as_i64()?.try_into().ok()
+}
+
+fn get_u64(object: &KeyValues, key: &str) -> Option<u64> {
+    match object.get(key)? {
+        Value::UInt64(value) => Some(*value),
+        value => value.as_i64()?.try_into().ok(),
+    }
+}
+
+impl PurchaseReceipt {
+    /// Parse the binary KeyValues receipt from the `purchase_receipt_info` of a purchase response
+    pub fn parse(receipt: &[u8]) -> Result<Self, KeyValuesError> {
+        let receipt = KeyValues::parse_binary(receipt)?;
+        let Some(object) = receipt.get("MessageObject").and_then(Value::as_object) else {
+            return Ok(PurchaseReceipt::default());
+        };
+        let packages = object
+            .get("lineitems")
+            .and_then(Value::as_object)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(|(_, item)| item.as_object())
+                    .filter_map(PurchasedPackage::from_kv)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(PurchaseReceipt {
+            packages,
+            transaction_id: get_u64(object, "TransactionID"),
+            transaction_time: object.get("TransactionTime").and_then(Value::as_u32),
+            payment_method: get_i32(object, "PaymentMethod"),
+            purchase_status: get_i32(object, "PurchaseStatus"),
+            result_detail: get_i32(object, "ResultDetail"),
+            base_price: object.get("BasePrice").and_then(Value::as_i64),
+            total_discount: object.get("TotalDiscount").and_then(Value::as_i64),
+            tax: object.get("Tax").and_then(Value::as_i64),
+            shipping_cost: object.get("ShippingCost").and_then(Value::as_i64),
+            currency_code: object
+                .get("CurrencyCode")
+                .and_then(Value::as_str)
+                .map(String::from),
+        })
+    }
+}
+
+/// Decode the receipt of a purchase response, failing if the purchase didn't succeed
+impl TryFrom<&CMsgClientPurchaseResponse> for PurchaseReceipt {
+    type Error = PurchaseError;
+
+    fn try_from(response: &CMsgClientPurchaseResponse) -> Result<Self, PurchaseError> {
+        if let Err(result) = EResult::from_result(response.eresult()) {
+            return Err(PurchaseError::Failed {
+                result,
+                details: response.purchase_result_details(),
+            });
+        }
+        Ok(PurchaseReceipt::parse(response.purchase_receipt_info())?)
+    }
+}
+
+impl Connection {
+    /// Activate a product key (cd key or gift key) on the account
+    pub async fn register_product_key(&self, key: &str) -> Result<PurchaseReceipt, PurchaseError> {
+        let req = CMsgClientRegisterKey {
+            key: Some(key.into()),
+            ..CMsgClientRegisterKey::default()
+        };
+        let response: CMsgClientPurchaseResponse = self.job(req).await?;
+        PurchaseReceipt::try_from(&response)
+    }
+}
+
+#[test]
+fn test_parse_receipt() {
+    let data = b"\
+        \x00MessageObject\x00\
+        \x02PaymentMethod\x00\x04\x00\x00\x00\
+        \x02PurchaseStatus\x00\x01\x00\x00\x00\
+        \x02ResultDetail\x00\x00\x00\x00\x00\
+        \x02BasePrice\x00\x00\x00\x00\x00\
+        \x02TotalDiscount\x00\x00\x00\x00\x00\
+        \x02Tax\x00\x00\x00\x00\x00\
+        \x02ShippingCost\x00\x00\x00\x00\x00\
+        \x01CurrencyCode\x000\x00\
+        \x07TransactionID\x00\xe1\xdeNn[\x85\x0b>\
+        \x02TransactionTime\x00\xff\xc6&e\
+        \x00lineitems\x00\
+        \x000\x00\x02PackageID\x00\xbet\x04\x00\
+        \x01ItemDescription\x00The Witcher 3: Wild Hunt\x00\
+        \x07TransactionID\x00\xe1\xdeNn[\x85\x0b>\
+        \x02ItemFlags\x00\x00\x00\x00\x00\x08\
+        \x001\x00\x02PackageID\x00\xfb\xe7\x01\x00\
+        \x01ItemDescription\x00Hearts of Stone\x00\
+        \x07TransactionID\x00\xe1\xdeNn[\x85\x0b>\
+        \x02ItemFlags\x00\x00\x00\x00\x00\x08\
+        \x002\x00\x02PackageID\x00\x01\x00\x00\x00\
+        \x01ItemDescription\x00High transaction id\x00\
+        \x07TransactionID\x00\x01\x00\x00\x00\x00\x00\x00\x80\
+        \x08\
+        \x003\x00\x02PackageID\x00\x02\x00\x00\x00\
+        \x01ItemDescription\x00Negative transaction id\x00\
+        \x0aTransactionID\x00\xff\xff\xff\xff\xff\xff\xff\xff\
+        \x08\
+        \x08\x08\x08";
+    let receipt = PurchaseReceipt::parse(data).unwrap();
+    assert_eq!(Some(4470813682834530017), receipt.transaction_id);
+    assert_eq!(Some(1697040127), receipt.transaction_time);
+    assert_eq!(Some(4), receipt.payment_method);
+    assert_eq!(Some(1), receipt.purchase_status);
+    assert_eq!(Some(0), receipt.result_detail);
+    assert_eq!(Some(0), receipt.tax);
+    assert_eq!(Some("0"), receipt.currency_code.as_deref());
+    assert_eq!(
+        vec![
+            PurchasedPackage {
+                package_id: 292030,
+                description: "The Witcher 3: Wild Hunt".into(),
+                transaction_id: Some(4470813682834530017),
+                item_flags: Some(0),
+            },
+            PurchasedPackage {
+                package_id: 124923,
+                description: "Hearts of Stone".into(),
+                transaction_id: Some(4470813682834530017),
+                item_flags: Some(0),
+            },
+            // unsigned ids above the signed range are kept as they are
+            PurchasedPackage {
+                package_id: 1,
+                description: "High transaction id".into(),
+                transaction_id: Some(0x8000_0000_0000_0001),
+                item_flags: None,
+            },
+            // negative signed ids aren't reinterpreted
+            PurchasedPackage {
+                package_id: 2,
+                description: "Negative transaction id".into(),
+                transaction_id: None,
+                item_flags: None,
+            },
+        ],
+        receipt.packages
+    );
+
+    let failed = CMsgClientPurchaseResponse {
+        eresult: Some(EResult::Fail as i32),
+        purchase_result_details: Some(9),
+        ..CMsgClientPurchaseResponse::default()
+    };
+    assert!(matches!(
+        PurchaseReceipt::try_from(&failed),
+        Err(PurchaseError::Failed {
+            result: EResult::Fail,
+            details: 9
+        })
+    ));
+}
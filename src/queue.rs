@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What happens to received messages when the queue for [`Connection::next`](crate::Connection::next) is full,
+/// see [`ConnectionOptions::with_receive_queue`](crate::ConnectionOptions::with_receive_queue)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Stop reading from the server until there is room in the queue
+    ///
+    /// No messages are lost, but responses and heartbeats are delayed until the application catches up.
+    #[default]
+    Block,
+    /// Drop the oldest message in the queue to make room for the new one
+    DropOldest,
+    /// Drop the new message, keeping the messages already in the queue
+    DropNewest,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicU64,
+    readable: Notify,
+    writable: Notify,
+}
+
+/// Create a bounded queue that handles a full queue according to the policy
+pub(crate) fn queue<T>(
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        capacity: capacity.max(1),
+        policy,
+        dropped: AtomicU64::new(0),
+        readable: Notify::new(),
+        writable: Notify::new(),
+    });
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+pub(crate) struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueSender<T> {
+    /// Add a value to the queue, waiting for room if the policy is [`OverflowPolicy::Block`]
+    ///
+    /// Returns the value that was dropped to stay within the capacity, if any,
+    /// or an error with the value if the receiver is gone.
+    pub async fn send(&self, value: T) -> Result<Option<T>, T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if !state.receiver_alive {
+                    return Err(value);
+                }
+                if state.queue.len() < self.shared.capacity {
+                    state.queue.push_back(value);
+                    self.shared.readable.notify_one();
+                    return Ok(None);
+                }
+                match self.shared.policy {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest => {
+                        let dropped = state.queue.pop_front();
+                        state.queue.push_back(value);
+                        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(dropped);
+                    }
+                    OverflowPolicy::DropNewest => {
+                        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(Some(value));
+                    }
+                }
+            }
+            self.shared.writable.notified().await;
+        }
+    }
+
+    /// The number of values dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        QueueSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.readable.notify_one();
+        }
+    }
+}
+
+pub(crate) struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The queue is empty, or closed if all senders are gone
+#[cfg(test)]
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Empty;
+
+impl<T> QueueReceiver<T> {
+    /// Wait for the next value, `None` once the queue is empty and all senders are gone
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if let Some(value) = state.queue.pop_front() {
+                    self.shared.writable.notify_one();
+                    return Some(value);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            self.shared.readable.notified().await;
+        }
+    }
+
+    #[cfg(test)]
+    pub fn try_recv(&mut self) -> Result<T, Empty> {
+        let value = self
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .queue
+            .pop_front()
+            .ok_or(Empty)?;
+        self.shared.writable.notify_one();
+        Ok(value)
+    }
+
+    /// The number of values dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().receiver_alive = false;
+        // wake up all senders waiting for room
+        self.shared.writable.notify_waiters();
+        self.shared.writable.notify_one();
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_queue_overflow_policies() {
+    let (tx, mut rx) = queue(2, OverflowPolicy::DropOldest);
+    for value in 0..4 {
+        tx.send(value).await.unwrap();
+    }
+    assert_eq!(2, rx.dropped());
+    assert_eq!(Some(2), rx.recv().await);
+    assert_eq!(Some(3), rx.recv().await);
+    assert_eq!(Err(Empty), rx.try_recv());
+
+    let (tx, mut rx) = queue(2, OverflowPolicy::DropNewest);
+    for value in 0..4 {
+        tx.send(value).await.unwrap();
+    }
+    assert_eq!(Ok(Some(4)), tx.send(4).await);
+    assert_eq!(3, rx.dropped());
+    assert_eq!(Some(0), rx.recv().await);
+    assert_eq!(Some(1), rx.recv().await);
+    drop(tx);
+    assert_eq!(None, rx.recv().await);
+
+    let (tx, mut rx) = queue(1, OverflowPolicy::Block);
+    tx.send(0).await.unwrap();
+    let blocked = tokio::spawn(async move { tx.send(1).await.map(|_| ()) });
+    tokio::task::yield_now().await;
+    assert!(!blocked.is_finished());
+    assert_eq!(Some(0), rx.recv().await);
+    blocked.await.unwrap().unwrap();
+    assert_eq!(Some(1), rx.recv().await);
+    assert_eq!(0, rx.dropped());
+    assert_eq!(None, rx.recv().await);
+}
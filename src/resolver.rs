@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+/// Resolves host names to ip addresses, for replacing the system resolver
+///
+/// The resolver is used for connecting to the servers and, when set in the [`DiscoverOptions`](crate::DiscoverOptions),
+/// for discovering the servers. Besides working around a dns server that gives wrong answers for the steam
+/// host names, this can be used to point the host names at a local server for testing.
+pub trait Resolver: Send + Sync + 'static {
+    /// Resolve the host name to the addresses to try connecting to, in order
+    fn resolve(&self, host: &str) -> impl Future<Output = std::io::Result<Vec<IpAddr>>> + Send;
+}
+
+/// Object safe version of [`Resolver`] so the resolver can be stored in the options
+trait DynResolver: Send + Sync {
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+    ) -> Pin<Box<dyn Future<Output = std::io::Result<Vec<IpAddr>>> + Send + 'a>>;
+}
+
+impl<R: Resolver> DynResolver for R {
+    fn resolve<'a>(
+        &'a self,
+        host: &'a str,
+    ) -> Pin<Box<dyn Future<Output = std::io::Result<Vec<IpAddr>>> + Send + 'a>> {
+        Box::pin(Resolver::resolve(self, host))
+    }
+}
+
+/// Resolve host names using the resolver of the operating system
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str) -> std::io::Result<Vec<IpAddr>> {
+        Ok(lookup_host((host, 0))
+            .await?
+            .map(|addr| addr.ip())
+            .collect())
+    }
+}
+
+/// Resolve host names to fixed addresses, other host names are resolved by the system resolver
+///
+/// ```
+/// # use steam_vent::StaticResolver;
+/// # use std::net::Ipv4Addr;
+/// let resolver = StaticResolver::default()
+///     .with_host("api.steampowered.com", [Ipv4Addr::new(23, 52, 74, 146).into()]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl StaticResolver {
+    /// Resolve the host to the given addresses
+    pub fn with_host(
+        mut self,
+        host: impl Into<String>,
+        addrs: impl IntoIterator<Item = IpAddr>,
+    ) -> Self {
+        self.hosts.insert(
+            host.into().to_ascii_lowercase(),
+            addrs.into_iter().collect(),
+        );
+        self
+    }
+}
+
+impl Resolver for StaticResolver {
+    async fn resolve(&self, host: &str) -> std::io::Result<Vec<IpAddr>> {
+        match self.hosts.get(&host.to_ascii_lowercase()) {
+            Some(addrs) => Ok(addrs.clone()),
+            None => Resolver::resolve(&SystemResolver, host).await,
+        }
+    }
+}
+
+/// A resolver shared between the options and the connections created from them
+#[derive(Clone)]
+pub(crate) struct SharedResolver(Arc<dyn DynResolver>);
+
+impl SharedResolver {
+    pub fn new<R: Resolver>(resolver: R) -> Self {
+        SharedResolver(Arc::new(resolver))
+    }
+
+    pub async fn resolve(&self, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+        // ip addresses don't need to be resolved
+        if let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        let addrs = self.0.resolve(host).await?;
+        if addrs.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("no addresses found for {host}"),
+            ));
+        }
+        Ok(addrs
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+
+    /// Open a tcp connection to the first address of the host that accepts it
+    ///
+    /// Each address gets `connect_timeout` to accept the connection before moving on to the next one,
+    /// if the last address times out the error has the [`ErrorKind::TimedOut`] kind.
+    pub async fn connect(
+        &self,
+        host: &str,
+        port: u16,
+        connect_timeout: Duration,
+    ) -> std::io::Result<TcpStream> {
+        let mut last_error = None;
+        for addr in self.resolve(host, port).await? {
+            match timeout(connect_timeout, TcpStream::connect(addr)).await {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => last_error = Some(e),
+                Err(_) => last_error = Some(ErrorKind::TimedOut.into()),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| ErrorKind::NotFound.into()))
+    }
+}
+
+impl Default for SharedResolver {
+    fn default() -> Self {
+        SharedResolver::new(SystemResolver)
+    }
+}
+
+impl Debug for SharedResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedResolver").finish_non_exhaustive()
+    }
+}
+
+impl reqwest::dns::Resolve for SharedResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let addrs = resolver.resolve(name.as_str(), 0).await?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_static_resolver() {
+    use std::net::Ipv4Addr;
+
+    let local = IpAddr::from(Ipv4Addr::LOCALHOST);
+    let resolver = SharedResolver::new(StaticResolver::default().with_host("CM.example", [local]));
+    assert_eq!(
+        vec![SocketAddr::new(local, 443)],
+        resolver.resolve("cm.example", 443).await.unwrap()
+    );
+    // ip addresses are used as-is
+    assert_eq!(
+        vec![SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 27017)],
+        resolver.resolve("10.0.0.1", 27017).await.unwrap()
+    );
+    assert!(resolver.resolve("localhost", 1).await.is_ok());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (stream, accepted) = tokio::join!(
+        resolver.connect("cm.example", port, Duration::from_secs(5)),
+        listener.accept()
+    );
+    assert!(stream.is_ok());
+    assert!(accepted.is_ok());
+}
@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How fast old results are forgotten, after this long the weight of a result is halved
+const HALF_LIFE: Duration = Duration::from_secs(30 * 60);
+
+/// The weight of a new result compared to the earlier results
+const SAMPLE_WEIGHT: f64 = 0.3;
+
+/// The latency assumed for servers without results
+const DEFAULT_LATENCY: Duration = Duration::from_millis(500);
+
+/// The connection quality of a single server, see [`ServerScores`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerScore {
+    /// The url of the server
+    pub url: String,
+    /// The share of connection attempts that succeeded, between 0 and 1
+    pub success_rate: f64,
+    /// The average time it took to connect
+    pub latency: Duration,
+    /// The unix timestamp of the last connection attempt
+    pub updated_at: u64,
+}
+
+impl ServerScore {
+    /// Move the score back towards the score of an unknown server, the further the older the results are
+    fn decayed(&self, now: u64) -> ServerScore {
+        let age = now.saturating_sub(self.updated_at) as f64;
+        let weight = 0.5f64.powf(age / HALF_LIFE.as_secs_f64());
+        ServerScore {
+            url: self.url.clone(),
+            success_rate: 1.0 - (1.0 - self.success_rate) * weight,
+            latency: DEFAULT_LATENCY.mul_f64(1.0 - weight) + self.latency.mul_f64(weight),
+            updated_at: self.updated_at,
+        }
+    }
+
+    fn rank(&self) -> f64 {
+        self.success_rate / (1.0 + self.latency.as_secs_f64())
+    }
+}
+
+/// Tracks how well connecting to each server worked, to prefer the servers that worked well before
+///
+/// The scores are shared between clones, so all connections created with the same
+/// [`ConnectionOptions`](crate::ConnectionOptions) update the same scores. Old results decay, so a server
+/// that was down for a while is tried again later. The scores can be persisted with [`ServerScores::snapshot`]
+/// and restored with [`ServerScores::from_snapshot`].
+#[derive(Debug, Clone, Default)]
+pub struct ServerScores {
+    scores: Arc<Mutex<HashMap<String, ServerScore>>>,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+impl ServerScores {
+    /// Restore the scores from an earlier [`ServerScores::snapshot`]
+    pub fn from_snapshot(scores: Vec<ServerScore>) -> Self {
+        ServerScores {
+            scores: Arc::new(Mutex::new(
+                scores
+                    .into_iter()
+                    .map(|score| (score.url.clone(), score))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// The current scores of all servers that were connected to, with the decay applied
+    pub fn snapshot(&self) -> Vec<ServerScore> {
+        let now = unix_time();
+        let mut scores: Vec<_> = self
+            .scores
+            .lock()
+            .unwrap()
+            .values()
+            .map(|score| score.decayed(now))
+            .collect();
+        scores.sort_by(|a, b| a.url.cmp(&b.url));
+        scores
+    }
+
+    /// The current score of a server, `None` if it wasn't connected to
+    pub fn get(&self, url: &str) -> Option<ServerScore> {
+        let scores = self.scores.lock().unwrap();
+        scores.get(url).map(|score| score.decayed(unix_time()))
+    }
+
+    pub(crate) fn record_success(&self, url: &str, latency: Duration) {
+        self.record(url, true, Some(latency), unix_time());
+    }
+
+    pub(crate) fn record_failure(&self, url: &str) {
+        self.record(url, false, None, unix_time());
+    }
+
+    fn record(&self, url: &str, success: bool, latency: Option<Duration>, now: u64) {
+        let mut scores = self.scores.lock().unwrap();
+        let score = match scores.get(url) {
+            Some(score) => {
+                let score = score.decayed(now);
+                let sample = if success { 1.0 } else { 0.0 };
+                ServerScore {
+                    success_rate: score.success_rate * (1.0 - SAMPLE_WEIGHT)
+                        + sample * SAMPLE_WEIGHT,
+                    latency: latency.map_or(score.latency, |latency| {
+                        score.latency.mul_f64(1.0 - SAMPLE_WEIGHT) + latency.mul_f64(SAMPLE_WEIGHT)
+                    }),
+                    updated_at: now,
+                    ..score
+                }
+            }
+            None => ServerScore {
+                url: url.into(),
+                success_rate: if success { 1.0 } else { 1.0 - SAMPLE_WEIGHT },
+                latency: latency.unwrap_or(DEFAULT_LATENCY),
+                updated_at: now,
+            },
+        };
+        scores.insert(url.into(), score);
+    }
+
+    /// Order the urls from the best to the worst score, servers with equal scores keep their order
+    pub(crate) fn rank(&self, urls: Vec<String>) -> Vec<String> {
+        self.rank_at(urls, unix_time())
+    }
+
+    fn rank_at(&self, mut urls: Vec<String>, now: u64) -> Vec<String> {
+        let scores = self.scores.lock().unwrap();
+        let rank = |url: &String| {
+            scores.get(url).map_or_else(
+                || 1.0 / (1.0 + DEFAULT_LATENCY.as_secs_f64()),
+                |score| score.decayed(now).rank(),
+            )
+        };
+        urls.sort_by(|a, b| rank(b).total_cmp(&rank(a)));
+        urls
+    }
+}
+
+#[test]
+fn test_server_scores() {
+    let scores = ServerScores::default();
+    let urls = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+    // without results the order is kept
+    assert_eq!(urls, scores.rank_at(urls.clone(), 1000));
+
+    scores.record("a", false, None, 1000);
+    scores.record("c", true, Some(Duration::from_millis(50)), 1000);
+    assert_eq!(vec!["c", "b", "a"], scores.rank_at(urls.clone(), 1000));
+
+    let failing = scores.scores.lock().unwrap()["a"].clone();
+    assert!(failing.success_rate < 1.0);
+    // the failure is mostly forgotten after a few hours
+    let recovered = failing.decayed(1000 + 4 * 3600);
+    assert!(recovered.success_rate > 0.99);
+    assert!(recovered.latency > Duration::from_millis(490));
+
+    for _ in 0..3 {
+        scores.record("c", false, None, 1000);
+    }
+    assert_eq!(vec!["b", "a", "c"], scores.rank_at(urls, 1000));
+
+    let restored =
+        ServerScores::from_snapshot(scores.scores.lock().unwrap().values().cloned().collect());
+    assert_eq!(
+        scores.scores.lock().unwrap()["c"],
+        restored.scores.lock().unwrap()["c"]
+    );
+}
@@ -1,6 +1,10 @@
+use crate::proto::steammessages_clientserver::CMsgClientCMList;
+use crate::resolver::{Resolver, SharedResolver};
+use crate::transport::Transport;
 use reqwest::{Client, Error};
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
 use thiserror::Error;
 use tracing::debug;
 
@@ -24,6 +28,7 @@ pub struct DiscoverOptions {
     // todo: some smart cell based routing based on
     // https://raw.githubusercontent.com/SteamDatabase/SteamTracking/6d23ebb0070998ae851278cfae5f38832f4ac28d/ClientExtracted/steam/cached/CellMap.vdf
     cell: u8,
+    resolver: Option<SharedResolver>,
 }
 
 impl DiscoverOptions {
@@ -37,15 +42,36 @@ impl DiscoverOptions {
     pub fn with_cell(self, cell: u8) -> Self {
         DiscoverOptions { cell, ..self }
     }
+
+    /// Set the resolver used for looking up the address of the web api, defaults to the system resolver
+    ///
+    /// This has no effect when a web client is set with [`DiscoverOptions::with_web_client`],
+    /// configure the resolver of that client instead.
+    pub fn with_resolver<R: Resolver>(self, resolver: R) -> Self {
+        DiscoverOptions {
+            resolver: Some(SharedResolver::new(resolver)),
+            ..self
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ServerList {
     servers: Vec<SocketAddr>,
     ws_servers: Vec<String>,
 }
 
 impl ServerList {
+    /// Create a server list from known servers, instead of discovering them
+    ///
+    /// Servers are given as `ip:port` for tcp and `host:port` for websocket connections
+    pub fn new(servers: Vec<SocketAddr>, ws_servers: Vec<String>) -> Self {
+        ServerList {
+            servers,
+            ws_servers,
+        }
+    }
+
     pub async fn discover() -> Result<ServerList, ServerDiscoveryError> {
         Self::discover_with(DiscoverOptions::default()).await
     }
@@ -53,11 +79,15 @@ impl ServerList {
     pub async fn discover_with(
         options: DiscoverOptions,
     ) -> Result<ServerList, ServerDiscoveryError> {
-        let client = options.web_client.unwrap_or_default();
+        let client = match (options.web_client, options.resolver) {
+            (Some(client), _) => client,
+            (None, Some(resolver)) => Client::builder().dns_resolver(Arc::new(resolver)).build()?,
+            (None, None) => Client::default(),
+        };
         let cell = options.cell;
 
         let response: ServerListResponse = client
-            .get(&format!(
+            .get(format!(
                 "https://api.steampowered.com/ISteamDirectory/GetCMList/v1/?cellid={cell}"
             ))
             .send()
@@ -83,6 +113,42 @@ impl ServerList {
         debug!(addr = ?addr, "picked websocket server from list");
         format!("wss://{addr}/cmsocket/")
     }
+
+    /// The urls of all websocket servers in the list
+    pub(crate) fn ws_urls(&self) -> Vec<String> {
+        self.ws_servers
+            .iter()
+            .map(|addr| format!("wss://{addr}/cmsocket/"))
+            .collect()
+    }
+
+    /// The addresses of the servers in the list that can be connected to with the transport
+    pub(crate) fn urls(&self, transport: Transport) -> Vec<String> {
+        match transport {
+            Transport::WebSocket => self.ws_urls(),
+            Transport::Tcp => self.servers.iter().map(SocketAddr::to_string).collect(),
+        }
+    }
+}
+
+/// The servers steam sends after logging on, these are picked for the cell of the account
+impl From<&CMsgClientCMList> for ServerList {
+    fn from(list: &CMsgClientCMList) -> Self {
+        ServerList {
+            servers: list
+                .cm_addresses
+                .iter()
+                .zip(list.cm_ports.iter())
+                .filter_map(|(ip, port)| {
+                    Some(SocketAddr::new(
+                        Ipv4Addr::from(*ip).into(),
+                        (*port).try_into().ok()?,
+                    ))
+                })
+                .collect(),
+            ws_servers: list.cm_websocket_addresses.clone(),
+        }
+    }
 }
 
 impl From<ServerListResponse> for ServerList {
@@ -106,3 +172,27 @@ struct ServerListResponseInner {
     #[serde(rename = "serverlist_websockets")]
     server_list_websockets: Vec<String>,
 }
+
+#[test]
+fn test_server_list_from_cm_list() {
+    let list = CMsgClientCMList {
+        cm_addresses: vec![u32::from(Ipv4Addr::new(162, 254, 197, 40))],
+        cm_ports: vec![27017],
+        cm_websocket_addresses: vec!["cmp1-ams1.steamserver.net:443".into()],
+        ..CMsgClientCMList::default()
+    };
+    let list = ServerList::from(&list);
+    assert_eq!(
+        vec![SocketAddr::from((Ipv4Addr::new(162, 254, 197, 40), 27017))],
+        list.servers
+    );
+    assert_eq!(
+        vec!["wss://cmp1-ams1.steamserver.net:443/cmsocket/"],
+        list.ws_urls()
+    );
+    assert_eq!(vec!["162.254.197.40:27017"], list.urls(Transport::Tcp));
+    assert_eq!(
+        "wss://cmp1-ams1.steamserver.net:443/cmsocket/",
+        list.pick_ws()
+    );
+}
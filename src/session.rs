@@ -1,13 +1,16 @@
 use crate::auth::{ConfirmationError, ConfirmationMethod};
-use crate::connection::Connection;
+use crate::connection::{Connection, ConnectionOptions};
 use crate::eresult::EResult;
+use crate::keyvalues::{KeyValues, Value};
 use crate::net::{NetMessageHeader, NetworkError};
 use crate::proto::steammessages_base::CMsgIPAddress;
 use crate::proto::steammessages_clientserver_login::{
     CMsgClientHello, CMsgClientLogon, CMsgClientLogonResponse,
 };
 use crate::serverlist::ServerDiscoveryError;
+use crate::transport::tcp::ConnectAddrsError;
 use protobuf::MessageField;
+use std::net::IpAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use steam_vent_crypto::CryptError;
@@ -26,6 +29,9 @@ pub enum ConnectionError {
     LoginError(#[from] LoginError),
     #[error(transparent)]
     Discovery(#[from] ServerDiscoveryError),
+    /// Connecting failed for every server that was tried, with the url and error of each server
+    #[error("Failed to connect: {0}")]
+    Unreachable(ConnectAddrsError<String, ConnectionError>),
     #[error("Aborted")]
     Aborted,
     #[error("Unsupported confirmation action")]
@@ -76,6 +82,17 @@ impl From<EResult> for LoginError {
     }
 }
 
+/// The chat protocol the session uses, see [`ConnectionOptions::with_chat_mode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u32)]
+pub enum ChatMode {
+    /// The chat protocol from before the chat rewrite, friend messages are only delivered as legacy messages
+    Legacy = 1,
+    /// The current chat protocol, required for receiving friend messages sent from current clients (recommended)
+    #[default]
+    New = 2,
+}
+
 #[derive(Default, Debug)]
 pub struct JobIdCounter(AtomicU64);
 
@@ -91,7 +108,11 @@ pub struct Session {
     pub session_id: i32,
     pub job_id: JobIdCounter,
     pub steam_id: SteamID,
+    pub universe: Universe,
     pub heartbeat_interval: Duration,
+    pub client_instance_id: u64,
+    pub steam2_ticket: Option<Vec<u8>>,
+    pub cell_id: u32,
 }
 
 impl Default for Session {
@@ -100,7 +121,11 @@ impl Default for Session {
             session_id: 0,
             job_id: JobIdCounter::default(),
             steam_id: SteamID::from(0),
+            universe: Universe::Public,
             heartbeat_interval: Duration::from_secs(15),
+            client_instance_id: 0,
+            steam2_ticket: None,
+            cell_id: 0,
         }
     }
 }
@@ -117,27 +142,33 @@ impl Session {
     }
 }
 
-pub async fn anonymous(connection: &mut Connection) -> Result<Session> {
-    let mut ip = CMsgIPAddress::new();
-    ip.set_v4(0);
-
+pub async fn anonymous(
+    connection: &mut Connection,
+    options: &ConnectionOptions,
+) -> Result<Session> {
     let logon = CMsgClientLogon {
         protocol_version: Some(65580),
         client_os_type: Some(203),
         anon_user_target_account_name: Some(String::from("anonymous")),
         account_name: Some(String::from("anonymous")),
-        supports_rate_limit_response: Some(false),
-        obfuscated_private_ip: MessageField::some(ip),
+        supports_rate_limit_response: Some(options.supports_rate_limit_response),
+        is_steam_box: Some(options.steam_box),
+        is_steam_deck: Some(options.steam_deck),
+        steam2_ticket_request: Some(options.steam2_ticket_request),
+        obfuscated_private_ip: MessageField::some(obfuscated_private_ip(connection.local_ip)),
         client_language: Some(String::new()),
-        chat_mode: Some(2),
+        launcher_type: Some(options.launcher_type),
+        ui_mode: Some(options.ui_mode as u32),
+        chat_mode: Some(options.chat_mode as u32),
         client_package_version: Some(1771),
         ..CMsgClientLogon::default()
     };
 
+    let universe = connection.universe.unwrap_or(Universe::Public);
     send_logon(
         connection,
         logon,
-        SteamID::new(0, Instance::All, AccountType::AnonUser, Universe::Public),
+        SteamID::new(0, Instance::All, AccountType::AnonUser, universe),
     )
     .await
 }
@@ -145,30 +176,77 @@ pub async fn anonymous(connection: &mut Connection) -> Result<Session> {
 pub async fn login(
     connection: &mut Connection,
     account: &str,
-    steam_id: SteamID,
+    mut steam_id: SteamID,
     access_token: &str,
+    options: &ConnectionOptions,
 ) -> Result<Session> {
-    let mut ip = CMsgIPAddress::new();
-    ip.set_v4(0);
+    steam_id.set_instance(options.instance);
 
     let logon = CMsgClientLogon {
         protocol_version: Some(65580),
         client_os_type: Some(203),
         account_name: Some(String::from(account)),
-        supports_rate_limit_response: Some(false),
-        obfuscated_private_ip: MessageField::some(ip),
+        supports_rate_limit_response: Some(options.supports_rate_limit_response),
+        is_steam_box: Some(options.steam_box),
+        is_steam_deck: Some(options.steam_deck),
+        steam2_ticket_request: Some(options.steam2_ticket_request),
+        obfuscated_private_ip: MessageField::some(obfuscated_private_ip(connection.local_ip)),
         client_language: Some(String::new()),
-        machine_name: Some(String::new()),
+        machine_name: Some(options.machine_name.clone()),
         steamguard_dont_remember_computer: Some(false),
-        chat_mode: Some(2),
+        launcher_type: Some(options.launcher_type),
+        ui_mode: Some(options.ui_mode as u32),
+        chat_mode: Some(options.chat_mode as u32),
         access_token: Some(access_token.into()),
         client_package_version: Some(1771),
+        client_instance_id: options.client_instance_id,
+        machine_id: options.machine_id.clone(),
         ..CMsgClientLogon::default()
     };
 
     send_logon(connection, logon, steam_id).await
 }
 
+/// Xor'ed into the private ip sent during logon
+const PRIVATE_IP_OBFUSCATION_MASK: u32 = 0xBAAD_F00D;
+
+/// The local ip address of the connection in the obfuscated form steam expects during logon
+///
+/// Ipv4 addresses are xor'ed with the mask, ipv6 addresses have every 4 byte chunk xor'ed with the
+/// little endian mask. Without a known address an unobfuscated zero address is sent.
+pub(crate) fn obfuscated_private_ip(ip: Option<IpAddr>) -> CMsgIPAddress {
+    let mut address = CMsgIPAddress::new();
+    match ip.map(|ip| ip.to_canonical()) {
+        Some(IpAddr::V4(ip)) => address.set_v4(u32::from(ip) ^ PRIVATE_IP_OBFUSCATION_MASK),
+        Some(IpAddr::V6(ip)) => {
+            let mut octets = ip.octets();
+            for chunk in octets.chunks_exact_mut(4) {
+                let obfuscated =
+                    u32::from_le_bytes(chunk.try_into().unwrap()) ^ PRIVATE_IP_OBFUSCATION_MASK;
+                chunk.copy_from_slice(&obfuscated.to_le_bytes());
+            }
+            address.set_v6(octets.to_vec());
+        }
+        None => address.set_v4(0),
+    }
+    address
+}
+
+/// Check that a machine id blob has the layout steam clients send
+///
+/// That is a binary KeyValues `MessageObject` with the `BB3`, `FF2` and `3B3` hashes as strings.
+pub(crate) fn is_plausible_machine_id(machine_id: &[u8]) -> bool {
+    let Ok(blob) = KeyValues::parse_binary(machine_id) else {
+        return false;
+    };
+    let Some(object) = blob.get("MessageObject").and_then(Value::as_object) else {
+        return false;
+    };
+    ["BB3", "FF2", "3B3"]
+        .iter()
+        .all(|key| object.get(key).and_then(Value::as_str).is_some())
+}
+
 async fn send_logon(
     connection: &mut Connection,
     logon: CMsgClientLogon,
@@ -191,8 +269,14 @@ async fn send_logon(
     Ok(Session {
         session_id: header.session_id,
         steam_id: header.steam_id,
+        universe: connection
+            .universe
+            .unwrap_or_else(|| header.steam_id.universe()),
         job_id: JobIdCounter::default(),
         heartbeat_interval: Duration::from_secs(response.heartbeat_seconds() as u64),
+        client_instance_id: response.client_instance_id(),
+        steam2_ticket: response.steam2_ticket.clone(),
+        cell_id: response.cell_id(),
     })
 }
 
@@ -214,3 +298,57 @@ pub async fn hello(conn: &mut Connection) -> Result<(), NetworkError> {
     conn.send(header, req).await?;
     Ok(())
 }
+
+#[test]
+fn test_plausible_machine_id() {
+    let mut hashes = KeyValues::default();
+    for key in ["BB3", "FF2", "3B3"] {
+        hashes.push(
+            key,
+            Value::String("a94a8fe5ccb19ba61c4c0873d391e987982fbbd3".into()),
+        );
+    }
+    let mut blob = KeyValues::default();
+    blob.push("MessageObject", Value::Object(hashes));
+    assert!(is_plausible_machine_id(&blob.to_binary()));
+
+    let options = ConnectionOptions::default().with_machine_id_blob(blob.to_binary());
+    assert_eq!(Some(blob.to_binary()), options.machine_id);
+
+    // a missing hash
+    let mut partial = KeyValues::default();
+    partial.push("BB3", Value::String("hash".into()));
+    let mut blob = KeyValues::default();
+    blob.push("MessageObject", Value::Object(partial));
+    assert!(!is_plausible_machine_id(&blob.to_binary()));
+
+    // not a KeyValues object
+    assert!(!is_plausible_machine_id(b"BB3 FF2 3B3"));
+    assert!(!is_plausible_machine_id(&[]));
+}
+
+#[test]
+fn test_obfuscated_private_ip() {
+    use crate::proto::steammessages_base::cmsg_ipaddress::Ip;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    let v4 = obfuscated_private_ip(Some(Ipv4Addr::new(192, 168, 1, 10).into()));
+    assert_eq!(Some(Ip::V4(0xc0a8_010a ^ 0xbaad_f00d)), v4.ip);
+
+    let v6 = obfuscated_private_ip(Some("2001:db8::1".parse::<Ipv6Addr>().unwrap().into()));
+    let Some(Ip::V6(octets)) = v6.ip else {
+        panic!("expected an ipv6 address, got {:?}", v6.ip);
+    };
+    assert_eq!(16, octets.len());
+    assert_eq!(&[0x2d, 0xf1, 0xa0, 0x02], &octets[0..4]);
+    assert_eq!(&[0x0d, 0xf0, 0xad, 0xba], &octets[4..8]);
+    assert_eq!(&[0x0d, 0xf0, 0xad, 0xbb], &octets[12..16]);
+
+    // dual stack sockets report ipv4 addresses as mapped ipv6 addresses
+    let mapped = Ipv4Addr::new(10, 0, 0, 2).to_ipv6_mapped();
+    assert_eq!(
+        Some(Ip::V4(0x0a00_0002 ^ 0xbaad_f00d)),
+        obfuscated_private_ip(Some(mapped.into())).ip
+    );
+    assert_eq!(Some(Ip::V4(0)), obfuscated_private_ip(None).ip);
+}
@@ -0,0 +1,44 @@
+//! Logging off cleanly when the process is asked to stop, requires the `signal` feature
+
+use crate::connection::{Connection, LogOffOutcome};
+use crate::net::NetworkError;
+use std::io;
+use std::time::Duration;
+use tracing::info;
+
+/// Wait until the process is interrupted with ctrl-c, or asked to stop with SIGTERM on unix
+pub async fn shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use futures_util::future::{select, Either};
+        use std::pin::pin;
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        let result = match select(pin!(tokio::signal::ctrl_c()), pin!(terminate.recv())).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Ok(()),
+        };
+        result
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
+
+impl Connection {
+    /// Keep the session running until the process is asked to stop, then log off
+    ///
+    /// Logging off is given `grace_period` to complete before the connection is closed forcefully,
+    /// see [`Connection::log_off`]. Messages can still be handled in the meantime from the streams
+    /// returned by methods like [`Connection::on`].
+    pub async fn log_off_on_signal(
+        self,
+        grace_period: Duration,
+    ) -> Result<LogOffOutcome, NetworkError> {
+        shutdown_signal().await?;
+        info!("received shutdown signal, logging off");
+        self.log_off(grace_period).await
+    }
+}
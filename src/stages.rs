@@ -0,0 +1,287 @@
+use crate::auth::{AuthConfirmationHandler, GuardDataStore};
+use crate::connection::{set_error_state, PendingTransport};
+use crate::serverlist::ServerList;
+use crate::session::ConnectionError;
+use crate::{Connection, ConnectionOptions, ConnectionState};
+use steamid_ng::SteamID;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::watch;
+
+/// A tcp connection to a server, created with [`Connection::connect_tcp`]
+///
+/// The connection steps can be awaited one by one to show the progress of connecting,
+/// [`Connection::anonymous_with`] and [`Connection::login_with`] perform all steps at once.
+///
+/// ```no_run
+/// # use steam_vent::{Connection, ConnectionError, ConnectionOptions, ServerList};
+/// # async fn run(server_list: ServerList) -> Result<(), ConnectionError> {
+/// let tcp = Connection::connect_tcp(&server_list, ConnectionOptions::default()).await?;
+/// println!("Encrypting…");
+/// let channel = tcp.encrypt_channel().await?;
+/// println!("Logging in…");
+/// let connection = channel.logon_anonymous().await?.ready();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct TcpConnected {
+    transport: PendingTransport,
+    options: ConnectionOptions,
+}
+
+/// A connection that completed the encryption handshake, see [`TcpConnected`]
+pub struct EncryptedChannel {
+    connection: Connection,
+}
+
+/// A connection that is logged on but doesn't send heartbeats yet, see [`TcpConnected`]
+pub struct LoggedOn {
+    connection: Connection,
+}
+
+impl Connection {
+    /// Open the tcp connection to the server from the list with the best score, the first step of connecting
+    ///
+    /// The next best servers are tried if opening the connection fails.
+    pub async fn connect_tcp(
+        server_list: &ServerList,
+        options: ConnectionOptions,
+    ) -> Result<TcpConnected, ConnectionError> {
+        let options = options.with_new_state();
+        let transport = set_error_state(
+            &options.state,
+            Connection::connect_tcp_any(server_list, &options).await,
+        )?;
+        Ok(TcpConnected { transport, options })
+    }
+}
+
+impl TcpConnected {
+    /// The url of the server that is connected to
+    pub fn url(&self) -> &str {
+        &self.transport.addr
+    }
+
+    /// Subscribe to the state of the connection, which is updated by the following steps
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.options.state.subscribe()
+    }
+
+    /// Take the halves of the socket instead of performing the encryption handshake
+    ///
+    /// This is an escape hatch for integrations that need the socket itself, it's not needed for normal use.
+    /// Nothing has been sent or received yet, so the socket is a plain connection to the server that sends
+    /// the encryption request next. The halves can be put back together with [`OwnedReadHalf::reunite`].
+    ///
+    /// Only a [`Transport::Tcp`](crate::Transport::Tcp) connection has a plain socket, a websocket connection
+    /// is returned as is. There is no equivalent for [`EncryptedChannel`]: once the handshake is done
+    /// everything on the socket is encrypted with the session key, and the halves are owned by the
+    /// background tasks of the connection.
+    // the stage is handed back as is, like `OwnedReadHalf::reunite` does
+    #[allow(clippy::result_large_err)]
+    pub fn into_inner(self) -> Result<(OwnedReadHalf, OwnedWriteHalf), Self> {
+        match self.transport.into_tcp_stream() {
+            Ok(stream) => Ok(stream.into_split()),
+            Err(transport) => Err(TcpConnected {
+                transport,
+                options: self.options,
+            }),
+        }
+    }
+
+    /// Perform the encryption handshake
+    pub async fn encrypt_channel(self) -> Result<EncryptedChannel, ConnectionError> {
+        let state = self.options.state.clone();
+        let result = Connection::encrypt_channel(self.transport, &self.options).await;
+        let connection = set_error_state(&state, result)?;
+        Ok(EncryptedChannel { connection })
+    }
+}
+
+impl EncryptedChannel {
+    /// Log on anonymously
+    pub async fn logon_anonymous(self) -> Result<LoggedOn, ConnectionError> {
+        let state = self.connection.state.clone();
+        let connection = set_error_state(&state, self.connection.anonymous_logon().await)?;
+        Ok(LoggedOn { connection })
+    }
+
+    /// Log on with the account credentials
+    pub async fn logon<H: AuthConfirmationHandler, G: GuardDataStore>(
+        self,
+        account: &str,
+        password: &str,
+        guard_data_store: G,
+        confirmation_handler: H,
+    ) -> Result<LoggedOn, ConnectionError> {
+        let state = self.connection.state.clone();
+        let options = self.connection.options.clone();
+        let result = self
+            .connection
+            .login_logon(
+                account,
+                password,
+                guard_data_store,
+                confirmation_handler,
+                &options,
+            )
+            .await;
+        let connection = set_error_state(&state, result)?;
+        Ok(LoggedOn { connection })
+    }
+}
+
+impl LoggedOn {
+    /// The steam id of the logged on account
+    pub fn steam_id(&self) -> SteamID {
+        self.connection.steam_id()
+    }
+
+    /// Start sending heartbeats and mark the connection as connected, the last step of connecting
+    pub fn ready(self) -> Connection {
+        let connection = self.connection.start_session();
+        connection.state.send_replace(ConnectionState::Connected);
+        connection
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_connection_stages() {
+    use crate::net::{NetMessageHeader, RawNetMessage};
+    use crate::proto::enums_clientserver::EMsg;
+    use crate::proto::steammessages_clientserver_login::CMsgClientLogonResponse;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("ws://{}/cmsocket/", listener.local_addr().unwrap());
+    let steam_id = SteamID::from(0x01a0_0000_0000_1234);
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        for kind in [EMsg::k_EMsgClientHello, EMsg::k_EMsgClientLogon] {
+            let data = ws.next().await.unwrap().unwrap().into_data();
+            let message = RawNetMessage::read(data.into_iter().collect()).unwrap();
+            assert_eq!(kind, message.kind);
+        }
+        let header = NetMessageHeader {
+            session_id: 1234,
+            steam_id,
+            ..NetMessageHeader::default()
+        };
+        let response = CMsgClientLogonResponse {
+            eresult: Some(1),
+            heartbeat_seconds: Some(30),
+            ..CMsgClientLogonResponse::default()
+        };
+        let response = RawNetMessage::from_message(header, response).unwrap();
+        ws.send(WsMessage::binary(response.into_bytes()))
+            .await
+            .unwrap();
+        ws.next().await;
+    });
+
+    let options = ConnectionOptions::default();
+    // the server list only produces tls urls, so open the plain websocket connection directly
+    options.state.send_replace(ConnectionState::Connecting);
+    let transport = crate::connection::open_tcp(&addr, &options).await.unwrap();
+    let tcp = TcpConnected { transport, options };
+    let state = tcp.state();
+    assert_eq!(addr, tcp.url());
+    assert!(matches!(*state.borrow(), ConnectionState::Connecting));
+
+    let channel = tcp.encrypt_channel().await.unwrap();
+    assert!(matches!(*state.borrow(), ConnectionState::Encrypting));
+
+    let logged_on = channel.logon_anonymous().await.unwrap();
+    assert_eq!(steam_id, logged_on.steam_id());
+    assert!(matches!(*state.borrow(), ConnectionState::LoggingIn));
+
+    let connection = logged_on.ready();
+    assert!(matches!(*state.borrow(), ConnectionState::Connected));
+    assert_eq!(1234, connection.session.session_id);
+
+    connection.close().await.unwrap();
+    server.abort();
+
+    // failing a step closes the connection
+    let closed = ServerList::new(Vec::new(), vec!["127.0.0.1:1".into()]);
+    let options = ConnectionOptions::default();
+    assert!(Connection::connect_tcp(&closed, options.clone())
+        .await
+        .is_err());
+    // the connection got its own state channel, the options are left untouched
+    assert!(matches!(
+        *options.state.borrow(),
+        ConnectionState::Connecting
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_connect_tcp_failover() {
+    use crate::{NetworkError, Transport};
+    use tokio::net::TcpListener;
+
+    // nothing is listening on a port after its listener is dropped
+    let refused = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let working = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let working_addr = working.local_addr().unwrap();
+
+    let options = ConnectionOptions::default().with_transport(Transport::Tcp);
+    let server_list = ServerList::new(vec![refused, working_addr], Vec::new());
+    let tcp = Connection::connect_tcp(&server_list, options.clone())
+        .await
+        .unwrap();
+    assert_eq!(working_addr.to_string(), tcp.url());
+
+    // without a reachable server the error of every attempt is returned
+    let server_list = ServerList::new(vec![refused, refused], Vec::new());
+    let Err(ConnectionError::Unreachable(error)) =
+        Connection::connect_tcp(&server_list, options).await
+    else {
+        panic!("connecting should fail");
+    };
+    assert_eq!(2, error.failures.len());
+    assert_eq!(refused.to_string(), error.failures[0].0);
+    assert!(matches!(
+        error.failures[1].1,
+        ConnectionError::Network(NetworkError::IO(_))
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_tcp_connected_into_inner() {
+    use crate::Transport;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_list = ServerList::new(vec![listener.local_addr().unwrap()], Vec::new());
+    let options = ConnectionOptions::default().with_transport(Transport::Tcp);
+    let (tcp, accepted) = tokio::join!(
+        Connection::connect_tcp(&server_list, options),
+        listener.accept()
+    );
+    let (mut server, _) = accepted.unwrap();
+    let Ok((mut read, mut write)) = tcp.unwrap().into_inner() else {
+        panic!("a tcp connection has a socket");
+    };
+
+    // nothing was sent on the socket before it was taken
+    server.write_all(b"ping").await.unwrap();
+    let mut received = [0; 4];
+    read.read_exact(&mut received).await.unwrap();
+    assert_eq!(b"ping", &received);
+    write.write_all(b"pong").await.unwrap();
+    server.read_exact(&mut received).await.unwrap();
+    assert_eq!(b"pong", &received);
+    assert!(read.reunite(write).is_ok());
+}
@@ -0,0 +1,293 @@
+use crate::connection::Connection;
+use crate::eresult::EResult;
+use crate::game_id::GameId;
+use crate::keyvalues::{KeyValues, KeyValuesError, Value};
+use crate::message::{MalformedBody, NetMessage};
+use crate::net::NetworkError;
+use crate::proto::steammessages_clientserver_userstats::{
+    CMsgClientGetUserStats, CMsgClientGetUserStatsResponse,
+};
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum StatsError {
+    #[error(transparent)]
+    Network(#[from] NetworkError),
+    /// The stats are not available, e.g. because the app isn't owned by the account or has no stats
+    #[error("stats not available: {0:?}")]
+    Unavailable(EResult),
+}
+
+impl From<KeyValuesError> for StatsError {
+    fn from(value: KeyValuesError) -> Self {
+        StatsError::Network(MalformedBody::new(CMsgClientGetUserStatsResponse::KIND, value).into())
+    }
+}
+
+const STAT_TYPE_INT: i64 = 1;
+const STAT_TYPE_FLOAT: i64 = 2;
+const STAT_TYPE_AVERAGE_RATE: i64 = 3;
+const STAT_TYPE_ACHIEVEMENTS: i64 = 4;
+const STAT_TYPE_GROUP_ACHIEVEMENTS: i64 = 5;
+
+/// The value of a single stat
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatValue {
+    Int(i32),
+    Float(f32),
+}
+
+/// The state of a single achievement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Achievement {
+    /// The unix timestamp the achievement was unlocked at, if it's unlocked
+    pub unlock_time: Option<u32>,
+}
+
+impl Achievement {
+    pub fn unlocked(&self) -> bool {
+        self.unlock_time.is_some()
+    }
+}
+
+/// The stats and achievements of a user for an app, keyed by their api name
+#[derive(Debug, Clone, Default)]
+pub struct UserStats {
+    pub stats: HashMap<String, StatValue>,
+    pub achievements: HashMap<String, Achievement>,
+}
+
+/// Integers in the schema are sometimes stored as strings
+fn int_value(value: &Value) -> Option<i64> {
+    value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|value| value.parse().ok()))
+}
+
+impl UserStats {
+    fn from_response(response: &CMsgClientGetUserStatsResponse) -> Result<Self, StatsError> {
+        if let Err(result) = EResult::from_result(response.eresult()) {
+            return Err(StatsError::Unavailable(result));
+        }
+
+        let schema = KeyValues::parse_binary(response.schema())?;
+        let values: HashMap<u32, u32> = response
+            .stats
+            .iter()
+            .map(|stat| (stat.stat_id(), stat.stat_value()))
+            .collect();
+        let unlock_times: HashMap<u32, &[u32]> = response
+            .achievement_blocks
+            .iter()
+            .map(|block| (block.achievement_id(), block.unlock_time.as_slice()))
+            .collect();
+
+        let mut user_stats = UserStats::default();
+        // the schema is nested in a single object named after the app id
+        let stats = schema
+            .iter()
+            .next()
+            .and_then(|(_, app)| app.as_object())
+            .and_then(|app| app.get("stats"))
+            .and_then(|stats| stats.as_object());
+        for (id, stat) in stats.iter().flat_map(|stats| stats.iter()) {
+            let (Ok(id), Some(stat)) = (id.parse::<u32>(), stat.as_object()) else {
+                continue;
+            };
+            match stat.get("type").and_then(int_value) {
+                Some(STAT_TYPE_INT | STAT_TYPE_FLOAT | STAT_TYPE_AVERAGE_RATE) => {
+                    let Some(name) = stat.get("name").and_then(Value::as_str) else {
+                        continue;
+                    };
+                    let value = values.get(&id).copied().unwrap_or_default();
+                    let value = if stat.get("type").and_then(int_value) == Some(STAT_TYPE_INT) {
+                        StatValue::Int(value as i32)
+                    } else {
+                        StatValue::Float(f32::from_bits(value))
+                    };
+                    user_stats.stats.insert(name.into(), value);
+                }
+                Some(STAT_TYPE_ACHIEVEMENTS | STAT_TYPE_GROUP_ACHIEVEMENTS) => {
+                    let bits = stat.get("bits").and_then(Value::as_object);
+                    let unlock_times = unlock_times.get(&id).copied().unwrap_or_default();
+                    for (bit, achievement) in bits.iter().flat_map(|bits| bits.iter()) {
+                        let (Ok(bit), Some(achievement)) =
+                            (bit.parse::<usize>(), achievement.as_object())
+                        else {
+                            continue;
+                        };
+                        let Some(name) = achievement.get("name").and_then(Value::as_str) else {
+                            continue;
+                        };
+                        let unlock_time = unlock_times.get(bit).copied().filter(|time| *time > 0);
+                        user_stats
+                            .achievements
+                            .insert(name.into(), Achievement { unlock_time });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(user_stats)
+    }
+}
+
+/// Get the schema from a response, regardless of whether the values of the user are included
+fn schema_from_response(
+    response: &CMsgClientGetUserStatsResponse,
+) -> Result<KeyValues, StatsError> {
+    if response.schema().is_empty() {
+        let result = EResult::from_result(response.eresult()).err();
+        return Err(StatsError::Unavailable(result.unwrap_or(EResult::Fail)));
+    }
+    Ok(KeyValues::parse_binary(response.schema())?)
+}
+
+impl Connection {
+    async fn request_user_stats(
+        &self,
+        app_id: u32,
+    ) -> Result<CMsgClientGetUserStatsResponse, StatsError> {
+        let req = CMsgClientGetUserStats {
+            game_id: Some(GameId::from_app_id(app_id).into()),
+            steam_id_for_user: Some(self.steam_id().into()),
+            // request the schema to be included in the response
+            schema_local_version: Some(-1),
+            crc_stats: Some(0),
+            ..CMsgClientGetUserStats::default()
+        };
+        Ok(self.job(req).await?)
+    }
+
+    /// Get the stats and achievements of the logged in user for an app
+    pub async fn get_user_stats(&self, app_id: u32) -> Result<UserStats, StatsError> {
+        let response = self.request_user_stats(app_id).await?;
+        UserStats::from_response(&response)
+    }
+
+    /// Get the stats and achievements schema of an app, without the values of the user
+    ///
+    /// Unlike [`get_user_stats`](Self::get_user_stats) this doesn't fail when steam has no values for the user,
+    /// e.g. because the app isn't owned by the account, as long as the schema is included in the response.
+    /// The schema contains a single object named after the app id, with the stats under `stats`.
+    pub async fn get_stats_schema(&self, app_id: u32) -> Result<KeyValues, StatsError> {
+        let response = self.request_user_stats(app_id).await?;
+        schema_from_response(&response)
+    }
+}
+
+#[test]
+fn test_user_stats_from_response() {
+    use crate::proto::steammessages_clientserver_userstats::cmsg_client_get_user_stats_response::{
+        Achievement_Blocks, Stats,
+    };
+
+    fn string(out: &mut Vec<u8>, key: &str, value: &str) {
+        out.push(1);
+        out.extend_from_slice(key.as_bytes());
+        out.push(0);
+        out.extend_from_slice(value.as_bytes());
+        out.push(0);
+    }
+    fn int(out: &mut Vec<u8>, key: &str, value: i32) {
+        out.push(2);
+        out.extend_from_slice(key.as_bytes());
+        out.push(0);
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    fn object(out: &mut Vec<u8>, key: &str, content: impl FnOnce(&mut Vec<u8>)) {
+        out.push(0);
+        out.extend_from_slice(key.as_bytes());
+        out.push(0);
+        content(out);
+        out.push(8);
+    }
+
+    let mut schema = Vec::new();
+    object(&mut schema, "440", |out| {
+        object(out, "stats", |out| {
+            object(out, "1", |out| {
+                int(out, "type", 1);
+                string(out, "name", "kills");
+            });
+            object(out, "2", |out| {
+                string(out, "type", "2");
+                string(out, "name", "distance");
+            });
+            object(out, "3", |out| {
+                int(out, "type", 4);
+                object(out, "bits", |out| {
+                    object(out, "0", |out| string(out, "name", "ACH_FIRST"));
+                    object(out, "1", |out| string(out, "name", "ACH_SECOND"));
+                });
+            });
+        });
+    });
+    schema.push(8);
+
+    let response = CMsgClientGetUserStatsResponse {
+        eresult: Some(1),
+        schema: Some(schema),
+        stats: vec![
+            Stats {
+                stat_id: Some(1),
+                stat_value: Some(12),
+                ..Stats::default()
+            },
+            Stats {
+                stat_id: Some(2),
+                stat_value: Some(1.5f32.to_bits()),
+                ..Stats::default()
+            },
+        ],
+        achievement_blocks: vec![Achievement_Blocks {
+            achievement_id: Some(3),
+            unlock_time: vec![1700000000, 0],
+            ..Achievement_Blocks::default()
+        }],
+        ..CMsgClientGetUserStatsResponse::default()
+    };
+    let stats = UserStats::from_response(&response).unwrap();
+    assert_eq!(Some(&StatValue::Int(12)), stats.stats.get("kills"));
+    assert_eq!(Some(&StatValue::Float(1.5)), stats.stats.get("distance"));
+    assert!(stats.achievements["ACH_FIRST"].unlocked());
+    assert_eq!(
+        Some(1700000000),
+        stats.achievements["ACH_FIRST"].unlock_time
+    );
+    assert!(!stats.achievements["ACH_SECOND"].unlocked());
+
+    let response = CMsgClientGetUserStatsResponse {
+        eresult: Some(EResult::Fail as i32),
+        ..CMsgClientGetUserStatsResponse::default()
+    };
+    assert!(matches!(
+        UserStats::from_response(&response),
+        Err(StatsError::Unavailable(EResult::Fail))
+    ));
+}
+
+#[test]
+fn test_stats_schema_from_response() {
+    // {"440": {"version": 3}}
+    let schema = b"\x00440\x00\x02version\x00\x03\x00\x00\x00\x08\x08".to_vec();
+    let response = CMsgClientGetUserStatsResponse {
+        eresult: Some(EResult::AccessDenied as i32),
+        schema: Some(schema),
+        ..CMsgClientGetUserStatsResponse::default()
+    };
+    let schema = schema_from_response(&response).unwrap();
+    let app = schema.get("440").and_then(Value::as_object).unwrap();
+    assert_eq!(Some(3), app.get("version").and_then(Value::as_i64));
+
+    let response = CMsgClientGetUserStatsResponse {
+        eresult: Some(EResult::AccessDenied as i32),
+        ..CMsgClientGetUserStatsResponse::default()
+    };
+    assert!(matches!(
+        schema_from_response(&response),
+        Err(StatsError::Unavailable(EResult::AccessDenied))
+    ));
+}
@@ -0,0 +1,28 @@
+//! Spawning the background tasks of the connections
+//!
+//! With the `task-names` feature and the `tokio_unstable` cfg enabled the tasks are named,
+//! so they can be told apart in `tokio-console`.
+
+use std::future::Future;
+use tokio::task::JoinHandle;
+
+#[cfg(all(feature = "task-names", tokio_unstable))]
+pub(crate) fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("failed to spawn task")
+}
+
+#[cfg(not(all(feature = "task-names", tokio_unstable)))]
+pub(crate) fn spawn_named<F>(_name: &str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::task::spawn(future)
+}
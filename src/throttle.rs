@@ -0,0 +1,202 @@
+use futures_util::ready;
+use std::future::Future;
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep_until, Instant, Sleep};
+
+/// Token bucket limiting the number of bytes per second, allowing bursts of up to one second worth of bytes
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    rate: u64,
+    tokens: f64,
+    last_refill: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let rate = bytes_per_second.max(1);
+        TokenBucket {
+            rate,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+            sleep: None,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.last_refill = now;
+    }
+
+    /// Take the tokens for `bytes` that were already transferred
+    ///
+    /// Transfers larger than the remaining tokens are let through, leaving the bucket in debt
+    pub fn take(&mut self, bytes: usize) {
+        self.refill();
+        self.tokens -= bytes as f64;
+    }
+
+    /// Wait until the bucket is out of debt
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            self.refill();
+            if self.tokens >= 0.0 {
+                self.sleep = None;
+                return Poll::Ready(());
+            }
+            let deadline =
+                Instant::now() + Duration::from_secs_f64(-self.tokens / self.rate as f64);
+            let sleep = self
+                .sleep
+                .get_or_insert_with(|| Box::pin(sleep_until(deadline)));
+            sleep.as_mut().reset(deadline);
+            ready!(sleep.as_mut().poll(cx));
+        }
+    }
+}
+
+/// The bandwidth limits of a connection, see
+/// [`ConnectionOptions::with_send_bandwidth_limit`](crate::ConnectionOptions::with_send_bandwidth_limit)
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BandwidthLimits {
+    pub send: Option<u64>,
+    pub receive: Option<u64>,
+}
+
+impl BandwidthLimits {
+    /// Limit the bytes read from and written to `stream`
+    pub fn wrap<S>(self, stream: S) -> Throttled<S> {
+        Throttled {
+            inner: stream,
+            read: self.receive.map(TokenBucket::new),
+            write: self.send.map(TokenBucket::new),
+        }
+    }
+}
+
+/// A socket with the bytes read and written limited by token buckets
+///
+/// This sits below the transport, so everything sent over the socket is counted, including the framing,
+/// tls and websocket overhead and the handshakes.
+#[derive(Debug)]
+pub(crate) struct Throttled<S> {
+    inner: S,
+    read: Option<TokenBucket>,
+    write: Option<TokenBucket>,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Some(bucket) = &mut this.read {
+            ready!(bucket.poll_ready(cx));
+        }
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(bucket) = &mut this.read {
+            bucket.take(buf.filled().len() - filled);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(bucket) = &mut this.write else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        ready!(bucket.poll_ready(cx));
+        // don't write more than a second worth of bytes at once, so large messages are spread out
+        let len = buf.len().min(bucket.rate as usize);
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]))?;
+        bucket.take(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match &this.write {
+            None => Pin::new(&mut this.inner).poll_write_vectored(cx, bufs),
+            Some(_) => {
+                let buf = bufs.iter().find(|buf| !buf.is_empty());
+                Pin::new(this).poll_write(cx, buf.map_or(&[][..], |buf| &buf[..]))
+            }
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.write.is_none() && self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_token_bucket_rate() {
+    use futures_util::future::poll_fn;
+    use tokio::time::sleep;
+
+    let mut bucket = TokenBucket::new(10_000);
+    let start = Instant::now();
+    for _ in 0..50 {
+        bucket.take(1_000);
+        poll_fn(|cx| bucket.poll_ready(cx)).await;
+    }
+    // the first second worth of bytes is a burst, the rest is limited to the rate
+    let elapsed = start.elapsed().as_secs_f64();
+    assert!((3.9..4.1).contains(&elapsed), "took {elapsed}s");
+
+    // after being idle, the bucket allows a burst again
+    sleep(Duration::from_secs(5)).await;
+    let start = Instant::now();
+    bucket.take(10_000);
+    poll_fn(|cx| bucket.poll_ready(cx)).await;
+    assert!(start.elapsed() < Duration::from_millis(10));
+}
+
+#[cfg(test)]
+#[tokio::test(start_paused = true)]
+async fn test_throttled_write() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (client, mut server) = tokio::io::duplex(64 * 1024);
+    let limits = BandwidthLimits {
+        send: Some(10_000),
+        receive: None,
+    };
+    let mut client = limits.wrap(client);
+    let start = Instant::now();
+    // a single large write is split up and spread over the seconds it takes at the rate
+    client.write_all(&[0; 40_000]).await.unwrap();
+    let elapsed = start.elapsed().as_secs_f64();
+    assert!((1.9..2.1).contains(&elapsed), "took {elapsed}s");
+
+    let mut received = vec![0; 40_000];
+    server.read_exact(&mut received).await.unwrap();
+}
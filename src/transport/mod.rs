@@ -1,9 +1,22 @@
 use bytes::BytesMut;
 
-#[allow(dead_code)]
+pub use proxy::HttpProxy;
+
+mod proxy;
 pub mod tcp;
+mod tls;
 pub mod websocket;
 
+/// The protocol used for connecting to the servers, see [`ConnectionOptions::with_transport`](crate::ConnectionOptions::with_transport)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// A websocket connection secured with tls, like the current steam client uses (recommended)
+    #[default]
+    WebSocket,
+    /// A plain tcp connection, encrypted with the encryption handshake of steam
+    Tcp,
+}
+
 /// Assert that two BytesMut can be unsplit without allocations
 #[track_caller]
 fn assert_can_unsplit(head: &BytesMut, tail: &BytesMut) {
@@ -0,0 +1,232 @@
+use crate::net::NetworkError;
+use crate::resolver::SharedResolver;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use std::fmt::{Debug, Formatter};
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::debug;
+
+type Result<T, E = NetworkError> = std::result::Result<T, E>;
+
+/// An http proxy to tunnel the connections to the servers through, using `CONNECT` requests
+///
+/// ```
+/// # use steam_vent::HttpProxy;
+/// let proxy = HttpProxy::new("proxy.example", 3128).with_credentials("user", "hunter2");
+/// ```
+#[derive(Clone)]
+pub struct HttpProxy {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+impl HttpProxy {
+    /// Connect through the proxy at `host` and `port`
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        HttpProxy {
+            host: host.into(),
+            port,
+            credentials: None,
+        }
+    }
+
+    /// Authenticate with the proxy using basic authentication
+    pub fn with_credentials(
+        self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        HttpProxy {
+            credentials: Some((username.into(), password.into())),
+            ..self
+        }
+    }
+}
+
+impl Debug for HttpProxy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // leave out the password
+        f.debug_struct("HttpProxy")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field(
+                "username",
+                &self.credentials.as_ref().map(|(username, _)| username),
+            )
+            .finish()
+    }
+}
+
+/// Open a tcp connection to `host` and `port`, tunneled through the proxy if one is set
+///
+/// Each address gets `connect_timeout` to accept the connection,
+/// fails with [`NetworkError::Timeout`] if the last address doesn't accept it in time.
+pub(crate) async fn dial(
+    host: &str,
+    port: u16,
+    proxy: Option<&HttpProxy>,
+    connect_timeout: Duration,
+    resolver: &SharedResolver,
+) -> Result<TcpStream> {
+    let Some(proxy) = proxy else {
+        return resolver
+            .connect(host, port, connect_timeout)
+            .await
+            .map_err(connect_error);
+    };
+    let mut stream = resolver
+        .connect(&proxy.host, proxy.port, connect_timeout)
+        .await
+        .map_err(connect_error)?;
+    debug!("connected to proxy");
+
+    let target = format!("{host}:{port}");
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some((username, password)) = &proxy.credentials {
+        let encoded = BASE64_STANDARD.encode(format!("{username}:{password}"));
+        request.push_str(&format!("Proxy-Authorization: Basic {encoded}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let response = read_proxy_response(&mut stream).await?;
+    let status = parse_proxy_status(&response)?;
+    if status != 200 {
+        return Err(NetworkError::ProxyFailed(format!(
+            "proxy responded with status {status}"
+        )));
+    }
+    debug!("proxy tunnel established");
+    Ok(stream)
+}
+
+fn connect_error(e: std::io::Error) -> NetworkError {
+    match e.kind() {
+        ErrorKind::TimedOut => NetworkError::Timeout,
+        _ => e.into(),
+    }
+}
+
+/// Read the response headers from the proxy
+///
+/// The response is read byte by byte to not consume any data from the tunnel that follows it
+async fn read_proxy_response(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    const MAX_RESPONSE_SIZE: usize = 8 * 1024;
+
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_RESPONSE_SIZE {
+            return Err(NetworkError::ProxyFailed(
+                "proxy response headers too large".into(),
+            ));
+        }
+        match stream.read_u8().await {
+            Ok(byte) => response.push(byte),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Err(NetworkError::EOF),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(response)
+}
+
+fn parse_proxy_status(response: &[u8]) -> Result<u16> {
+    let status_line = response
+        .split(|byte| *byte == b'\n')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .unwrap_or_default();
+    let mut parts = status_line.split_whitespace();
+    match (parts.next(), parts.next().map(str::parse)) {
+        (Some(version), Some(Ok(status))) if version.starts_with("HTTP/") => Ok(status),
+        _ => Err(NetworkError::ProxyFailed(format!(
+            "invalid proxy response {:?}",
+            status_line.trim_end()
+        ))),
+    }
+}
+
+#[test]
+fn test_parse_proxy_status() {
+    assert_eq!(
+        200,
+        parse_proxy_status(b"HTTP/1.1 200 Connection established\r\n\r\n").unwrap()
+    );
+    assert_eq!(
+        407,
+        parse_proxy_status(b"HTTP/1.0 407 Proxy Authentication Required\r\n\r\n").unwrap()
+    );
+    assert!(matches!(
+        parse_proxy_status(b"SSH-2.0-OpenSSH\r\n\r\n"),
+        Err(NetworkError::ProxyFailed(_))
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_connect_through_proxy() {
+    use crate::connection::Connection;
+    use crate::net::RawNetMessage;
+    use crate::{ConnectionError, ConnectionOptions};
+    use futures_util::StreamExt;
+    use steam_vent_proto::enums_clientserver::EMsg;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    /// Accept a connection and read the `CONNECT` request, answering it with `status`
+    async fn accept_connect(
+        listener: &tokio::net::TcpListener,
+        status: &str,
+    ) -> (TcpStream, Vec<String>) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        let mut stream = stream.into_inner();
+        stream
+            .write_all(format!("HTTP/1.1 {status}\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        (stream, lines)
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = async {
+        let (stream, lines) = accept_connect(&listener, "200 Connection established").await;
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let data = ws.next().await.unwrap().unwrap().into_data();
+        let hello = RawNetMessage::read(data.into_iter().collect()).unwrap();
+        (lines, hello.kind, ws)
+    };
+    let options = ConnectionOptions::default()
+        .with_http_proxy(HttpProxy::new("127.0.0.1", port).with_credentials("user", "hunter2"));
+    let (connection, (lines, kind, _ws)) = tokio::join!(
+        Connection::connect("ws://cm.example:27020/cmsocket/", &options),
+        server
+    );
+    assert!(connection.is_ok());
+    assert_eq!("CONNECT cm.example:27020 HTTP/1.1", lines[0]);
+    assert!(lines.contains(&"Proxy-Authorization: Basic dXNlcjpodW50ZXIy".to_string()));
+    assert_eq!(EMsg::k_EMsgClientHello, kind);
+
+    let options = ConnectionOptions::default().with_http_proxy(HttpProxy::new("127.0.0.1", port));
+    let (connection, _) = tokio::join!(
+        Connection::connect("ws://cm.example:27020/cmsocket/", &options),
+        accept_connect(&listener, "407 Proxy Authentication Required")
+    );
+    assert!(matches!(
+        connection,
+        Err(ConnectionError::Network(NetworkError::ProxyFailed(_)))
+    ));
+}
@@ -1,86 +1,203 @@
+use crate::framing::{decode_frame, frame_length, FrameHeader, FRAME_HEADER_SIZE as HEADER_SIZE};
 use crate::message::{
     flatten_multi, ChannelEncryptRequest, ChannelEncryptResult, ClientEncryptResponse, NetMessage,
 };
 use crate::net::{NetMessageHeader, NetworkError, RawNetMessage};
+use crate::resolver::SharedResolver;
 use crate::transport::assert_can_unsplit;
-use bytemuck::{cast, Pod, Zeroable};
-use bytes::{Buf, BufMut, BytesMut};
+use crate::transport::proxy::{dial, HttpProxy};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures_util::future::ready;
+use futures_util::ready;
 use futures_util::{Sink, SinkExt, StreamExt, TryStreamExt};
-use std::convert::TryInto;
-use std::fmt::Debug;
-use steam_vent_crypto::{
-    generate_session_key, symmetric_decrypt, symmetric_encrypt_with_iv_buffer,
-};
-use tokio::net::{TcpStream, ToSocketAddrs};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io::{ErrorKind, IoSlice};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use steam_vent_crypto::{CryptError, CryptoProvider, DecryptBuffer, DefaultCrypto, SessionKeys};
+use steamid_ng::Universe;
+use thiserror::Error;
+use tokio::io::{split, AsyncRead, AsyncReadExt, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
 use tokio_stream::Stream;
 use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
+use tokio_util::io::poll_write_buf;
 use tracing::{debug, instrument, trace};
 
 type Result<T, E = NetworkError> = std::result::Result<T, E>;
 
-const MAGIC: [u8; 4] = *b"VT01";
+pub(crate) struct FrameCodec;
 
-#[derive(Debug, Default, Copy, Clone, Zeroable, Pod)]
-#[repr(C)]
-pub struct Header {
-    length: u32,
-    magic: [u8; 4],
-}
+impl Decoder for FrameCodec {
+    type Item = BytesMut;
+    type Error = NetworkError;
 
-impl Header {
-    pub fn validate(&self) -> Result<()> {
-        if self.magic != MAGIC {
-            Err(NetworkError::InvalidHeader)
-        } else {
-            Ok(())
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        let frame = decode_frame(src)?;
+        if let Some(frame) = &frame {
+            trace!("got packet of {} bytes", frame.len());
         }
+        Ok(frame)
     }
 }
 
-struct FrameCodec;
+/// A frame with the first [`HEADER_SIZE`] bytes reserved for the frame header
+///
+/// This allows the encoder to write the header in place instead of copying the payload after it
+struct Frame(BytesMut);
 
-impl Decoder for FrameCodec {
-    type Item = BytesMut;
+impl Frame {
+    fn with_capacity(capacity: usize) -> Self {
+        let mut buff = BytesMut::with_capacity(HEADER_SIZE + capacity);
+        buff.extend_from_slice(&[0; HEADER_SIZE]);
+        Frame(buff)
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
     type Error = NetworkError;
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
-        if src.len() < 8 {
-            return Ok(None);
+    fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let mut frame = item.0;
+        FrameHeader::new(frame.len() - HEADER_SIZE)?
+            .write((&mut frame[..HEADER_SIZE]).try_into().unwrap());
+
+        write_frame(frame, dst);
+        Ok(())
+    }
+}
+
+/// Move the frame into the write buffer, only copying if the buffer still contains earlier frames
+fn write_frame(frame: BytesMut, dst: &mut BytesMut) {
+    if dst.is_empty() {
+        *dst = frame;
+    } else {
+        dst.extend_from_slice(&frame);
+    }
+}
+
+/// The frames waiting to be written, as a single [`Buf`] so they can be written with one vectored write
+#[derive(Default)]
+struct FrameQueue {
+    frames: VecDeque<Bytes>,
+    len: usize,
+}
+
+impl Buf for FrameQueue {
+    fn remaining(&self) -> usize {
+        self.len
+    }
+
+    fn chunk(&self) -> &[u8] {
+        self.frames.front().map(Bytes::as_ref).unwrap_or_default()
+    }
+
+    fn chunks_vectored<'a>(&'a self, dst: &mut [IoSlice<'a>]) -> usize {
+        let mut count = 0;
+        for (slice, frame) in dst.iter_mut().zip(&self.frames) {
+            *slice = IoSlice::new(frame);
+            count += 1;
         }
+        count
+    }
 
-        let header_bytes = src[0..8].try_into().unwrap();
-        let header = cast::<[u8; 8], Header>(header_bytes);
-        header.validate()?;
-        trace!("got header for packet of {} bytes", header.length);
+    fn advance(&mut self, mut cnt: usize) {
+        self.len -= cnt;
+        while cnt > 0 {
+            let front = self
+                .frames
+                .front_mut()
+                .expect("advanced past the queued frames");
+            if cnt < front.len() {
+                front.advance(cnt);
+                return;
+            }
+            cnt -= front.len();
+            self.frames.pop_front();
+        }
+    }
+}
 
-        if src.len() < 8 + header.length as usize {
-            return Ok(None);
+/// Writes complete frames to the socket without copying them into a write buffer
+///
+/// Unlike a [`FramedWrite`], which appends every frame after the first to its buffer, the frames are queued as
+/// they are and written with vectored writes if the socket supports them, or one after the other if it doesn't.
+#[doc(hidden)]
+pub struct FrameWriter<W> {
+    inner: W,
+    queue: FrameQueue,
+}
+
+impl<W: AsyncWrite + Unpin> FrameWriter<W> {
+    /// Queued frames are written before accepting more once they reach this many bytes
+    const BACKPRESSURE_BOUNDARY: usize = 128 * 1024;
+
+    pub fn new(inner: W) -> Self {
+        FrameWriter {
+            inner,
+            queue: FrameQueue::default(),
         }
+    }
 
-        src.advance(8);
-        Ok(Some(src.split_to(header.length as usize)))
+    fn poll_write_queue(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while self.queue.has_remaining() {
+            let written = ready!(poll_write_buf(
+                Pin::new(&mut self.inner),
+                cx,
+                &mut self.queue
+            ))?;
+            if written == 0 {
+                return Poll::Ready(Err(std::io::Error::from(ErrorKind::WriteZero).into()));
+            }
+        }
+        Poll::Ready(Ok(()))
     }
 }
 
-impl Encoder<BytesMut> for FrameCodec {
+impl<W: AsyncWrite + Unpin> Sink<Bytes> for FrameWriter<W> {
     type Error = NetworkError;
 
-    fn encode(&mut self, item: BytesMut, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        dst.reserve(8 + item.len());
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if this.queue.remaining() >= Self::BACKPRESSURE_BOUNDARY {
+            ready!(this.poll_write_queue(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
 
-        dst.extend_from_slice(&u32::to_le_bytes(item.len() as u32));
-        dst.extend_from_slice(&MAGIC);
-        dst.extend_from_slice(item.as_ref());
+    fn start_send(self: Pin<&mut Self>, frame: Bytes) -> Result<()> {
+        let queue = &mut self.get_mut().queue;
+        queue.len += frame.len();
+        queue.frames.push_back(frame);
         Ok(())
     }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_queue(cx))?;
+        Poll::Ready(Ok(ready!(Pin::new(&mut this.inner).poll_flush(cx))?))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Poll::Ready(Ok(ready!(
+            Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+        )?))
+    }
 }
 
-struct RawMessageEncoder {
+struct RawMessageEncoder<C> {
     key: [u8; 32],
+    crypto: Arc<C>,
 }
 
-impl Encoder<RawNetMessage> for RawMessageEncoder {
+impl<C: CryptoProvider> Encoder<RawNetMessage> for RawMessageEncoder<C> {
     type Error = NetworkError;
 
     fn encode(&mut self, mut item: RawNetMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
@@ -113,62 +230,229 @@ impl Encoder<RawNetMessage> for RawMessageEncoder {
         debug_assert_eq!(16, iv_buffer.len());
 
         assert_can_unsplit(&iv_buffer, &raw);
-        let encrypted = symmetric_encrypt_with_iv_buffer(iv_buffer, raw, &self.key);
+        let encrypted = self.crypto.symmetric_encrypt(iv_buffer, raw, &self.key);
 
         let mut buf = item
             .frame_header_buffer
-            .unwrap_or_else(|| BytesMut::from(&[0; 8][..]));
-        debug_assert_eq!(8, buf.len());
-        buf.clear();
-        buf.extend_from_slice(&u32::to_le_bytes(encrypted.len() as u32));
-        buf.extend_from_slice(&MAGIC);
+            .unwrap_or_else(|| BytesMut::from(&[0; HEADER_SIZE][..]));
+        debug_assert_eq!(HEADER_SIZE, buf.len());
+        FrameHeader::new(encrypted.len())?.write((&mut buf[..HEADER_SIZE]).try_into().unwrap());
 
         assert_can_unsplit(&buf, &encrypted);
         buf.unsplit(encrypted);
 
-        // dst.extend_from_slice(&buf);
-        *dst = buf;
+        write_frame(buf, dst);
 
         Ok(())
     }
 }
 
+/// Decrypts the frames of the tcp reader into a reusable [`DecryptBuffer`]
+///
+/// The frame is only borrowed from the read buffer, so the read buffer can be reused for the next frames
+/// even while the decrypted messages are still being handled.
+struct RawMessageDecoder<C> {
+    key: [u8; 32],
+    crypto: Arc<C>,
+    buffer: DecryptBuffer,
+}
+
+impl<C: CryptoProvider> Decoder for RawMessageDecoder<C> {
+    type Item = BytesMut;
+    type Error = NetworkError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        let Some(encrypted) = FrameCodec.decode(src)? else {
+            return Ok(None);
+        };
+        let decrypted = self.buffer.decrypt(&*self.crypto, &encrypted, &self.key)?;
+        trace!("decrypted message of {} bytes", decrypted.len());
+        Ok(Some(decrypted))
+    }
+}
+
+/// Read a single frame from the reader and return the payload, `None` if the reader ends before the next frame
+///
+/// This allows reading frames from any source, e.g. a captured tcp stream, the payload can be read with
+/// [`RawNetMessage::read`] for unencrypted messages. Fails with [`NetworkError::EOF`] if the reader ends
+/// in the middle of a frame, and with [`NetworkError::FrameTooLarge`] without reading the payload if the
+/// header announces more than [`MAX_FRAME_SIZE`](crate::framing::MAX_FRAME_SIZE) bytes, the same limit
+/// the tcp transport applies.
+pub async fn read_message<R: AsyncRead + Unpin>(mut reader: R) -> Result<Option<BytesMut>> {
+    let mut header = [0; HEADER_SIZE];
+    let mut read = 0;
+    while read < HEADER_SIZE {
+        match reader.read(&mut header[read..]).await? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(NetworkError::EOF),
+            count => read += count,
+        }
+    }
+    // checks the length against the maximum frame size before anything is read or allocated for the payload
+    let length = FrameHeader::read(&header).payload_length()?;
+
+    // don't trust the length for allocating, it might be larger than the remaining data
+    let mut payload = Vec::new();
+    (&mut reader)
+        .take(length as u64)
+        .read_to_end(&mut payload)
+        .await?;
+    if payload.len() != length {
+        return Err(NetworkError::EOF);
+    }
+    Ok(Some(payload.as_slice().into()))
+}
+
 /// Write a message to a Sink
-pub async fn encode_message<T: NetMessage, S: Sink<BytesMut, Error = NetworkError> + Unpin>(
+async fn encode_message<T: NetMessage, S: Sink<Frame, Error = NetworkError> + Unpin>(
     header: &NetMessageHeader,
     message: &T,
     dst: &mut S,
 ) -> Result<(), NetworkError> {
-    let mut buff = BytesMut::with_capacity(message.encode_size() + 4);
+    let size = header
+        .encode_size(T::KIND, T::IS_PROTOBUF)
+        .checked_add(message.encode_size())
+        .ok_or(NetworkError::FrameTooLarge(u64::MAX))?;
+    frame_length(size)?;
+    let mut frame = Frame::with_capacity(size);
 
-    let mut writer = (&mut buff).writer();
+    let mut writer = (&mut frame.0).writer();
     header.write(&mut writer, T::KIND, T::IS_PROTOBUF)?;
     message.write_body(&mut writer)?;
 
-    trace!("encoded message({} bytes): {:?}", buff.len(), buff.as_ref());
-    dst.send(buff).await?;
+    trace!(
+        "encoded message({} bytes): {:?}",
+        frame.0.len() - HEADER_SIZE,
+        &frame.0[HEADER_SIZE..]
+    );
+    dst.send(frame).await?;
 
     Ok(())
 }
 
+/// The parameters of the encrypted channel negotiated in the handshake
+#[derive(Debug, Clone)]
+pub struct HandshakeInfo {
+    /// The protocol version requested by the server
+    pub protocol: u32,
+    /// The universe the server belongs to
+    pub universe: Universe,
+}
+
+/// A crypto provider shared between the options and the connections created from them
+#[derive(Clone)]
+pub(crate) struct SharedCrypto(Arc<dyn CryptoProvider>);
+
+impl SharedCrypto {
+    pub fn new<C: CryptoProvider>(crypto: C) -> Self {
+        SharedCrypto(Arc::new(crypto))
+    }
+}
+
+impl Default for SharedCrypto {
+    fn default() -> Self {
+        SharedCrypto::new(DefaultCrypto)
+    }
+}
+
+impl Debug for SharedCrypto {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedCrypto").finish_non_exhaustive()
+    }
+}
+
+impl CryptoProvider for SharedCrypto {
+    fn generate_session_key(
+        &self,
+        universe: Universe,
+        nonce: Option<&[u8; 16]>,
+    ) -> Result<SessionKeys, CryptError> {
+        self.0.generate_session_key(universe, nonce)
+    }
+
+    fn symmetric_encrypt(&self, iv_buff: BytesMut, input: BytesMut, key: &[u8; 32]) -> BytesMut {
+        self.0.symmetric_encrypt(iv_buff, input, key)
+    }
+
+    fn symmetric_decrypt(&self, input: BytesMut, key: &[u8; 32]) -> Result<BytesMut, CryptError> {
+        self.0.symmetric_decrypt(input, key)
+    }
+
+    fn hmac_sha1(&self, key: &[u8], data: &[&[u8]]) -> [u8; 20] {
+        self.0.hmac_sha1(key, data)
+    }
+}
+
+/// Parse the universe the server sent in the handshake
+fn parse_universe(universe: u32) -> Result<Universe> {
+    match universe {
+        1 => Ok(Universe::Public),
+        2 => Ok(Universe::Beta),
+        3 => Ok(Universe::Internal),
+        4 => Ok(Universe::Dev),
+        _ => Err(NetworkError::UnknownUniverse(universe)),
+    }
+}
+
+/// Open the tcp connection to the server at `addr` (as `host:port`)
+///
+/// Each address of the host gets `connect_timeout` to accept the connection,
+/// fails with [`NetworkError::Timeout`] if the last address doesn't accept it in time.
 #[instrument]
-pub async fn connect<A: ToSocketAddrs + Debug>(
-    addr: A,
+pub async fn connect_tcp(
+    addr: &str,
+    nodelay: bool,
+    connect_timeout: Duration,
+    proxy: Option<&HttpProxy>,
+    resolver: &SharedResolver,
+) -> Result<TcpStream> {
+    let (host, port) = addr
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidInput, "invalid server address"))?;
+    let stream = dial(host, port, proxy, connect_timeout, resolver).await?;
+    stream.set_nodelay(nodelay)?;
+    debug!("connected to server");
+    Ok(stream)
+}
+
+/// Perform the encryption handshake on an open tcp connection
+///
+/// The server sends its universe, the session key is encrypted with the public key the crypto provider has
+/// for that universe. Fails with [`NetworkError::UnsupportedUniverse`] if it has none.
+///
+/// The server also sends a 16 byte challenge, which is passed as the nonce to
+/// [`CryptoProvider::generate_session_key`] so it's encrypted together with the session key.
+/// This is the handshake of protocol version 1, the version current servers use. Encrypting the key without
+/// the nonce is the handshake without a challenge, which servers that send one don't expect.
+pub async fn encrypt<S: AsyncRead + AsyncWrite, C: CryptoProvider>(
+    stream: S,
+    crypto: C,
 ) -> Result<(
+    HandshakeInfo,
     impl Stream<Item = Result<RawNetMessage>>,
     impl Sink<RawNetMessage, Error = NetworkError>,
 )> {
-    let stream = TcpStream::connect(addr).await?;
-    debug!("connected to server");
-    let (read, write) = stream.into_split();
+    let (read, write) = split(stream);
     let mut raw_reader = FramedRead::new(read, FrameCodec);
     let mut raw_writer = FramedWrite::new(write, FrameCodec);
 
     let encrypt_request = RawNetMessage::read(raw_reader.next().await.ok_or(NetworkError::EOF)??)?
         .into_message::<ChannelEncryptRequest>()?;
 
+    let universe = parse_universe(encrypt_request.universe)?;
+
     trace!("using nonce: {:?}", encrypt_request.nonce);
-    let key = generate_session_key(None);
+    let crypto = Arc::new(crypto);
+    // the session key is encrypted with the public key of the universe
+    let key = crypto
+        .generate_session_key(universe, Some(&encrypt_request.nonce))
+        .map_err(|e| match e {
+            CryptError::UnsupportedUniverse(universe) => {
+                NetworkError::UnsupportedUniverse(universe)
+            }
+            e => e.into(),
+        })?;
 
     trace!("generated session keys: {:?}", key.plain);
     trace!("  encrypted: {:?}", key.encrypted);
@@ -188,19 +472,812 @@ pub async fn connect<A: ToSocketAddrs + Debug>(
 
     debug!("crypt handshake complete");
     let key = key.plain;
+    let info = HandshakeInfo {
+        protocol: encrypt_request.protocol,
+        universe,
+    };
 
+    let decoder = RawMessageDecoder {
+        key,
+        crypto: crypto.clone(),
+        buffer: DecryptBuffer::new(),
+    };
     Ok((
+        info,
         flatten_multi(
             raw_reader
-                .and_then(move |encrypted| {
-                    let decrypted = symmetric_decrypt(encrypted, &key).map_err(Into::into);
-                    if let Ok(bytes) = decrypted.as_ref() {
-                        trace!("decrypted message of {} bytes", bytes.len());
-                    }
-                    ready(decrypted)
-                })
+                .map_decoder(|_| decoder)
                 .and_then(|raw| ready(RawNetMessage::read(raw))),
         ),
-        FramedWrite::new(raw_writer.into_inner(), RawMessageEncoder { key }),
+        {
+            let mut encoder = RawMessageEncoder { key, crypto };
+            // encoding into an empty buffer hands over the frame without copying
+            FrameWriter::new(raw_writer.into_inner()).with(move |message| {
+                let mut frame = BytesMut::new();
+                ready(encoder.encode(message, &mut frame).map(|()| frame.freeze()))
+            })
+        },
     ))
 }
+
+/// Connecting failed for every address, see [`connect_addrs`]
+///
+/// Connecting to the servers of a server list fails with the server urls and the error of each attempt,
+/// see [`ConnectionError::Unreachable`](crate::ConnectionError::Unreachable).
+#[derive(Debug, Error)]
+#[error("failed to connect to any of the {} addresses", .failures.len())]
+pub struct ConnectAddrsError<A = SocketAddr, E = NetworkError> {
+    /// The error for each address, in the order they were tried
+    pub failures: Vec<(A, E)>,
+}
+
+/// Try `connect` for each address in order, returning the first address it succeeds for
+pub(crate) async fn first_reachable<A, T, E, F, Fut>(
+    addrs: &[A],
+    mut connect: F,
+) -> Result<(A, T), ConnectAddrsError<A, E>>
+where
+    A: Clone + Debug,
+    E: Debug,
+    F: FnMut(A) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut failures = Vec::new();
+    for addr in addrs {
+        let addr = addr.clone();
+        match connect(addr.clone()).await {
+            Ok(connected) => return Ok((addr, connected)),
+            Err(error) => {
+                debug!(addr = ?addr, error = ?error, "failed to connect to server");
+                failures.push((addr, error));
+            }
+        }
+    }
+    Err(ConnectAddrsError { failures })
+}
+
+/// Try to connect to each address in order, returning the first connection that completes the handshake
+///
+/// Every attempt, including the encryption handshake, is limited to `per_addr_timeout`, so an unresponsive
+/// server only delays the failover by that long. The address that was connected to is returned with the connection.
+///
+/// Connecting with [`ConnectionOptions`](crate::ConnectionOptions) fails over between the servers of the
+/// server list the same way.
+#[instrument]
+pub async fn connect_addrs(
+    addrs: &[SocketAddr],
+    per_addr_timeout: Duration,
+    nodelay: bool,
+) -> Result<
+    (
+        SocketAddr,
+        HandshakeInfo,
+        impl Stream<Item = Result<RawNetMessage>>,
+        impl Sink<RawNetMessage, Error = NetworkError>,
+    ),
+    ConnectAddrsError,
+> {
+    let resolver = &SharedResolver::default();
+    let (addr, (info, read, write)) = first_reachable(addrs, |addr| async move {
+        let connect = async {
+            let stream =
+                connect_tcp(&addr.to_string(), nodelay, per_addr_timeout, None, resolver).await?;
+            encrypt(stream, DefaultCrypto).await
+        };
+        timeout(per_addr_timeout, connect)
+            .await
+            .unwrap_or(Err(NetworkError::Timeout))
+    })
+    .await?;
+    Ok((addr, info, read, write))
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_read_message() {
+    use crate::framing::{write_frame, MAX_FRAME_SIZE};
+    use crate::proto::steammessages_clientserver_login::CMsgClientHeartBeat;
+    use steam_vent_proto::enums_clientserver::EMsg;
+
+    let heartbeat =
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .unwrap()
+            .into_bytes();
+    let mut capture = Vec::new();
+    write_frame(&mut capture, &heartbeat).unwrap();
+    write_frame(&mut capture, &[1, 2, 3]).unwrap();
+
+    let mut reader = capture.as_slice();
+    let frame = read_message(&mut reader).await.unwrap().unwrap();
+    assert_eq!(
+        EMsg::k_EMsgClientHeartBeat,
+        RawNetMessage::read(frame).unwrap().kind
+    );
+    assert_eq!(
+        &[1, 2, 3][..],
+        read_message(&mut reader).await.unwrap().unwrap()
+    );
+    assert!(read_message(&mut reader).await.unwrap().is_none());
+
+    // the capture ends in the middle of the header or the payload
+    assert!(matches!(
+        read_message(&capture[..3]).await,
+        Err(NetworkError::EOF)
+    ));
+    assert!(matches!(
+        read_message(&capture[..HEADER_SIZE + 2]).await,
+        Err(NetworkError::EOF)
+    ));
+    // a header announcing more data than there is doesn't allocate for it
+    let mut huge = MAX_FRAME_SIZE.to_le_bytes().to_vec();
+    huge.extend_from_slice(b"VT01");
+    assert!(matches!(
+        read_message(huge.as_slice()).await,
+        Err(NetworkError::EOF)
+    ));
+    assert!(matches!(
+        read_message(&b"\x01\x00\x00\x00VT02\x00"[..]).await,
+        Err(NetworkError::InvalidHeader)
+    ));
+
+    // an oversized header is rejected before its payload is read
+    let mut oversized = (MAX_FRAME_SIZE + 1).to_le_bytes().to_vec();
+    oversized.extend_from_slice(b"VT01");
+    oversized.extend_from_slice(&[1, 2, 3]);
+    let mut reader = oversized.as_slice();
+    assert!(matches!(
+        read_message(&mut reader).await,
+        Err(NetworkError::FrameTooLarge(len)) if len == u64::from(MAX_FRAME_SIZE) + 1
+    ));
+    assert_eq!(&[1, 2, 3], reader);
+}
+
+#[test]
+fn test_decode_oversized_frame() {
+    use crate::framing::MAX_FRAME_SIZE;
+
+    let mut src = BytesMut::new();
+    src.extend_from_slice(&(MAX_FRAME_SIZE + 1).to_le_bytes());
+    src.extend_from_slice(b"VT01");
+    assert!(matches!(
+        FrameCodec.decode(&mut src),
+        Err(NetworkError::FrameTooLarge(len)) if len == u64::from(MAX_FRAME_SIZE) + 1
+    ));
+}
+
+#[test]
+fn test_encode_frame() {
+    let mut frame = Frame::with_capacity(4);
+    frame.0.extend_from_slice(&[1, 2, 3, 4]);
+    let ptr = frame.0.as_ptr();
+
+    let mut dst = BytesMut::new();
+    FrameCodec.encode(frame, &mut dst).unwrap();
+    assert_eq!(
+        &[4, 0, 0, 0, b'V', b'T', b'0', b'1', 1, 2, 3, 4],
+        dst.as_ref()
+    );
+    // the frame is used as buffer without copying
+    assert_eq!(ptr, dst.as_ptr());
+
+    let mut frame = Frame::with_capacity(1);
+    frame.0.extend_from_slice(&[5]);
+    FrameCodec.encode(frame, &mut dst).unwrap();
+    assert_eq!(&[1, 0, 0, 0, b'V', b'T', b'0', b'1', 5], &dst[12..]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_frame_writer() {
+    use tokio::io::AsyncReadExt;
+
+    let frames = [
+        Bytes::from_static(b"first frame"),
+        Bytes::from_static(b"second"),
+        Bytes::from_static(b"third frame"),
+    ];
+    // the duplex only takes a few bytes at a time, so frames are written partially
+    let (write, mut read) = tokio::io::duplex(5);
+    let mut writer = FrameWriter::new(write);
+    let reader = async {
+        let mut received = vec![0; 28];
+        read.read_exact(&mut received).await.unwrap();
+        received
+    };
+    let writer = async {
+        for frame in frames {
+            writer.feed(frame).await.unwrap();
+        }
+        writer.close().await.unwrap();
+    };
+    let (received, ()) = tokio::join!(reader, writer);
+    assert_eq!(b"first framesecondthird frame", received.as_slice());
+    assert_eq!(0, read.read(&mut [0; 1]).await.unwrap());
+
+    // vectored writes take all queued frames at once
+    let mut writer = FrameWriter::new(Vec::new());
+    writer.feed(Bytes::from_static(b"ab")).await.unwrap();
+    writer.feed(Bytes::from_static(b"cd")).await.unwrap();
+    writer.flush().await.unwrap();
+    assert_eq!(b"abcd", writer.inner.as_slice());
+}
+
+#[test]
+fn test_encode_message_with_crypto() {
+    use crate::proto::steammessages_clientserver_login::CMsgClientHeartBeat;
+    use steam_vent_crypto::MockCrypto;
+
+    let message =
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .unwrap();
+    let mut expected = message.header_buffer.clone();
+    expected.extend_from_slice(&message.data);
+
+    let mut encoder = RawMessageEncoder {
+        key: [0; 32],
+        crypto: Arc::new(MockCrypto),
+    };
+    let mut dst = BytesMut::new();
+    encoder.encode(message, &mut dst).unwrap();
+
+    let mut frame = FrameCodec.decode(&mut dst).unwrap().unwrap();
+    // the mock crypto prefixes the message with an empty iv instead of encrypting it
+    assert_eq!(&[0; 16], &frame[..16]);
+    assert_eq!(expected, frame.split_off(16));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_handshake_timeout() {
+    use crate::{serverlist::ServerList, ConnectionOptions, Transport};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_list = ServerList::new(vec![listener.local_addr().unwrap()], vec![]);
+    let options = ConnectionOptions::default()
+        .with_transport(Transport::Tcp)
+        .with_handshake_timeout(Duration::from_millis(100));
+
+    // the server accepts the connection but never sends the encrypt request
+    let (result, accepted) = tokio::join!(
+        crate::connection::Connection::connect_any(&server_list, &options),
+        listener.accept()
+    );
+    assert!(accepted.is_ok());
+    let Err(crate::ConnectionError::Unreachable(error)) = result else {
+        panic!("the handshake should time out");
+    };
+    assert!(matches!(
+        error.failures[..],
+        [(_, crate::ConnectionError::Network(NetworkError::Timeout))]
+    ));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_connect_with_tcp_transport() {
+    use crate::{connection::Connection, ConnectionOptions, Transport};
+    use protobuf::Enum;
+    use steam_vent_crypto::MockCrypto;
+    use steam_vent_proto::enums_clientserver::EMsg;
+    use tokio_util::codec::Framed;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut framed = Framed::new(stream, FrameCodec);
+
+        let mut request = Frame::with_capacity(44);
+        request
+            .0
+            .put_u32_le(EMsg::k_EMsgChannelEncryptRequest.value() as u32);
+        request.0.put_u64_le(u64::MAX);
+        request.0.put_u64_le(u64::MAX);
+        request.0.put_u32_le(1); // protocol
+        request.0.put_u32_le(1); // universe
+        request.0.extend_from_slice(&[7; 16]); // nonce
+        framed.send(request).await.unwrap();
+
+        let response = framed.next().await.unwrap().unwrap();
+        assert_eq!(
+            EMsg::k_EMsgChannelEncryptResponse.value() as u32,
+            u32::from_le_bytes(response[0..4].try_into().unwrap())
+        );
+
+        let mut result = Frame::with_capacity(24);
+        result
+            .0
+            .put_u32_le(EMsg::k_EMsgChannelEncryptResult.value() as u32);
+        result.0.put_u64_le(u64::MAX);
+        result.0.put_u64_le(u64::MAX);
+        result.0.put_u32_le(1);
+        framed.send(result).await.unwrap();
+
+        // the mock crypto "encrypts" the hello by prefixing an empty iv
+        let mut hello = framed.next().await.unwrap().unwrap();
+        let hello = RawNetMessage::read(hello.split_off(16)).unwrap();
+        assert_eq!(EMsg::k_EMsgClientHello, hello.kind);
+        framed
+    });
+
+    let options = ConnectionOptions::default()
+        .with_transport(Transport::Tcp)
+        .with_crypto_provider(MockCrypto);
+    let connection = Connection::connect(&addr, &options).await.unwrap();
+    assert_eq!(
+        Some(std::net::IpAddr::from(std::net::Ipv4Addr::LOCALHOST)),
+        connection.local_ip
+    );
+    assert_eq!(Some(Universe::Public), connection.universe);
+    let _framed = server.await.unwrap();
+}
+
+/// Accept a connection and perform the server side of the handshake for a client using
+/// [`MockCrypto`](steam_vent_crypto::MockCrypto)
+#[cfg(test)]
+pub(crate) async fn accept_mock_handshake(
+    listener: &tokio::net::TcpListener,
+) -> tokio_util::codec::Framed<TcpStream, FrameCodec> {
+    use protobuf::Enum;
+    use steam_vent_proto::enums_clientserver::EMsg;
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut framed = tokio_util::codec::Framed::new(stream, FrameCodec);
+
+    let mut request = Frame::with_capacity(44);
+    request
+        .0
+        .put_u32_le(EMsg::k_EMsgChannelEncryptRequest.value() as u32);
+    request.0.put_u64_le(u64::MAX);
+    request.0.put_u64_le(u64::MAX);
+    request.0.put_u32_le(1); // protocol
+    request.0.put_u32_le(1); // universe
+    request.0.extend_from_slice(&[7; 16]); // nonce
+    framed.send(request).await.unwrap();
+    framed.next().await.unwrap().unwrap();
+
+    let mut result = Frame::with_capacity(24);
+    result
+        .0
+        .put_u32_le(EMsg::k_EMsgChannelEncryptResult.value() as u32);
+    result.0.put_u64_le(u64::MAX);
+    result.0.put_u64_le(u64::MAX);
+    result.0.put_u32_le(1);
+    framed.send(result).await.unwrap();
+    framed
+}
+
+/// Receive a message sent with [`MockCrypto`](steam_vent_crypto::MockCrypto), which prefixes an empty iv
+#[cfg(test)]
+pub(crate) async fn receive_mock_message(
+    framed: &mut tokio_util::codec::Framed<TcpStream, FrameCodec>,
+) -> RawNetMessage {
+    let mut frame = framed.next().await.unwrap().unwrap();
+    RawNetMessage::read(frame.split_off(16)).unwrap()
+}
+
+/// Send a message to a client using [`MockCrypto`](steam_vent_crypto::MockCrypto)
+#[cfg(test)]
+async fn send_mock_message(
+    framed: &mut tokio_util::codec::Framed<TcpStream, FrameCodec>,
+    message: RawNetMessage,
+) {
+    let mut frame = Frame::with_capacity(16 + message.encoded_len());
+    frame.0.extend_from_slice(&[0; 16]);
+    frame.0.extend_from_slice(&message.into_bytes());
+    framed.send(frame).await.unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_anonymous_logon_with_tcp_transport() {
+    use crate::proto::steammessages_clientserver_login::{
+        CMsgClientLogon, CMsgClientLogonResponse,
+    };
+    use crate::{Connection, ConnectionOptions, ServerList, Transport};
+    use steam_vent_crypto::MockCrypto;
+    use steam_vent_proto::enums_clientserver::EMsg;
+    use steamid_ng::SteamID;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server_list = ServerList::new(vec![listener.local_addr().unwrap()], Vec::new());
+    let steam_id = SteamID::from(0x01a0_0000_0000_1234);
+
+    let server = tokio::spawn(async move {
+        let mut framed = accept_mock_handshake(&listener).await;
+        let hello = receive_mock_message(&mut framed).await;
+        assert_eq!(EMsg::k_EMsgClientHello, hello.kind);
+
+        let logon = receive_mock_message(&mut framed).await;
+        assert_eq!(EMsg::k_EMsgClientLogon, logon.kind);
+        let logon: CMsgClientLogon = logon.into_message().unwrap();
+        assert_eq!(Some("anonymous"), logon.account_name.as_deref());
+
+        let header = NetMessageHeader {
+            session_id: 1234,
+            steam_id,
+            ..NetMessageHeader::default()
+        };
+        let response = CMsgClientLogonResponse {
+            eresult: Some(1),
+            heartbeat_seconds: Some(30),
+            cell_id: Some(42),
+            ..CMsgClientLogonResponse::default()
+        };
+        send_mock_message(
+            &mut framed,
+            RawNetMessage::from_message(header, response).unwrap(),
+        )
+        .await;
+        framed
+    });
+
+    let options = ConnectionOptions::default()
+        .with_transport(Transport::Tcp)
+        .with_crypto_provider(MockCrypto);
+    let connection = Connection::anonymous_with(server_list, options)
+        .await
+        .unwrap();
+    assert_eq!(1234, connection.session.session_id);
+    assert_eq!(steam_id, connection.steam_id());
+    assert_eq!(42, connection.cell_id());
+    assert_eq!(Some(Universe::Public), connection.universe);
+
+    let _framed = server.await.unwrap();
+    connection.close().await.unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_connect_with_compression() {
+    use crate::message::flatten_multi;
+    use crate::proto::steammessages_clientserver_friends::CMsgClientFriendMsg;
+    use crate::{connection::Connection, ConnectionOptions, Transport};
+    use futures_util::stream::once;
+    use steam_vent_crypto::MockCrypto;
+    use steam_vent_proto::enums_clientserver::EMsg;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let server = tokio::spawn(async move {
+        let mut framed = accept_mock_handshake(&listener).await;
+        let hello = receive_mock_message(&mut framed).await;
+        assert_eq!(EMsg::k_EMsgClientHello, hello.kind);
+
+        let multi = receive_mock_message(&mut framed).await;
+        assert_eq!(EMsg::k_EMsgMulti, multi.kind);
+        let compressed_len = multi.encoded_len();
+        // decoded the same way as the multi messages the server sends
+        let messages: Vec<_> = flatten_multi(once(ready(Ok(multi)))).collect().await;
+        assert_eq!(1, messages.len());
+        let message = messages.into_iter().next().unwrap().unwrap();
+        (compressed_len, message)
+    });
+
+    let options = ConnectionOptions::default()
+        .with_transport(Transport::Tcp)
+        .with_crypto_provider(MockCrypto)
+        .with_compression_threshold(1000);
+    let connection = Connection::connect(&addr, &options).await.unwrap();
+    let message = CMsgClientFriendMsg {
+        steamid: Some(76561198000000001),
+        message: Some(vec![b'a'; 10_000]),
+        ..CMsgClientFriendMsg::default()
+    };
+    connection
+        .send(NetMessageHeader::default(), message.clone())
+        .await
+        .unwrap();
+
+    let (compressed_len, received) = server.await.unwrap();
+    assert!(compressed_len < 1000);
+    assert_eq!(EMsg::k_EMsgClientFriendMsg, received.kind);
+    assert_eq!(
+        message,
+        received.into_message::<CMsgClientFriendMsg>().unwrap()
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_connect_with_bandwidth_limit() {
+    use crate::proto::steammessages_clientserver_friends::CMsgClientFriendMsg;
+    use crate::{connection::Connection, ConnectionOptions, Transport};
+    use protobuf::Enum;
+    use steam_vent_crypto::MockCrypto;
+    use steam_vent_proto::enums_clientserver::EMsg;
+    use tokio_util::codec::Framed;
+
+    // the timing of the limit is tested with a paused clock in the throttle module,
+    // this only checks that everything still arrives over a throttled connection
+    const LIMIT: u64 = 8_000;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut framed = Framed::new(stream, FrameCodec);
+
+        let mut request = Frame::with_capacity(44);
+        request
+            .0
+            .put_u32_le(EMsg::k_EMsgChannelEncryptRequest.value() as u32);
+        request.0.put_u64_le(u64::MAX);
+        request.0.put_u64_le(u64::MAX);
+        request.0.put_u32_le(1); // protocol
+        request.0.put_u32_le(1); // universe
+        request.0.extend_from_slice(&[7; 16]); // nonce
+        framed.send(request).await.unwrap();
+
+        framed.next().await.unwrap().unwrap();
+
+        let mut result = Frame::with_capacity(24);
+        result
+            .0
+            .put_u32_le(EMsg::k_EMsgChannelEncryptResult.value() as u32);
+        result.0.put_u64_le(u64::MAX);
+        result.0.put_u64_le(u64::MAX);
+        result.0.put_u32_le(1);
+        framed.send(result).await.unwrap();
+
+        // the hello followed by the messages
+        let mut received = 0;
+        for _ in 0..17 {
+            received += framed.next().await.unwrap().unwrap().len();
+        }
+        received
+    });
+
+    let options = ConnectionOptions::default()
+        .with_transport(Transport::Tcp)
+        .with_crypto_provider(MockCrypto)
+        .with_send_bandwidth_limit(LIMIT);
+    let connection = Connection::connect(&addr, &options).await.unwrap();
+    let message = CMsgClientFriendMsg {
+        message: Some(vec![0; 1000]),
+        ..CMsgClientFriendMsg::default()
+    };
+    for _ in 0..16 {
+        connection
+            .send(NetMessageHeader::default(), message.clone())
+            .await
+            .unwrap();
+    }
+
+    // more than a second worth of bytes, so part of it had to wait for the limit
+    let received = server.await.unwrap();
+    assert!(received > 16 * 1000);
+    assert!(received > 2 * LIMIT as usize);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_handshake_with_mock_server() {
+    use crate::proto::steammessages_clientserver_login::CMsgClientHeartBeat;
+    use protobuf::Enum;
+    use std::pin::pin;
+    use steam_vent_crypto::MockCrypto;
+    use steam_vent_proto::enums_clientserver::EMsg;
+    use tokio_util::codec::Framed;
+
+    fn frame(data: &[u8]) -> Frame {
+        let mut frame = Frame::with_capacity(data.len());
+        frame.0.extend_from_slice(data);
+        frame
+    }
+    fn encrypt_header(kind: EMsg) -> Vec<u8> {
+        let mut data = (kind.value() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        data
+    }
+    fn heartbeat() -> BytesMut {
+        RawNetMessage::from_message(NetMessageHeader::default(), CMsgClientHeartBeat::default())
+            .unwrap()
+            .into_bytes()
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut framed = Framed::new(stream, FrameCodec);
+
+        let mut request = encrypt_header(EMsg::k_EMsgChannelEncryptRequest);
+        request.extend_from_slice(&1u32.to_le_bytes()); // protocol
+        request.extend_from_slice(&1u32.to_le_bytes()); // universe
+        request.extend_from_slice(&[7; 16]); // nonce
+        framed.send(frame(&request)).await.unwrap();
+
+        let response = framed.next().await.unwrap().unwrap();
+        assert_eq!(
+            EMsg::k_EMsgChannelEncryptResponse.value() as u32,
+            u32::from_le_bytes(response[0..4].try_into().unwrap())
+        );
+        // kind, job ids, protocol and key length, then the key: the mock key is the plain key followed by the nonce
+        assert_eq!(48, u32::from_le_bytes(response[24..28].try_into().unwrap()));
+        assert_eq!(&[0; 32], &response[28..60]);
+        assert_eq!(&[7; 16], &response[60..76]);
+
+        let mut result = encrypt_header(EMsg::k_EMsgChannelEncryptResult);
+        result.extend_from_slice(&1u32.to_le_bytes());
+        framed.send(frame(&result)).await.unwrap();
+
+        // after the handshake, messages are encrypted, which the mock crypto does by prefixing an empty iv
+        let mut encrypted = vec![0; 16];
+        encrypted.extend_from_slice(&heartbeat());
+        framed.send(frame(&encrypted)).await.unwrap();
+
+        let mut received = framed.next().await.unwrap().unwrap();
+        RawNetMessage::read(received.split_off(16)).unwrap()
+    };
+
+    let client = async {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (info, read, write) = encrypt(stream, MockCrypto).await.unwrap();
+        assert_eq!(1, info.protocol);
+        assert_eq!(Universe::Public, info.universe);
+        let mut read = pin!(read);
+        let mut write = pin!(write);
+        let received = read.next().await.unwrap().unwrap();
+        let heartbeat = RawNetMessage::from_message(
+            NetMessageHeader::default(),
+            CMsgClientHeartBeat::default(),
+        )
+        .unwrap();
+        write.send(heartbeat).await.unwrap();
+        received
+    };
+
+    let (received_by_server, received_by_client) = tokio::join!(server, client);
+    assert_eq!(EMsg::k_EMsgClientHeartBeat, received_by_client.kind);
+    assert_eq!(EMsg::k_EMsgClientHeartBeat, received_by_server.kind);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_handshake_universe() {
+    use protobuf::Enum;
+    use steam_vent_crypto::MockCrypto;
+    use steam_vent_proto::enums_clientserver::EMsg;
+    use tokio_util::codec::Framed;
+
+    assert_eq!(Universe::Dev, parse_universe(4).unwrap());
+    assert!(matches!(
+        parse_universe(0),
+        Err(NetworkError::UnknownUniverse(0))
+    ));
+
+    // the default crypto only has the key of the public universe, other providers can have more
+    let cases = [
+        (
+            9u32,
+            SharedCrypto::new(MockCrypto),
+            false,
+            "Unknown universe 9",
+        ),
+        (2, SharedCrypto::default(), false, "No public key"),
+        (
+            2,
+            SharedCrypto::new(MockCrypto),
+            true,
+            "Unexpected end of stream",
+        ),
+    ];
+    for (universe, crypto, answers, expected) in cases {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, FrameCodec);
+            let mut frame = Frame::with_capacity(44);
+            frame
+                .0
+                .put_u32_le(EMsg::k_EMsgChannelEncryptRequest.value() as u32);
+            frame.0.put_u64_le(u64::MAX);
+            frame.0.put_u64_le(u64::MAX);
+            frame.0.put_u32_le(1); // protocol
+            frame.0.put_u32_le(universe);
+            frame.0.extend_from_slice(&[7; 16]); // nonce
+            framed.send(frame).await.unwrap();
+            // the connection is closed after the encrypted session key, if the client sends one
+            framed.next().await.is_some()
+        };
+        let client = async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            encrypt(stream, crypto).await
+        };
+        let (answered, result) = tokio::join!(server, client);
+        assert_eq!(answers, answered);
+        let Err(error) = result else {
+            panic!("handshake should fail");
+        };
+        assert!(error.to_string().starts_with(expected));
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_connect_addrs_failover() {
+    use protobuf::Enum;
+    use steam_vent_proto::enums_clientserver::EMsg;
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    fn frame(kind: EMsg, body: &[u8]) -> Frame {
+        let mut frame = Frame::with_capacity(20 + body.len());
+        frame.0.put_u32_le(kind.value() as u32);
+        frame.0.put_u64_le(u64::MAX);
+        frame.0.put_u64_le(u64::MAX);
+        frame.0.extend_from_slice(body);
+        frame
+    }
+
+    // nothing is listening on a port after its listener is dropped
+    let refused = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let working = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addrs = [
+        refused,
+        silent.local_addr().unwrap(),
+        working.local_addr().unwrap(),
+    ];
+
+    let silent_server = async {
+        let (stream, _) = silent.accept().await.unwrap();
+        stream
+    };
+    let working_server = async {
+        let (stream, _) = working.accept().await.unwrap();
+        let mut framed = Framed::new(stream, FrameCodec);
+        let mut request = Vec::new();
+        request.extend_from_slice(&1u32.to_le_bytes()); // protocol
+        request.extend_from_slice(&1u32.to_le_bytes()); // universe
+        request.extend_from_slice(&[7; 16]); // nonce
+        framed
+            .send(frame(EMsg::k_EMsgChannelEncryptRequest, &request))
+            .await
+            .unwrap();
+        framed.next().await.unwrap().unwrap();
+        framed
+            .send(frame(EMsg::k_EMsgChannelEncryptResult, &1u32.to_le_bytes()))
+            .await
+            .unwrap();
+        framed
+    };
+
+    // the silent server accepts the connection but never starts the handshake, so it only costs the timeout
+    let started = std::time::Instant::now();
+    let (result, _silent, _working) = tokio::join!(
+        connect_addrs(&addrs, Duration::from_millis(200), true),
+        silent_server,
+        working_server
+    );
+    let Ok((addr, info, _, _)) = result else {
+        panic!("connecting failed");
+    };
+    assert_eq!(addrs[2], addr);
+    assert_eq!(Universe::Public, info.universe);
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    let Err(error) = connect_addrs(&addrs[..2], Duration::from_millis(200), true).await else {
+        panic!("connecting should fail");
+    };
+    assert_eq!(2, error.failures.len());
+    assert_eq!(refused, error.failures[0].0);
+    assert!(matches!(error.failures[0].1, NetworkError::IO(_)));
+    assert_eq!(addrs[1], error.failures[1].0);
+    assert!(matches!(error.failures[1].1, NetworkError::Timeout));
+    assert_eq!(
+        "failed to connect to any of the 2 addresses",
+        error.to_string()
+    );
+}
@@ -1,23 +1,80 @@
 use crate::message::flatten_multi;
 use crate::net::{NetworkError, RawNetMessage};
+use crate::resolver::SharedResolver;
+use crate::throttle::BandwidthLimits;
 use crate::transport::assert_can_unsplit;
+use crate::transport::proxy::{dial, HttpProxy};
+use crate::transport::tls::insecure_connector;
 use futures_util::{Sink, SinkExt, StreamExt, TryStreamExt};
 use std::future::ready;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
 use tokio_stream::Stream;
-use tokio_tungstenite::connect_async;
+use tokio_tungstenite::client_async_tls_with_config;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request;
 use tokio_tungstenite::tungstenite::Message as WsMessage;
 use tracing::{debug, instrument};
 
 type Result<T, E = NetworkError> = std::result::Result<T, E>;
 
+/// A tcp connection to a websocket server, before the tls and websocket handshakes
+#[derive(Debug)]
+pub struct TcpConnection {
+    request: Request,
+    stream: TcpStream,
+}
+
+impl TcpConnection {
+    /// The local ip address of the connection
+    pub fn local_ip(&self) -> Option<IpAddr> {
+        self.stream.local_addr().ok().map(|addr| addr.ip())
+    }
+}
+
+/// Open the tcp connection to the websocket server
 #[instrument]
-pub async fn connect(
+pub async fn connect_tcp(
     addr: &str,
+    nodelay: bool,
+    connect_timeout: Duration,
+    proxy: Option<&HttpProxy>,
+    resolver: &SharedResolver,
+) -> Result<TcpConnection> {
+    let request = addr.into_client_request()?;
+    let host = request.uri().host().unwrap_or_default();
+    let port = request.uri().port_u16().unwrap_or_else(|| {
+        if request.uri().scheme_str() == Some("ws") {
+            80
+        } else {
+            443
+        }
+    });
+    let stream = dial(host, port, proxy, connect_timeout, resolver).await?;
+    stream.set_nodelay(nodelay)?;
+    Ok(TcpConnection { request, stream })
+}
+
+/// Perform the tls and websocket handshakes on an open tcp connection
+///
+/// The bandwidth limits apply to everything sent over the socket, starting with the tls handshake.
+#[instrument(skip(tcp))]
+pub async fn encrypt(
+    tcp: TcpConnection,
+    accept_invalid_certs: bool,
+    limits: BandwidthLimits,
 ) -> Result<(
     impl Stream<Item = Result<RawNetMessage>>,
     impl Sink<RawNetMessage, Error = NetworkError>,
 )> {
-    let (stream, _) = connect_async(addr).await?;
+    let connector = if accept_invalid_certs {
+        Some(insecure_connector()?)
+    } else {
+        None
+    };
+    let (stream, _) =
+        client_async_tls_with_config(tcp.request, limits.wrap(tcp.stream), None, connector).await?;
     debug!("connected to websocket server");
     let (raw_write, raw_read) = stream.split();
 
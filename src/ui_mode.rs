@@ -0,0 +1,80 @@
+use crate::connection::Connection;
+use crate::message::NetMessage;
+use crate::net::NetworkError;
+use crate::proto::enums_clientserver::EMsg;
+use crate::proto::steammessages_clientserver_2::CMsgClientUIMode;
+use protobuf::Message;
+use std::io::Write;
+use tracing::trace;
+
+/// The kind of client the session presents itself as, see [`Connection::set_ui_mode`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u32)]
+pub enum UiMode {
+    /// The desktop client
+    #[default]
+    Desktop = 0,
+    /// The fullscreen interface of the client, used on tvs and the steam deck
+    BigPicture = 1,
+    /// The mobile app
+    Mobile = 2,
+    /// The website
+    Web = 3,
+}
+
+/// The ui mode message, the protobuf is sent with a kind that has a different name
+#[derive(Debug)]
+struct ClientCurrentUiMode(CMsgClientUIMode);
+
+impl NetMessage for ClientCurrentUiMode {
+    const KIND: EMsg = EMsg::k_EMsgClientCurrentUIMode;
+    const IS_PROTOBUF: bool = true;
+
+    fn write_body<W: Write>(&self, mut writer: W) -> Result<(), std::io::Error> {
+        trace!("writing body of protobuf message {:?}", Self::KIND);
+        self.0
+            .write_to_writer(&mut writer)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))
+    }
+
+    fn encode_size(&self) -> usize {
+        self.0.compute_size() as usize
+    }
+}
+
+impl From<UiMode> for ClientCurrentUiMode {
+    fn from(mode: UiMode) -> Self {
+        ClientCurrentUiMode(CMsgClientUIMode {
+            uimode: Some(mode as u32),
+            ..CMsgClientUIMode::default()
+        })
+    }
+}
+
+impl Connection {
+    /// Set the kind of client the session presents itself as
+    ///
+    /// This changes how the session appears to the account owner, e.g. in the list of active sessions
+    /// and on the friends list, bots can use [`UiMode::Mobile`] to match the session of a mobile authenticator.
+    pub async fn set_ui_mode(&self, mode: UiMode) -> Result<(), NetworkError> {
+        self.send(self.session.header(), ClientCurrentUiMode::from(mode))
+            .await?;
+        self.presence.lock().unwrap().ui_mode = Some(mode);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_ui_mode_message() {
+    use crate::net::{NetMessageHeader, RawNetMessage};
+
+    let raw = RawNetMessage::from_message(
+        NetMessageHeader::default(),
+        ClientCurrentUiMode::from(UiMode::Mobile),
+    )
+    .unwrap();
+    assert_eq!(EMsg::k_EMsgClientCurrentUIMode, raw.kind);
+    assert!(raw.is_protobuf);
+    let message = CMsgClientUIMode::parse_from_bytes(&raw.data).unwrap();
+    assert_eq!(2, message.uimode());
+}
@@ -0,0 +1,80 @@
+use crate::message::{MalformedBody, NetMessage};
+use crate::net::NetMessageHeader;
+use crate::proto::enums_clientserver::EMsg;
+use binread::{BinRead, BinReaderExt};
+use bytes::BytesMut;
+use std::io::Cursor;
+use tracing::trace;
+
+#[derive(Debug, BinRead)]
+#[br(little)]
+struct VacBanStatusBody {
+    #[allow(dead_code)]
+    num_bans: u32,
+    #[br(count = num_bans)]
+    banned_apps: Vec<u32>,
+}
+
+/// The VAC bans on the logged in account, see [`Connection::vac_bans`](crate::Connection::vac_bans)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VacBanStatus {
+    /// The apps the account is VAC banned in
+    pub banned_apps: Vec<u32>,
+}
+
+impl VacBanStatus {
+    pub fn ban_count(&self) -> usize {
+        self.banned_apps.len()
+    }
+
+    pub fn is_banned(&self) -> bool {
+        !self.banned_apps.is_empty()
+    }
+
+    pub fn is_banned_in(&self, app_id: u32) -> bool {
+        self.banned_apps.contains(&app_id)
+    }
+}
+
+impl NetMessage for VacBanStatus {
+    const KIND: EMsg = EMsg::k_EMsgClientVACBanStatus;
+
+    fn read_body(data: BytesMut, _header: &NetMessageHeader) -> Result<Self, MalformedBody> {
+        trace!("reading body of {:?} message", Self::KIND);
+        // accounts without bans can get a message without any body
+        if data.is_empty() {
+            return Ok(VacBanStatus::default());
+        }
+        let mut reader = Cursor::new(data);
+        let body: VacBanStatusBody = reader
+            .read_le()
+            .map_err(|e| MalformedBody::new(Self::KIND, e))?;
+        Ok(VacBanStatus {
+            banned_apps: body.banned_apps,
+        })
+    }
+}
+
+#[test]
+fn test_read_vac_ban_status() {
+    let header = NetMessageHeader::default();
+    let mut data = BytesMut::new();
+    for value in [2u32, 730, 440] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    let status = VacBanStatus::read_body(data, &header).unwrap();
+    assert_eq!(2, status.ban_count());
+    assert!(status.is_banned_in(730));
+    assert!(!status.is_banned_in(570));
+
+    let status = VacBanStatus::read_body(BytesMut::from(&0u32.to_le_bytes()[..]), &header).unwrap();
+    assert!(!status.is_banned());
+    let status = VacBanStatus::read_body(BytesMut::new(), &header).unwrap();
+    assert!(!status.is_banned());
+
+    // the ban count doesn't match the listed apps
+    let mut data = BytesMut::new();
+    data.extend_from_slice(&3u32.to_le_bytes());
+    data.extend_from_slice(&730u32.to_le_bytes());
+    assert!(VacBanStatus::read_body(data, &header).is_err());
+}
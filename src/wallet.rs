@@ -0,0 +1,56 @@
+use crate::proto::steammessages_clientserver::CMsgClientWalletInfoUpdate;
+
+/// The steam wallet of the logged in account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Wallet {
+    pub has_wallet: bool,
+    /// The available balance, in the smallest unit of the currency (e.g. cents)
+    pub balance: i64,
+    /// Funds that have been added but aren't available yet, in the smallest unit of the currency
+    pub balance_delayed: i64,
+    /// The `ECurrencyCode` of the wallet, e.g. `1` for USD or `3` for EUR
+    pub currency: i32,
+}
+
+impl From<&CMsgClientWalletInfoUpdate> for Wallet {
+    fn from(update: &CMsgClientWalletInfoUpdate) -> Self {
+        // accounts without a wallet can still get an update, with meaningless balance and currency
+        if !update.has_wallet() {
+            return Wallet::default();
+        }
+        Wallet {
+            has_wallet: true,
+            // the 32 bit fields are only kept for older clients
+            balance: update.balance64.unwrap_or_else(|| update.balance().into()),
+            balance_delayed: update
+                .balance64_delayed
+                .unwrap_or_else(|| update.balance_delayed().into()),
+            currency: update.currency(),
+        }
+    }
+}
+
+#[test]
+fn test_wallet_from_update() {
+    let update = CMsgClientWalletInfoUpdate {
+        has_wallet: Some(true),
+        balance: Some(150),
+        currency: Some(3),
+        balance_delayed: Some(0),
+        balance64: Some(150),
+        balance64_delayed: Some(500),
+        ..CMsgClientWalletInfoUpdate::default()
+    };
+    let wallet = Wallet::from(&update);
+    assert!(wallet.has_wallet);
+    assert_eq!(150, wallet.balance);
+    assert_eq!(500, wallet.balance_delayed);
+    assert_eq!(3, wallet.currency);
+
+    let update = CMsgClientWalletInfoUpdate {
+        has_wallet: Some(false),
+        currency: Some(1),
+        ..CMsgClientWalletInfoUpdate::default()
+    };
+    assert_eq!(Wallet::default(), Wallet::from(&update));
+}
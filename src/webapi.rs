@@ -0,0 +1,20 @@
+use crate::connection::Connection;
+use crate::eresult::EResult;
+use crate::net::NetworkError;
+use crate::proto::steammessages_clientserver_login::{
+    CMsgClientRequestWebAPIAuthenticateUserNonce,
+    CMsgClientRequestWebAPIAuthenticateUserNonceResponse,
+};
+
+impl Connection {
+    /// Request a new nonce for authenticating with the web api
+    ///
+    /// Nonces expire after a while, long running sessions can use this to get a fresh one.
+    pub async fn request_web_api_nonce(&self) -> Result<String, NetworkError> {
+        let response: CMsgClientRequestWebAPIAuthenticateUserNonceResponse = self
+            .job(CMsgClientRequestWebAPIAuthenticateUserNonce::default())
+            .await?;
+        EResult::from_result(response.eresult())?;
+        Ok(response.webapi_authenticate_user_nonce.unwrap_or_default())
+    }
+}